@@ -45,6 +45,11 @@ async fn main() -> Result<()> {
             Rtp::new(None, rtsp.client_port_rtp, rtsp.server_addr_rtp.unwrap()).await?;
         rtp_stream.connect(Decoders::OpenH264).await?;
 
+        // Codec from the rtpmap and SPS/PPS so decoding starts at the first keyframe
+        if let Some(section) = rtsp.media_sections().first() {
+            rtp_stream.configure_from_sdp(section);
+        }
+
         // NOTE: Display decoded images with SDL2
         let sdl_context = sdl2::init().expect("Error sdl2 init");
         let video_subsystem = sdl_context.video().expect("Error sld2 video subsystem");
@@ -63,9 +68,6 @@ async fn main() -> Result<()> {
         let mut texture = texture_creator.create_texture_static(PixelFormatEnum::IYUV, 640, 352)?;
         let mut event_pump = sdl_context.event_pump().expect("Error sld2 event");
 
-        // Need this during testing as the first 40 frames
-        // or so are blank because it's not starting from SPS
-        // and instead getting frames from mid stream
         let mut wait_frames = 0;
 
         'read_rtp_packets: loop {
@@ -82,49 +84,56 @@ async fn main() -> Result<()> {
 
             rtp_stream.get_rtp().await?;
 
-            let maybe_some_yuv = rtp_stream.try_decode();
-            match maybe_some_yuv {
-                Ok(some_yuv) => match some_yuv {
-                    Some(yuv) => {
-                        info!("Decoded YUV!");
-
-                        wait_frames += 1;
-
-                        if wait_frames > 40 && wait_frames % 100 == 0 {
-                            let mut buf_rgb = [0u8; 640 * 352 * 4]; // rgba
-                            yuv.write_rgba8(&mut buf_rgb[..]);
-                            let buf_rgb = buf_rgb.to_vec();
-
-                            let boxes = detect_objects_on_image(buf_rgb);
-                            println!(
-                                "Detection: {}",
-                                if boxes.len() > 0 { boxes[0].4 } else { "None" }
+            // One packet can complete more than one access unit
+            loop {
+                let maybe_some_yuv = rtp_stream.try_decode();
+                match maybe_some_yuv {
+                    Ok(some_yuv) => match some_yuv {
+                        Some(yuv) => {
+                            info!("Decoded YUV!");
+
+                            wait_frames += 1;
+
+                            if wait_frames % 100 == 0 {
+                                let mut buf_rgb = [0u8; 640 * 352 * 4]; // rgba
+                                yuv.write_rgba8(&mut buf_rgb[..]);
+                                let buf_rgb = buf_rgb.to_vec();
+
+                                let boxes = detect_objects_on_image(buf_rgb);
+                                println!(
+                                    "Detection: {}",
+                                    if boxes.len() > 0 { boxes[0].4 } else { "None" }
+                                );
+                            }
+
+                            let (y_size, u_size, v_size) = yuv.strides_yuv();
+                            let _result = texture.update_yuv(
+                                None,
+                                yuv.y_with_stride(),
+                                y_size,
+                                yuv.u_with_stride(),
+                                u_size,
+                                yuv.v_with_stride(),
+                                v_size,
                             );
+
+                            canvas.clear();
+                            canvas
+                                .copy(&texture, None, None)
+                                .expect("Error copying texture");
+                            canvas.present();
                         }
+                        None => info!("Unable to decode to YUV"),
+                    },
+                    // Have been unable to decipher OpenH264 error codes
+                    // Instead, used ffprobe to get errors pertaining to malformed streams
+                    // save to raw h264 file and then ffprobe or ffplay (FFMPEG)
+                    Err(e) => warn!("Error: {e}"),
+                }
 
-                        let (y_size, u_size, v_size) = yuv.strides_yuv();
-                        let _result = texture.update_yuv(
-                            None,
-                            yuv.y_with_stride(),
-                            y_size,
-                            yuv.u_with_stride(),
-                            u_size,
-                            yuv.v_with_stride(),
-                            v_size,
-                        );
-
-                        canvas.clear();
-                        canvas
-                            .copy(&texture, None, None)
-                            .expect("Error copying texture");
-                        canvas.present();
-                    }
-                    None => info!("Unable to decode to YUV"),
-                },
-                // Have been unable to decipher OpenH264 error codes
-                // Instead, used ffprobe to get errors pertaining to malformed streams
-                // save to raw h264 file and then ffprobe or ffplay (FFMPEG)
-                Err(e) => warn!("Error: {e}"),
+                if rtp_stream.depacketizer().pending_count() == 0 {
+                    break;
+                }
             }
         }
     }
@@ -31,12 +31,23 @@ async fn main() -> Result<()> {
             Rtp::new(None, rtsp.client_port_rtp, rtsp.server_addr_rtp.unwrap()).await?;
         rtp_stream.connect(Decoders::OpenH264).await?;
 
+        // Codec from the rtpmap and SPS/PPS so decoding starts at the first keyframe
+        if let Some(section) = rtsp.media_sections().first() {
+            rtp_stream.configure_from_sdp(section);
+        }
+
+        // Size from the SDP, falling back to what this camera sends
+        let (width, height) = match rtsp.video_info() {
+            Some(video) => (video.width as u32, video.height as u32),
+            None => (640, 352),
+        };
+
         // NOTE: Display decoded images with SDL2
         let sdl_context = sdl2::init().expect("Error sdl2 init");
         let video_subsystem = sdl_context.video().expect("Error sld2 video subsystem");
 
         let window = video_subsystem
-            .window("IP Camera Video", 640, 352)
+            .window("IP Camera Video", width, height)
             .position_centered()
             .opengl()
             .build()?;
@@ -44,15 +55,9 @@ async fn main() -> Result<()> {
         let mut canvas = window.into_canvas().build()?;
         let texture_creator = canvas.texture_creator();
 
-        // TODO: Figure out how to move this into loop
-        // so as not to have to apply static definition
-        let mut texture = texture_creator.create_texture_static(PixelFormatEnum::IYUV, 640, 352)?;
+        let mut texture = texture_creator.create_texture_static(PixelFormatEnum::IYUV, width, height)?;
         let mut event_pump = sdl_context.event_pump().expect("Error sld2 event");
 
-        // Need this during testing as the first 40 frames
-        // or so are blank because it's not starting from SPS
-        // and instead getting frames from mid stream
-
         'read_rtp_packets: loop {
             for event in event_pump.poll_iter() {
                 match event {
@@ -67,35 +72,42 @@ async fn main() -> Result<()> {
 
             rtp_stream.get_rtp().await?;
 
-            let maybe_some_yuv = rtp_stream.try_decode();
-            match maybe_some_yuv {
-                Ok(some_yuv) => match some_yuv {
-                    Some(yuv) => {
-                        trace!("Decoded YUV!");
-
-                        let (y_size, u_size, v_size) = yuv.strides_yuv();
-                        let _result = texture.update_yuv(
-                            None,
-                            yuv.y_with_stride(),
-                            y_size,
-                            yuv.u_with_stride(),
-                            u_size,
-                            yuv.v_with_stride(),
-                            v_size,
-                        );
-
-                        canvas.clear();
-                        canvas
-                            .copy(&texture, None, None)
-                            .expect("Error copying texture");
-                        canvas.present();
-                    }
-                    None => trace!("Unable to decode to YUV"),
-                },
-                // Have been unable to decipher OpenH264 error codes
-                // Instead, used ffprobe to get errors pertaining to malformed streams
-                // save to raw h264 file and then ffprobe or ffplay (FFMPEG)
-                Err(e) => warn!("Error: {e}"),
+            // One packet can complete more than one access unit
+            loop {
+                let maybe_some_yuv = rtp_stream.try_decode();
+                match maybe_some_yuv {
+                    Ok(some_yuv) => match some_yuv {
+                        Some(yuv) => {
+                            trace!("Decoded YUV!");
+
+                            let (y_size, u_size, v_size) = yuv.strides_yuv();
+                            let _result = texture.update_yuv(
+                                None,
+                                yuv.y_with_stride(),
+                                y_size,
+                                yuv.u_with_stride(),
+                                u_size,
+                                yuv.v_with_stride(),
+                                v_size,
+                            );
+
+                            canvas.clear();
+                            canvas
+                                .copy(&texture, None, None)
+                                .expect("Error copying texture");
+                            canvas.present();
+                        }
+                        None => trace!("Unable to decode to YUV"),
+                    },
+                    // Have been unable to decipher OpenH264 error codes
+                    // Instead, used ffprobe to get errors pertaining to malformed streams
+                    // save to raw h264 file and then ffprobe or ffplay (FFMPEG)
+                    Err(e) => warn!("Error: {e}"),
+                }
+
+                if rtp_stream.depacketizer().pending_count() == 0 {
+                    break;
+                }
             }
         }
     }
@@ -0,0 +1,237 @@
+use crate::h264::BitReader;
+use crate::sdp::MediaSection;
+use log::trace;
+use std::cmp::Ordering;
+
+// AAC over RTP as MPEG4-GENERIC (RFC 3640), which most cameras send
+// alongside H264. Access units come out as ADTS frames, ready for a
+// player or to be written one after the other into a .aac file
+//
+// a=rtpmap:97 MPEG4-GENERIC/16000/1
+// a=fmtp:97 streamtype=5;profile-level-id=15;mode=AAC-hbr;config=1408;
+//     sizeLength=13;indexLength=3;indexDeltaLength=3
+
+// Samples in an AAC frame, the RTP timestamp step between
+// access units of one packet
+pub const SAMPLES_PER_FRAME: u32 = 1024;
+
+// Largest access unit an ADTS header can describe
+const MAX_FRAME_LEN: usize = 0x1FFF - ADTS_HEADER_LEN;
+const ADTS_HEADER_LEN: usize = 7;
+
+// ISO/IEC 14496-3 table 1.16
+#[rustfmt::skip]
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000,
+    22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+// AudioSpecificConfig (ISO/IEC 14496-3 section 1.6.2.1), the hex
+// string in the fmtp config parameter
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AacConfig {
+    pub object_type: u8, // 2 is AAC LC
+    pub sample_rate: u32,
+    pub channels: u8,
+    sample_rate_index: u8,
+}
+
+impl AacConfig {
+    // e.g. "1408" is AAC LC, 16kHz, mono
+    pub fn parse(hex: &str) -> Option<Self> {
+        let bytes = decode_hex(hex)?;
+        if bytes.len() < 2 {
+            return None;
+        }
+        let bits = u32::from_be_bytes([bytes[0], bytes[1], *bytes.get(2).unwrap_or(&0), *bytes.get(3).unwrap_or(&0)]);
+
+        // 5 bit object type, 31 escapes to 6 more bits
+        let (object_type, rest) = match bits >> 27 {
+            31 => (32 + ((bits >> 21) & 0x3F) as u8, bits << 11),
+            object_type => (object_type as u8, bits << 5),
+        };
+
+        // 4 bit index, 15 is an explicit 24 bit rate ADTS has no room for
+        let sample_rate_index = (rest >> 28) as u8;
+        let sample_rate = *SAMPLE_RATES.get(sample_rate_index as usize)?;
+        let channels = ((rest >> 24) & 0x0F) as u8;
+
+        Some(AacConfig {
+            object_type,
+            sample_rate,
+            channels,
+            sample_rate_index,
+        })
+    }
+
+    // ADTS header (ISO/IEC 13818-7 section 6.2) for a raw access unit
+    // of 'len' bytes: no CRC, one raw data block
+    pub fn adts_header(&self, len: usize) -> [u8; ADTS_HEADER_LEN] {
+        let frame_len = (len + ADTS_HEADER_LEN).min(0x1FFF);
+        // ADTS only has room for the MPEG-2 profiles, e.g. HE-AAC is
+        // signalled as LC with the rest left to implicit signalling
+        let profile = match self.object_type {
+            1..=4 => self.object_type - 1,
+            _ => 1,
+        };
+
+        [
+            0xFF,
+            0xF1, // sync word, MPEG-4, layer 0, no CRC
+            (profile << 6) | (self.sample_rate_index << 2) | ((self.channels >> 2) & 1),
+            ((self.channels & 3) << 6) | (frame_len >> 11) as u8,
+            (frame_len >> 3) as u8,
+            ((frame_len & 7) << 5) as u8 | 0x1F, // buffer fullness 0x7FF, VBR
+            0xFC,                                 // one raw data block
+        ]
+    }
+}
+
+// One access unit as an ADTS frame
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AacFrame {
+    pub timestamp: u32, // RTP timestamp
+    pub data: Vec<u8>,
+}
+
+pub struct AacDepacketizer {
+    config: AacConfig,
+    size_length: u32,
+    index_length: u32,
+    index_delta_length: u32,
+    // Access unit spread over packets: timestamp, size announced
+    // and what arrived so far
+    fragment: Option<(u32, usize, Vec<u8>)>,
+}
+
+impl AacDepacketizer {
+    // AU-header field sizes in bits, e.g. 13, 3, 3 for AAC-hbr
+    // and 6, 2, 2 for AAC-lbr
+    pub fn new(config: AacConfig, size_length: u32, index_length: u32, index_delta_length: u32) -> Self {
+        AacDepacketizer {
+            config,
+            size_length,
+            index_length,
+            index_delta_length,
+            fragment: None,
+        }
+    }
+
+    // From the fmtp of the track, None when it isn't AAC or the
+    // parameters needed are missing
+    pub fn from_sdp(section: &MediaSection) -> Option<Self> {
+        let fmtp = section.fmtp_for(section.payload_type?)?;
+        let config = AacConfig::parse(fmtp.get("config")?)?;
+        let param = |name: &str| fmtp.get(name).and_then(|value| value.trim().parse::<u32>().ok());
+
+        let size_length = param("sizeLength").filter(|&len| (1..=16).contains(&len))?;
+        let index_length = param("indexLength").unwrap_or(0).min(16);
+        let index_delta_length = param("indexDeltaLength").unwrap_or(0).min(16);
+
+        Some(Self::new(config, size_length, index_length, index_delta_length))
+    }
+
+    pub fn config(&self) -> &AacConfig {
+        &self.config
+    }
+
+    // Access units completed by an RTP payload. The marker bit is
+    // set on the last packet of a fragmented access unit
+    pub fn push(&mut self, payload: &[u8], timestamp: u32, marker: bool) -> Vec<AacFrame> {
+        let mut frames = Vec::new();
+
+        let headers_bits = match payload {
+            [high, low, ..] => u16::from_be_bytes([*high, *low]) as usize,
+            _ => return frames,
+        };
+        let headers_len = 2 + headers_bits.div_ceil(8);
+        let data = match payload.get(headers_len..) {
+            Some(data) => data,
+            None => return frames,
+        };
+
+        // AU-headers: size, then index for the first and index delta
+        // for the rest, which are 0 as RTSP cameras don't interleave
+        let mut reader = BitReader::new(&payload[2..headers_len]);
+        let mut sizes = Vec::new();
+        let mut read = 0;
+        while read + (self.size_length + self.index_length) as usize <= headers_bits {
+            let index_length = match sizes.is_empty() {
+                true => self.index_length,
+                false => self.index_delta_length,
+            };
+            let size = match reader.u(self.size_length as usize) {
+                Some(size) => size as usize,
+                None => break,
+            };
+            reader.u(index_length as usize);
+            read += (self.size_length + index_length) as usize;
+            sizes.push(size);
+        }
+
+        // A single access unit larger than the packet is a fragment
+        if let [size] = sizes[..] {
+            let continues = self.fragment.as_ref().is_some_and(|(started, ..)| *started == timestamp);
+            if size > data.len() || continues {
+                self.push_fragment(size, data, timestamp, marker, &mut frames);
+                return frames;
+            }
+        }
+        self.fragment = None;
+
+        let mut offset = 0;
+        for (i, size) in sizes.into_iter().enumerate() {
+            let au = match data.get(offset..offset + size) {
+                Some(au) => au,
+                None => break,
+            };
+            offset += size;
+            if size > MAX_FRAME_LEN {
+                continue;
+            }
+
+            let timestamp = timestamp.wrapping_add(i as u32 * SAMPLES_PER_FRAME);
+            frames.push(self.frame(timestamp, au));
+        }
+
+        frames
+    }
+
+    fn push_fragment(&mut self, size: usize, data: &[u8], timestamp: u32, marker: bool, frames: &mut Vec<AacFrame>) {
+        // A fragment of another access unit means the end of the
+        // last one was lost
+        let continues = matches!(&self.fragment, Some((started, expected, _)) if *started == timestamp && *expected == size);
+        let mut au = match self.fragment.take() {
+            Some((_, _, au)) if continues => au,
+            _ => Vec::new(),
+        };
+        au.extend_from_slice(data);
+
+        match au.len().cmp(&size) {
+            Ordering::Equal if size <= MAX_FRAME_LEN => frames.push(self.frame(timestamp, &au)),
+            // Marker before it is complete: a fragment went missing
+            Ordering::Less if !marker => self.fragment = Some((timestamp, size, au)),
+            _ => trace!("[Aac] Dropping a {size} byte access unit with {} bytes received", au.len()),
+        }
+    }
+
+    fn frame(&self, timestamp: u32, au: &[u8]) -> AacFrame {
+        let mut data = Vec::with_capacity(ADTS_HEADER_LEN + au.len());
+        data.extend_from_slice(&self.config.adts_header(au.len()));
+        data.extend_from_slice(au);
+
+        AacFrame { timestamp, data }
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,264 @@
+use crate::error::RtspError;
+use crate::rtsp::{Methods, Rtsp};
+use log::info;
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, RtspError>;
+
+// Stepping back up needs the total to stay this far under the cap
+const HEADROOM: f64 = 0.8;
+// Time for bitrates to settle after a change before judging again
+const SETTLE: Duration = Duration::from_secs(3);
+
+// Keeps the inbound bandwidth of several cameras under a total cap
+// (and each under its own) by stepping the least important ones down
+// to their substream, then to keyframes only, and back up once there
+// is room again. The host feeds in bitrates and applies the actions:
+//
+// let mut manager = BandwidthManager::new();
+// manager.set_max_bitrate(Some(40_000_000.0));
+// let door = manager.add_session("rtsp://door/main", 10);
+// manager.set_substream(door, Some("rtsp://door/sub"));
+// ...
+// manager.update(door, rtp.stats().bitrate);
+// for event in manager.enforce() {
+//     manager.apply(&event, &mut rtsp).await?;
+// }
+pub struct BandwidthManager {
+    max_bitrate: Option<f64>,
+    sessions: Vec<Session>,
+    changed_at: Option<Instant>,
+}
+
+// How much of a camera's stream is received, best first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum StreamLevel {
+    Main,
+    Substream,
+    // Of whichever stream it was on, see Rtsp::set_keyframes_only
+    KeyframesOnly,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BandwidthReason {
+    // The session went over its own cap
+    SessionCap { bitrate: f64, max: f64 },
+    // All sessions together went over the total cap
+    TotalCap { bitrate: f64, max: f64 },
+    // There is room for the session to step back up
+    Headroom { bitrate: f64 },
+}
+
+// A session changing level, for the host to apply (see apply)
+#[derive(Clone, Debug, PartialEq)]
+pub struct BandwidthEvent {
+    pub session: usize,
+    pub from: StreamLevel,
+    pub to: StreamLevel,
+    pub reason: BandwidthReason,
+}
+
+struct Session {
+    main: String,
+    substream: Option<String>,
+    priority: u32,
+    max_bitrate: Option<f64>,
+    bitrate: f64,
+    level: StreamLevel,
+    // Level and bitrate before each step down, to step back up
+    stepped_down: Vec<(StreamLevel, f64)>,
+}
+
+impl Session {
+    fn next_down(&self) -> Option<StreamLevel> {
+        match self.level {
+            StreamLevel::Main if self.substream.is_some() => Some(StreamLevel::Substream),
+            StreamLevel::Main | StreamLevel::Substream => Some(StreamLevel::KeyframesOnly),
+            StreamLevel::KeyframesOnly => None,
+        }
+    }
+}
+
+impl BandwidthManager {
+    pub fn new() -> Self {
+        BandwidthManager {
+            max_bitrate: None,
+            sessions: Vec::new(),
+            changed_at: None,
+        }
+    }
+
+    // Total inbound bits per second for all sessions, None for no cap
+    pub fn set_max_bitrate(&mut self, max_bitrate: Option<f64>) -> &mut Self {
+        self.max_bitrate = max_bitrate;
+        self
+    }
+
+    // Add a camera on its main stream, returns the session id. Higher
+    // priority sessions are stepped down last and back up first
+    pub fn add_session(&mut self, main: &str, priority: u32) -> usize {
+        self.sessions.push(Session {
+            main: main.to_string(),
+            substream: None,
+            priority,
+            max_bitrate: None,
+            bitrate: 0.0,
+            level: StreamLevel::Main,
+            stepped_down: Vec::new(),
+        });
+
+        self.sessions.len() - 1
+    }
+
+    // Lower resolution stream of the same camera, without one the
+    // session goes from its main stream straight to keyframes only
+    pub fn set_substream(&mut self, session: usize, substream: Option<&str>) -> &mut Self {
+        if let Some(state) = self.sessions.get_mut(session) {
+            state.substream = substream.map(|substream| substream.to_string());
+        }
+        self
+    }
+
+    // Cap of the session on its own, bits per second
+    pub fn set_session_max_bitrate(&mut self, session: usize, max_bitrate: Option<f64>) -> &mut Self {
+        if let Some(state) = self.sessions.get_mut(session) {
+            state.max_bitrate = max_bitrate;
+        }
+        self
+    }
+
+    // Bitrate received by the session (all its tracks), e.g. from
+    // Rtp::stats. Call regularly, at least before each enforce
+    pub fn update(&mut self, session: usize, bitrate: f64) {
+        if let Some(state) = self.sessions.get_mut(session) {
+            state.bitrate = bitrate;
+        }
+    }
+
+    pub fn level(&self, session: usize) -> Option<StreamLevel> {
+        self.sessions.get(session).map(|state| state.level)
+    }
+
+    pub fn total_bitrate(&self) -> f64 {
+        self.sessions.iter().map(|state| state.bitrate).sum()
+    }
+
+    // Sessions to step down (or up) to stay within the caps. A step
+    // at a time and none until bitrates settled after the last one
+    pub fn enforce(&mut self) -> Vec<BandwidthEvent> {
+        if self.changed_at.is_some_and(|changed_at| changed_at.elapsed() < SETTLE) {
+            return Vec::new();
+        }
+
+        let mut events = Vec::new();
+
+        // Over their own cap
+        for session in 0..self.sessions.len() {
+            let state = &self.sessions[session];
+            if let Some(max) = state.max_bitrate.filter(|&max| state.bitrate > max) {
+                let reason = BandwidthReason::SessionCap { bitrate: state.bitrate, max };
+                events.extend(self.step_down(session, reason));
+            }
+        }
+
+        // Over the total cap: the least important session, and of
+        // those the one using most
+        let total = self.total_bitrate();
+        match self.max_bitrate {
+            Some(max) if total > max && events.is_empty() => {
+                let session = (0..self.sessions.len())
+                    .filter(|&session| self.sessions[session].next_down().is_some())
+                    .min_by(|&a, &b| {
+                        let (a, b) = (&self.sessions[a], &self.sessions[b]);
+                        a.priority.cmp(&b.priority).then(b.bitrate.total_cmp(&a.bitrate))
+                    });
+                if let Some(session) = session {
+                    events.extend(self.step_down(session, BandwidthReason::TotalCap { bitrate: total, max }));
+                }
+            }
+            _ if events.is_empty() => events.extend(self.step_up(total)),
+            _ => (),
+        }
+
+        if !events.is_empty() {
+            self.changed_at = Some(Instant::now());
+        }
+
+        events
+    }
+
+    // Make the change on the session's connection. Switching streams
+    // sets the session up again on the same client ports, the Rtp
+    // receiving it then needs Rtp::set_server_addr for the new port
+    pub async fn apply(&self, event: &BandwidthEvent, rtsp: &mut Rtsp) -> Result<()> {
+        let state = match self.sessions.get(event.session) {
+            Some(state) => state,
+            None => return Ok(()),
+        };
+
+        match (event.from, event.to) {
+            (_, StreamLevel::KeyframesOnly) | (StreamLevel::KeyframesOnly, _) => {
+                rtsp.set_keyframes_only(event.to == StreamLevel::KeyframesOnly);
+                rtsp.send(Methods::Play).await?.check_status()?;
+            }
+            (_, StreamLevel::Substream) => {
+                if let Some(substream) = &state.substream {
+                    rtsp.switch_stream(substream).await?;
+                }
+            }
+            (_, StreamLevel::Main) => {
+                rtsp.switch_stream(&state.main).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn step_down(&mut self, session: usize, reason: BandwidthReason) -> Option<BandwidthEvent> {
+        let state = &mut self.sessions[session];
+        let to = state.next_down()?;
+
+        state.stepped_down.push((state.level, state.bitrate));
+        let from = std::mem::replace(&mut state.level, to);
+
+        info!("[Bandwidth] Session {session} {from:?} -> {to:?}: {reason:?}");
+        Some(BandwidthEvent { session, from, to, reason })
+    }
+
+    // The most important session stepped down whose previous bitrate
+    // fits again, within its own cap as well
+    fn step_up(&mut self, total: f64) -> Option<BandwidthEvent> {
+        let session = (0..self.sessions.len())
+            .filter(|&session| {
+                let state = &self.sessions[session];
+                let before = match state.stepped_down.last() {
+                    Some((_, before)) => *before,
+                    None => return false,
+                };
+                let fits_total = match self.max_bitrate {
+                    Some(max) => total - state.bitrate + before <= max * HEADROOM,
+                    None => true,
+                };
+                let fits_session = match state.max_bitrate {
+                    Some(max) => before <= max * HEADROOM,
+                    None => true,
+                };
+                fits_total && fits_session
+            })
+            .max_by_key(|&session| self.sessions[session].priority)?;
+
+        let state = &mut self.sessions[session];
+        let (to, _) = state.stepped_down.pop()?;
+        let from = std::mem::replace(&mut state.level, to);
+        let reason = BandwidthReason::Headroom { bitrate: total };
+
+        info!("[Bandwidth] Session {session} {from:?} -> {to:?}: {reason:?}");
+        Some(BandwidthEvent { session, from, to, reason })
+    }
+}
+
+impl Default for BandwidthManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,38 @@
+// Upper limits on the memory buffers of a stream can grow to, for
+// gateways where running out of memory is worse than losing frames.
+// None is unbounded (the default everywhere).
+//
+// When a limit would be exceeded the buffer is dropped instead of
+// grown: the depacketizer throws away the access unit it was
+// assembling and waits for the next SPS/PPS, so the stream degrades
+// to resuming at keyframes rather than allocating past the budget.
+//
+// let budget = MemoryBudget::new(16 * 1024 * 1024);
+// rtp.set_memory_budget(&budget);
+// timeshift.set_max_bytes(budget.max_timeshift);
+#[derive(Clone, Debug, Default)]
+pub struct MemoryBudget {
+    // Access unit being assembled (including FU-A fragments)
+    pub max_access_unit: Option<usize>,
+    // Decoded stream kept for Rtp::save_file
+    pub max_recording: Option<usize>,
+    // Timeshift buffer
+    pub max_timeshift: Option<usize>,
+}
+
+impl MemoryBudget {
+    // Split one total between the buffers: an eighth for the access
+    // unit (plenty for a 1080p IDR at 16 MB), the rest shared
+    // between the recording and the timeshift buffer
+    pub fn new(total: usize) -> Self {
+        MemoryBudget {
+            max_access_unit: Some(total / 8),
+            max_recording: Some(total / 8 * 3),
+            max_timeshift: Some(total / 2),
+        }
+    }
+
+    pub fn unbounded() -> Self {
+        Self::default()
+    }
+}
@@ -0,0 +1,257 @@
+use log::{debug, warn};
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::time::{sleep_until, Instant};
+
+// Byte exact capture of a session for vendor bug reports: everything
+// written to and read from the RTSP connection plus every RTP packet
+// received, each with the time since the capture started.
+//
+// let capture = Capture::new(File::create("session.cap")?);
+// rtsp.set_capture(capture.clone()).await;
+// rtp.set_capture(capture);
+//
+// File format: the magic "RTSPCAP1" then one record per read/write
+//   kind         u8   0 = TCP to server, 1 = TCP from server, 2 = UDP from server
+//   offset_us    u64  microseconds since the capture started
+//   local_port   u16  our port (UDP only, else 0)
+//   remote_port  u16  the server's port (UDP only, else 0)
+//   len          u32
+//   data         len bytes
+// all big endian.
+
+const MAGIC: &[u8; 8] = b"RTSPCAP1";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    TcpToServer,
+    TcpFromServer,
+    UdpFromServer,
+}
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub direction: Direction,
+    pub offset: Duration,
+    pub local_port: u16,
+    pub remote_port: u16,
+    pub data: Vec<u8>,
+}
+
+struct Inner {
+    writer: Box<dyn Write + Send>,
+    start: Instant,
+}
+
+// Cheap to clone, every clone writes to the same capture
+#[derive(Clone)]
+pub struct Capture {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Capture {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        let mut writer: Box<dyn Write + Send> = Box::new(writer);
+        if let Err(e) = writer.write_all(MAGIC) {
+            warn!("[Capture] Unable to write header: {e}");
+        }
+
+        Capture {
+            inner: Arc::new(Mutex::new(Inner {
+                writer,
+                start: Instant::now(),
+            })),
+        }
+    }
+
+    pub(crate) fn tcp(&self, direction: Direction, data: &[u8]) {
+        self.write(direction, 0, 0, data);
+    }
+
+    pub(crate) fn udp(&self, local_port: u16, remote: SocketAddr, data: &[u8]) {
+        self.write(Direction::UdpFromServer, local_port, remote.port(), data);
+    }
+
+    fn write(&self, direction: Direction, local_port: u16, remote_port: u16, data: &[u8]) {
+        let mut inner = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let offset_us = inner.start.elapsed().as_micros() as u64;
+
+        let kind: u8 = match direction {
+            Direction::TcpToServer => 0,
+            Direction::TcpFromServer => 1,
+            Direction::UdpFromServer => 2,
+        };
+
+        let mut record = Vec::with_capacity(17 + data.len());
+        record.push(kind);
+        record.extend_from_slice(&offset_us.to_be_bytes());
+        record.extend_from_slice(&local_port.to_be_bytes());
+        record.extend_from_slice(&remote_port.to_be_bytes());
+        record.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        record.extend_from_slice(data);
+
+        // Capture must never take the session down with it
+        if let Err(e) = inner.writer.write_all(&record) {
+            warn!("[Capture] Unable to write record: {e}");
+        }
+    }
+}
+
+// Read a capture file back
+pub fn read_capture<R: Read>(mut reader: R) -> io::Result<Vec<Record>> {
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a capture file"));
+    }
+
+    let mut records = Vec::new();
+    let mut header = [0u8; 17];
+
+    loop {
+        match reader.read_exact(&mut header) {
+            Ok(()) => (),
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+
+        let direction = match header[0] {
+            0 => Direction::TcpToServer,
+            1 => Direction::TcpFromServer,
+            2 => Direction::UdpFromServer,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown record kind")),
+        };
+        let offset_us = u64::from_be_bytes(header[1..9].try_into().unwrap_or_default());
+        let local_port = u16::from_be_bytes([header[9], header[10]]);
+        let remote_port = u16::from_be_bytes([header[11], header[12]]);
+        let len = u32::from_be_bytes([header[13], header[14], header[15], header[16]]) as usize;
+
+        let mut data = vec![0u8; len];
+        reader.read_exact(&mut data)?;
+
+        records.push(Record {
+            direction,
+            offset: Duration::from_micros(offset_us),
+            local_port,
+            remote_port,
+            data,
+        });
+    }
+
+    Ok(records)
+}
+
+// Plays a capture back as the server, so the same client code can be
+// run against it. Point Rtsp at rtsp://<listener address>/... and use
+// the client port of the capture (Rtsp::new(addr, Some(port))).
+//
+// Each captured response is only sent once the client has sent as
+// many bytes as it had at that point, RTP packets follow with their
+// captured timing, from the captured server ports on the listener's IP.
+pub struct CaptureReplay {
+    records: Vec<Record>,
+}
+
+impl CaptureReplay {
+    pub fn new(records: Vec<Record>) -> Self {
+        CaptureReplay { records }
+    }
+
+    // Serve one connection on 'listener' until the capture runs out
+    pub async fn serve(&self, listener: TcpListener) -> io::Result<()> {
+        let ip = listener.local_addr()?.ip();
+        let (mut stream, client) = listener.accept().await?;
+        debug!("[CaptureReplay] Client connected from {client}");
+
+        let mut sockets: Vec<(u16, UdpSocket)> = Vec::new();
+        let mut buf = vec![0u8; 4096];
+        // Captured offset and local time the replay last synced on
+        let mut sync = (Duration::ZERO, Instant::now());
+
+        for record in &self.records {
+            match record.direction {
+                Direction::TcpToServer => {
+                    let mut remaining = record.data.len();
+                    while remaining > 0 {
+                        let len = remaining.min(buf.len());
+                        match stream.read(&mut buf[..len]).await? {
+                            0 => return Ok(()),
+                            read => remaining -= read,
+                        }
+                    }
+                    sync = (record.offset, Instant::now());
+                }
+                Direction::TcpFromServer => {
+                    sleep_until(sync.1 + record.offset.saturating_sub(sync.0)).await;
+                    stream.write_all(&record.data).await?;
+                }
+                Direction::UdpFromServer => {
+                    sleep_until(sync.1 + record.offset.saturating_sub(sync.0)).await;
+
+                    let index = match sockets.iter().position(|(port, _)| *port == record.remote_port) {
+                        Some(index) => index,
+                        None => {
+                            let socket = UdpSocket::bind(SocketAddr::new(ip, record.remote_port)).await?;
+                            sockets.push((record.remote_port, socket));
+                            sockets.len() - 1
+                        }
+                    };
+
+                    let target = SocketAddr::new(client.ip(), record.local_port);
+                    sockets[index].1.send_to(&record.data, target).await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Wraps the RTSP connection, recording everything read and written
+pub(crate) struct CaptureStream<S> {
+    inner: S,
+    capture: Capture,
+}
+
+impl<S> CaptureStream<S> {
+    pub(crate) fn new(inner: S, capture: Capture) -> Self {
+        CaptureStream { inner, capture }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CaptureStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+
+        if let Poll::Ready(Ok(())) = poll {
+            self.capture.tcp(Direction::TcpFromServer, &buf.filled()[before..]);
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CaptureStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(written)) = poll {
+            self.capture.tcp(Direction::TcpToServer, &buf[..written]);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
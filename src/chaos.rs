@@ -0,0 +1,171 @@
+use log::warn;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// Failure injection for soak testing recovery logic (reconnects,
+// stall timeouts, loss concealment) without touching the network.
+// Built with the chaos feature only, never meant for production.
+//
+// let chaos = Chaos::new();
+// rtsp.set_chaos(chaos.clone()).await;
+// rtp.set_chaos(chaos.clone());
+// chaos.set_corrupt_percent(2.0);
+// ...
+// chaos.drop_connection();
+//
+// Everything can be changed at any time from any thread, every clone
+// controls the same faults.
+
+// Corruption probability is kept in parts per million
+const PPM: f64 = 1_000_000.0;
+
+struct Inner {
+    corrupt_ppm: AtomicU32,
+    rtcp_delay_us: AtomicU64,
+    // Bumped by drop_connection, connections made before it fail
+    drops: AtomicU64,
+    random: AtomicU64,
+    corrupted: AtomicU64,
+}
+
+#[derive(Clone)]
+pub struct Chaos {
+    inner: Arc<Inner>,
+}
+
+impl Chaos {
+    pub fn new() -> Self {
+        // xorshift state must not be zero
+        let seed = RandomState::new().build_hasher().finish() | 1;
+
+        Chaos {
+            inner: Arc::new(Inner {
+                corrupt_ppm: AtomicU32::new(0),
+                rtcp_delay_us: AtomicU64::new(0),
+                drops: AtomicU64::new(0),
+                random: AtomicU64::new(seed),
+                corrupted: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    // Share of received RTP packets (0 to 100) that get a random byte
+    // flipped before they are parsed
+    pub fn set_corrupt_percent(&self, percent: f64) {
+        let ppm = (percent.clamp(0.0, 100.0) / 100.0 * PPM) as u32;
+        self.inner.corrupt_ppm.store(ppm, Ordering::Relaxed);
+    }
+
+    pub fn corrupt_percent(&self) -> f64 {
+        self.inner.corrupt_ppm.load(Ordering::Relaxed) as f64 / PPM * 100.0
+    }
+
+    // Hold every RTCP report back this long before it is sent,
+    // for the RTCP code to honour
+    pub fn set_rtcp_delay(&self, delay: Duration) {
+        self.inner.rtcp_delay_us.store(delay.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn rtcp_delay(&self) -> Duration {
+        Duration::from_micros(self.inner.rtcp_delay_us.load(Ordering::Relaxed))
+    }
+
+    // Break the RTSP connection as if the network went away: the next
+    // read or write fails with ConnectionReset and so does everything
+    // after it, until Rtsp connects again
+    pub fn drop_connection(&self) {
+        warn!("[Chaos] Dropping the RTSP connection");
+        self.inner.drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Packets corrupted so far
+    pub fn corrupted(&self) -> u64 {
+        self.inner.corrupted.load(Ordering::Relaxed)
+    }
+
+    // Maybe flip a byte of 'packet', true if it was
+    pub(crate) fn corrupt(&self, packet: &mut [u8]) -> bool {
+        let ppm = self.inner.corrupt_ppm.load(Ordering::Relaxed);
+        if ppm == 0 || packet.is_empty() || (self.random() % PPM as u64) as u32 >= ppm {
+            return false;
+        }
+
+        let random = self.random();
+        let index = (random % packet.len() as u64) as usize;
+        // Never 0 so the byte always changes
+        packet[index] ^= ((random >> 32) as u8).max(1);
+
+        self.inner.corrupted.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    pub(crate) fn stream<S>(&self, inner: S) -> ChaosStream<S> {
+        ChaosStream {
+            inner,
+            chaos: self.clone(),
+            drops: self.inner.drops.load(Ordering::Relaxed),
+        }
+    }
+
+    // xorshift64, plenty for picking packets
+    fn random(&self) -> u64 {
+        let mut x = self.inner.random.load(Ordering::Relaxed);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.inner.random.store(x, Ordering::Relaxed);
+        x
+    }
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Wraps the RTSP connection so drop_connection can break it
+pub(crate) struct ChaosStream<S> {
+    inner: S,
+    chaos: Chaos,
+    drops: u64, // when the connection was made
+}
+
+impl<S> ChaosStream<S> {
+    fn check(&self) -> io::Result<()> {
+        match self.chaos.inner.drops.load(Ordering::Relaxed) == self.drops {
+            true => Ok(()),
+            false => Err(io::Error::new(io::ErrorKind::ConnectionReset, "connection dropped by chaos")),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ChaosStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        self.check()?;
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ChaosStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.check()?;
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.check()?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
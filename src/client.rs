@@ -0,0 +1,676 @@
+use crate::aac::AacDepacketizer;
+use crate::codec::{Codec, PayloadFormat, StreamFingerprint};
+use crate::degrade::DecodeMode;
+use crate::error::{ClientError, RtpError, RtspError};
+use crate::frame::Frame;
+use crate::latency::LatencyProfile;
+use crate::opus::OpusDepacketizer;
+use crate::rtp::{self, Decoders, Rtp};
+use crate::rtsp::{Methods, Rtsp, TrackTransport};
+use crate::sdp::MediaSection;
+use log::warn;
+use std::collections::VecDeque;
+use std::future::poll_fn;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::{Duration, SystemTime};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, mpsc};
+
+type Result<T> = std::result::Result<T, ClientError>;
+
+// How far a subscriber may fall behind before it misses the oldest media
+const SUBSCRIBER_CAPACITY: usize = 32;
+
+// Everything a session delivers, from any of its tracks
+#[derive(Clone)]
+pub enum Media {
+    VideoFrame {
+        track: usize,
+        timestamp: SystemTime,
+        frame: Frame,
+    },
+    // Encoded audio, one RTP payload (codec is in tracks()), for AAC
+    // one access unit as an ADTS frame, for Opus one Opus packet
+    AudioFrame {
+        track: usize,
+        timestamp: SystemTime,
+        payload: Vec<u8>,
+    },
+    // e.g. ONVIF metadata XML from an "application" track
+    Metadata {
+        track: usize,
+        timestamp: SystemTime,
+        payload: Vec<u8>,
+    },
+    Event(ClientEvent),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    // Nothing arrived for the stall timeout so the session was set up again
+    Reconnected,
+    // Decoding a video track fell behind (or caught up), see set_decode_budget
+    DegradedMode { track: usize, mode: DecodeMode },
+    // After a reconnect the server describes the track differently,
+    // e.g. the camera was reconfigured or swapped for another one
+    StreamChanged {
+        track: usize,
+        previous: Box<StreamFingerprint>,
+        current: Box<StreamFingerprint>,
+    },
+}
+
+// Track set up by the client
+#[derive(Clone, Debug)]
+pub struct ClientTrack {
+    pub track: usize,
+    pub media: String,         // e.g. "video", "audio" or "application"
+    pub codec: Option<String>, // e.g. "H264", "PCMU"
+    pub clock_rate: u32,
+    pub fingerprint: Option<StreamFingerprint>, // as of the last DESCRIBE
+}
+
+// Another consumer of the session's media, see Client::subscribe
+pub struct Subscription {
+    receiver: broadcast::Receiver<Arc<Media>>,
+    missed: u64,
+}
+
+impl Subscription {
+    pub(crate) fn new(receiver: broadcast::Receiver<Arc<Media>>) -> Self {
+        Subscription { receiver, missed: 0 }
+    }
+
+    // Next media delivered by the Client, None once it is dropped
+    pub async fn recv(&mut self) -> Option<Arc<Media>> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(media) => return Some(media),
+                Err(broadcast::error::RecvError::Lagged(missed)) => self.missed += missed,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    // Without waiting, None if nothing is queued
+    pub fn try_recv(&mut self) -> Option<Arc<Media>> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(media) => return Some(media),
+                Err(broadcast::error::TryRecvError::Lagged(missed)) => self.missed += missed,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    // Media dropped because this subscriber fell behind
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+// Changes a ControlHandle sends to the Client
+enum Setting {
+    JitterBuffer(Option<Duration>),
+    DecodeBudget(Option<Duration>),
+    SkipBacklog(bool),
+    Recording(bool),
+    StatsInterval(Duration),
+}
+
+// Changes settings of a running session from anywhere, e.g. a UI or
+// an admin endpoint, without reconnecting or sharing the Client. They
+// apply to every video track, between two packets the next time the
+// Client receives. Each setter is false once the Client is dropped
+//
+// let control = client.control_handle();
+// tokio::spawn(async move { control.set_decode_budget(Some(Duration::from_millis(20))); });
+#[derive(Clone)]
+pub struct ControlHandle {
+    sender: mpsc::UnboundedSender<Setting>,
+}
+
+impl ControlHandle {
+    // How long packets wait for a retransmission of what is missing
+    // before them, see Rtp::set_retransmission
+    pub fn set_jitter_buffer(&self, hold: Option<Duration>) -> bool {
+        self.send(Setting::JitterBuffer(hold))
+    }
+
+    // See Rtp::set_decode_budget
+    pub fn set_decode_budget(&self, budget: Option<Duration>) -> bool {
+        self.send(Setting::DecodeBudget(budget))
+    }
+
+    // Whether a backlog of frames is dropped up to the last
+    // keyframe, see Rtp::set_skip_backlog
+    pub fn set_skip_backlog(&self, skip_backlog: bool) -> bool {
+        self.send(Setting::SkipBacklog(skip_backlog))
+    }
+
+    // See Rtp::set_recording
+    pub fn set_recording(&self, recording: bool) -> bool {
+        self.send(Setting::Recording(recording))
+    }
+
+    // See Rtp::set_stats_interval
+    pub fn set_stats_interval(&self, interval: Duration) -> bool {
+        self.send(Setting::StatsInterval(interval))
+    }
+
+    fn send(&self, setting: Setting) -> bool {
+        self.sender.send(setting).is_ok()
+    }
+}
+
+enum Receiver {
+    // H264 video, decoded
+    Video(Box<Rtp>),
+    // Anything else is handed on as RTP payloads
+    Payload {
+        socket: UdpSocket,
+        buf: Vec<u8>,
+        clock_base: Option<(u32, SystemTime)>,
+        ssrc: Option<u32>, // from SETUP, others are dropped
+        audio: Option<AudioDepacketizer>,
+    },
+}
+
+// Audio with payloads that need taking apart, or checking
+enum AudioDepacketizer {
+    Aac(AacDepacketizer),
+    Opus(OpusDepacketizer),
+}
+
+impl AudioDepacketizer {
+    fn from_sdp(codec: &Codec, section: &MediaSection) -> Option<Self> {
+        match codec {
+            Codec::Mpeg4Generic => AacDepacketizer::from_sdp(section).map(AudioDepacketizer::Aac),
+            Codec::Opus => Some(AudioDepacketizer::Opus(OpusDepacketizer::new())),
+            _ => None,
+        }
+    }
+
+    // Frames in the payload with their RTP timestamps
+    fn push(&mut self, header: &rtp::RtpHeader, payload: &[u8]) -> Vec<(u32, Vec<u8>)> {
+        match self {
+            AudioDepacketizer::Aac(aac) => aac
+                .push(payload, header.timestamp, header.marker)
+                .into_iter()
+                .map(|frame| (frame.timestamp, frame.data))
+                .collect(),
+            AudioDepacketizer::Opus(opus) => opus
+                .push(payload, header.timestamp)
+                .map(|packet| (packet.timestamp, packet.data))
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+// High level client: connects, sets up every track, plays and hands
+// out everything received through next()
+//
+// let mut client = Client::connect("rtsp://192.168.1.100:554/stream").await?;
+// loop {
+//     match client.next().await? {
+//         Media::VideoFrame { frame, .. } => ...,
+//         Media::AudioFrame { payload, .. } => ...,
+//         Media::Metadata { payload, .. } => ...,
+//         Media::Event(event) => ...,
+//     }
+// }
+//
+// Other parts of the application (UI, recorder, analytics) can get
+// everything next() delivers too, each through its own subscribe()
+// and without sharing the Client. Whoever owns the Client keeps
+// calling next(), or run() if it has no use for the media itself.
+//
+// The Client owns the background work of its session (the RTSP
+// keep-alive): should it fail or panic, next() returns the error.
+// shutdown() tears the session down and waits for all of it to end,
+// dropping the Client stops it without waiting.
+pub struct Client {
+    rtsp: Rtsp,
+    tracks: Vec<(ClientTrack, Receiver)>,
+    stall_timeout: Option<Duration>,
+    next: usize,
+    // Delivered before anything else is received, e.g. the frame
+    // decoded along with a DegradedMode event
+    pending: VecDeque<Media>,
+    subscribers: broadcast::Sender<Arc<Media>>,
+    // Kept to hand out ControlHandles, so receiving never ends
+    control: (mpsc::UnboundedSender<Setting>, mpsc::UnboundedReceiver<Setting>),
+}
+
+impl Client {
+    pub async fn connect(addr: &str) -> Result<Self> {
+        Self::connect_with_latency(addr, LatencyProfile::Default).await
+    }
+
+    // Connection and every video receiver set up for the profile,
+    // e.g. LatencyProfile::UltraLow
+    pub async fn connect_with_latency(addr: &str, profile: LatencyProfile) -> Result<Self> {
+        let mut rtsp = Rtsp::builder(addr).latency_profile(profile).connect().await?;
+
+        rtsp.send(Methods::Options).await?;
+        rtsp.send(Methods::Describe).await?.check_status()?;
+
+        Self::start(rtsp, profile).await
+    }
+
+    // Straight to SETUP and PLAY with tracks known beforehand, no
+    // OPTIONS or DESCRIBE, see Rtsp::set_tracks
+    pub async fn connect_with_tracks(addr: &str, tracks: Vec<MediaSection>) -> Result<Self> {
+        let mut rtsp = Rtsp::new(addr, None).await?;
+        rtsp.set_tracks(tracks);
+
+        Self::start(rtsp, LatencyProfile::Default).await
+    }
+
+    // Set up every track described, play and receive
+    async fn start(mut rtsp: Rtsp, profile: LatencyProfile) -> Result<Self> {
+        let transports = rtsp.setup_all().await?.to_vec();
+        rtsp.send(Methods::Play).await?.check_status()?;
+        rtsp.start_keepalive()?;
+
+        // Receive buffers fit what the server agreed to send
+        let max_packet_size = match rtsp.blocksize() {
+            Some(blocksize) => rtp::max_packet_size_for(blocksize),
+            None => rtp::DEFAULT_MAX_PACKET_SIZE,
+        };
+
+        let mut tracks = Vec::new();
+        for transport in &transports {
+            let section = rtsp.media_sections().get(transport.track);
+            let codec = section.and_then(|section| section.codec.clone());
+            let clock_rate = section.and_then(|section| section.clock_rate).unwrap_or(90000);
+
+            // Decoded when it's H264, on its own or in a transport stream
+            let format = section.and_then(PayloadFormat::from_sdp);
+            let is_h264 = transport.media == "video"
+                && match format.as_ref().map(|format| &format.codec) {
+                    Some(Codec::H264) => true,
+                    Some(Codec::Mp2t) => cfg!(feature = "mpegts"),
+                    Some(_) => false,
+                    None => true,
+                };
+
+            let receiver = match is_h264 {
+                true => {
+                    let mut rtp = Rtp::new(None, transport.client_port_rtp, transport.server_addr_rtp).await?;
+                    rtp.set_max_packet_size(max_packet_size);
+                    rtp.set_ssrc(transport.ssrc);
+                    rtp.set_latency_profile(profile);
+                    rtp.connect(Decoders::OpenH264).await?;
+                    if let Some(section) = section {
+                        rtp.configure_from_sdp(section);
+                    }
+                    Receiver::Video(Box::new(rtp))
+                }
+                false => Receiver::Payload {
+                    socket: bind_payload(transport).await.map_err(RtpError::from)?,
+                    buf: vec![0u8; max_packet_size + 1],
+                    clock_base: None,
+                    ssrc: transport.ssrc,
+                    audio: match (format.as_ref(), section) {
+                        (Some(format), Some(section)) => AudioDepacketizer::from_sdp(&format.codec, section),
+                        _ => None,
+                    },
+                },
+            };
+
+            let track = ClientTrack {
+                track: transport.track,
+                media: transport.media.clone(),
+                codec,
+                clock_rate,
+                fingerprint: section.map(StreamFingerprint::from_sdp),
+            };
+            tracks.push((track, receiver));
+        }
+
+        Ok(Client {
+            rtsp,
+            tracks,
+            stall_timeout: None,
+            next: 0,
+            pending: VecDeque::new(),
+            subscribers: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            control: mpsc::unbounded_channel(),
+        })
+    }
+
+    pub fn tracks(&self) -> impl Iterator<Item = &ClientTrack> {
+        self.tracks.iter().map(|(track, _)| track)
+    }
+
+    // The RTSP session e.g. for set_reconnect_policy or a TEARDOWN
+    pub fn rtsp(&mut self) -> &mut Rtsp {
+        &mut self.rtsp
+    }
+
+    // Receiver of a video track e.g. to add privacy masks
+    pub fn video_mut(&mut self, track: usize) -> Option<&mut Rtp> {
+        self.tracks.iter_mut().find_map(|(info, receiver)| match receiver {
+            Receiver::Video(rtp) if info.track == track => Some(rtp.as_mut()),
+            _ => None,
+        })
+    }
+
+    // When nothing arrives on any track for this long the session is
+    // re-established (Rtsp::reconnect) and ClientEvent::Reconnected
+    // is delivered, followed by StreamChanged for every track the
+    // server now describes differently. None (the default) waits forever
+    pub fn set_stall_timeout(&mut self, stall_timeout: Option<Duration>) {
+        self.stall_timeout = stall_timeout;
+    }
+
+    // Largest RTP packet received on every track, header included
+    // e.g. for jumbo frames. By default it fits the Blocksize the
+    // server agreed to, or DEFAULT_MAX_PACKET_SIZE. Larger packets
+    // are dropped
+    pub fn set_max_packet_size(&mut self, max_packet_size: usize) {
+        for (_, receiver) in &mut self.tracks {
+            match receiver {
+                Receiver::Video(rtp) => rtp.set_max_packet_size(max_packet_size),
+                Receiver::Payload { buf, .. } => *buf = vec![0u8; max_packet_size + 1],
+            }
+        }
+    }
+
+    // Decode budget of every video track, see Rtp::set_decode_budget
+    // Mode changes are delivered as ClientEvent::DegradedMode
+    pub fn set_decode_budget(&mut self, budget: Option<Duration>) {
+        self.apply(Setting::DecodeBudget(budget));
+    }
+
+    // Handle to change settings while another task owns the Client
+    pub fn control_handle(&self) -> ControlHandle {
+        ControlHandle {
+            sender: self.control.0.clone(),
+        }
+    }
+
+    fn apply(&mut self, setting: Setting) {
+        for (_, receiver) in &mut self.tracks {
+            if let Receiver::Video(rtp) = receiver {
+                match setting {
+                    Setting::JitterBuffer(hold) => rtp.set_retransmission(hold),
+                    Setting::DecodeBudget(budget) => rtp.set_decode_budget(budget),
+                    Setting::SkipBacklog(skip_backlog) => rtp.set_skip_backlog(skip_backlog),
+                    Setting::Recording(recording) => rtp.set_recording(recording),
+                    Setting::StatsInterval(interval) => rtp.set_stats_interval(interval),
+                }
+            }
+        }
+    }
+
+    // Attach another consumer, which gets a copy of everything next()
+    // delivers from now on. Dropping the Subscription detaches it.
+    // Subscribers that fall behind miss the oldest media rather than
+    // holding up the session
+    pub fn subscribe(&self) -> Subscription {
+        Subscription::new(self.subscribers.subscribe())
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.receiver_count()
+    }
+
+    // Keep the session going for subscribers only, until an error
+    pub async fn run(&mut self) -> Result<()> {
+        loop {
+            self.next().await?;
+        }
+    }
+
+    // Stop everything running for the session, wait for it to end
+    // and send TEARDOWN. Errors of background tasks come first
+    pub async fn shutdown(mut self) -> Result<()> {
+        let joined = self.rtsp.join_keepalive().await;
+        let teardown = self.rtsp.send(Methods::Teardown).await;
+
+        joined?;
+        teardown?.check_status()?;
+        Ok(())
+    }
+
+    pub async fn next(&mut self) -> Result<Media> {
+        let media = self.next_media().await?;
+
+        // Copied once and shared by every subscriber
+        if self.subscribers.receiver_count() > 0 {
+            let _ = self.subscribers.send(Arc::new(media.clone()));
+        }
+
+        Ok(media)
+    }
+
+    async fn next_media(&mut self) -> Result<Media> {
+        if let Some(media) = self.pending.pop_front() {
+            return Ok(media);
+        }
+
+        loop {
+            self.rtsp.check_keepalive().await?;
+
+            // Nothing is half received here, settings can change
+            while let Ok(setting) = self.control.1.try_recv() {
+                self.apply(setting);
+            }
+
+            let index = match self.stall_timeout {
+                Some(stall_timeout) => match tokio::time::timeout(stall_timeout, self.ready()).await {
+                    Ok(index) => index?,
+                    Err(_) => {
+                        self.reconnect().await?;
+                        return Ok(Media::Event(ClientEvent::Reconnected));
+                    }
+                },
+                None => self.ready().await?,
+            };
+
+            if let Some(media) = self.receive(index)? {
+                return Ok(media);
+            }
+        }
+    }
+
+    // Index of a track with a packet waiting, taking turns
+    // so a busy track can't starve the others
+    async fn ready(&mut self) -> Result<usize> {
+        if self.tracks.is_empty() {
+            return Err(RtspError::SetupFailed(0).into());
+        }
+
+        let count = self.tracks.len();
+        let start = self.next;
+
+        let (index, ready) = poll_fn(|cx| {
+            for i in 0..count {
+                let index = (start + i) % count;
+                let poll = match &self.tracks[index].1 {
+                    Receiver::Video(rtp) => rtp.poll_recv_ready(cx),
+                    Receiver::Payload { socket, .. } => socket.poll_recv_ready(cx),
+                };
+
+                if let Poll::Ready(ready) = poll {
+                    return Poll::Ready((index, ready));
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+
+        ready.map_err(RtpError::from)?;
+        self.next = (index + 1) % count;
+
+        Ok(index)
+    }
+
+    fn receive(&mut self, index: usize) -> Result<Option<Media>> {
+        let (info, receiver) = &mut self.tracks[index];
+        let track = info.track;
+
+        match receiver {
+            Receiver::Video(rtp) => {
+                if !rtp.try_get_rtp()? {
+                    return Ok(None);
+                }
+
+                // One packet can complete more than one access unit,
+                // e.g. when the marker of the one before was lost. An
+                // access unit the decoder rejects is dropped, the session
+                // goes on with the next one
+                let mode = rtp.decode_mode();
+                let mut frames = VecDeque::new();
+                loop {
+                    let frame = match rtp.try_decode_frame() {
+                        Ok(frame) => frame,
+                        #[cfg(feature = "decode-openh264")]
+                        Err(RtpError::Decoder(error)) => {
+                            warn!("[Client] Skipping access unit of track {track}: {error}");
+                            None
+                        }
+                        Err(error) => return Err(error.into()),
+                    };
+                    frames.extend(frame.map(|frame| Media::VideoFrame {
+                        track,
+                        timestamp: rtp.capture_time().unwrap_or_else(SystemTime::now),
+                        frame,
+                    }));
+                    if rtp.depacketizer().pending_count() == 0 {
+                        break;
+                    }
+                }
+
+                if rtp.decode_mode() == mode {
+                    let frame = frames.pop_front();
+                    self.pending.extend(frames);
+                    return Ok(frame);
+                }
+
+                self.pending.extend(frames);
+                let mode = rtp.decode_mode();
+                Ok(Some(Media::Event(ClientEvent::DegradedMode { track, mode })))
+            }
+            Receiver::Payload { socket, buf, clock_base, ssrc, audio } => {
+                let len = match socket.try_recv(buf) {
+                    Ok(len) => len,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                    Err(e) => return Err(RtpError::from(e).into()),
+                };
+                // Reached the spare byte: cut short, see set_max_packet_size
+                if len == buf.len() {
+                    return Ok(None);
+                }
+                let header = match rtp::RtpHeader::parse(&buf[..len]) {
+                    Some(header) if !header.payload(&buf[..len]).is_empty() => header,
+                    _ => return Ok(None),
+                };
+                // Something else sending to our port
+                if ssrc.is_some_and(|ssrc| ssrc != header.ssrc) {
+                    return Ok(None);
+                }
+
+                // Same clock as video: anchored to when the first packet arrived
+                let (base_timestamp, base_time) = *clock_base.get_or_insert((header.timestamp, SystemTime::now()));
+                let clock_rate = info.clock_rate.max(1) as i64;
+                // Reordered packets can be slightly before the base
+                let time_of = |rtp_timestamp: u32| {
+                    let ticks = rtp_timestamp.wrapping_sub(base_timestamp) as i32 as i64;
+                    let micros = ticks * 1_000_000 / clock_rate;
+
+                    match micros >= 0 {
+                        true => base_time + Duration::from_micros(micros as u64),
+                        false => base_time - Duration::from_micros(micros.unsigned_abs()),
+                    }
+                };
+
+                if let Some(audio) = audio {
+                    let mut frames = audio
+                        .push(&header, header.payload(&buf[..len]))
+                        .into_iter()
+                        .map(|(rtp_timestamp, payload)| Media::AudioFrame { track, timestamp: time_of(rtp_timestamp), payload });
+
+                    let first = frames.next();
+                    self.pending.extend(frames);
+                    return Ok(first);
+                }
+
+                let timestamp = time_of(header.timestamp);
+                let payload = header.payload(&buf[..len]).to_vec();
+
+                match info.media.as_str() {
+                    "audio" => Ok(Some(Media::AudioFrame { track, timestamp, payload })),
+                    _ => Ok(Some(Media::Metadata { track, timestamp, payload })),
+                }
+            }
+        }
+    }
+
+    // After roaming to another network the session is resumed on new
+    // client ports, otherwise it is set up again from scratch
+    async fn reconnect(&mut self) -> Result<()> {
+        match self.rtsp.local_addr_changed().await {
+            true => self.rtsp.resume().await.map(|_| ())?,
+            false => self.rtsp.reconnect().await?,
+        }
+
+        let transports: Vec<TrackTransport> = self.rtsp.transports().to_vec();
+        for (info, receiver) in &mut self.tracks {
+            // Same camera, same settings?
+            let current = self.rtsp.media_sections().get(info.track).map(StreamFingerprint::from_sdp);
+            if let (Some(previous), Some(current)) = (&info.fingerprint, &current) {
+                if previous != current {
+                    self.pending.push_back(Media::Event(ClientEvent::StreamChanged {
+                        track: info.track,
+                        previous: Box::new(previous.clone()),
+                        current: Box::new(current.clone()),
+                    }));
+                }
+            }
+            if current.is_some() {
+                info.fingerprint = current;
+            }
+
+            let transport = match transports.iter().find(|transport| transport.track == info.track) {
+                Some(transport) => transport,
+                None => continue,
+            };
+
+            match receiver {
+                Receiver::Video(rtp) => {
+                    rtp.rebind(None, transport.client_port_rtp, transport.server_addr_rtp).await?;
+                    rtp.set_ssrc(transport.ssrc);
+                }
+                Receiver::Payload { socket, ssrc, .. } => {
+                    let port = socket.local_addr().map_err(RtpError::from)?.port();
+                    match port == transport.client_port_rtp {
+                        true => socket.connect(transport.server_addr_rtp).await.map_err(RtpError::from)?,
+                        false => *socket = bind_payload(transport).await.map_err(RtpError::from)?,
+                    }
+                    *ssrc = transport.ssrc;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+async fn bind_payload(transport: &TrackTransport) -> io::Result<UdpSocket> {
+    let bind_addr: SocketAddr = match transport.server_addr_rtp.is_ipv6() {
+        true => (std::net::Ipv6Addr::UNSPECIFIED, transport.client_port_rtp).into(),
+        false => (std::net::Ipv4Addr::UNSPECIFIED, transport.client_port_rtp).into(),
+    };
+
+    let socket = UdpSocket::bind(bind_addr).await?;
+    socket.connect(transport.server_addr_rtp).await?;
+
+    Ok(socket)
+}
@@ -0,0 +1,168 @@
+use crate::h264;
+use crate::sdp::MediaSection;
+
+// Codec of an RTP stream, from the encoding name in a=rtpmap
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Codec {
+    H264,
+    H265,
+    Pcmu,         // G.711 mu-law
+    Pcma,         // G.711 A-law
+    Mpeg4Generic, // usually AAC (RFC 3640)
+    Opus,         // RFC 7587
+    Mp2t,         // MPEG transport stream (RFC 2250)
+    Other(String),
+}
+
+impl Codec {
+    // Encoding names are case-insensitive
+    #[rustfmt::skip]
+    pub fn from_encoding(encoding: &str) -> Self {
+        match encoding.to_ascii_uppercase().as_str() {
+            "H264"           => Codec::H264,
+            "H265" | "HEVC"  => Codec::H265,
+            "PCMU"           => Codec::Pcmu,
+            "PCMA"           => Codec::Pcma,
+            "MPEG4-GENERIC"  => Codec::Mpeg4Generic,
+            "OPUS"           => Codec::Opus,
+            "MP2T"           => Codec::Mp2t,
+            _                => Codec::Other(encoding.to_string()),
+        }
+    }
+}
+
+// Picture size and rate of a video stream
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VideoInfo {
+    pub width: usize,
+    pub height: usize,
+    pub framerate: Option<f64>,
+}
+
+impl VideoInfo {
+    // From the SPS in sprop-parameter-sets, which is exact, else from
+    // the vendor attributes (MediaSection::dimensions)
+    // Framerate from the SPS timing info, else the vendor attributes
+    pub fn from_sdp(section: &MediaSection) -> Option<Self> {
+        let framerate = section.framerate;
+
+        let sps = section
+            .payload_type
+            .and_then(|payload_type| section.fmtp_for(payload_type))
+            .and_then(|fmtp| fmtp.get("sprop-parameter-sets"))
+            .and_then(|sprop| {
+                h264::decode_sprop_parameter_sets(sprop)
+                    .iter()
+                    .find_map(|nal| h264::parse_sps(nal))
+            });
+        if let Some(sps) = sps {
+            return Some(VideoInfo::from_sps(&sps, framerate));
+        }
+
+        let (width, height) = section.dimensions?;
+
+        Some(VideoInfo { width, height, framerate })
+    }
+
+    // 'framerate' is used when the SPS has no timing info
+    pub fn from_sps(sps: &h264::Sps, framerate: Option<f64>) -> Self {
+        VideoInfo {
+            width: sps.width,
+            height: sps.height,
+            framerate: sps.framerate.or(framerate),
+        }
+    }
+}
+
+// What a negotiated stream looks like, to notice a camera being
+// reconfigured or swapped between sessions: compare the fingerprints
+// taken before and after a reconnect
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StreamFingerprint {
+    pub codec: Option<Codec>,
+    pub clock_rate: Option<u32>,
+    pub channels: Option<u16>,
+    // profile_idc, constraint flags, level_idc
+    pub profile: Option<(u8, u8, u8)>,
+    pub dimensions: Option<(usize, usize)>,
+    // FNV-1a of the SPS NAL unit, the same in every process
+    pub sps_hash: Option<u64>,
+}
+
+impl StreamFingerprint {
+    // From the SPS in sprop-parameter-sets where there is one, else
+    // from profile-level-id and the vendor attributes
+    pub fn from_sdp(section: &MediaSection) -> Self {
+        let format = PayloadFormat::from_sdp(section);
+        let fmtp = section
+            .payload_type
+            .and_then(|payload_type| section.fmtp_for(payload_type));
+
+        let sps = fmtp
+            .and_then(|fmtp| fmtp.get("sprop-parameter-sets"))
+            .and_then(|sprop| {
+                h264::decode_sprop_parameter_sets(sprop)
+                    .into_iter()
+                    .find(|nal| nal[0] & 31 == 7)
+            });
+
+        let profile = match sps.as_deref().and_then(h264::parse_sps) {
+            Some(sps) => Some((sps.profile_idc, sps.constraint_flags, sps.level_idc)),
+            None => fmtp
+                .and_then(|fmtp| fmtp.get("profile-level-id"))
+                .and_then(|id| u32::from_str_radix(id, 16).ok())
+                .map(|id| ((id >> 16) as u8, (id >> 8) as u8, id as u8)),
+        };
+
+        StreamFingerprint {
+            codec: format.as_ref().map(|format| format.codec.clone()),
+            clock_rate: format.as_ref().map(|format| format.clock_rate),
+            channels: section.channels,
+            profile,
+            dimensions: VideoInfo::from_sdp(section).map(|info| (info.width, info.height)),
+            sps_hash: sps.as_deref().map(fnv1a),
+        }
+    }
+}
+
+// 64 bit FNV-1a, unlike std's hashers stable across Rust versions
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+// What the payload type of a stream carries, from a=rtpmap
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayloadFormat {
+    pub payload_type: u8,
+    pub codec: Codec,
+    pub clock_rate: u32,
+    pub channels: Option<u16>,
+}
+
+impl PayloadFormat {
+    // Format of the payload type SETUP gets for this media section.
+    // Video with a dynamic payload type but no rtpmap is assumed to be
+    // H264, as this crate always did
+    pub fn from_sdp(section: &MediaSection) -> Option<Self> {
+        let payload_type = section.payload_type?;
+
+        let codec = match &section.codec {
+            Some(codec) => Codec::from_encoding(codec),
+            None if section.media == "video" => Codec::H264,
+            None => return None,
+        };
+
+        let clock_rate = match section.clock_rate {
+            Some(clock_rate) => clock_rate,
+            None if section.media == "video" => 90000,
+            None => 8000,
+        };
+
+        Some(PayloadFormat {
+            payload_type,
+            codec,
+            clock_rate,
+            channels: section.channels,
+        })
+    }
+}
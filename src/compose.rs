@@ -0,0 +1,390 @@
+use crate::frame::{Extensions, Frame};
+use std::time::{Duration, Instant};
+
+// Picture in picture: one stream drawn small over another, e.g. a
+// doorbell sub-stream over the main yard camera, giving one frame
+// for a single display or recording
+//
+// The inset comes from its own stream at its own pace, so the latest
+// one is kept and drawn over every main frame:
+//
+// let mut pip = Compositor::new(PipLayout::default());
+// ...
+// if let Some(frame) = doorbell.try_decode_frame()? { pip.set_inset(&frame); }
+// if let Some(mut frame) = yard.try_decode_frame()? { pip.compose(&mut frame); }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Position {
+    // Against a corner, 'margin' luma pixels in from both edges
+    Corner { corner: Corner, margin: usize },
+    // Top left of the inset in luma pixels of the main picture
+    At { x: usize, y: usize },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PipLayout {
+    pub position: Position,
+    // Inset width as a fraction of the main picture width,
+    // its height follows from the inset's aspect ratio
+    pub scale: f32,
+    // Border around the inset in luma pixels, drawn in black
+    pub border: usize,
+}
+
+impl Default for PipLayout {
+    fn default() -> Self {
+        PipLayout {
+            position: Position::Corner { corner: Corner::BottomRight, margin: 16 },
+            scale: 0.3,
+            border: 2,
+        }
+    }
+}
+
+// Black in video range YUV
+const BLACK: (u8, u8, u8) = (16, 128, 128);
+
+pub struct Compositor {
+    layout: PipLayout,
+    inset: Option<Frame>,
+    // Inset scaled for the main picture size it was last drawn on
+    scaled: Option<Frame>,
+}
+
+impl Compositor {
+    pub fn new(layout: PipLayout) -> Self {
+        Compositor {
+            layout,
+            inset: None,
+            scaled: None,
+        }
+    }
+
+    pub fn set_layout(&mut self, layout: PipLayout) {
+        self.layout = layout;
+        self.scaled = None;
+    }
+
+    pub fn layout(&self) -> PipLayout {
+        self.layout
+    }
+
+    // Latest frame of the inset stream
+    pub fn set_inset(&mut self, frame: &Frame) {
+        self.inset = Some(frame.clone());
+        self.scaled = None;
+    }
+
+    // Stop drawing the inset, e.g. when its stream went away
+    pub fn clear_inset(&mut self) {
+        self.inset = None;
+        self.scaled = None;
+    }
+
+    // Draw the latest inset over 'main', nothing happens before the first one
+    pub fn compose(&mut self, main: &mut Frame) {
+        let inset = match &self.inset {
+            Some(inset) if inset.width > 0 && inset.height > 0 => inset,
+            _ => return,
+        };
+
+        // Even sizes and offsets keep the chroma planes aligned
+        let width = ((main.width as f32 * self.layout.scale) as usize & !1).min(main.width & !1);
+        let height = ((width * inset.height / inset.width) & !1).min(main.height & !1);
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let needs_scaling = match &self.scaled {
+            Some(scaled) => scaled.width != width || scaled.height != height,
+            None => true,
+        };
+        if needs_scaling {
+            self.scaled = Some(scale(inset, width, height));
+        }
+        let scaled = match &self.scaled {
+            Some(scaled) => scaled,
+            None => return,
+        };
+
+        let (x, y) = self.origin(main, width, height);
+        let border = self.layout.border;
+
+        if border > 0 {
+            let x0 = x.saturating_sub(border) & !1;
+            let y0 = y.saturating_sub(border) & !1;
+            let rect = Rect {
+                x: x0,
+                y: y0,
+                width: x + width + border - x0,
+                height: y + height + border - y0,
+            };
+            fill(main, rect, BLACK);
+        }
+
+        blit(main, scaled, x, y);
+    }
+
+    fn origin(&self, main: &Frame, width: usize, height: usize) -> (usize, usize) {
+        let max_x = main.width.saturating_sub(width);
+        let max_y = main.height.saturating_sub(height);
+
+        let (x, y) = match self.layout.position {
+            Position::At { x, y } => (x, y),
+            Position::Corner { corner, margin } => match corner {
+                Corner::TopLeft => (margin, margin),
+                Corner::TopRight => (max_x.saturating_sub(margin), margin),
+                Corner::BottomLeft => (margin, max_y.saturating_sub(margin)),
+                Corner::BottomRight => (max_x.saturating_sub(margin), max_y.saturating_sub(margin)),
+            },
+        };
+
+        (x.min(max_x) & !1, y.min(max_y) & !1)
+    }
+}
+
+// Camera wall: frames from several streams, each at its own rate and
+// resolution, laid out on a columns x rows grid of a fixed size output,
+// handed out at a fixed rate for one encoder or display
+//
+// let mut wall = Mosaic::new(2, 2, 1280, 720, 15.0);
+// loop {
+//     tokio::select! {
+//         media = clients[0].next() => if let Media::VideoFrame { frame, .. } = media? { wall.set_tile(0, &frame) },
+//         ...
+//         frame = wall.tick() => encoder.encode(&frame),
+//     }
+// }
+pub struct Mosaic {
+    columns: usize,
+    rows: usize,
+    width: usize,
+    height: usize,
+    interval: Duration,
+    next_due: Instant,
+    // Latest frame of each tile, already fitted to its cell
+    tiles: Vec<Option<Frame>>,
+}
+
+impl Mosaic {
+    // Tiles are numbered left to right, top to bottom
+    pub fn new(columns: usize, rows: usize, width: usize, height: usize, framerate: f64) -> Self {
+        let (columns, rows) = (columns.max(1), rows.max(1));
+
+        Mosaic {
+            columns,
+            rows,
+            width: width & !1,
+            height: height & !1,
+            interval: Duration::from_secs_f64(1.0 / framerate.max(0.001)),
+            next_due: Instant::now(),
+            tiles: vec![None; columns * rows],
+        }
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    // Latest frame of a tile's stream, scaled to fit its cell keeping
+    // the aspect ratio. Indexes past the grid are ignored
+    pub fn set_tile(&mut self, index: usize, frame: &Frame) {
+        let (cell_width, cell_height) = self.cell_size();
+        if index >= self.tiles.len() || frame.width == 0 || frame.height == 0 || cell_width == 0 || cell_height == 0 {
+            return;
+        }
+
+        let mut width = cell_width;
+        let mut height = (cell_width * frame.height / frame.width) & !1;
+        if height > cell_height {
+            height = cell_height;
+            width = (cell_height * frame.width / frame.height) & !1;
+        }
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        self.tiles[index] = Some(scale(frame, width, height));
+    }
+
+    // Blank a tile, e.g. while its camera reconnects
+    pub fn clear_tile(&mut self, index: usize) {
+        if let Some(tile) = self.tiles.get_mut(index) {
+            *tile = None;
+        }
+    }
+
+    // The wall as it is now, empty cells black
+    pub fn frame(&self) -> Frame {
+        let (chroma_width, chroma_height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut frame = Frame {
+            width: self.width,
+            height: self.height,
+            framerate: Some(1.0 / self.interval.as_secs_f64()),
+            y: vec![BLACK.0; self.width * self.height],
+            u: vec![BLACK.1; chroma_width * chroma_height],
+            v: vec![BLACK.2; chroma_width * chroma_height],
+            extensions: Extensions::new(),
+        };
+
+        let (cell_width, cell_height) = self.cell_size();
+        for (index, tile) in self.tiles.iter().enumerate() {
+            let tile = match tile {
+                Some(tile) => tile,
+                None => continue,
+            };
+
+            // Centred in its cell
+            let x = (index % self.columns) * cell_width + (cell_width - tile.width) / 2;
+            let y = (index / self.columns) * cell_height + (cell_height - tile.height) / 2;
+            blit(&mut frame, tile, x & !1, y & !1);
+        }
+
+        frame
+    }
+
+    // Composited frame if one is due at the output rate, else None.
+    // Late calls don't bunch up, missed frames are skipped
+    pub fn next_frame(&mut self) -> Option<Frame> {
+        let now = Instant::now();
+        if now < self.next_due {
+            return None;
+        }
+
+        self.advance(now);
+        Some(self.frame())
+    }
+
+    // Wait until the next frame is due and return it
+    pub async fn tick(&mut self) -> Frame {
+        tokio::time::sleep_until(self.next_due.into()).await;
+
+        self.advance(Instant::now());
+        self.frame()
+    }
+
+    fn advance(&mut self, now: Instant) {
+        self.next_due += self.interval;
+        if self.next_due < now {
+            self.next_due = now + self.interval;
+        }
+    }
+
+    // Even so tiles keep the chroma planes aligned
+    fn cell_size(&self) -> (usize, usize) {
+        ((self.width / self.columns) & !1, (self.height / self.rows) & !1)
+    }
+}
+
+// Bilinear resize of all three planes
+fn scale(frame: &Frame, width: usize, height: usize) -> Frame {
+    let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+    Frame {
+        width,
+        height,
+        framerate: frame.framerate,
+        y: scale_plane(&frame.y, frame.width, frame.height, width, height),
+        u: scale_plane(&frame.u, frame.chroma_width(), frame.chroma_height(), chroma_width, chroma_height),
+        v: scale_plane(&frame.v, frame.chroma_width(), frame.chroma_height(), chroma_width, chroma_height),
+        extensions: frame.extensions.clone(),
+    }
+}
+
+fn scale_plane(src: &[u8], src_width: usize, src_height: usize, width: usize, height: usize) -> Vec<u8> {
+    let mut dst = vec![0u8; width * height];
+    if src_width == 0 || src_height == 0 || src.len() < src_width * src_height {
+        return dst;
+    }
+
+    let x_ratio = src_width as f32 / width as f32;
+    let y_ratio = src_height as f32 / height as f32;
+
+    for dy in 0..height {
+        // Sample at pixel centres
+        let sy = ((dy as f32 + 0.5) * y_ratio - 0.5).max(0.0);
+        let y0 = (sy as usize).min(src_height - 1);
+        let y1 = (y0 + 1).min(src_height - 1);
+        let fy = sy - y0 as f32;
+
+        for dx in 0..width {
+            let sx = ((dx as f32 + 0.5) * x_ratio - 0.5).max(0.0);
+            let x0 = (sx as usize).min(src_width - 1);
+            let x1 = (x0 + 1).min(src_width - 1);
+            let fx = sx - x0 as f32;
+
+            let top = src[y0 * src_width + x0] as f32 * (1.0 - fx) + src[y0 * src_width + x1] as f32 * fx;
+            let bottom = src[y1 * src_width + x0] as f32 * (1.0 - fx) + src[y1 * src_width + x1] as f32 * fx;
+
+            dst[dy * width + dx] = (top * (1.0 - fy) + bottom * fy + 0.5) as u8;
+        }
+    }
+
+    dst
+}
+
+// Copy 'src' into 'dst' at x, y (both even), clipped to dst
+fn blit(dst: &mut Frame, src: &Frame, x: usize, y: usize) {
+    let size = (dst.width, dst.height);
+    let chroma_size = (dst.chroma_width(), dst.chroma_height());
+    let src_chroma_size = (src.chroma_width(), src.chroma_height());
+
+    blit_plane(&mut dst.y, size, &src.y, (src.width, src.height), (x, y));
+    blit_plane(&mut dst.u, chroma_size, &src.u, src_chroma_size, (x / 2, y / 2));
+    blit_plane(&mut dst.v, chroma_size, &src.v, src_chroma_size, (x / 2, y / 2));
+}
+
+// Sizes are (width, height)
+fn blit_plane(dst: &mut [u8], dst_size: (usize, usize), src: &[u8], src_size: (usize, usize), (x, y): (usize, usize)) {
+    let width = src_size.0.min(dst_size.0.saturating_sub(x));
+    let height = src_size.1.min(dst_size.1.saturating_sub(y));
+
+    for row in 0..height {
+        let dst_start = (y + row) * dst_size.0 + x;
+        let src_start = row * src_size.0;
+        dst[dst_start..dst_start + width].copy_from_slice(&src[src_start..src_start + width]);
+    }
+}
+
+// Rectangle in pixels of a plane
+#[derive(Clone, Copy)]
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+// Fill a rectangle in luma pixels (x, y even) with one colour
+fn fill(frame: &mut Frame, rect: Rect, (luma, u, v): (u8, u8, u8)) {
+    let size = (frame.width, frame.height);
+    let chroma_size = (frame.chroma_width(), frame.chroma_height());
+    let chroma_rect = Rect {
+        x: rect.x / 2,
+        y: rect.y / 2,
+        width: rect.width.div_ceil(2),
+        height: rect.height.div_ceil(2),
+    };
+
+    fill_plane(&mut frame.y, size, rect, luma);
+    fill_plane(&mut frame.u, chroma_size, chroma_rect, u);
+    fill_plane(&mut frame.v, chroma_size, chroma_rect, v);
+}
+
+fn fill_plane(plane: &mut [u8], (plane_width, plane_height): (usize, usize), rect: Rect, value: u8) {
+    let x_end = (rect.x + rect.width).min(plane_width);
+    let y_end = (rect.y + rect.height).min(plane_height);
+    let x = rect.x.min(x_end);
+
+    for row in rect.y.min(y_end)..y_end {
+        plane[row * plane_width + x..row * plane_width + x_end].fill(value);
+    }
+}
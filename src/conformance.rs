@@ -0,0 +1,350 @@
+use crate::journal::escape;
+use crate::response::RtspResponse;
+use std::fmt;
+
+// Checks the server's responses against RFC 2326 during a session
+// (see Rtsp::set_conformance) and reports what it found, e.g. for a
+// camera vendor fixing their server or an integrator deciding which
+// quirks a camera needs
+//
+// rtsp.set_conformance(true);
+// ... OPTIONS, DESCRIBE, SETUP, PLAY, TEARDOWN ...
+// if let Some(report) = rtsp.conformance_report() {
+//     println!("{report}");
+//     std::fs::write("conformance.json", report.to_json())?;
+// }
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    // Allowed, but worth knowing about
+    Info,
+    // Goes against a SHOULD
+    Warning,
+    // Goes against a MUST
+    Violation,
+}
+
+impl Severity {
+    fn as_str(self) -> &'static str {
+        match self {
+            Severity::Info => "info",
+            Severity::Warning => "warning",
+            Severity::Violation => "violation",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub method: String,
+    pub cseq: u32,
+    pub severity: Severity,
+    pub rule: &'static str, // section of RFC 2326
+    pub message: String,
+}
+
+// A request sent and what the checks need to know about it
+#[derive(Clone, Copy, Debug)]
+pub struct Exchange<'a> {
+    pub method: &'a str,
+    pub cseq: u32,
+    pub transport: Option<&'a str>, // Transport header of a SETUP
+    pub session: Option<&'a str>,   // Session id sent with the request
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConformanceReport {
+    pub server: String,
+    pub exchanges: u32,
+    pub findings: Vec<Finding>,
+}
+
+impl ConformanceReport {
+    // No MUST broken
+    pub fn passed(&self) -> bool {
+        self.count(Severity::Violation) == 0
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.findings.iter().filter(|finding| finding.severity == severity).count()
+    }
+
+    // e.g. {"server":"192.168.1.100:554","exchanges":5,"passed":false,"findings":[
+    // {"method":"SETUP","cseq":3,"severity":"violation","rule":"12.37","message":"..."}]}
+    pub fn to_json(&self) -> String {
+        let findings: Vec<String> = self
+            .findings
+            .iter()
+            .map(|finding| {
+                format!(
+                    r#"{{"method":"{}","cseq":{},"severity":"{}","rule":"{}","message":"{}"}}"#,
+                    escape(&finding.method),
+                    finding.cseq,
+                    finding.severity.as_str(),
+                    finding.rule,
+                    escape(&finding.message)
+                )
+            })
+            .collect();
+
+        format!(
+            r#"{{"server":"{}","exchanges":{},"passed":{},"findings":[{}]}}"#,
+            escape(&self.server),
+            self.exchanges,
+            self.passed(),
+            findings.join(",")
+        )
+    }
+}
+
+impl fmt::Display for ConformanceReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "RTSP conformance of {}: {} ({} exchanges, {} violations, {} warnings, {} info)",
+            self.server,
+            match self.passed() {
+                true => "passed",
+                false => "failed",
+            },
+            self.exchanges,
+            self.count(Severity::Violation),
+            self.count(Severity::Warning),
+            self.count(Severity::Info),
+        )?;
+
+        for finding in &self.findings {
+            writeln!(
+                f,
+                "  {:<9} {} (CSeq {}) RFC 2326 {}: {}",
+                finding.severity.as_str(),
+                finding.method,
+                finding.cseq,
+                finding.rule,
+                finding.message
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Conformance {
+    report: ConformanceReport,
+    session: Option<String>, // id given in the first SETUP response
+}
+
+impl Conformance {
+    pub fn new(server: &str) -> Self {
+        Conformance {
+            report: ConformanceReport {
+                server: server.to_string(),
+                ..ConformanceReport::default()
+            },
+            session: None,
+        }
+    }
+
+    // Check the response to a request, None if it wasn't RTSP at all
+    pub fn check(&mut self, exchange: &Exchange, response: Option<&RtspResponse>) {
+        self.report.exchanges += 1;
+
+        let response = match response {
+            Some(response) => response,
+            None => return self.find(exchange, Severity::Violation, "7.1", "response has no valid status line".to_string()),
+        };
+
+        if response.version != "RTSP/1.0" {
+            let message = format!("status line version is {}, expected RTSP/1.0", response.version);
+            self.find(exchange, Severity::Warning, "7.1", message);
+        }
+
+        self.check_cseq(exchange, response);
+        self.check_body(exchange, response);
+        self.check_session(exchange, response);
+
+        match exchange.method {
+            "OPTIONS" if response.is_success() && response.header("Public").is_none() => {
+                self.find(exchange, Severity::Warning, "10.1", "no Public header listing the methods".to_string());
+            }
+            "DESCRIBE" if response.is_success() => self.check_describe(exchange, response),
+            "SETUP" if response.is_success() => self.check_transport(exchange, response),
+            _ => (),
+        }
+
+        if response.status == 401 && response.header("WWW-Authenticate").is_none() {
+            self.find(exchange, Severity::Violation, "12.44", "401 without WWW-Authenticate".to_string());
+        }
+        if response.status == 405 && response.header("Allow").is_none() {
+            self.find(exchange, Severity::Violation, "12.4", "405 without Allow".to_string());
+        }
+    }
+
+    pub fn report(&self) -> &ConformanceReport {
+        &self.report
+    }
+
+    fn check_cseq(&mut self, exchange: &Exchange, response: &RtspResponse) {
+        let message = match response.header("CSeq").map(|cseq| cseq.trim().parse::<u32>()) {
+            None => "no CSeq header".to_string(),
+            Some(Ok(cseq)) if cseq == exchange.cseq => return,
+            Some(Ok(cseq)) => format!("CSeq {cseq} doesn't echo the request's"),
+            Some(Err(_)) => "CSeq isn't a number".to_string(),
+        };
+
+        self.find(exchange, Severity::Violation, "12.17", message);
+    }
+
+    fn check_body(&mut self, exchange: &Exchange, response: &RtspResponse) {
+        if response.body.is_empty() {
+            return;
+        }
+
+        match response.content_length() {
+            None => self.find(exchange, Severity::Violation, "12.14", "body without Content-Length".to_string()),
+            Some(len) if len > response.body.len() => {
+                let message = format!("Content-Length {len} but only {} body bytes", response.body.len());
+                self.find(exchange, Severity::Violation, "12.14", message);
+            }
+            Some(_) => (),
+        }
+
+        if response.header("Content-Type").is_none() {
+            self.find(exchange, Severity::Warning, "12.16", "body without Content-Type".to_string());
+        }
+    }
+
+    // Same id for the whole session, echoed in responses to requests
+    // that sent it
+    fn check_session(&mut self, exchange: &Exchange, response: &RtspResponse) {
+        let id = response
+            .header("Session")
+            .map(|session| session.split(';').next().unwrap_or_default().trim().to_string());
+
+        if exchange.method == "SETUP" && response.is_success() {
+            match (&id, &self.session) {
+                (None, _) => {
+                    self.find(exchange, Severity::Violation, "12.37", "SETUP response without Session".to_string());
+                }
+                (Some(id), Some(session)) if id != session => {
+                    let message = format!("SETUP gave session {id} within session {session}");
+                    self.find(exchange, Severity::Violation, "12.37", message);
+                }
+                (Some(id), _) => self.session = Some(id.clone()),
+            }
+            return;
+        }
+
+        if let Some(sent) = exchange.session.filter(|_| response.is_success()) {
+            match id {
+                None => {
+                    let message = "response doesn't echo the request's Session".to_string();
+                    self.find(exchange, Severity::Warning, "12.37", message);
+                }
+                Some(id) if id != sent => {
+                    let message = format!("response Session {id}, request sent {sent}");
+                    self.find(exchange, Severity::Violation, "12.37", message);
+                }
+                Some(_) => (),
+            }
+        }
+
+        if exchange.method == "TEARDOWN" && response.is_success() {
+            self.session = None;
+        }
+    }
+
+    fn check_describe(&mut self, exchange: &Exchange, response: &RtspResponse) {
+        if response.body.is_empty() {
+            self.find(exchange, Severity::Violation, "10.2", "DESCRIBE response without a description".to_string());
+            return;
+        }
+
+        match response.header("Content-Type") {
+            Some(content_type) if content_type.trim().eq_ignore_ascii_case("application/sdp") => (),
+            Some(content_type) => {
+                let message = format!("Content-Type {content_type}, expected application/sdp");
+                self.find(exchange, Severity::Info, "10.2", message);
+            }
+            None => (),
+        }
+
+        if response.header("Content-Base").is_none() && response.header("Content-Location").is_none() {
+            let message = "no Content-Base, relative a=control URLs resolve against the request URL".to_string();
+            self.find(exchange, Severity::Info, "C.1.1", message);
+        }
+    }
+
+    // The server's Transport must be one of those asked for, with the
+    // same client ports
+    fn check_transport(&mut self, exchange: &Exchange, response: &RtspResponse) {
+        let transport = match response.header("Transport") {
+            Some(transport) => transport,
+            None => {
+                self.find(exchange, Severity::Violation, "12.39", "SETUP response without Transport".to_string());
+                return;
+            }
+        };
+
+        let params = |transport: &str| -> Vec<(String, String)> {
+            transport
+                .split(';')
+                .map(|param| match param.trim().split_once('=') {
+                    Some((name, value)) => (name.trim().to_ascii_lowercase(), value.trim().to_string()),
+                    None => (param.trim().to_ascii_lowercase(), String::new()),
+                })
+                .collect()
+        };
+        let get = |params: &[(String, String)], name: &str| {
+            params.iter().find(|(param, _)| param == name).map(|(_, value)| value.clone())
+        };
+
+        let got = params(transport);
+        let protocol = got.first().map(|(protocol, _)| protocol.to_ascii_uppercase()).unwrap_or_default();
+        let sent = exchange.transport.map(params).unwrap_or_default();
+
+        if let Some((requested, _)) = sent.first() {
+            // RTP/AVP is short for RTP/AVP/UDP
+            let normalize = |protocol: &str| protocol.to_ascii_uppercase().trim_end_matches("/UDP").to_string();
+            if normalize(requested) != normalize(&protocol) {
+                let message = format!("Transport {protocol}, requested {}", requested.to_ascii_uppercase());
+                self.find(exchange, Severity::Violation, "12.39", message);
+            }
+        }
+
+        let client_port = get(&sent, "client_port");
+        match (client_port, get(&got, "client_port")) {
+            (Some(sent), Some(got)) if sent != got => {
+                let message = format!("client_port {got}, requested {sent}");
+                self.find(exchange, Severity::Violation, "12.39", message);
+            }
+            (Some(_), None) => {
+                let message = "Transport doesn't echo client_port".to_string();
+                self.find(exchange, Severity::Warning, "12.39", message);
+            }
+            _ => (),
+        }
+
+        let multicast = got.iter().any(|(param, _)| param == "multicast");
+        let interleaved = protocol.ends_with("/TCP");
+        if !multicast && !interleaved && get(&got, "server_port").is_none() {
+            self.find(exchange, Severity::Violation, "12.39", "UDP unicast Transport without server_port".to_string());
+        }
+
+        if let Some(ssrc) = get(&got, "ssrc") {
+            if ssrc.len() > 8 || u32::from_str_radix(&ssrc, 16).is_err() {
+                self.find(exchange, Severity::Warning, "12.39", format!("ssrc {ssrc} isn't a 32 bit hex number"));
+            }
+        }
+    }
+
+    fn find(&mut self, exchange: &Exchange, severity: Severity, rule: &'static str, message: String) {
+        self.report.findings.push(Finding {
+            method: exchange.method.to_string(),
+            cseq: exchange.cseq,
+            severity,
+            rule,
+            message,
+        });
+    }
+}
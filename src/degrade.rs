@@ -0,0 +1,120 @@
+use crate::h264;
+use log::info;
+use std::time::{Duration, Instant};
+
+// Time to wait after changing mode before judging the new one
+const SETTLE: Duration = Duration::from_secs(1);
+// Decoding must stay well within budget this long before stepping back up
+const RECOVER: Duration = Duration::from_secs(5);
+
+// Which frames get decoded, from everything down to keyframes only
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DecodeMode {
+    Full,
+    // Frames no other frame refers to (nal_ref_idc 0) are skipped
+    ReferenceOnly,
+    // Only IDR frames, e.g. one frame every GOP
+    KeyframesOnly,
+}
+
+type DecodeModeFn = Box<dyn FnMut(DecodeMode) + Send>;
+
+// Steps the decode mode down while decoding takes longer than
+// the budget and back up once it comfortably fits again
+pub(crate) struct Degradation {
+    budget: Option<Duration>,
+    mode: DecodeMode,
+    average: Duration,
+    changed_at: Instant,
+    // After skipping frames others depend on, wait for an IDR
+    waiting_for_keyframe: bool,
+    on_change: Option<DecodeModeFn>,
+}
+
+impl Degradation {
+    pub(crate) fn new() -> Self {
+        Degradation {
+            budget: None,
+            mode: DecodeMode::Full,
+            average: Duration::ZERO,
+            changed_at: Instant::now(),
+            waiting_for_keyframe: false,
+            on_change: None,
+        }
+    }
+
+    pub(crate) fn set_budget(&mut self, budget: Option<Duration>) {
+        self.budget = budget;
+        if budget.is_none() {
+            self.change(DecodeMode::Full);
+        }
+    }
+
+    pub(crate) fn set_on_change(&mut self, on_change: DecodeModeFn) {
+        self.on_change = Some(on_change);
+    }
+
+    pub(crate) fn mode(&self) -> DecodeMode {
+        self.mode
+    }
+
+    // Whether an access unit should go to the decoder in the current mode
+    pub(crate) fn should_decode(&mut self, annexb: &[u8], is_keyframe: bool) -> bool {
+        if is_keyframe {
+            self.waiting_for_keyframe = false;
+            return true;
+        }
+
+        match self.mode {
+            _ if self.waiting_for_keyframe => false,
+            DecodeMode::Full => true,
+            DecodeMode::ReferenceOnly => h264::is_reference(annexb),
+            DecodeMode::KeyframesOnly => false,
+        }
+    }
+
+    // After each frame decoded, 'took' is how long the decoder took
+    pub(crate) fn update(&mut self, took: Duration) {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return,
+        };
+
+        self.average = (self.average * 7 + took) / 8;
+        let since_change = self.changed_at.elapsed();
+
+        let mode = match self.mode {
+            mode if self.average > budget && since_change > SETTLE => match mode {
+                DecodeMode::Full => DecodeMode::ReferenceOnly,
+                _ => DecodeMode::KeyframesOnly,
+            },
+            mode if self.average < budget / 2 && since_change > RECOVER => match mode {
+                DecodeMode::KeyframesOnly => DecodeMode::ReferenceOnly,
+                _ => DecodeMode::Full,
+            },
+            mode => mode,
+        };
+
+        self.change(mode);
+    }
+
+    fn change(&mut self, mode: DecodeMode) {
+        if mode == self.mode {
+            return;
+        }
+
+        info!("[Rtp] Decode mode {:?} -> {:?} (average decode {:?})", self.mode, mode, self.average);
+
+        // P frames after the skipped ones can't be decoded until the next IDR
+        if self.mode == DecodeMode::KeyframesOnly {
+            self.waiting_for_keyframe = true;
+        }
+
+        self.mode = mode;
+        self.changed_at = Instant::now();
+
+        if let Some(on_change) = &mut self.on_change {
+            on_change(mode);
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use crate::h264;
+use crate::rtp::RtpHeader;
+use log::{debug, trace, warn};
+use std::collections::VecDeque;
+
+// ----------------- NOTE
+// Most implementations will break up IDR frames
+// into fragments (e.g. FU-A)
+// see section 5.8 of RFC 6184
+
+// PAYLOAD starts right after the RTP header, which is 12 bytes
+// plus 4 per CSRC plus the header extension if there is one
+// The first payload byte is the NAL header, UNLESS this is a
+// fragment (e.g. FU-A) in which case the data starts 1 byte later
+// as FU-A has extra byte for header (FU-B 3, the DON follows it)
+
+// Start prefix code (3 or 4 bytes)
+// For beginning of entire stream or SPS/PPS nal units -> 0x00 0x00 x00 0x01
+// All other nal units use -> 0x00 0x00 0x01
+
+// Reassembles H264 NAL units from RTP packets into an Annex B
+// byte stream which can be handed to a decoder.
+//
+// Packets normally come from the UDP socket owned by Rtp, but
+// RTP packets or bare NAL units from any other source (a capture
+// file, parameter sets from ONVIF GetVideoEncoderConfiguration)
+// can be pushed in as well.
+//
+// Once RTP headers are seen, every NAL unit sharing an RTP timestamp
+// (AUD, SEI, parameter sets and all the slices of a multi-slice
+// frame) goes into one access unit. It is complete when a packet
+// has the marker bit or, if that packet was lost, when the timestamp
+// changes or an AUD starts the next one. Complete access units are
+// handed out one at a time, oldest first. Bare NAL units are handed
+// out as they come.
+pub struct Depacketizer {
+    buf_temp: Vec<u8>,
+    buf_sps: Vec<u8>,
+    buf_fragments: Vec<u8>,
+    is_sps_found: bool,
+    is_start_decoding: bool,
+    is_fragment_start: bool,
+    is_fragment_end: bool,
+    max_bytes: Option<usize>,
+    dropped: u64,
+    sps: Option<h264::Sps>,
+    // Framing from RTP headers: timestamp of the access unit being
+    // gathered, whether it has a slice yet, and the length and
+    // timestamp of each complete access unit at the start of buf_temp
+    is_framed: bool,
+    timestamp: Option<u32>,
+    has_slice: bool,
+    complete: VecDeque<(usize, Option<u32>)>,
+}
+
+impl Depacketizer {
+    pub fn new() -> Self {
+        Depacketizer {
+            buf_temp: Vec::new(),
+            buf_sps: Vec::new(),
+            buf_fragments: Vec::new(),
+            is_sps_found: false,
+            is_start_decoding: false,
+            is_fragment_start: false,
+            is_fragment_end: false,
+            max_bytes: None,
+            dropped: 0,
+            sps: None,
+            is_framed: false,
+            timestamp: None,
+            has_slice: false,
+            complete: VecDeque::new(),
+        }
+    }
+
+    // Push a complete RTP packet (header included)
+    pub fn push_rtp_packet(&mut self, packet: &[u8]) {
+        let header = match RtpHeader::parse(packet) {
+            Some(header) => header,
+            None => {
+                debug!("Not an RTP packet: {} bytes", packet.len());
+                return;
+            }
+        };
+
+        // Get first 16 BITS of RTP packet which is part of header (RFC 6184)
+        trace!("RTP Header ------->>> {:08b}{:08b}", packet[0], packet[1]);
+
+        self.push_rtp(&header, header.payload(packet));
+    }
+
+    // Push a payload along with its (already parsed) RTP header, which
+    // frames access units by marker bit and timestamp
+    pub fn push_rtp(&mut self, header: &RtpHeader, payload: &[u8]) {
+        self.is_framed = true;
+
+        // Timestamp moved on without a marker: the packet ending the
+        // previous access unit got lost, it's as complete as it gets
+        if let Some(timestamp) = self.timestamp {
+            if timestamp != header.timestamp {
+                self.end_access_unit();
+            }
+        }
+        self.timestamp = Some(header.timestamp);
+
+        self.push_payload(payload);
+
+        if header.marker {
+            trace!("Marker bit, access unit complete");
+            self.end_access_unit();
+        }
+    }
+
+    // Everything pushed so far is one complete access unit, e.g. at
+    // the end of a capture whose last marker bit is missing
+    pub fn end_access_unit(&mut self) {
+        // A fragment whose end never came can't be decoded
+        if !self.buf_fragments.is_empty() || (self.is_fragment_start && !self.is_fragment_end) {
+            debug!("Access unit ended inside a fragment, fragment dropped");
+            self.buf_fragments.clear();
+        }
+        self.is_fragment_start = false;
+        self.is_fragment_end = false;
+
+        // Slices before the first SPS/PPS can't be decoded either
+        let complete = self.complete_len();
+        if !self.is_start_decoding {
+            self.buf_temp.truncate(complete);
+        }
+
+        if self.buf_temp.len() > complete {
+            self.complete.push_back((self.buf_temp.len() - complete, self.timestamp));
+        }
+        self.timestamp = None;
+        self.has_slice = false;
+    }
+
+    // Bytes of buf_temp taken by complete access units
+    fn complete_len(&self) -> usize {
+        self.complete.iter().map(|(len, _)| len).sum()
+    }
+
+    // Push the payload of an RTP packet, everything after the header
+    // (CSRCs and header extension included)
+    pub fn push_payload(&mut self, payload: &[u8]) {
+        let len = payload.len();
+        if len == 0 {
+            debug!("RTP packet has no payload");
+            return;
+        }
+
+        // NAL Unit Header (1st byte of NAL unit)
+        // +---------------+
+        // |0|1|2|3|4|5|6|7|
+        // +-+-+-+-+-+-+-+-+
+        // |F|NRI|  Type   |
+        // +---------------+
+
+        // First payload byte is NAL unit header
+        let nal_header = &payload[0];
+
+        // Get the NAL unit header TYPE (last 8 BITS)
+        // Use mask 00011111 = decimal 31
+        let nal_header_type = nal_header & 31;
+
+        trace!("{} bytes received", len);
+
+        // Check for fragment (FU-A, or FU-B which only differs by the
+        // 16 bit decoding order number (DON) after the fragment header,
+        // used for the first fragment in interleaved mode)
+        if nal_header_type == 28u8 || nal_header_type == 29u8 {
+            let data_start = match nal_header_type {
+                28u8 => 2,
+                _ => 4,
+            };
+            if len < data_start {
+                debug!("FU-A/FU-B packet too short: {} bytes", len);
+                return;
+            }
+
+            debug!("Fragment started!! ----- ");
+            self.is_fragment_start = true;
+
+            // Fragment header (2nd NAL unit byte)
+            //  +---------------+
+            // |0|1|2|3|4|5|6|7| bit position
+            // +-+-+-+-+-+-+-+-+
+            // |S|E|R|  Type   |
+            // +---------------+
+            // S = Start of fragment?
+            // E = End of fragment?
+
+            // Check fragment header which is byte
+            // after NAL header
+            let header_frag = &payload[1];
+            debug!("Fragment header -- {:08b}", header_frag);
+
+            // Or fragment END?
+            if *header_frag & 0b01000000 == 64u8 {
+                trace!("Fragment ended!! ----- ");
+                self.is_fragment_end = true;
+
+                // Reconstruct new NAL header using NAL
+                // NAL unit type in FRAGMENT header
+                // AND F and NRI from the FU indicator, so a
+                // non-reference frame is still seen as one
+                // use bitmasks to get first 3 bits and last 5 bits
+                let nal_header = (payload[0] & 0b11100000) | (*header_frag & 0b00011111);
+                debug!("New NAL header for conbined fragment: {:08b}", nal_header);
+
+                // Need to swap outside nal header to inside payload type
+                // as after combining packet it's not a fragment anymore,
+                // then it goes in like any other NAL unit
+                let mut nal = std::mem::take(&mut self.buf_fragments);
+                nal.insert(0, nal_header);
+                nal.extend_from_slice(&payload[data_start..]);
+                self.push_nal(&nal);
+            } else {
+                // Append fragment payload EXCLUDING ALL HEADERS
+                self.buf_fragments.extend_from_slice(&payload[data_start..]);
+            }
+        } else if nal_header_type == 24u8 {
+            self.push_stap_a(&payload[1..]);
+        } else {
+            self.push_nal(payload);
+        }
+
+        self.enforce_max_bytes();
+    }
+
+    // Single-time aggregation packet (STAP-A), section 5.7.1 of RFC 6184
+    // Usually SPS + PPS (+ SEI) sent together, each NAL unit preceded
+    // by its size:
+    // +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    // |STAP-A NAL HDR |         NALU 1 Size           | NALU 1 HDR    |
+    // +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    // |                         NALU 1 Data                           |
+    // :                                                               :
+    // +               +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    // |               | NALU 2 Size                   | NALU 2 HDR    |
+    // +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+    fn push_stap_a(&mut self, mut aggregate: &[u8]) {
+        debug!("STAP-A packet ----- ");
+
+        while aggregate.len() >= 2 {
+            let size = u16::from_be_bytes([aggregate[0], aggregate[1]]) as usize;
+            let nal = match aggregate.get(2..2 + size) {
+                Some(nal) => nal,
+                None => {
+                    debug!("STAP-A NAL unit of {} bytes runs past the packet", size);
+                    return;
+                }
+            };
+
+            self.push_nal(nal);
+            aggregate = &aggregate[2 + size..];
+        }
+    }
+
+    // Push a single NAL unit WITHOUT start code prefix
+    // (first byte is the NAL header)
+    pub fn push_nal(&mut self, nal: &[u8]) {
+        if nal.is_empty() {
+            return;
+        }
+
+        let nal_header = &nal[0];
+        let nal_header_type = nal_header & 31;
+
+        trace!("-----------\n{:08b}", nal_header);
+        trace!(
+            "NAL HEADER TYPE: ---------->>> {}:{}",
+            nal_header_type,
+            get_nal_type(nal_header_type)
+        );
+
+        // An AUD after a slice starts the next access unit, even
+        // with the same timestamp
+        if nal_header_type == 9u8 && self.has_slice {
+            let timestamp = self.timestamp;
+            self.end_access_unit();
+            self.timestamp = timestamp;
+        }
+
+        // Check if this is an SPS packet
+        // NAL header byte -> 01100111
+        if nal_header_type == 7u8 {
+            trace!("Sequence started! --------------------------------------");
+
+            self.is_sps_found = true;
+            if let Some(sps) = h264::parse_sps(nal) {
+                self.sps = Some(sps);
+            }
+            self.buf_sps.extend_from_slice(&[0u8, 0u8, 0u8, 1u8]);
+            self.buf_sps.extend_from_slice(nal);
+        }
+        // Check if this is an PPS packet
+        else if nal_header_type == 8u8 {
+            debug!("PPS packet ----- ");
+
+            if self.is_sps_found {
+                self.is_start_decoding = true;
+
+                self.buf_temp.extend_from_slice(self.buf_sps.as_slice());
+                self.buf_temp.extend_from_slice(&[0u8, 0u8, 0u8, 1u8]);
+                self.buf_temp.extend_from_slice(nal);
+                self.buf_sps.clear();
+            }
+        }
+        // Check if this is an SEI packet
+        else if nal_header_type == 6u8 {
+            debug!("SEI packet ----- ");
+
+            self.buf_temp.extend_from_slice(&[0u8, 0u8, 1u8]);
+            self.buf_temp.extend_from_slice(nal);
+        } else {
+            debug!("Slice packet ----- ");
+
+            self.is_sps_found = false;
+            self.has_slice |= matches!(nal_header_type, 1..=5);
+            self.buf_temp.extend_from_slice(&[0u8, 0u8, 1u8]);
+            self.buf_temp.extend_from_slice(nal);
+        }
+
+        self.enforce_max_bytes();
+    }
+
+    // Push the SPS and PPS from an SDP sprop-parameter-sets value
+    // e.g. Z0IAH5WoFAFuQA==,aM48gA== so decoding can start without
+    // waiting for them in band. Returns the number of NAL units pushed
+    pub fn push_parameter_sets(&mut self, sprop: &str) -> usize {
+        let nals = h264::decode_sprop_parameter_sets(sprop);
+        for nal in &nals {
+            self.push_nal(nal);
+        }
+
+        nals.len()
+    }
+
+    // Last SPS seen, in band or from push_parameter_sets
+    pub fn sps(&self) -> Option<&h264::Sps> {
+        self.sps.as_ref()
+    }
+
+    // Returns the Annex B data gathered so far if it is ready
+    // to be decoded. Call clear() once it has been consumed.
+    // With RTP framing that is the oldest complete access unit
+    pub fn pending(&mut self) -> Option<&[u8]> {
+        if self.is_framed {
+            return match (self.complete.front(), self.is_start_decoding) {
+                (Some(&(len, _)), true) => Some(&self.buf_temp[..len]),
+                _ => None,
+            };
+        }
+
+        if self.buf_temp.is_empty() || !self.is_start_decoding || (self.is_fragment_start && !self.is_fragment_end) {
+            return None;
+        }
+
+        // Clear fragment flags
+        self.is_fragment_start = false;
+        self.is_fragment_end = false;
+
+        Some(self.buf_temp.as_slice())
+    }
+
+    // RTP timestamp of the access unit pending() returns
+    pub fn pending_timestamp(&self) -> Option<u32> {
+        self.complete.front().and_then(|(_, timestamp)| *timestamp)
+    }
+
+    // Complete access units waiting, with RTP framing
+    pub fn pending_count(&self) -> usize {
+        self.complete.len()
+    }
+
+    // Drop the complete access units before the last keyframe among
+    // them, e.g. to catch up after a stall. Returns how many
+    pub fn skip_to_keyframe(&mut self) -> usize {
+        let mut offset = 0;
+        let mut last = None;
+        for (i, &(len, _)) in self.complete.iter().enumerate() {
+            if h264::is_keyframe(&self.buf_temp[offset..offset + len]) {
+                last = Some((i, offset));
+            }
+            offset += len;
+        }
+
+        match last {
+            Some((skipped, skipped_len)) if skipped > 0 => {
+                self.buf_temp.drain(..skipped_len);
+                self.complete.drain(..skipped);
+                skipped
+            }
+            _ => 0,
+        }
+    }
+
+    // With RTP framing only the access unit pending() returned goes,
+    // the rest and the one being gathered are kept
+    pub fn clear(&mut self) {
+        if let Some((len, _)) = self.complete.pop_front() {
+            self.buf_temp.drain(..len);
+        }
+
+        if !self.is_framed {
+            self.buf_temp.clear();
+            self.complete.clear();
+        }
+    }
+
+    // Most memory an access unit being assembled (fragments included)
+    // may take, see MemoryBudget. None is unbounded
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+        self.enforce_max_bytes();
+    }
+
+    // Access units thrown away for going over max_bytes
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    // Over the limit: drop everything and start again from the
+    // next SPS/PPS, i.e. the next keyframe
+    fn enforce_max_bytes(&mut self) {
+        let max_bytes = match self.max_bytes {
+            Some(max_bytes) => max_bytes,
+            None => return,
+        };
+
+        if self.buf_temp.len() + self.buf_fragments.len() + self.buf_sps.len() <= max_bytes {
+            return;
+        }
+
+        warn!("[Depacketizer] Access unit over {max_bytes} bytes, dropped until next keyframe");
+
+        // Give the memory back rather than keeping the capacity
+        self.buf_temp = Vec::new();
+        self.buf_sps = Vec::new();
+        self.buf_fragments = Vec::new();
+        self.is_sps_found = false;
+        self.is_start_decoding = false;
+        self.is_fragment_start = false;
+        self.is_fragment_end = false;
+        self.timestamp = None;
+        self.has_slice = false;
+        self.complete.clear();
+        self.dropped += 1;
+    }
+}
+
+impl Default for Depacketizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn get_nal_type(nal: u8) -> String {
+    let nal_types = r#"0:Unspecified:non-VCL
+        1:Coded slice of a non-IDR picture slice_layer_without_partitioning_rbsp():VCL
+        2:Coded slice data partition A slice_data_partition_a_layer_rbsp():VCL
+        3:Coded slice data partition B slice_data_partition_b_layer_rbsp():VCL
+        4:Coded slice data partition C slice_data_partition_c_layer_rbsp():VCL
+        5:Coded slice of an IDR picture slice_layer_without_partitioning_rbsp():VCL
+        6:Supplemental enhancement information (SEI) sei_rbsp():non-VCL
+        7:Sequence parameter set seq_parameter_set_rbsp():non-VCL
+        8:Picture parameter set pic_parameter_set_rbsp():non-VCL
+        9:Access unit delimiter access_unit_delimiter_rbsp():non-VCL
+        10:End of sequence end_of_seq_rbsp():non-VCL
+        11:End of stream end_of_stream_rbsp():non-VCL
+        12:Filler data filler_data_rbsp():non-VCL
+        13:Sequence parameter set extension seq_parameter_set_extension_rbsp():non-VCL
+        14:Prefix NAL unit prefix_nal_unit_rbsp():non-VCL
+        15:Subset sequence parameter set subset_seq_parameter_set_rbsp():non-VCL
+        16:Reserved:non-VCL
+        18:Reserved:non-VCL
+        19:Coded slice of an auxiliary coded picture without partitioning slice_layer_without_partitioning_rbsp():non-VCL
+        20:Coded slice extension slice_layer_extension_rbsp():non-VCL
+        21:Coded slice extension for depth view components slice_layer_extension_rbsp() (specified in Annex I):non-VCL
+        22:Reserved:non-VCL
+        23:Reserved:non-VCL
+        24:STAP-A:non-VCL
+        25:STAP-B:non-VCL
+        26:MTAP16:non-VCL
+        27:MTAP24:non-VCL
+        28:FU-A:non-VCL
+        29:FU-B:non-VCL
+        30:reserved:non-VCL
+        31:reserved:non-VCL"#;
+
+    nal_types
+        .lines()
+        .enumerate()
+        .filter(|(i, _)| *i as u8 == nal)
+        .map(|(_, line)| line.split(':').collect::<Vec<&str>>()[1])
+        .collect::<String>()
+}
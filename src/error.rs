@@ -0,0 +1,142 @@
+use crate::rtsp::{Methods, SessionState};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RtspError {
+    #[error("invalid RTSP URL {url}: {source}")]
+    InvalidUrl {
+        url: String,
+        source: url::ParseError,
+    },
+
+    #[error("unable to resolve server address for {0}")]
+    UnresolvedAddress(String),
+
+    #[error("connection error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("proxy error: {0}")]
+    Proxy(String),
+
+    #[error("TLS error: {0}")]
+    Tls(String),
+
+    #[error("rtsps:// requires the 'tls' feature")]
+    TlsNotEnabled,
+
+    #[error("request timed out")]
+    Timeout,
+
+    #[error("protocol error: {0}")]
+    Protocol(String),
+
+    #[error("response is not a valid RTSP response")]
+    InvalidResponse,
+
+    #[error("response CSeq {got} doesn't match request CSeq {expected}")]
+    CSeqMismatch { expected: u32, got: u32 },
+
+    #[error("server requires authentication (401)")]
+    Unauthorized,
+
+    #[error("stream not found (404)")]
+    NotFound,
+
+    #[error("session not found (454)")]
+    SessionNotFound,
+
+    #[error("unsupported transport (461)")]
+    UnsupportedTransport,
+
+    #[error("server answered {status} {reason}")]
+    Status { status: u16, reason: String },
+
+    #[error("{0:?} is not supported by server")]
+    UnsupportedMethod(Methods),
+
+    #[error("{method:?} is not allowed in the {state:?} state")]
+    InvalidState { method: Methods, state: SessionState },
+
+    #[error("redirected more than {0} times")]
+    TooManyRedirects(u32),
+
+    #[error("SETUP response has no Transport header")]
+    MissingTransportHeader,
+
+    #[error("SETUP Transport header has no valid server_port")]
+    InvalidServerPort,
+
+    #[error("no Session, send SETUP first")]
+    MissingSession,
+
+    #[error("invalid SDP: {0}")]
+    InvalidSdp(String),
+
+    #[error("SETUP failed for track {0}")]
+    SetupFailed(usize),
+
+    #[error("no free client ports left")]
+    NoFreePorts,
+
+    #[error("{0} task panicked")]
+    TaskPanicked(&'static str),
+}
+
+impl RtspError {
+    // Error for a non 2xx response, common codes get their own variant
+    pub fn from_status(status: u16, reason: &str) -> Self {
+        match status {
+            401 => RtspError::Unauthorized,
+            404 => RtspError::NotFound,
+            454 => RtspError::SessionNotFound,
+            461 => RtspError::UnsupportedTransport,
+            _ => RtspError::Status { status, reason: reason.to_string() },
+        }
+    }
+
+    // RTSP status code behind this error, if it came from a response
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            RtspError::Unauthorized => Some(401),
+            RtspError::NotFound => Some(404),
+            RtspError::SessionNotFound => Some(454),
+            RtspError::UnsupportedTransport => Some(461),
+            RtspError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RtpError {
+    #[error("invalid client address: {0}")]
+    InvalidAddress(#[from] std::net::AddrParseError),
+
+    #[error("socket error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(feature = "decode-openh264")]
+    #[error("decoder error: {0}")]
+    Decoder(#[from] openh264::Error),
+
+    #[error("no decoder, call connect first")]
+    NoDecoder,
+
+    #[error("no audio packetizer, call set_audio_packetizer first")]
+    NoAudioPacketizer,
+
+    #[error("receive timed out")]
+    Timeout,
+
+    #[error("server ended the stream (RTCP BYE){}", .0.as_ref().map(|reason| format!(": {reason}")).unwrap_or_default())]
+    StreamEnded(Option<String>),
+}
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error(transparent)]
+    Rtsp(#[from] RtspError),
+
+    #[error(transparent)]
+    Rtp(#[from] RtpError),
+}
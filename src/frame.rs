@@ -0,0 +1,255 @@
+#[cfg(feature = "decode-openh264")]
+use openh264::decoder::DecodedYUV;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+// Decoded picture owned by the caller
+// Planes are I420 (YUV 4:2:0) and tightly packed (no stride padding)
+// U and V planes are half the width and height of the Y plane
+#[derive(Clone)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    // From the stream's SPS or SDP, if it says
+    pub framerate: Option<f64>,
+    pub y: Vec<u8>,
+    pub u: Vec<u8>,
+    pub v: Vec<u8>,
+    // Whatever earlier stages found out about the frame
+    pub extensions: Extensions,
+}
+
+impl Frame {
+    // Copy a frame out of the decoder so it can be modified
+    // and kept around after the next decode
+    #[cfg(feature = "decode-openh264")]
+    pub fn from_yuv(yuv: &DecodedYUV) -> Self {
+        let (width, height) = yuv.dimension_rgb();
+        let (y_stride, u_stride, v_stride) = yuv.strides_yuv();
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+        Frame {
+            width,
+            height,
+            framerate: None,
+            y: copy_plane(yuv.y_with_stride(), y_stride, width, height),
+            u: copy_plane(yuv.u_with_stride(), u_stride, chroma_width, chroma_height),
+            v: copy_plane(yuv.v_with_stride(), v_stride, chroma_width, chroma_height),
+            extensions: Extensions::new(),
+        }
+    }
+
+    pub fn chroma_width(&self) -> usize {
+        self.width.div_ceil(2)
+    }
+
+    pub fn chroma_height(&self) -> usize {
+        self.height.div_ceil(2)
+    }
+
+    // Packed RGB, 3 bytes per pixel (BT.601, limited range)
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.to_packed(3)
+    }
+
+    // Packed RGBA, 4 bytes per pixel with alpha always 255
+    pub fn to_rgba(&self) -> Vec<u8> {
+        self.to_packed(4)
+    }
+
+    // Y plane followed by interleaved UV (U first) at half resolution
+    pub fn to_nv12(&self) -> Vec<u8> {
+        let mut nv12 = Vec::with_capacity(self.y.len() + self.u.len() * 2);
+
+        nv12.extend_from_slice(&self.y);
+        for (u, v) in self.u.iter().zip(&self.v) {
+            nv12.push(*u);
+            nv12.push(*v);
+        }
+
+        nv12
+    }
+
+    // Planes one after another (Y, U, V), a.k.a. IYUV
+    pub fn to_i420(&self) -> Vec<u8> {
+        let mut i420 = Vec::with_capacity(self.y.len() + self.u.len() * 2);
+
+        i420.extend_from_slice(&self.y);
+        i420.extend_from_slice(&self.u);
+        i420.extend_from_slice(&self.v);
+
+        i420
+    }
+
+    pub fn to_image(&self, format: PixelFormat) -> Image {
+        let data = match format {
+            PixelFormat::I420 => self.to_i420(),
+            PixelFormat::Nv12 => self.to_nv12(),
+            PixelFormat::Rgb24 => self.to_rgb(),
+            PixelFormat::Rgba => self.to_rgba(),
+        };
+
+        Image {
+            format,
+            width: self.width,
+            height: self.height,
+            data,
+        }
+    }
+
+    // RGB with 'channels' bytes per pixel, the 4th (if any) is alpha
+    fn to_packed(&self, channels: usize) -> Vec<u8> {
+        let mut packed = Vec::with_capacity(self.width * self.height * channels);
+        let chroma_width = self.chroma_width();
+
+        for row in 0..self.height {
+            for col in 0..self.width {
+                let y = self.y[row * self.width + col] as f32 - 16.0;
+                let chroma = (row / 2) * chroma_width + col / 2;
+                let u = self.u[chroma] as f32 - 128.0;
+                let v = self.v[chroma] as f32 - 128.0;
+
+                packed.push((1.164 * y + 1.596 * v).clamp(0.0, 255.0) as u8);
+                packed.push((1.164 * y - 0.392 * u - 0.813 * v).clamp(0.0, 255.0) as u8);
+                packed.push((1.164 * y + 2.017 * u).clamp(0.0, 255.0) as u8);
+                if channels == 4 {
+                    packed.push(255);
+                }
+            }
+        }
+
+        packed
+    }
+}
+
+// Typed values attached to a frame by the stages it passes through,
+// one per type, e.g. detection boxes from analytics which the
+// recorder and the display pick up again further down:
+//
+// frame.extensions.insert(Detections(boxes));
+// ...
+// if let Some(Detections(boxes)) = frame.extensions.get::<Detections>() { ... }
+//
+// Give each kind of result its own type (a newtype at least) so
+// stages don't overwrite each other's. Cloned along with the frame
+#[derive(Clone, Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Extension>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Attach 'value', returning the one of the same type it replaces
+    pub fn insert<T: Clone + Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.map
+            .insert(TypeId::of::<T>(), Box::new(value))
+            .and_then(|previous| previous.into_any().downcast().ok())
+            .map(|previous| *previous)
+    }
+
+    pub fn get<T: 'static>(&self) -> Option<&T> {
+        self.map
+            .get(&TypeId::of::<T>())
+            .and_then(|value| (**value).as_any().downcast_ref())
+    }
+
+    pub fn get_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        self.map
+            .get_mut(&TypeId::of::<T>())
+            .and_then(|value| (**value).as_any_mut().downcast_mut())
+    }
+
+    pub fn remove<T: 'static>(&mut self) -> Option<T> {
+        self.map
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.into_any().downcast().ok())
+            .map(|value| *value)
+    }
+
+    pub fn contains<T: 'static>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+// What the map needs of a value: to be cloned with the frame and
+// sent to other threads along with it. Boxes are dereferenced before
+// calling these, the box itself is an Extension too
+trait Extension: Send + Sync {
+    fn clone_box(&self) -> Box<dyn Extension>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Clone + Send + Sync + 'static> Extension for T {
+    fn clone_box(&self) -> Box<dyn Extension> {
+        Box::new(self.clone())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+impl Clone for Box<dyn Extension> {
+    fn clone(&self) -> Self {
+        (**self).clone_box()
+    }
+}
+
+// Layout of the bytes in an Image
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PixelFormat {
+    // Planar Y, U, V (e.g. SDL IYUV)
+    #[default]
+    I420,
+    // Planar Y, interleaved UV (e.g. hardware encoders)
+    Nv12,
+    // Packed R, G, B
+    Rgb24,
+    // Packed R, G, B, A (e.g. wgpu Rgba8Unorm textures)
+    Rgba,
+}
+
+// Decoded picture converted to a single buffer in one pixel format
+#[derive(Clone)]
+pub struct Image {
+    pub format: PixelFormat,
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+#[cfg(feature = "decode-openh264")]
+fn copy_plane(src: &[u8], stride: usize, width: usize, height: usize) -> Vec<u8> {
+    let mut plane = Vec::with_capacity(width * height);
+
+    for row in src.chunks(stride).take(height) {
+        plane.extend_from_slice(&row[..width.min(row.len())]);
+    }
+
+    plane
+}
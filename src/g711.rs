@@ -0,0 +1,119 @@
+use crate::codec::Codec;
+
+// G.711 (ITU-T) companding of 16 bit PCM to 8 bit samples, as
+// sent to cameras with an audio backchannel (talk-down).
+// Always 8kHz mono, payload type 0 (PCMU) or 8 (PCMA) (RFC 3551)
+
+// Added to mu-law samples before companding, and the largest
+// magnitude which still fits once added
+const ULAW_BIAS: i32 = 0x84;
+const ULAW_CLIP: i32 = 32635;
+
+pub const SAMPLE_RATE: u32 = 8000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum G711 {
+    Ulaw, // PCMU, North America and Japan
+    Alaw, // PCMA, everywhere else
+}
+
+impl G711 {
+    pub fn from_codec(codec: &Codec) -> Option<Self> {
+        match codec {
+            Codec::Pcmu => Some(G711::Ulaw),
+            Codec::Pcma => Some(G711::Alaw),
+            _ => None,
+        }
+    }
+
+    // Static payload type
+    pub fn payload_type(&self) -> u8 {
+        match self {
+            G711::Ulaw => 0,
+            G711::Alaw => 8,
+        }
+    }
+
+    pub fn encode(&self, pcm: &[i16]) -> Vec<u8> {
+        match self {
+            G711::Ulaw => pcm.iter().map(|&sample| encode_ulaw(sample)).collect(),
+            G711::Alaw => pcm.iter().map(|&sample| encode_alaw(sample)).collect(),
+        }
+    }
+
+    pub fn decode(&self, data: &[u8]) -> Vec<i16> {
+        match self {
+            G711::Ulaw => data.iter().map(|&sample| decode_ulaw(sample)).collect(),
+            G711::Alaw => data.iter().map(|&sample| decode_alaw(sample)).collect(),
+        }
+    }
+}
+
+// Sign, 3 bit exponent (segment) and 4 bit mantissa, all inverted
+pub fn encode_ulaw(sample: i16) -> u8 {
+    let sign = match sample < 0 {
+        true => 0x80,
+        false => 0x00,
+    };
+    let magnitude = (sample as i32).abs().min(ULAW_CLIP) + ULAW_BIAS;
+
+    // Position of the highest bit set, the bias makes it at least 7
+    let exponent = 31 - magnitude.leading_zeros() as i32 - 7;
+    let mantissa = (magnitude >> (exponent + 3)) & 0x0F;
+
+    !(sign | (exponent << 4) as u8 | mantissa as u8)
+}
+
+pub fn decode_ulaw(sample: u8) -> i16 {
+    let sample = !sample;
+    let exponent = (sample >> 4) & 0x07;
+    let mantissa = (sample & 0x0F) as i32;
+    let magnitude = (((mantissa << 3) + ULAW_BIAS) << exponent) - ULAW_BIAS;
+
+    match sample & 0x80 != 0 {
+        true => -magnitude as i16,
+        false => magnitude as i16,
+    }
+}
+
+// Sign, 3 bit segment and 4 bit mantissa of the 13 bit sample,
+// even bits inverted
+pub fn encode_alaw(sample: i16) -> u8 {
+    let sample = (sample as i32) >> 3;
+    let (magnitude, mask) = match sample >= 0 {
+        true => (sample, 0xD5),
+        false => (-sample - 1, 0x55),
+    };
+
+    let segment = match magnitude {
+        0..=0x1F => 0,
+        _ => 31 - magnitude.leading_zeros() as i32 - 4,
+    };
+    if segment >= 8 {
+        return 0x7F ^ mask;
+    }
+
+    let mantissa = match segment {
+        0 | 1 => (magnitude >> 1) & 0x0F,
+        _ => (magnitude >> segment) & 0x0F,
+    };
+
+    ((segment << 4) as u8 | mantissa as u8) ^ mask
+}
+
+pub fn decode_alaw(sample: u8) -> i16 {
+    let sample = sample ^ 0x55;
+    let segment = (sample & 0x70) >> 4;
+    let mut magnitude = ((sample & 0x0F) as i32) << 4;
+
+    match segment {
+        0 => magnitude += 8,
+        1 => magnitude += 0x108,
+        _ => magnitude = (magnitude + 0x108) << (segment - 1),
+    }
+
+    match sample & 0x80 != 0 {
+        true => magnitude as i16,
+        false => -magnitude as i16,
+    }
+}
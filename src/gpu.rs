@@ -0,0 +1,204 @@
+use crate::frame::Frame;
+
+// Converts the Y, U and V textures to RGB on the GPU (BT.601, limited range)
+// Draw a single triangle of 3 vertices to cover the target, no vertex buffer needed
+// Bindings in group 0: y, u, v textures (0-2) and a filtering sampler (3)
+pub const YUV_SHADER: &str = r#"
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+@group(0) @binding(0) var y_texture: texture_2d<f32>;
+@group(0) @binding(1) var u_texture: texture_2d<f32>;
+@group(0) @binding(2) var v_texture: texture_2d<f32>;
+@group(0) @binding(3) var yuv_sampler: sampler;
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let y = textureSample(y_texture, yuv_sampler, in.uv).r - 0.0625;
+    let u = textureSample(u_texture, yuv_sampler, in.uv).r - 0.5;
+    let v = textureSample(v_texture, yuv_sampler, in.uv).r - 0.5;
+
+    let r = 1.164 * y + 1.596 * v;
+    let g = 1.164 * y - 0.392 * u - 0.813 * v;
+    let b = 1.164 * y + 2.017 * u;
+    return vec4<f32>(clamp(vec3<f32>(r, g, b), vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
+"#;
+
+// One R8 texture per plane of a decoded frame, recreated
+// whenever the stream changes resolution
+//
+// let layout = YuvTextures::bind_group_layout(&device);
+// let mut textures = YuvTextures::new(&device, 1920, 1080);
+// loop {
+//     let frame = rtp.try_decode_frame()?;
+//     textures.upload(&device, &queue, &frame);
+//     let bind_group = textures.bind_group(&device, &layout, &sampler);
+//     // render pass: set_pipeline (YUV_SHADER), set_bind_group(0, ..), draw(0..3, 0..1)
+// }
+pub struct YuvTextures {
+    pub y: wgpu::Texture,
+    pub u: wgpu::Texture,
+    pub v: wgpu::Texture,
+    width: u32,
+    height: u32,
+}
+
+impl YuvTextures {
+    pub fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+
+        YuvTextures {
+            y: plane_texture(device, "y plane", width, height),
+            u: plane_texture(device, "u plane", chroma_width, chroma_height),
+            v: plane_texture(device, "v plane", chroma_width, chroma_height),
+            width,
+            height,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // Copy the planes of a frame to the GPU
+    // Returns true when the textures were recreated for a new
+    // resolution, in which case any bind group must be created again
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, frame: &Frame) -> bool {
+        let (width, height) = (frame.width as u32, frame.height as u32);
+        let resized = width != self.width || height != self.height;
+
+        if resized {
+            *self = YuvTextures::new(device, width, height);
+        }
+
+        let (chroma_width, chroma_height) = (frame.chroma_width() as u32, frame.chroma_height() as u32);
+        write_plane(queue, &self.y, &frame.y, width, height);
+        write_plane(queue, &self.u, &frame.u, chroma_width, chroma_height);
+        write_plane(queue, &self.v, &frame.v, chroma_width, chroma_height);
+
+        resized
+    }
+
+    // Layout matching the bindings in YUV_SHADER
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let texture = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("yuv bind group layout"),
+            entries: &[
+                texture(0),
+                texture(1),
+                texture(2),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        })
+    }
+
+    pub fn bind_group(
+        &self,
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        let y = self.y.create_view(&wgpu::TextureViewDescriptor::default());
+        let u = self.u.create_view(&wgpu::TextureViewDescriptor::default());
+        let v = self.v.create_view(&wgpu::TextureViewDescriptor::default());
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("yuv bind group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&y),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&u),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&v),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+}
+
+fn plane_texture(device: &wgpu::Device, label: &str, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::R8Unorm,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    })
+}
+
+// Frame planes are tightly packed so a row is exactly 'width' bytes
+fn write_plane(queue: &wgpu::Queue, texture: &wgpu::Texture, plane: &[u8], width: u32, height: u32) {
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+            aspect: wgpu::TextureAspect::All,
+        },
+        plane,
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(width),
+            rows_per_image: Some(height),
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+}
@@ -0,0 +1,591 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+// Helpers for H264 bitstream details the client needs before
+// (or without) decoding, e.g. the picture size from the SPS.
+// See ITU-T H.264 section 7.3.2.1.1 for the SPS syntax
+
+// Ranges of section 7.4.2: log2_max_frame_num_minus4 (and
+// log2_max_pic_order_cnt_lsb_minus4) up to 12, QP and reference
+// list lengths
+const MAX_LOG2_MAX_FRAME_NUM: u32 = 16;
+const MAX_QP: i32 = 51;
+const MAX_REF_IDX_ACTIVE: u32 = 32;
+
+// Picture size in macroblocks per dimension. Level 6.2 allows
+// sqrt(8 * MaxFS) = 1055, this keeps the size in pixels within u16
+const MAX_MBS_PER_DIMENSION: u32 = 1024;
+
+// Stream properties read from a Sequence Parameter Set
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sps {
+    pub profile_idc: u8,
+    pub constraint_flags: u8,
+    pub level_idc: u8,
+    pub width: usize,
+    pub height: usize,
+    // Only present when the encoder writes VUI timing info
+    pub framerate: Option<f64>,
+    // Needed to read slice headers, see slice_qp
+    log2_max_frame_num: u32,
+    pic_order_cnt_type: u32,
+    log2_max_pic_order_cnt_lsb: u32,
+    delta_pic_order_always_zero: bool,
+    frame_mbs_only: bool,
+    chroma_array_type: u32,
+}
+
+// What slice_qp needs from a Picture Parameter Set
+// (H.264 section 7.3.2.2)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Pps {
+    pub pic_parameter_set_id: u32,
+    pub pic_init_qp: i32,
+    pub entropy_coding_mode: bool, // CABAC
+    bottom_field_pic_order_in_frame_present: bool,
+    num_ref_idx_l0_default_active: u32,
+    num_ref_idx_l1_default_active: u32,
+    weighted_pred: bool,
+    weighted_bipred_idc: u32,
+    redundant_pic_cnt_present: bool,
+}
+
+// Decode the base64 parameter sets from an SDP fmtp line
+// e.g. sprop-parameter-sets=Z0IAH5WoFAFuQA==,aM48gA==
+// Returns NAL units WITHOUT start codes (SPS first, then PPS)
+pub fn decode_sprop_parameter_sets(sprop: &str) -> Vec<Vec<u8>> {
+    sprop
+        .split(',')
+        .map(|set| set.trim())
+        .filter(|set| !set.is_empty())
+        .filter_map(|set| STANDARD.decode(set).ok())
+        .filter(|nal| !nal.is_empty())
+        .collect()
+}
+
+// Encode parameter sets (NAL units WITHOUT start codes, SPS first)
+// for an SDP fmtp line, the reverse of decode_sprop_parameter_sets
+pub fn encode_sprop_parameter_sets(nals: &[&[u8]]) -> String {
+    nals.iter()
+        .map(|nal| STANDARD.encode(nal))
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Split an Annex B byte stream into NAL units WITHOUT start codes
+pub fn nal_units(annexb: &[u8]) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+
+    while i + 3 <= annexb.len() {
+        if annexb[i..i + 3] == [0, 0, 1] {
+            if let Some(start) = start {
+                units.push(trim_trailing_zeros(&annexb[start..i]));
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+
+    if let Some(start) = start {
+        units.push(&annexb[start..]);
+    }
+
+    units.retain(|nal| !nal.is_empty());
+    units
+}
+
+// Split an Annex B byte stream into access units, start codes
+// included. One begins at an AUD, SEI, SPS or PPS after a slice, or
+// at a slice starting a new picture, i.e. first_mb_in_slice is 0
+// (H.264 section 7.4.1.2.3, some of the rarer NAL types left out)
+pub fn access_units(annexb: &[u8]) -> Vec<&[u8]> {
+    let mut units = Vec::new();
+    let mut start = 0;
+    let mut has_slice = false;
+    let mut i = 0;
+
+    while i + 3 < annexb.len() {
+        if annexb[i..i + 3] != [0, 0, 1] {
+            i += 1;
+            continue;
+        }
+
+        // Including the extra zero of a 4 byte start code
+        let code = match i > 0 && annexb[i - 1] == 0 {
+            true => i - 1,
+            false => i,
+        };
+        let header = annexb[i + 3];
+
+        let new_unit = match header & 31 {
+            6..=9 => has_slice,
+            // first_mb_in_slice is ue(v), 0 is the single bit 1
+            1 | 5 => has_slice && annexb.get(i + 4).is_some_and(|byte| byte & 0x80 != 0),
+            _ => false,
+        };
+        if new_unit && code > start {
+            units.push(&annexb[start..code]);
+            start = code;
+            has_slice = false;
+        }
+        if matches!(header & 31, 1 | 5) {
+            has_slice = true;
+        }
+
+        i += 3;
+    }
+
+    if start < annexb.len() {
+        units.push(&annexb[start..]);
+    }
+
+    units
+}
+
+// The extra zero of a 4 byte start code (00 00 00 01)
+// ends up at the end of the previous unit
+fn trim_trailing_zeros(nal: &[u8]) -> &[u8] {
+    let len = nal.iter().rposition(|&byte| byte != 0).map_or(0, |pos| pos + 1);
+    &nal[..len]
+}
+
+// True if the Annex B data holds an IDR slice, i.e. it
+// can be decoded without any earlier frames
+pub fn is_keyframe(annexb: &[u8]) -> bool {
+    nal_units(annexb).iter().any(|nal| nal[0] & 31 == 5)
+}
+
+// True if any slice in the Annex B data may be referred to by later
+// frames (nal_ref_idc not 0), i.e. skipping it would break decoding
+pub fn is_reference(annexb: &[u8]) -> bool {
+    nal_units(annexb)
+        .iter()
+        .any(|nal| matches!(nal[0] & 31, 1..=5) && nal[0] & 0b01100000 != 0)
+}
+
+// Parse an SPS NAL unit (including its 1 byte NAL header)
+pub fn parse_sps(nal: &[u8]) -> Option<Sps> {
+    if nal.len() < 4 || nal[0] & 31 != 7 {
+        return None;
+    }
+
+    let rbsp = remove_emulation_prevention(&nal[1..]);
+    let mut bits = BitReader::new(&rbsp);
+
+    let profile_idc = bits.u(8)? as u8;
+    let constraint_flags = bits.u(8)? as u8;
+    let level_idc = bits.u(8)? as u8;
+    let _seq_parameter_set_id = bits.ue()?;
+
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+
+    // High profiles carry chroma format and scaling matrices
+    if [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135].contains(&profile_idc) {
+        chroma_format_idc = bits.ue()?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = bits.u(1)? == 1;
+        }
+        let _bit_depth_luma_minus8 = bits.ue()?;
+        let _bit_depth_chroma_minus8 = bits.ue()?;
+        let _qpprime_y_zero_transform_bypass = bits.u(1)?;
+
+        if bits.u(1)? == 1 {
+            let count = if chroma_format_idc != 3 { 8 } else { 12 };
+            for i in 0..count {
+                if bits.u(1)? == 1 {
+                    skip_scaling_list(&mut bits, if i < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    // Values read from the stream are checked against the ranges the
+    // spec allows, a broken or hostile camera mustn't overflow them
+    let log2_max_frame_num = bits.ue()?.checked_add(4).filter(|&log2| log2 <= MAX_LOG2_MAX_FRAME_NUM)?;
+    let pic_order_cnt_type = bits.ue()?;
+    let mut log2_max_pic_order_cnt_lsb = 0;
+    let mut delta_pic_order_always_zero = false;
+    if pic_order_cnt_type == 0 {
+        log2_max_pic_order_cnt_lsb = bits.ue()?.checked_add(4).filter(|&log2| log2 <= MAX_LOG2_MAX_FRAME_NUM)?;
+    } else if pic_order_cnt_type == 1 {
+        delta_pic_order_always_zero = bits.u(1)? == 1;
+        let _offset_for_non_ref_pic = bits.se()?;
+        let _offset_for_top_to_bottom_field = bits.se()?;
+        for _ in 0..bits.ue()? {
+            let _offset_for_ref_frame = bits.se()?;
+        }
+    }
+
+    let _max_num_ref_frames = bits.ue()?;
+    let _gaps_in_frame_num_allowed = bits.u(1)?;
+    let pic_width_in_mbs = bits.ue()?.checked_add(1).filter(|&mbs| mbs <= MAX_MBS_PER_DIMENSION)? as usize;
+    let pic_height_in_map_units = bits.ue()?.checked_add(1).filter(|&mbs| mbs <= MAX_MBS_PER_DIMENSION)? as usize;
+    let frame_mbs_only = bits.u(1)? as usize;
+    if frame_mbs_only == 0 {
+        let _mb_adaptive_frame_field = bits.u(1)?;
+    }
+    let _direct_8x8_inference = bits.u(1)?;
+
+    let mut width = pic_width_in_mbs * 16;
+    let mut height = (2 - frame_mbs_only) * pic_height_in_map_units * 16;
+    let chroma_array_type = if separate_colour_plane { 0 } else { chroma_format_idc };
+
+    // Cropping is given in chroma sample units
+    if bits.u(1)? == 1 {
+        let (left, right) = (bits.ue()? as usize, bits.ue()? as usize);
+        let (top, bottom) = (bits.ue()? as usize, bits.ue()? as usize);
+        let crop_width = left.checked_add(right)?;
+        let crop_height = top.checked_add(bottom)?;
+
+        let (crop_x, crop_y) = match chroma_array_type {
+            1 => (2, 2 * (2 - frame_mbs_only)),
+            2 => (2, 2 - frame_mbs_only),
+            _ => (1, 2 - frame_mbs_only),
+        };
+
+        width = width.saturating_sub(crop_width.saturating_mul(crop_x));
+        height = height.saturating_sub(crop_height.saturating_mul(crop_y));
+    }
+
+    // Framerate lives in the VUI timing info, if the encoder sent it
+    let framerate = match bits.u(1) {
+        Some(1) => parse_vui_framerate(&mut bits),
+        _ => None,
+    };
+
+    Some(Sps {
+        profile_idc,
+        constraint_flags,
+        level_idc,
+        width,
+        height,
+        framerate,
+        log2_max_frame_num,
+        pic_order_cnt_type,
+        log2_max_pic_order_cnt_lsb,
+        delta_pic_order_always_zero,
+        frame_mbs_only: frame_mbs_only == 1,
+        chroma_array_type,
+    })
+}
+
+// Parse a PPS NAL unit (including its 1 byte NAL header), up to
+// what slice headers depend on
+pub fn parse_pps(nal: &[u8]) -> Option<Pps> {
+    if nal.len() < 2 || nal[0] & 31 != 8 {
+        return None;
+    }
+
+    let rbsp = remove_emulation_prevention(&nal[1..]);
+    let mut bits = BitReader::new(&rbsp);
+
+    let pic_parameter_set_id = bits.ue()?;
+    let _seq_parameter_set_id = bits.ue()?;
+    let entropy_coding_mode = bits.u(1)? == 1;
+    let bottom_field_pic_order_in_frame_present = bits.u(1)? == 1;
+
+    // Slice groups (FMO) are Extended profile only, which cameras don't use
+    if bits.ue()? != 0 {
+        return None;
+    }
+
+    let num_ref_idx_l0_default_active = num_ref_idx_active(&mut bits)?;
+    let num_ref_idx_l1_default_active = num_ref_idx_active(&mut bits)?;
+    let weighted_pred = bits.u(1)? == 1;
+    let weighted_bipred_idc = bits.u(2)?;
+    let pic_init_qp = qp(bits.se()?.checked_add(26)?)?;
+    let _pic_init_qs_minus26 = bits.se()?;
+    let _chroma_qp_index_offset = bits.se()?;
+    let _deblocking_filter_control_present = bits.u(1)?;
+    let _constrained_intra_pred = bits.u(1)?;
+    let redundant_pic_cnt_present = bits.u(1)? == 1;
+
+    Some(Pps {
+        pic_parameter_set_id,
+        pic_init_qp,
+        entropy_coding_mode,
+        bottom_field_pic_order_in_frame_present,
+        num_ref_idx_l0_default_active,
+        num_ref_idx_l1_default_active,
+        weighted_pred,
+        weighted_bipred_idc,
+        redundant_pic_cnt_present,
+    })
+}
+
+// Quantization parameter of a slice NAL unit (H.264 section
+// 7.3.3), 0 (best) to 51 (worst). Reading up to slice_qp_delta
+// needs most of the slice header, hence the SPS and PPS it uses
+pub fn slice_qp(nal: &[u8], sps: &Sps, pps: &Pps) -> Option<i32> {
+    let nal_type = *nal.first()? & 31;
+    if !matches!(nal_type, 1 | 5) {
+        return None;
+    }
+    let nal_ref_idc = (nal[0] >> 5) & 3;
+    let idr = nal_type == 5;
+
+    // Only the header is needed, which is well within the first bytes
+    let rbsp = remove_emulation_prevention(&nal[1..nal.len().min(64)]);
+    let mut bits = BitReader::new(&rbsp);
+
+    let _first_mb_in_slice = bits.ue()?;
+    // P 0, B 1, I 2, SP 3, SI 4, plus 5 when all slices are the same type
+    let slice_type = bits.ue()? % 5;
+    if bits.ue()? != pps.pic_parameter_set_id {
+        return None;
+    }
+    let _frame_num = bits.u(sps.log2_max_frame_num as usize)?;
+
+    let mut field_pic = false;
+    if !sps.frame_mbs_only {
+        field_pic = bits.u(1)? == 1;
+        if field_pic {
+            let _bottom_field = bits.u(1)?;
+        }
+    }
+    if idr {
+        let _idr_pic_id = bits.ue()?;
+    }
+    if sps.pic_order_cnt_type == 0 {
+        let _pic_order_cnt_lsb = bits.u(sps.log2_max_pic_order_cnt_lsb as usize)?;
+        if pps.bottom_field_pic_order_in_frame_present && !field_pic {
+            let _delta_pic_order_cnt_bottom = bits.se()?;
+        }
+    }
+    if sps.pic_order_cnt_type == 1 && !sps.delta_pic_order_always_zero {
+        let _delta_pic_order_cnt_0 = bits.se()?;
+        if pps.bottom_field_pic_order_in_frame_present && !field_pic {
+            let _delta_pic_order_cnt_1 = bits.se()?;
+        }
+    }
+    if pps.redundant_pic_cnt_present {
+        let _redundant_pic_cnt = bits.ue()?;
+    }
+
+    let (p, b) = (matches!(slice_type, 0 | 3), slice_type == 1);
+    if b {
+        let _direct_spatial_mv_pred = bits.u(1)?;
+    }
+
+    let mut num_ref_idx_l0 = pps.num_ref_idx_l0_default_active;
+    let mut num_ref_idx_l1 = pps.num_ref_idx_l1_default_active;
+    if p || b {
+        if bits.u(1)? == 1 {
+            num_ref_idx_l0 = num_ref_idx_active(&mut bits)?;
+            if b {
+                num_ref_idx_l1 = num_ref_idx_active(&mut bits)?;
+            }
+        }
+
+        // ref_pic_list_modification, for each list in use
+        for _ in 0..1 + b as usize {
+            if bits.u(1)? == 1 {
+                loop {
+                    match bits.ue()? {
+                        3 => break,
+                        0..=2 => {
+                            let _value = bits.ue()?;
+                        }
+                        // MVC and other extensions
+                        _ => return None,
+                    }
+                }
+            }
+        }
+    }
+
+    if (pps.weighted_pred && p) || (pps.weighted_bipred_idc == 1 && b) {
+        skip_pred_weight_table(&mut bits, sps, num_ref_idx_l0, b.then_some(num_ref_idx_l1))?;
+    }
+
+    // dec_ref_pic_marking
+    if nal_ref_idc != 0 {
+        if idr {
+            let _no_output_of_prior_pics = bits.u(1)?;
+            let _long_term_reference = bits.u(1)?;
+        } else if bits.u(1)? == 1 {
+            // memory_management_control_operation and its arguments
+            loop {
+                let arguments = match bits.ue()? {
+                    0 => break,
+                    1 | 2 | 4 | 6 => 1,
+                    3 => 2,
+                    5 => 0,
+                    _ => return None,
+                };
+                for _ in 0..arguments {
+                    bits.ue()?;
+                }
+            }
+        }
+    }
+
+    if pps.entropy_coding_mode && !matches!(slice_type, 2 | 4) {
+        let _cabac_init_idc = bits.ue()?;
+    }
+    let slice_qp_delta = bits.se()?;
+
+    qp((pps.pic_init_qp as i64).checked_add(slice_qp_delta)?)
+}
+
+// num_ref_idx_active_minus1 plus 1, at most 32 (field pictures)
+fn num_ref_idx_active(bits: &mut BitReader) -> Option<u32> {
+    bits.ue()?.checked_add(1).filter(|&count| count <= MAX_REF_IDX_ACTIVE)
+}
+
+// A QP in the 0 to 51 range of 8 bit video
+fn qp(value: i64) -> Option<i32> {
+    i32::try_from(value).ok().filter(|qp| (0..=MAX_QP).contains(qp))
+}
+
+fn parse_vui_framerate(bits: &mut BitReader) -> Option<f64> {
+    // aspect_ratio_info
+    if bits.u(1)? == 1 && bits.u(8)? == 255 {
+        let _sar_width = bits.u(16)?;
+        let _sar_height = bits.u(16)?;
+    }
+    // overscan_info
+    if bits.u(1)? == 1 {
+        let _overscan_appropriate = bits.u(1)?;
+    }
+    // video_signal_type
+    if bits.u(1)? == 1 {
+        let _video_format = bits.u(3)?;
+        let _video_full_range = bits.u(1)?;
+        if bits.u(1)? == 1 {
+            let _colour_primaries = bits.u(8)?;
+            let _transfer_characteristics = bits.u(8)?;
+            let _matrix_coefficients = bits.u(8)?;
+        }
+    }
+    // chroma_loc_info
+    if bits.u(1)? == 1 {
+        let _top = bits.ue()?;
+        let _bottom = bits.ue()?;
+    }
+    // timing_info
+    if bits.u(1)? == 0 {
+        return None;
+    }
+    let num_units_in_tick = bits.u(32)?;
+    let time_scale = bits.u(32)?;
+
+    if num_units_in_tick == 0 {
+        return None;
+    }
+
+    // Each frame is two ticks (fields)
+    Some(time_scale as f64 / (2.0 * num_units_in_tick as f64))
+}
+
+fn skip_pred_weight_table(bits: &mut BitReader, sps: &Sps, num_ref_idx_l0: u32, num_ref_idx_l1: Option<u32>) -> Option<()> {
+    let chroma = sps.chroma_array_type != 0;
+
+    let _luma_log2_weight_denom = bits.ue()?;
+    if chroma {
+        let _chroma_log2_weight_denom = bits.ue()?;
+    }
+
+    for _ in 0..num_ref_idx_l0.checked_add(num_ref_idx_l1.unwrap_or(0))? {
+        // Weight and offset for luma, then for both chroma planes
+        if bits.u(1)? == 1 {
+            bits.se()?;
+            bits.se()?;
+        }
+        if chroma && bits.u(1)? == 1 {
+            for _ in 0..4 {
+                bits.se()?;
+            }
+        }
+    }
+
+    Some(())
+}
+
+fn skip_scaling_list(bits: &mut BitReader, size: usize) -> Option<()> {
+    let mut last_scale = 8i64;
+    let mut next_scale = 8i64;
+
+    for _ in 0..size {
+        if next_scale != 0 {
+            let delta_scale = bits.se()?;
+            next_scale = (last_scale + delta_scale + 256) % 256;
+        }
+        if next_scale != 0 {
+            last_scale = next_scale;
+        }
+    }
+
+    Some(())
+}
+
+// 0x00 0x00 0x03 in a NAL unit is an escaped 0x00 0x00
+pub(crate) fn remove_emulation_prevention(data: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(data.len());
+    let mut zeros = 0;
+
+    for &byte in data {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+
+    rbsp
+}
+
+// Reads bits MSB first, including Exp-Golomb codes
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    pub(crate) fn u(&mut self, count: usize) -> Option<u32> {
+        let mut value = 0u32;
+
+        for _ in 0..count {
+            let byte = self.data.get(self.pos / 8)?;
+            let bit = (byte >> (7 - self.pos % 8)) & 1;
+            value = (value << 1) | bit as u32;
+            self.pos += 1;
+        }
+
+        Some(value)
+    }
+
+    // Unsigned Exp-Golomb
+    pub(crate) fn ue(&mut self) -> Option<u32> {
+        let mut leading_zeros = 0;
+        while self.u(1)? == 0 {
+            leading_zeros += 1;
+            if leading_zeros > 31 {
+                return None;
+            }
+        }
+
+        Some((1u32 << leading_zeros) - 1 + self.u(leading_zeros)?)
+    }
+
+    // Signed Exp-Golomb
+    pub(crate) fn se(&mut self) -> Option<i64> {
+        let value = self.ue()? as i64;
+
+        match value % 2 {
+            0 => Some(-(value / 2)),
+            _ => Some((value + 1) / 2),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Audit log of session lifecycle events, one JSON object per line
+// e.g. {"timestamp_ms":1697440000000,"server":"192.168.1.100:554","event":"response","method":"PLAY","status":200}
+//
+// Writes go to any std::io::Write (file, socket, Vec) so hosts can
+// send the journal wherever their compliance tooling expects it.
+pub struct Journal {
+    writer: Box<dyn Write + Send>,
+}
+
+pub enum Event<'a> {
+    Connect,
+    Reconnect { attempt: u32 },
+    Redirect { location: &'a str },
+    Request { method: &'a str, cseq: u32 },
+    Response { method: &'a str, status: u16 },
+    // Server answered 401, credentials are needed or were rejected
+    Unauthorized { method: &'a str },
+    Play,
+    Record,
+    Teardown { ok: bool },
+    Disconnect,
+}
+
+impl Journal {
+    pub fn new<W: Write + Send + 'static>(writer: W) -> Self {
+        Journal {
+            writer: Box::new(writer),
+        }
+    }
+
+    pub fn record(&mut self, server: SocketAddr, event: Event) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_millis())
+            .unwrap_or_default();
+
+        let details = match event {
+            Event::Connect => r#""event":"connect""#.to_string(),
+            Event::Reconnect { attempt } => format!(r#""event":"reconnect","attempt":{}"#, attempt),
+            Event::Redirect { location } => {
+                format!(r#""event":"redirect","location":"{}""#, escape(location))
+            }
+            Event::Request { method, cseq } => {
+                format!(r#""event":"request","method":"{}","cseq":{}"#, escape(method), cseq)
+            }
+            Event::Response { method, status } => {
+                format!(r#""event":"response","method":"{}","status":{}"#, escape(method), status)
+            }
+            Event::Unauthorized { method } => {
+                format!(r#""event":"unauthorized","method":"{}""#, escape(method))
+            }
+            Event::Play => r#""event":"play""#.to_string(),
+            Event::Record => r#""event":"record""#.to_string(),
+            Event::Teardown { ok } => format!(r#""event":"teardown","ok":{}"#, ok),
+            Event::Disconnect => r#""event":"disconnect""#.to_string(),
+        };
+
+        let line = format!(
+            "{{\"timestamp_ms\":{},\"server\":\"{}\",{}}}\n",
+            timestamp_ms, server, details
+        );
+
+        // Journal must never take the session down with it
+        if let Err(e) = self.writer.write_all(line.as_bytes()) {
+            log::warn!("[Journal] Unable to write event: {e}");
+        }
+        let _ = self.writer.flush();
+    }
+}
+
+// Minimal JSON string escaping
+pub(crate) fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
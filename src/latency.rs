@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+// Presets of the settings that trade smoothness for latency, so
+// getting glass-to-glass latency down doesn't take finding each one
+//
+// let rtsp = Rtsp::builder(url).latency_profile(LatencyProfile::UltraLow).connect().await?;
+// ...
+// rtp.set_latency_profile(LatencyProfile::UltraLow);
+//
+// There is no decoder setting to go with them: OpenH264 already
+// returns each frame as soon as its access unit is decoded, without
+// reordering delay (DecodeFrameNoDelay)
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LatencyProfile {
+    // Nothing changed from the defaults
+    #[default]
+    Default,
+    // Every frame shown as soon as it can be, dropping what is late
+    // rather than catching up on it, e.g. for sub 200ms remote control
+    UltraLow,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LatencySettings {
+    // TCP_NODELAY on the RTSP connection, see RtspBuilder::nodelay
+    pub nodelay: bool,
+    // How long packets wait for a retransmission, see
+    // Rtp::set_retransmission. None hands them on as they come
+    pub retransmission_hold: Option<Duration>,
+    // See Rtp::set_decode_budget
+    pub decode_budget: Option<Duration>,
+    // With several access units pending decode only from the last
+    // keyframe among them, see Rtp::set_skip_backlog
+    pub skip_backlog: bool,
+    // Ask for a keyframe (PLI) as soon as the stream starts instead
+    // of waiting for the next GOP, see Rtp::request_keyframe
+    pub request_keyframe_on_join: bool,
+}
+
+impl LatencyProfile {
+    pub fn settings(self) -> LatencySettings {
+        match self {
+            LatencyProfile::Default => LatencySettings {
+                nodelay: false,
+                retransmission_hold: None,
+                decode_budget: None,
+                skip_backlog: false,
+                request_keyframe_on_join: false,
+            },
+            LatencyProfile::UltraLow => LatencySettings {
+                nodelay: true,
+                retransmission_hold: None,
+                // A 30 fps frame interval, slower than that falls behind
+                decode_budget: Some(Duration::from_millis(33)),
+                skip_backlog: true,
+                request_keyframe_on_join: true,
+            },
+        }
+    }
+}
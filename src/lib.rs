@@ -4,5 +4,59 @@ See the github repo example for usage details.
 
 */
 
+pub mod aac;
+pub mod bandwidth;
+pub mod budget;
+pub mod capture;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(feature = "decode-openh264")]
+pub mod client;
+pub mod codec;
+pub mod compose;
+pub mod conformance;
+#[cfg(feature = "decode-openh264")]
+pub mod degrade;
+pub mod depacketizer;
+pub mod error;
+pub mod frame;
+pub mod g711;
+#[cfg(feature = "wgpu")]
+pub mod gpu;
+pub mod h264;
+pub mod journal;
+pub mod latency;
+pub mod mask;
+#[cfg(feature = "mux-mp4")]
+pub mod mp4;
+#[cfg(feature = "mpegts")]
+pub mod mpegts;
+#[cfg(feature = "decode-openh264")]
+pub mod multitrack;
+#[cfg(feature = "decode-openh264")]
+pub mod ondemand;
+pub mod opus;
+pub mod osd;
+pub mod packetizer;
+pub mod ports;
+pub mod probe;
+pub mod proxy;
+pub mod quality;
+pub mod recording;
+pub mod redundancy;
+pub mod replay;
+pub mod resolver;
+pub mod response;
+pub mod retransmit;
+pub mod retention;
+pub mod rtcp;
 pub mod rtp;
 pub mod rtsp;
+pub mod sdp;
+#[cfg(feature = "srtp")]
+pub mod srtp;
+pub mod stats;
+pub mod storage;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod timeshift;
@@ -0,0 +1,143 @@
+use crate::frame::Frame;
+
+// Privacy zones applied to decoded frames
+// Coordinates are in pixels of the full size (luma) picture
+
+pub enum Shape {
+    Rect {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    },
+    // Points of a closed polygon, in order
+    Polygon(Vec<(usize, usize)>),
+}
+
+pub enum Fill {
+    // Solid black
+    Black,
+    // Blocks of the given size (in luma pixels) replaced
+    // by their average value
+    Pixelate(usize),
+}
+
+pub struct PrivacyMask {
+    pub shape: Shape,
+    pub fill: Fill,
+}
+
+impl PrivacyMask {
+    pub fn new(shape: Shape, fill: Fill) -> Self {
+        PrivacyMask { shape, fill }
+    }
+
+    pub fn apply(&self, frame: &mut Frame) {
+        let (width, height) = (frame.width, frame.height);
+        let (chroma_width, chroma_height) = (frame.chroma_width(), frame.chroma_height());
+
+        // Black in video range YUV is Y = 16, U = V = 128
+        self.apply_plane(&mut frame.y, width, height, 1, 16);
+        self.apply_plane(&mut frame.u, chroma_width, chroma_height, 2, 128);
+        self.apply_plane(&mut frame.v, chroma_width, chroma_height, 2, 128);
+    }
+
+    // 'scale' is how many luma pixels one pixel of this plane covers
+    fn apply_plane(&self, plane: &mut [u8], width: usize, height: usize, scale: usize, black: u8) {
+        let (x0, y0, x1, y1) = self.bounds();
+        let x0 = (x0 / scale).min(width);
+        let y0 = (y0 / scale).min(height);
+        let x1 = x1.div_ceil(scale).min(width);
+        let y1 = y1.div_ceil(scale).min(height);
+
+        match self.fill {
+            Fill::Black => {
+                for py in y0..y1 {
+                    for px in x0..x1 {
+                        if self.contains_scaled(px, py, scale) {
+                            plane[py * width + px] = black;
+                        }
+                    }
+                }
+            }
+            Fill::Pixelate(block) => {
+                let block = (block / scale).max(1);
+
+                // Walk the block grid covering the bounding box
+                let mut by = y0 - y0 % block;
+                while by < y1 {
+                    let mut bx = x0 - x0 % block;
+                    while bx < x1 {
+                        let bx_end = (bx + block).min(width);
+                        let by_end = (by + block).min(height);
+
+                        let mut sum = 0usize;
+                        for py in by..by_end {
+                            for px in bx..bx_end {
+                                sum += plane[py * width + px] as usize;
+                            }
+                        }
+                        let average = (sum / ((bx_end - bx) * (by_end - by))) as u8;
+
+                        for py in by..by_end {
+                            for px in bx..bx_end {
+                                if self.contains_scaled(px, py, scale) {
+                                    plane[py * width + px] = average;
+                                }
+                            }
+                        }
+
+                        bx += block;
+                    }
+                    by += block;
+                }
+            }
+        }
+    }
+
+    // Bounding box in luma pixels -> (x0, y0, x1, y1) with x1/y1 exclusive
+    fn bounds(&self) -> (usize, usize, usize, usize) {
+        match &self.shape {
+            Shape::Rect { x, y, width, height } => (*x, *y, x + width, y + height),
+            Shape::Polygon(points) => {
+                let x0 = points.iter().map(|p| p.0).min().unwrap_or(0);
+                let y0 = points.iter().map(|p| p.1).min().unwrap_or(0);
+                let x1 = points.iter().map(|p| p.0 + 1).max().unwrap_or(0);
+                let y1 = points.iter().map(|p| p.1 + 1).max().unwrap_or(0);
+                (x0, y0, x1, y1)
+            }
+        }
+    }
+
+    // Test the centre of a plane pixel against the shape in luma coordinates
+    fn contains_scaled(&self, px: usize, py: usize, scale: usize) -> bool {
+        let x = (px * scale) as f32 + scale as f32 / 2.0;
+        let y = (py * scale) as f32 + scale as f32 / 2.0;
+
+        match &self.shape {
+            Shape::Rect { x: rx, y: ry, width, height } => {
+                x >= *rx as f32
+                    && x < (rx + width) as f32
+                    && y >= *ry as f32
+                    && y < (ry + height) as f32
+            }
+            // Even-odd rule ray casting
+            Shape::Polygon(points) => {
+                let mut inside = false;
+                let mut j = points.len().wrapping_sub(1);
+
+                for i in 0..points.len() {
+                    let (xi, yi) = (points[i].0 as f32, points[i].1 as f32);
+                    let (xj, yj) = (points[j].0 as f32, points[j].1 as f32);
+
+                    if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+                        inside = !inside;
+                    }
+                    j = i;
+                }
+
+                inside
+            }
+        }
+    }
+}
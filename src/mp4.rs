@@ -0,0 +1,557 @@
+use crate::depacketizer::Depacketizer;
+use crate::h264;
+use crate::replay::Replay;
+use crate::storage::{self, StorageBackend};
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+// RTP clock for H264 is also used as the MP4 media timescale
+// so sample timestamps can be used as they are
+const TIMESCALE_MEDIA: u32 = 90000;
+const TIMESCALE_MOVIE: u32 = 1000;
+
+// Duration of the last sample when there is nothing after it
+const DEFAULT_SAMPLE_DURATION: u32 = TIMESCALE_MEDIA / 30;
+
+// One access unit (Annex B) and its RTP timestamp (90kHz)
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub timestamp: u32,
+    pub data: Vec<u8>,
+}
+
+// Text shown for a while alongside the video, e.g. ONVIF/KLV metadata
+// or detection results, muxed as a subtitle track (3GPP timed text)
+// so players show it in sync with the picture
+#[derive(Clone, Debug)]
+pub struct Cue {
+    pub offset: Duration,
+    pub duration: Duration,
+    pub text: String,
+}
+
+// Write the part of a capture between 'start' and 'end' (offsets
+// from the first packet) to a standalone MP4 file
+//
+// Decoding has to begin at a keyframe, so the clip starts at the
+// last IDR at or before 'start' and an edit list hides the frames
+// before 'start', keeping the clip frame accurate.
+pub async fn export_clip<P: AsRef<Path>>(replay: &Replay, start: Duration, end: Duration, path: P) -> io::Result<()> {
+    export_clip_with_metadata(replay, start, end, &[], path).await
+}
+
+// export_clip with a subtitle track of 'cues', their offsets being
+// from the first packet of the capture like 'start' and 'end'
+pub async fn export_clip_with_metadata<P: AsRef<Path>>(
+    replay: &Replay,
+    start: Duration,
+    end: Duration,
+    cues: &[Cue],
+    path: P,
+) -> io::Result<()> {
+    tokio::fs::write(path, clip(replay, start, end, cues)?).await
+}
+
+// export_clip_with_metadata to a storage backend instead of a file
+pub async fn export_clip_to(
+    replay: &Replay,
+    start: Duration,
+    end: Duration,
+    cues: &[Cue],
+    storage: &dyn StorageBackend,
+    key: &str,
+) -> io::Result<()> {
+    storage::store(storage, key, &clip(replay, start, end, cues)?).await
+}
+
+// The MP4 file export_clip_with_metadata writes
+pub fn clip(replay: &Replay, start: Duration, end: Duration, cues: &[Cue]) -> io::Result<Vec<u8>> {
+    let units = access_units(replay);
+
+    let first = units
+        .iter()
+        .position(|(offset, _)| *offset >= start)
+        .ok_or_else(|| invalid_input("no frames after start of clip"))?;
+    let keyframe = units[..=first]
+        .iter()
+        .rposition(|(_, sample)| h264::is_keyframe(&sample.data))
+        .ok_or_else(|| invalid_input("no keyframe before start of clip"))?;
+
+    let samples: Vec<Sample> = units[keyframe..]
+        .iter()
+        .take_while(|(offset, _)| *offset < end)
+        .map(|(_, sample)| sample.clone())
+        .collect();
+
+    let skip = units[first].1.timestamp.wrapping_sub(units[keyframe].1.timestamp);
+
+    // Cues are timed from the first sample in the file
+    let clip_start = units[keyframe].0;
+    let cues: Vec<Cue> = cues
+        .iter()
+        .filter(|cue| cue.offset + cue.duration > clip_start)
+        .map(|cue| Cue {
+            offset: cue.offset.saturating_sub(clip_start),
+            duration: (cue.offset + cue.duration) - cue.offset.max(clip_start),
+            text: cue.text.clone(),
+        })
+        .collect();
+
+    mux_with_metadata(&samples, skip, &cues)
+}
+
+// Group the packets of a capture into access units using the RTP
+// timestamp (every packet of a frame shares it), along with the
+// offset of the first packet of each
+pub fn access_units(replay: &Replay) -> Vec<(Duration, Sample)> {
+    let mut depacketizer = Depacketizer::new();
+    let mut units = Vec::new();
+    let mut current: Option<(Duration, u32)> = None;
+
+    for (offset, packet) in replay.packets() {
+        if packet.len() < 12 {
+            continue;
+        }
+
+        let timestamp = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        if current.map(|(_, current)| current) != Some(timestamp) {
+            flush(&mut depacketizer, current, &mut units);
+            current = Some((offset, timestamp));
+        }
+
+        depacketizer.push_rtp_packet(packet);
+    }
+    flush(&mut depacketizer, current, &mut units);
+
+    units
+}
+
+// Finish the access unit being gathered, if there is one
+fn flush(depacketizer: &mut Depacketizer, current: Option<(Duration, u32)>, units: &mut Vec<(Duration, Sample)>) {
+    depacketizer.end_access_unit();
+    // More than one when an AUD split the timestamp
+    while let Some(data) = depacketizer.pending() {
+        if let Some((offset, timestamp)) = current {
+            units.push((offset, Sample { timestamp, data: data.to_vec() }));
+        }
+        depacketizer.clear();
+    }
+}
+
+// Mux access units into an MP4 file held in memory, 'skip' is how
+// much (90kHz units) at the start is hidden by the edit list
+pub fn mux(samples: &[Sample], skip: u32) -> io::Result<Vec<u8>> {
+    mux_with_metadata(samples, skip, &[])
+}
+
+// mux with a subtitle track of 'cues' (offsets from the first sample),
+// none is added if there are no cues. Overlapping cues are cut short
+// where the next one starts
+pub fn mux_with_metadata(samples: &[Sample], skip: u32, cues: &[Cue]) -> io::Result<Vec<u8>> {
+    // Parameter sets go in the avcC box, not the samples
+    let mut sps = None;
+    let mut pps = None;
+    let mut sizes = Vec::with_capacity(samples.len());
+    let mut keyframes = Vec::new();
+    let mut mdat = Vec::new();
+
+    for (i, sample) in samples.iter().enumerate() {
+        let start = mdat.len();
+
+        for nal in h264::nal_units(&sample.data) {
+            match nal[0] & 31 {
+                7 => sps = sps.or(Some(nal)),
+                8 => pps = pps.or(Some(nal)),
+                // Length prefixed instead of start codes
+                _ => {
+                    mdat.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    mdat.extend_from_slice(nal);
+                }
+            }
+        }
+
+        if h264::is_keyframe(&sample.data) {
+            keyframes.push(i as u32 + 1);
+        }
+        sizes.push((mdat.len() - start) as u32);
+    }
+
+    let sps = sps.ok_or_else(|| invalid_input("no SPS in clip"))?;
+    let pps = pps.ok_or_else(|| invalid_input("no PPS in clip"))?;
+    let info = h264::parse_sps(sps).ok_or_else(|| invalid_input("invalid SPS in clip"))?;
+
+    // Each sample lasts until the next one starts
+    let mut durations: Vec<u32> = samples
+        .windows(2)
+        .map(|pair| pair[1].timestamp.wrapping_sub(pair[0].timestamp))
+        .collect();
+    durations.push(durations.last().copied().unwrap_or(DEFAULT_SAMPLE_DURATION));
+
+    let (Ok(width), Ok(height)) = (u16::try_from(info.width), u16::try_from(info.height)) else {
+        return Err(invalid_input("picture size does not fit an MP4 track"));
+    };
+
+    let track = Track {
+        width,
+        height,
+        sps,
+        pps,
+        durations: &durations,
+        sizes: &sizes,
+        keyframes: &keyframes,
+        skip,
+    };
+
+    let media_duration: u64 = durations.iter().map(|&duration| duration as u64).sum();
+    let text = match cues.is_empty() {
+        true => None,
+        false => Some(TextTrack::new(cues, media_duration)?),
+    };
+
+    let ftyp = mp4_box(b"ftyp", &[b"isom".as_slice(), &0x200u32.to_be_bytes(), b"isomiso2avc1mp41"].concat());
+
+    // stco needs to know where mdat starts, which depends on the
+    // size of moov, which doesn't depend on the offset value
+    let moov_len = track.moov(text.as_ref(), 0).len();
+    let mdat_offset = (ftyp.len() + moov_len + 8) as u32;
+    let moov = track.moov(text.as_ref(), mdat_offset);
+
+    // Text samples go after the video in the same mdat
+    if let Some(text) = &text {
+        mdat.extend_from_slice(&text.data);
+    }
+
+    let mut mp4 = Vec::with_capacity(ftyp.len() + moov.len() + mdat.len() + 8);
+    mp4.extend_from_slice(&ftyp);
+    mp4.extend_from_slice(&moov);
+    mp4.extend_from_slice(&mp4_box(b"mdat", &mdat));
+
+    Ok(mp4)
+}
+
+struct Track<'a> {
+    width: u16,
+    height: u16,
+    sps: &'a [u8],
+    pps: &'a [u8],
+    durations: &'a [u32],
+    sizes: &'a [u32],
+    keyframes: &'a [u32],
+    skip: u32,
+}
+
+impl Track<'_> {
+    // All video samples in a single chunk starting at 'mdat_offset',
+    // the text samples in a second chunk right after it
+    #[rustfmt::skip]
+    fn moov(&self, text: Option<&TextTrack>, mdat_offset: u32) -> Vec<u8> {
+        let media_duration: u64 = self.durations.iter().map(|&duration| duration as u64).sum();
+        let shown = media_duration.saturating_sub(self.skip as u64);
+        let movie_duration = (shown * TIMESCALE_MOVIE as u64 / TIMESCALE_MEDIA as u64) as u32;
+        let next_track_id: u32 = match text {
+            Some(_) => 3,
+            None => 2,
+        };
+
+        let mvhd = full_box(b"mvhd", 0, &[
+            &[0u8; 8][..],                        // creation, modification time
+            &TIMESCALE_MOVIE.to_be_bytes(),
+            &movie_duration.to_be_bytes(),
+            &0x00010000u32.to_be_bytes(),         // rate 1.0
+            &0x0100u16.to_be_bytes(),             // volume 1.0
+            &[0u8; 10],                           // reserved
+            &MATRIX,
+            &[0u8; 24],                           // pre_defined
+            &next_track_id.to_be_bytes(),
+        ].concat());
+
+        let tkhd = full_box(b"tkhd", 0x000003, &[
+            &[0u8; 8][..],                        // creation, modification time
+            &1u32.to_be_bytes(),                  // track ID
+            &[0u8; 4],                            // reserved
+            &movie_duration.to_be_bytes(),
+            &[0u8; 8],                            // reserved
+            &[0u8; 4],                            // layer, alternate group
+            &[0u8; 4],                            // volume, reserved
+            &MATRIX,
+            &((self.width as u32) << 16).to_be_bytes(),
+            &((self.height as u32) << 16).to_be_bytes(),
+        ].concat());
+
+        // Hide the frames between the keyframe and the clip start
+        let elst = full_box(b"elst", 0, &[
+            &1u32.to_be_bytes()[..],
+            &movie_duration.to_be_bytes(),
+            &self.skip.to_be_bytes(),             // media time
+            &0x00010000u32.to_be_bytes(),         // rate 1.0
+        ].concat());
+        let edts = mp4_box(b"edts", &elst);
+
+        let mdhd = full_box(b"mdhd", 0, &[
+            &[0u8; 8][..],
+            &TIMESCALE_MEDIA.to_be_bytes(),
+            &(media_duration as u32).to_be_bytes(),
+            &0x55c4u16.to_be_bytes(),             // language 'und'
+            &[0u8; 2],
+        ].concat());
+
+        let hdlr = full_box(b"hdlr", 0, &[
+            &[0u8; 4][..],
+            b"vide",
+            &[0u8; 12],
+            b"VideoHandler\0",
+        ].concat());
+
+        let vmhd = full_box(b"vmhd", 1, &[0u8; 8]);
+        let dref = full_box(b"dref", 0, &[&1u32.to_be_bytes()[..], &full_box(b"url ", 1, &[])].concat());
+        let dinf = mp4_box(b"dinf", &dref);
+
+        let stbl = mp4_box(b"stbl", &[
+            self.stsd(),
+            stts(self.durations),
+            full_box(b"stss", 0, &u32_table(self.keyframes)),
+            // 1 entry: from chunk 1, every sample in the chunk, description 1
+            full_box(b"stsc", 0, &u32_bytes(&[1, 1, self.sizes.len() as u32, 1])),
+            full_box(b"stsz", 0, &[&0u32.to_be_bytes()[..], &u32_table(self.sizes)].concat()),
+            full_box(b"stco", 0, &u32_table(&[mdat_offset])),
+        ].concat());
+
+        let minf = mp4_box(b"minf", &[vmhd, dinf, stbl].concat());
+        let mdia = mp4_box(b"mdia", &[mdhd, hdlr, minf].concat());
+        let text_offset = mdat_offset + self.sizes.iter().sum::<u32>();
+        let text_trak = match text {
+            Some(text) => text.trak(self, movie_duration, &edts, text_offset),
+            None => Vec::new(),
+        };
+
+        let trak = mp4_box(b"trak", &[tkhd, edts, mdia].concat());
+
+        mp4_box(b"moov", &[mvhd, trak, text_trak].concat())
+    }
+
+    #[rustfmt::skip]
+    fn stsd(&self) -> Vec<u8> {
+        // AVCDecoderConfigurationRecord
+        let avcc = mp4_box(b"avcC", &[
+            &[1, self.sps[1], self.sps[2], self.sps[3], 0xff, 0xe1][..], // 4 byte NAL lengths, 1 SPS
+            &(self.sps.len() as u16).to_be_bytes(),
+            self.sps,
+            &[1],                                 // 1 PPS
+            &(self.pps.len() as u16).to_be_bytes(),
+            self.pps,
+        ].concat());
+
+        let avc1 = mp4_box(b"avc1", &[
+            &[0u8; 6][..],                        // reserved
+            &1u16.to_be_bytes(),                  // data reference index
+            &[0u8; 16],                           // pre_defined, reserved
+            &self.width.to_be_bytes(),
+            &self.height.to_be_bytes(),
+            &0x00480000u32.to_be_bytes(),         // 72 dpi
+            &0x00480000u32.to_be_bytes(),
+            &[0u8; 4],                            // reserved
+            &1u16.to_be_bytes(),                  // frame count
+            &[0u8; 32],                           // compressor name
+            &0x0018u16.to_be_bytes(),             // depth
+            &(-1i16).to_be_bytes(),               // pre_defined
+            &avcc,
+        ].concat());
+
+        full_box(b"stsd", 0, &[&1u32.to_be_bytes()[..], &avc1].concat())
+    }
+}
+
+// 3GPP timed text (TS 26.245) subtitle track, on the same 90kHz
+// timeline as the video with gaps between cues filled by empty samples
+struct TextTrack {
+    durations: Vec<u32>,
+    sizes: Vec<u32>,
+    data: Vec<u8>,
+}
+
+impl TextTrack {
+    fn new(cues: &[Cue], media_duration: u64) -> io::Result<Self> {
+        let mut cues: Vec<&Cue> = cues.iter().collect();
+        cues.sort_by_key(|cue| cue.offset);
+
+        let mut text = TextTrack { durations: Vec::new(), sizes: Vec::new(), data: Vec::new() };
+        let mut position = 0u64;
+
+        for (i, cue) in cues.iter().enumerate() {
+            let start = ticks(cue.offset).max(position).min(media_duration);
+            let mut end = ticks(cue.offset + cue.duration).min(media_duration);
+            if let Some(next) = cues.get(i + 1) {
+                end = end.min(ticks(next.offset));
+            }
+            if end <= start {
+                continue;
+            }
+
+            text.push(&[], start - position)?;
+            text.push(cue.text.as_bytes(), end - start)?;
+            position = end;
+        }
+        text.push(&[], media_duration - position)?;
+
+        Ok(text)
+    }
+
+    // Sample of 'duration' (90kHz), skipped if empty
+    fn push(&mut self, text: &[u8], duration: u64) -> io::Result<()> {
+        if duration == 0 {
+            return Ok(());
+        }
+        let len = u16::try_from(text.len()).map_err(|_| invalid_input("cue text too long"))?;
+
+        self.data.extend_from_slice(&len.to_be_bytes());
+        self.data.extend_from_slice(text);
+        self.sizes.push(2 + len as u32);
+        self.durations.push(duration as u32);
+        Ok(())
+    }
+
+    // Track 2, shown over the video (layer -1) and cut by the same
+    // edit list so it stays in sync
+    #[rustfmt::skip]
+    fn trak(&self, video: &Track, movie_duration: u32, edts: &[u8], chunk_offset: u32) -> Vec<u8> {
+        let media_duration: u64 = self.durations.iter().map(|&duration| duration as u64).sum();
+
+        let tkhd = full_box(b"tkhd", 0x000003, &[
+            &[0u8; 8][..],                        // creation, modification time
+            &2u32.to_be_bytes(),                  // track ID
+            &[0u8; 4],                            // reserved
+            &movie_duration.to_be_bytes(),
+            &[0u8; 8],                            // reserved
+            &(-1i16).to_be_bytes(),               // layer, in front of the video
+            &[0u8; 2],                            // alternate group
+            &[0u8; 4],                            // volume, reserved
+            &MATRIX,
+            &((video.width as u32) << 16).to_be_bytes(),
+            &((video.height as u32) << 16).to_be_bytes(),
+        ].concat());
+
+        let mdhd = full_box(b"mdhd", 0, &[
+            &[0u8; 8][..],
+            &TIMESCALE_MEDIA.to_be_bytes(),
+            &(media_duration as u32).to_be_bytes(),
+            &0x55c4u16.to_be_bytes(),             // language 'und'
+            &[0u8; 2],
+        ].concat());
+
+        let hdlr = full_box(b"hdlr", 0, &[
+            &[0u8; 4][..],
+            b"sbtl",
+            &[0u8; 12],
+            b"SubtitleHandler\0",
+        ].concat());
+
+        let nmhd = full_box(b"nmhd", 0, &[]);
+        let dref = full_box(b"dref", 0, &[&1u32.to_be_bytes()[..], &full_box(b"url ", 1, &[])].concat());
+        let dinf = mp4_box(b"dinf", &dref);
+
+        let stbl = mp4_box(b"stbl", &[
+            self.stsd(video),
+            stts(&self.durations),
+            full_box(b"stsc", 0, &u32_bytes(&[1, 1, self.sizes.len() as u32, 1])),
+            full_box(b"stsz", 0, &[&0u32.to_be_bytes()[..], &u32_table(&self.sizes)].concat()),
+            full_box(b"stco", 0, &u32_table(&[chunk_offset])),
+        ].concat());
+
+        let minf = mp4_box(b"minf", &[nmhd, dinf, stbl].concat());
+        let mdia = mp4_box(b"mdia", &[mdhd, hdlr, minf].concat());
+
+        mp4_box(b"trak", &[&tkhd, edts, &mdia].concat())
+    }
+
+    // White text, centred at the bottom of the picture
+    #[rustfmt::skip]
+    fn stsd(&self, video: &Track) -> Vec<u8> {
+        let font_size = (video.height / 18).clamp(12, 255) as u8;
+
+        let ftab = mp4_box(b"ftab", &[
+            &1u16.to_be_bytes()[..],              // 1 font
+            &1u16.to_be_bytes(),                  // font ID
+            &[5],
+            b"Serif",
+        ].concat());
+
+        let tx3g = mp4_box(b"tx3g", &[
+            &[0u8; 6][..],                        // reserved
+            &1u16.to_be_bytes(),                  // data reference index
+            &0u32.to_be_bytes(),                  // display flags
+            &[1, 0xff],                           // centred, at the bottom
+            &[0, 0, 0, 0],                        // transparent background
+            &0u16.to_be_bytes(),                  // text box top
+            &0u16.to_be_bytes(),                  // left
+            &video.height.to_be_bytes(),          // bottom
+            &video.width.to_be_bytes(),           // right
+            &0u16.to_be_bytes(),                  // style start char
+            &0u16.to_be_bytes(),                  // end char
+            &1u16.to_be_bytes(),                  // font ID
+            &[0, font_size],                      // plain
+            &[0xff, 0xff, 0xff, 0xff],            // white
+            &ftab,
+        ].concat());
+
+        full_box(b"stsd", 0, &[&1u32.to_be_bytes()[..], &tx3g].concat())
+    }
+}
+
+// Duration in 90kHz units
+fn ticks(duration: Duration) -> u64 {
+    (duration.as_micros() * TIMESCALE_MEDIA as u128 / 1_000_000) as u64
+}
+
+// Runs of equal sample durations
+fn stts(durations: &[u32]) -> Vec<u8> {
+    let mut runs: Vec<(u32, u32)> = Vec::new();
+
+    for &duration in durations {
+        match runs.last_mut() {
+            Some((count, last)) if *last == duration => *count += 1,
+            _ => runs.push((1, duration)),
+        }
+    }
+
+    let mut content = (runs.len() as u32).to_be_bytes().to_vec();
+    for (count, duration) in runs {
+        content.extend_from_slice(&count.to_be_bytes());
+        content.extend_from_slice(&duration.to_be_bytes());
+    }
+
+    full_box(b"stts", 0, &content)
+}
+
+// Unity transformation matrix used by mvhd and tkhd
+const MATRIX: [u8; 36] = [
+    0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0x40, 0, 0, 0,
+];
+
+fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut mp4_box = Vec::with_capacity(content.len() + 8);
+    mp4_box.extend_from_slice(&(content.len() as u32 + 8).to_be_bytes());
+    mp4_box.extend_from_slice(kind);
+    mp4_box.extend_from_slice(content);
+    mp4_box
+}
+
+// Box starting with version 0 and 24 bits of flags
+fn full_box(kind: &[u8; 4], flags: u32, content: &[u8]) -> Vec<u8> {
+    mp4_box(kind, &[&(flags & 0x00ffffff).to_be_bytes()[..], content].concat())
+}
+
+fn u32_bytes(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|value| value.to_be_bytes()).collect()
+}
+
+// Entry count followed by the entries
+fn u32_table(entries: &[u32]) -> Vec<u8> {
+    [(entries.len() as u32).to_be_bytes().to_vec(), u32_bytes(entries)].concat()
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
@@ -0,0 +1,207 @@
+use log::{debug, trace};
+
+// Static RTP payload type for MPEG2 transport streams (RFC 3551)
+pub const PAYLOAD_TYPE_MP2T: u8 = 33;
+
+const TS_PACKET_SIZE: usize = 188;
+const TS_SYNC_BYTE: u8 = 0x47;
+
+// Program Association Table is always on PID 0
+const PID_PAT: u16 = 0;
+
+// PMT stream_type for H264 video
+const STREAM_TYPE_H264: u8 = 0x1b;
+
+// Pulls H264 out of an MPEG transport stream (MP2T over RTP, RFC 2250)
+//
+// The PAT gives the PID of the PMT, the PMT gives the PID of the
+// H264 stream and its packets carry PES packets, each of which is
+// normally one access unit in Annex B format (with start codes).
+// Other streams (e.g. audio) are skipped.
+pub struct TsDemuxer {
+    pmt_pid: Option<u16>,
+    video_pid: Option<u16>,
+    pes: Vec<u8>,
+    pes_length: Option<usize>, // from the PES header, None if unbounded
+}
+
+impl TsDemuxer {
+    pub fn new() -> Self {
+        TsDemuxer {
+            pmt_pid: None,
+            video_pid: None,
+            pes: Vec::new(),
+            pes_length: None,
+        }
+    }
+
+    // PID carrying H264, once the PMT has been seen
+    pub fn video_pid(&self) -> Option<u16> {
+        self.video_pid
+    }
+
+    // Push the payload of an RTP packet (a whole number of 188 byte
+    // TS packets). Returns the H264 data of each PES packet completed
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut completed = Vec::new();
+
+        for packet in data.chunks_exact(TS_PACKET_SIZE) {
+            if packet[0] != TS_SYNC_BYTE {
+                debug!("[TS] Lost sync, dropping packet");
+                continue;
+            }
+
+            // Header
+            // sync(8) TEI(1) PUSI(1) priority(1) PID(13)
+            // scrambling(2) adaptation field control(2) continuity(4)
+            let payload_start = packet[1] & 0x40 != 0;
+            let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+            let adaptation = (packet[3] >> 4) & 0b11;
+
+            let offset = match adaptation {
+                0b01 => 4,
+                0b11 => 5 + packet[4] as usize,
+                // Adaptation field only, or reserved
+                _ => continue,
+            };
+            if offset >= TS_PACKET_SIZE {
+                continue;
+            }
+            let payload = &packet[offset..];
+
+            if pid == PID_PAT {
+                self.parse_pat(payload, payload_start);
+            } else if Some(pid) == self.pmt_pid {
+                self.parse_pmt(payload, payload_start);
+            } else if Some(pid) == self.video_pid {
+                if let Some(es) = self.push_pes(payload, payload_start) {
+                    completed.push(es);
+                }
+            }
+        }
+
+        completed
+    }
+
+    // Start of a PSI section, skipping the pointer field
+    fn section(payload: &[u8], payload_start: bool) -> Option<&[u8]> {
+        // Sections spanning several TS packets aren't needed
+        // for the small tables used here
+        if !payload_start {
+            return None;
+        }
+
+        let pointer = *payload.first()? as usize;
+        let section = payload.get(1 + pointer..)?;
+
+        // table_id(8) flags(4) section_length(12)
+        let section_length = (u16::from_be_bytes([*section.get(1)?, *section.get(2)?]) & 0x0fff) as usize;
+
+        // Leave out the CRC at the end
+        section.get(..(3 + section_length).checked_sub(4)?)
+    }
+
+    fn parse_pat(&mut self, payload: &[u8], payload_start: bool) {
+        let section = match Self::section(payload, payload_start) {
+            Some(section) => section,
+            None => return,
+        };
+
+        // Entries follow the 8 byte header, each is
+        // program_number(16) reserved(3) PID(13)
+        for entry in section.get(8..).unwrap_or_default().chunks_exact(4) {
+            let program_number = u16::from_be_bytes([entry[0], entry[1]]);
+            // Program 0 is the network PID, not a program
+            if program_number != 0 {
+                let pid = u16::from_be_bytes([entry[2] & 0x1f, entry[3]]);
+                if self.pmt_pid != Some(pid) {
+                    trace!("[TS] PMT on PID {pid}");
+                    self.pmt_pid = Some(pid);
+                }
+                break;
+            }
+        }
+    }
+
+    fn parse_pmt(&mut self, payload: &[u8], payload_start: bool) {
+        let section = match Self::section(payload, payload_start) {
+            Some(section) => section,
+            None => return,
+        };
+        if section.len() < 12 {
+            return;
+        }
+
+        let program_info_length = (u16::from_be_bytes([section[10], section[11]]) & 0x0fff) as usize;
+        let mut streams = section.get(12 + program_info_length..).unwrap_or_default();
+
+        // stream_type(8) reserved(3) PID(13) reserved(4) ES_info_length(12)
+        while streams.len() >= 5 {
+            let stream_type = streams[0];
+            let pid = u16::from_be_bytes([streams[1] & 0x1f, streams[2]]);
+            let es_info_length = (u16::from_be_bytes([streams[3], streams[4]]) & 0x0fff) as usize;
+
+            if stream_type == STREAM_TYPE_H264 {
+                if self.video_pid != Some(pid) {
+                    debug!("[TS] H264 video on PID {pid}");
+                    self.video_pid = Some(pid);
+                    self.pes.clear();
+                }
+                return;
+            }
+
+            streams = streams.get(5 + es_info_length..).unwrap_or_default();
+        }
+    }
+
+    fn push_pes(&mut self, payload: &[u8], payload_start: bool) -> Option<Vec<u8>> {
+        let mut completed = None;
+
+        if payload_start {
+            // New PES, so the previous one is complete
+            if !self.pes.is_empty() {
+                completed = Some(std::mem::take(&mut self.pes));
+            }
+
+            // PES header
+            // start code prefix(24) stream_id(8) PES_packet_length(16)
+            // flags(16) PES_header_data_length(8) ...
+            if payload.len() < 9 || payload[..3] != [0, 0, 1] {
+                debug!("[TS] Invalid PES header");
+                self.pes_length = None;
+                return completed;
+            }
+
+            let pes_packet_length = u16::from_be_bytes([payload[4], payload[5]]) as usize;
+            let header_length = 9 + payload[8] as usize;
+
+            // PES_packet_length counts the bytes after itself, 0 means
+            // unbounded which is usual for video
+            self.pes_length = match pes_packet_length {
+                0 => None,
+                len => (len + 6).checked_sub(header_length),
+            };
+
+            self.pes.extend_from_slice(payload.get(header_length..).unwrap_or_default());
+        } else if !self.pes.is_empty() {
+            self.pes.extend_from_slice(payload);
+        }
+
+        // Bounded PES can be handed on as soon as it is all here
+        if let Some(len) = self.pes_length {
+            if completed.is_none() && self.pes.len() >= len {
+                self.pes.truncate(len);
+                self.pes_length = None;
+                completed = Some(std::mem::take(&mut self.pes));
+            }
+        }
+
+        completed
+    }
+}
+
+impl Default for TsDemuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,102 @@
+use crate::error::RtpError;
+use crate::frame::Frame;
+use crate::rtp::{Decoders, Rtp};
+use crate::rtsp::TrackTransport;
+use std::future::poll_fn;
+use std::task::Poll;
+
+type Result<T> = std::result::Result<T, RtpError>;
+
+// Decoded frame and the track (media section index) it came from
+pub struct TrackFrame {
+    pub track: usize,
+    pub frame: Frame,
+}
+
+// Receives several video tracks at once (e.g. each sensor of a
+// multi-lens camera), each with its own socket and decoder
+//
+// rtsp.send(Methods::Describe).await?;
+// let transports = rtsp.setup_all().await?.to_vec();
+// rtsp.send(Methods::Play).await?;
+// let mut tracks = MultiTrack::new(&transports, Decoders::OpenH264).await?;
+// loop {
+//     let TrackFrame { track, frame } = tracks.next_frame().await?;
+// }
+pub struct MultiTrack {
+    tracks: Vec<(usize, Rtp)>,
+    // Track polled first next time, so a busy track can't starve the others
+    next: usize,
+}
+
+impl MultiTrack {
+    // One receiver for every video track set up
+    pub async fn new(transports: &[TrackTransport], decoder: Decoders) -> Result<Self> {
+        let mut tracks = Vec::new();
+
+        for transport in transports.iter().filter(|transport| transport.media == "video") {
+            let mut rtp = Rtp::new(None, transport.client_port_rtp, transport.server_addr_rtp).await?;
+            rtp.set_ssrc(transport.ssrc);
+            rtp.connect(decoder).await?;
+            tracks.push((transport.track, rtp));
+        }
+
+        Ok(MultiTrack { tracks, next: 0 })
+    }
+
+    // Track ids received, in the order they were set up
+    pub fn tracks(&self) -> impl Iterator<Item = usize> + '_ {
+        self.tracks.iter().map(|(track, _)| *track)
+    }
+
+    // Receiver of one track e.g. to add privacy masks
+    pub fn track_mut(&mut self, track: usize) -> Option<&mut Rtp> {
+        self.tracks
+            .iter_mut()
+            .find(|(id, _)| *id == track)
+            .map(|(_, rtp)| rtp)
+    }
+
+    // Wait for the next frame decoded on any track
+    pub async fn next_frame(&mut self) -> Result<TrackFrame> {
+        if self.tracks.is_empty() {
+            return Err(RtpError::NoDecoder);
+        }
+
+        loop {
+            // One packet can complete more than one access unit,
+            // those already complete are decoded before waiting
+            for (track, rtp) in &mut self.tracks {
+                while rtp.depacketizer().pending_count() > 0 {
+                    if let Some(frame) = rtp.try_decode_frame()? {
+                        return Ok(TrackFrame { track: *track, frame });
+                    }
+                }
+            }
+
+            let count = self.tracks.len();
+            let start = self.next;
+
+            let (index, ready) = poll_fn(|cx| {
+                for i in 0..count {
+                    let index = (start + i) % count;
+                    if let Poll::Ready(ready) = self.tracks[index].1.poll_recv_ready(cx) {
+                        return Poll::Ready((index, ready));
+                    }
+                }
+                Poll::Pending
+            })
+            .await;
+            ready?;
+
+            self.next = (index + 1) % count;
+            let (track, rtp) = &mut self.tracks[index];
+
+            if rtp.try_get_rtp()? {
+                if let Some(frame) = rtp.try_decode_frame()? {
+                    return Ok(TrackFrame { track: *track, frame });
+                }
+            }
+        }
+    }
+}
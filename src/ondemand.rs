@@ -0,0 +1,107 @@
+use crate::client::{Client, Media, Subscription};
+use crate::error::ClientError;
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Notify};
+use tokio::time::{timeout, Instant};
+
+type Result<T> = std::result::Result<T, ClientError>;
+
+// Same as a Client's subscribers
+const SUBSCRIBER_CAPACITY: usize = 32;
+
+// How long the camera stays connected after the last subscriber left
+const DEFAULT_IDLE_GRACE: Duration = Duration::from_secs(30);
+
+// How often subscribers are counted while nothing is received
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+// A camera only connected to while someone is watching: the first
+// subscribe() connects, once the last Subscription is dropped the
+// session is torn down after the idle grace, saving the camera an
+// encode session and the bandwidth. A viewer coming back within the
+// grace gets the running session
+//
+// let camera = Arc::new(OnDemand::new("rtsp://192.168.1.100:554/stream"));
+// // e.g. to the web server, which subscribes once per viewer
+// let viewers = camera.clone();
+// camera.run().await?;
+pub struct OnDemand {
+    addr: String,
+    idle_grace: Duration,
+    subscribers: broadcast::Sender<Arc<Media>>,
+    // Woken by subscribe() while nobody is watching
+    attached: Notify,
+}
+
+impl OnDemand {
+    pub fn new(addr: &str) -> Self {
+        OnDemand {
+            addr: addr.to_string(),
+            idle_grace: DEFAULT_IDLE_GRACE,
+            subscribers: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            attached: Notify::new(),
+        }
+    }
+
+    // Zero disconnects as soon as the last subscriber is gone
+    pub fn set_idle_grace(&mut self, idle_grace: Duration) {
+        self.idle_grace = idle_grace;
+    }
+
+    // Attach a consumer, connecting to the camera if nobody else is
+    // watching. Dropping the Subscription detaches it
+    pub fn subscribe(&self) -> Subscription {
+        let subscription = Subscription::new(self.subscribers.subscribe());
+        self.attached.notify_one();
+        subscription
+    }
+
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.receiver_count()
+    }
+
+    // Connect and disconnect as subscribers come and go, handing
+    // them everything the session delivers. Runs until the session
+    // fails
+    pub async fn run(&self) -> Result<()> {
+        loop {
+            // A subscribe() before waiting leaves a permit behind
+            while self.subscriber_count() == 0 {
+                self.attached.notified().await;
+            }
+
+            info!("[OnDemand] Subscriber attached, connecting to {}", self.addr);
+            let mut client = Client::connect(&self.addr).await?;
+            self.serve(&mut client).await?;
+
+            info!("[OnDemand] No subscribers for {:?}, disconnecting", self.idle_grace);
+            if let Err(e) = client.shutdown().await {
+                warn!("[OnDemand] Teardown failed: {e}");
+            }
+        }
+    }
+
+    // Hand media on until nobody has been watching for the idle grace
+    async fn serve(&self, client: &mut Client) -> Result<()> {
+        let mut idle_since = None;
+
+        loop {
+            match self.subscriber_count() {
+                0 => {
+                    let since = *idle_since.get_or_insert_with(Instant::now);
+                    if since.elapsed() >= self.idle_grace {
+                        return Ok(());
+                    }
+                }
+                _ => idle_since = None,
+            }
+
+            // Even a silent session notices everyone leaving
+            if let Ok(media) = timeout(IDLE_CHECK_INTERVAL, client.next()).await {
+                let _ = self.subscribers.send(Arc::new(media?));
+            }
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use crate::sdp::MediaSection;
+
+// Opus over RTP (RFC 7587), as doorbells and newer NVRs pair with
+// H264. Each RTP payload is one Opus packet of one or more frames,
+// handed on whole for a decoder such as libopus
+//
+// a=rtpmap:111 opus/48000/2
+// a=fmtp:111 sprop-stereo=0;useinbandfec=1
+
+// The RTP clock, whatever rate the audio was captured at
+pub const CLOCK_RATE: u32 = 48000;
+
+// Longest an Opus packet can play for, 120ms (RFC 6716 section 3.2.5)
+const MAX_PACKET_SAMPLES: u32 = CLOCK_RATE / 1000 * 120;
+
+// What the SDP says about the stream. The rtpmap always has 2
+// channels, whether the sender actually sends stereo is in fmtp
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpusConfig {
+    pub stereo: bool,       // sprop-stereo
+    pub in_band_fec: bool,  // useinbandfec
+    pub dtx: bool,          // usedtx, silence sends nothing
+}
+
+impl OpusConfig {
+    // None when the section isn't Opus
+    pub fn from_sdp(section: &MediaSection) -> Option<Self> {
+        if !section.codec.as_deref().is_some_and(|codec| codec.eq_ignore_ascii_case("opus")) {
+            return None;
+        }
+
+        let fmtp = section.payload_type.and_then(|payload_type| section.fmtp_for(payload_type));
+        let flag = |name: &str| fmtp.and_then(|fmtp| fmtp.get(name)).is_some_and(|value| value.trim() == "1");
+
+        Some(OpusConfig {
+            stereo: flag("sprop-stereo"),
+            in_band_fec: flag("useinbandfec"),
+            dtx: flag("usedtx"),
+        })
+    }
+
+    pub fn channels(&self) -> u8 {
+        match self.stereo {
+            true => 2,
+            false => 1,
+        }
+    }
+}
+
+// One Opus packet with where it is on the RTP clock
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OpusPacket {
+    pub timestamp: u32, // RTP timestamp, 48kHz
+    pub samples: u32,   // duration at 48kHz, e.g. 960 for 20ms
+    pub frames: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct OpusDepacketizer {
+    last_timestamp: Option<u32>,
+}
+
+impl OpusDepacketizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The Opus packet in an RTP payload, None if it isn't a valid one
+    // or repeats the timestamp of the last (a duplicate)
+    pub fn push(&mut self, payload: &[u8], timestamp: u32) -> Option<OpusPacket> {
+        let (frames, samples) = packet_duration(payload)?;
+        if self.last_timestamp == Some(timestamp) {
+            return None;
+        }
+        self.last_timestamp = Some(timestamp);
+
+        Some(OpusPacket {
+            timestamp,
+            samples,
+            frames,
+            data: payload.to_vec(),
+        })
+    }
+}
+
+// Number of frames and samples (at 48kHz) in an Opus packet, from
+// its TOC byte (RFC 6716 section 3.1)
+pub fn packet_duration(packet: &[u8]) -> Option<(u8, u32)> {
+    let toc = *packet.first()?;
+    let config = toc >> 3;
+
+    // In units of 2.5ms, i.e. 120 samples
+    #[rustfmt::skip]
+    let frame_units = match config {
+        0..=11  => [4, 8, 16, 24][config as usize % 4], // SILK 10, 20, 40, 60ms
+        12..=15 => [4, 8][config as usize % 2],         // hybrid 10, 20ms
+        _       => [1, 2, 4, 8][config as usize % 4],   // CELT 2.5, 5, 10, 20ms
+    };
+
+    let frames = match toc & 3 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => *packet.get(1)? & 0x3F,
+    };
+
+    let samples = frames as u32 * frame_units * 120;
+    match frames > 0 && samples <= MAX_PACKET_SAMPLES {
+        true => Some((frames, samples)),
+        false => None,
+    }
+}
@@ -0,0 +1,146 @@
+use crate::frame::Frame;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Glyphs are 5 pixels wide and 7 high, one byte per row
+// with the leftmost pixel in bit 4
+const GLYPH_WIDTH: usize = 5;
+const GLYPH_HEIGHT: usize = 7;
+
+// Pixels between glyphs and around the text
+const SPACING: usize = 1;
+
+// Luma for text and its background box (limited range)
+const WHITE: u8 = 235;
+const BLACK: u8 = 16;
+
+// On screen display burned into frames e.g. the capture time
+// White text on a black box so it stays readable on any picture
+pub struct Osd {
+    pub x: usize,
+    pub y: usize,
+    pub scale: usize, // each font pixel becomes scale x scale pixels
+}
+
+impl Osd {
+    pub fn new(x: usize, y: usize, scale: usize) -> Self {
+        Osd {
+            x,
+            y,
+            scale: scale.max(1),
+        }
+    }
+
+    // Draw the time as UTC e.g. 2023-10-16 08:30:05
+    pub fn draw_timestamp(&self, frame: &mut Frame, time: SystemTime) {
+        self.draw_text(frame, &format_timestamp(time));
+    }
+
+    // Only digits, space, '-', ':' and '.' can be drawn,
+    // anything else is left blank
+    pub fn draw_text(&self, frame: &mut Frame, text: &str) {
+        let cell = (GLYPH_WIDTH + SPACING) * self.scale;
+        let box_width = text.chars().count() * cell + SPACING * self.scale;
+        let box_height = (GLYPH_HEIGHT + 2 * SPACING) * self.scale;
+
+        self.fill_box(frame, box_width, box_height);
+
+        for (i, c) in text.chars().enumerate() {
+            let rows = glyph(c);
+            let left = self.x + SPACING * self.scale + i * cell;
+            let top = self.y + SPACING * self.scale;
+
+            for (row, bits) in rows.iter().enumerate() {
+                for col in 0..GLYPH_WIDTH {
+                    if bits & (1 << (GLYPH_WIDTH - 1 - col)) != 0 {
+                        self.fill_luma(frame, left + col * self.scale, top + row * self.scale, self.scale, self.scale, WHITE);
+                    }
+                }
+            }
+        }
+    }
+
+    fn fill_box(&self, frame: &mut Frame, width: usize, height: usize) {
+        self.fill_luma(frame, self.x, self.y, width, height, BLACK);
+
+        // No colour inside the box
+        let chroma_width = frame.chroma_width();
+        let chroma_height = frame.chroma_height();
+        let x_end = (self.x + width).div_ceil(2).min(chroma_width);
+        let y_end = (self.y + height).div_ceil(2).min(chroma_height);
+
+        for row in self.y / 2..y_end {
+            for col in self.x / 2..x_end {
+                frame.u[row * chroma_width + col] = 128;
+                frame.v[row * chroma_width + col] = 128;
+            }
+        }
+    }
+
+    // Clipped to the frame
+    fn fill_luma(&self, frame: &mut Frame, x: usize, y: usize, width: usize, height: usize, value: u8) {
+        let x_end = (x + width).min(frame.width);
+        let y_end = (y + height).min(frame.height);
+
+        for row in y..y_end {
+            for col in x..x_end {
+                frame.y[row * frame.width + col] = value;
+            }
+        }
+    }
+}
+
+// UTC as YYYY-MM-DD HH:MM:SS
+pub fn format_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default();
+
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+// Days since 1970-01-01 to (year, month, day)
+// see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c {
+        '0' => [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e],
+        '1' => [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e],
+        '2' => [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f],
+        '3' => [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e],
+        '4' => [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02],
+        '5' => [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e],
+        '6' => [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e],
+        '7' => [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08],
+        '8' => [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e],
+        '9' => [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c],
+        '-' => [0x00, 0x00, 0x00, 0x1f, 0x00, 0x00, 0x00],
+        ':' => [0x00, 0x0c, 0x0c, 0x00, 0x0c, 0x0c, 0x00],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x00, 0x0c, 0x0c],
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
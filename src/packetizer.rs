@@ -0,0 +1,319 @@
+use crate::codec::PayloadFormat;
+use crate::g711::{self, G711};
+use crate::h264;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+// Default largest RTP packet, keeps packets under a
+// 1500 byte ethernet MTU once IP and UDP headers are added
+const DEFAULT_MAX_PACKET_SIZE: usize = 1400;
+
+// Dynamic payload type commonly used for H264
+const DEFAULT_PAYLOAD_TYPE: u8 = 96;
+
+const RTP_HEADER_SIZE: usize = 12;
+
+// FU indicator and FU header
+const FU_A_HEADER_SIZE: usize = 2;
+
+// Audio packet duration recommended by RFC 3551 (ptime)
+const DEFAULT_PACKET_DURATION: Duration = Duration::from_millis(20);
+
+// Splits H264 access units into RTP packets (RFC 6184), the
+// reverse of Depacketizer. NAL units which fit in one packet
+// are sent as is (single NAL unit mode), larger ones as FU-A.
+pub struct Packetizer {
+    payload_type: u8,
+    ssrc: u32,
+    sequence: u16,
+    max_packet_size: usize,
+}
+
+impl Packetizer {
+    pub fn new() -> Self {
+        // SSRC and first sequence number should be random (RFC 3550 5.1)
+        let random = RandomState::new().build_hasher().finish();
+
+        Packetizer {
+            payload_type: DEFAULT_PAYLOAD_TYPE,
+            ssrc: random as u32,
+            sequence: (random >> 32) as u16,
+            max_packet_size: DEFAULT_MAX_PACKET_SIZE,
+        }
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    // Use an SSRC given by someone else e.g. negotiated
+    // by a HomeKit camera bridge along with the SRTP keys
+    pub fn set_ssrc(&mut self, ssrc: u32) -> &mut Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    pub fn payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    // Dynamic payload type (96-127) agreed in the SDP, default is 96
+    pub fn set_payload_type(&mut self, payload_type: u8) -> &mut Self {
+        self.payload_type = payload_type & 0b01111111;
+        self
+    }
+
+    // Sequence number of the next packet
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    pub fn set_sequence(&mut self, sequence: u16) -> &mut Self {
+        self.sequence = sequence;
+        self
+    }
+
+    // Largest RTP packet sent, header included. Leave room for
+    // anything added afterwards e.g. the SRTP auth tag
+    pub fn set_max_packet_size(&mut self, max_packet_size: usize) -> &mut Self {
+        self.max_packet_size = max_packet_size.max(RTP_HEADER_SIZE + FU_A_HEADER_SIZE + 1);
+        self
+    }
+
+    // Packets for one access unit (Annex B, start codes included)
+    // All packets share the timestamp, the last one has the marker bit
+    pub fn packetize(&mut self, annexb: &[u8], timestamp: u32) -> Vec<Vec<u8>> {
+        let nal_units = h264::nal_units(annexb);
+        let mut packets = Vec::new();
+
+        for (i, nal) in nal_units.iter().enumerate() {
+            let last = i + 1 == nal_units.len();
+            self.packetize_nal(nal, timestamp, last, &mut packets);
+        }
+
+        packets
+    }
+
+    // Packets for a single NAL unit WITHOUT start code
+    pub fn packetize_nal(&mut self, nal: &[u8], timestamp: u32, marker: bool, packets: &mut Vec<Vec<u8>>) {
+        if nal.is_empty() {
+            return;
+        }
+
+        let max_payload = self.max_packet_size - RTP_HEADER_SIZE;
+
+        // Single NAL unit packet
+        if nal.len() <= max_payload {
+            let mut packet = self.header(timestamp, marker);
+            packet.extend_from_slice(nal);
+            packets.push(packet);
+            return;
+        }
+
+        // FU-A, NAL header is replaced by the FU indicator
+        // (same F and NRI bits, type 28) and FU header
+        // (start/end bits and the original type)
+        let fu_indicator = (nal[0] & 0b11100000) | 28;
+        let nal_type = nal[0] & 0b00011111;
+
+        let chunks: Vec<&[u8]> = nal[1..].chunks(max_payload - FU_A_HEADER_SIZE).collect();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let start = i == 0;
+            let end = i + 1 == chunks.len();
+
+            let mut fu_header = nal_type;
+            if start {
+                fu_header |= 0b10000000;
+            }
+            if end {
+                fu_header |= 0b01000000;
+            }
+
+            let mut packet = self.header(timestamp, marker && end);
+            packet.push(fu_indicator);
+            packet.push(fu_header);
+            packet.extend_from_slice(chunk);
+            packets.push(packet);
+        }
+    }
+
+    fn header(&mut self, timestamp: u32, marker: bool) -> Vec<u8> {
+        let header = rtp_header(self.payload_type, self.sequence, timestamp, self.ssrc, marker, self.max_packet_size);
+        self.sequence = self.sequence.wrapping_add(1);
+        header
+    }
+}
+
+impl Default for Packetizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Encodes 8kHz mono PCM as G.711 and splits it into packets of
+// equal duration (20 ms by default), e.g. for the audio backchannel
+// of a camera. Pair it with the backchannel track from the SDP:
+//
+// let format = PayloadFormat::from_sdp(&section).unwrap();
+// let audio = AudioPacketizer::from_format(&format).unwrap();
+//
+// Timestamps count samples, so they follow the audio fed in rather
+// than the wall clock. The first packet after new() or flush() has
+// the marker bit, as the start of a talkspurt (RFC 3551 section 4.1)
+pub struct AudioPacketizer {
+    law: G711,
+    payload_type: u8,
+    ssrc: u32,
+    sequence: u16,
+    timestamp: u32,
+    samples_per_packet: usize,
+    pending: Vec<i16>,
+    talkspurt: bool,
+}
+
+impl AudioPacketizer {
+    pub fn new(law: G711) -> Self {
+        // SSRC, first sequence number and timestamp should be random (RFC 3550 5.1)
+        let random = RandomState::new().build_hasher().finish();
+
+        AudioPacketizer {
+            law,
+            payload_type: law.payload_type(),
+            ssrc: random as u32,
+            sequence: (random >> 32) as u16,
+            timestamp: (random >> 16) as u32,
+            samples_per_packet: samples(DEFAULT_PACKET_DURATION),
+            pending: Vec::new(),
+            talkspurt: true,
+        }
+    }
+
+    // None if the track isn't G.711
+    pub fn from_format(format: &PayloadFormat) -> Option<Self> {
+        let law = G711::from_codec(&format.codec)?;
+
+        let mut packetizer = AudioPacketizer::new(law);
+        packetizer.set_payload_type(format.payload_type);
+        Some(packetizer)
+    }
+
+    pub fn law(&self) -> G711 {
+        self.law
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    pub fn set_ssrc(&mut self, ssrc: u32) -> &mut Self {
+        self.ssrc = ssrc;
+        self
+    }
+
+    pub fn payload_type(&self) -> u8 {
+        self.payload_type
+    }
+
+    // Defaults to the static payload type, 0 for PCMU or 8 for PCMA
+    pub fn set_payload_type(&mut self, payload_type: u8) -> &mut Self {
+        self.payload_type = payload_type & 0b01111111;
+        self
+    }
+
+    // Sequence number of the next packet
+    pub fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    pub fn set_sequence(&mut self, sequence: u16) -> &mut Self {
+        self.sequence = sequence;
+        self
+    }
+
+    // Timestamp of the next packet (8kHz)
+    pub fn timestamp(&self) -> u32 {
+        self.timestamp
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: u32) -> &mut Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    // Audio in each packet (ptime), rounded to whole samples
+    pub fn set_packet_duration(&mut self, duration: Duration) -> &mut Self {
+        self.samples_per_packet = samples(duration).max(1);
+        self
+    }
+
+    pub fn packet_duration(&self) -> Duration {
+        Duration::from_micros(self.samples_per_packet as u64 * 1_000_000 / g711::SAMPLE_RATE as u64)
+    }
+
+    // Packets for every full packet duration of audio fed in so far,
+    // the rest waits for the next push or flush
+    pub fn push(&mut self, pcm: &[i16]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(pcm);
+
+        let mut packets = Vec::new();
+        let mut start = 0;
+        while self.pending.len() - start >= self.samples_per_packet {
+            let end = start + self.samples_per_packet;
+            let samples: Vec<i16> = self.pending[start..end].to_vec();
+            packets.push(self.packet(&samples));
+            start = end;
+        }
+        self.pending.drain(..start);
+
+        packets
+    }
+
+    // Send what is left as a shorter packet, e.g. when the user lets go
+    // of the talk button. The next packet starts a new talkspurt
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        let samples = std::mem::take(&mut self.pending);
+        let packet = match samples.is_empty() {
+            true => None,
+            false => Some(self.packet(&samples)),
+        };
+
+        self.talkspurt = true;
+        packet
+    }
+
+    fn packet(&mut self, samples: &[i16]) -> Vec<u8> {
+        let mut packet = rtp_header(
+            self.payload_type,
+            self.sequence,
+            self.timestamp,
+            self.ssrc,
+            self.talkspurt,
+            RTP_HEADER_SIZE + samples.len(),
+        );
+        packet.extend_from_slice(&self.law.encode(samples));
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.timestamp = self.timestamp.wrapping_add(samples.len() as u32);
+        self.talkspurt = false;
+        packet
+    }
+}
+
+// Number of 8kHz samples in 'duration'
+fn samples(duration: Duration) -> usize {
+    (duration.as_micros() * g711::SAMPLE_RATE as u128 / 1_000_000) as usize
+}
+
+// RTP header (RFC 3550 5.1), no CSRCs or extension
+fn rtp_header(payload_type: u8, sequence: u16, timestamp: u32, ssrc: u32, marker: bool, capacity: usize) -> Vec<u8> {
+    let mut header = Vec::with_capacity(capacity);
+
+    header.push(0b10000000); // version 2
+    header.push(((marker as u8) << 7) | (payload_type & 0b01111111));
+    header.extend_from_slice(&sequence.to_be_bytes());
+    header.extend_from_slice(&timestamp.to_be_bytes());
+    header.extend_from_slice(&ssrc.to_be_bytes());
+
+    header
+}
@@ -0,0 +1,79 @@
+use log::warn;
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+// Client port allocation shared by every session in the process
+//
+// Each allocation is a pair: an even RTP port and the RTCP port
+// right after it, held by a PortPair and handed back when it is
+// dropped (on teardown) so two sessions never advertise the same
+// client_port in SETUP.
+
+// Same default the crate has always used for the first session
+const FIRST_PORT: u16 = 4588;
+const LAST_PORT: u16 = 65534;
+
+static USED_PORTS: Mutex<BTreeSet<u16>> = Mutex::new(BTreeSet::new());
+
+// A client port pair taken from the registry. Only a pair the
+// registry actually handed out goes back when it is dropped, one
+// already in use elsewhere stays taken for whoever holds it
+#[derive(Debug)]
+pub struct PortPair {
+    port: u16,
+    owned: bool,
+}
+
+impl PortPair {
+    // The RTP port, RTCP is the one after it
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for PortPair {
+    fn drop(&mut self) {
+        if self.owned {
+            let mut used = USED_PORTS.lock().unwrap_or_else(|e| e.into_inner());
+            used.remove(&self.port);
+            used.remove(&(self.port + 1));
+        }
+    }
+}
+
+// A free pair
+pub fn allocate_pair() -> Option<PortPair> {
+    let mut used = USED_PORTS.lock().unwrap_or_else(|e| e.into_inner());
+
+    let port = (FIRST_PORT..LAST_PORT)
+        .step_by(2)
+        .find(|port| !used.contains(port) && !used.contains(&(port + 1)))?;
+
+    used.insert(port);
+    used.insert(port + 1);
+
+    Some(PortPair { port, owned: true })
+}
+
+// Mark a pair chosen by the user as taken. If another session
+// already holds it the pair is still used, as asked, but stays
+// that session's to hand back. None when there is no room for
+// the RTCP port after it
+pub fn reserve_pair(port: u16) -> Option<PortPair> {
+    if port > LAST_PORT {
+        return None;
+    }
+
+    let mut used = USED_PORTS.lock().unwrap_or_else(|e| e.into_inner());
+    let rtcp_port = port + 1;
+
+    if used.contains(&port) || used.contains(&rtcp_port) {
+        warn!("[Ports] Client ports {port}-{rtcp_port} are already used by another session");
+        return Some(PortPair { port, owned: false });
+    }
+
+    used.insert(port);
+    used.insert(rtcp_port);
+
+    Some(PortPair { port, owned: true })
+}
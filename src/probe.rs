@@ -0,0 +1,98 @@
+use crate::error::RtspError;
+use crate::h264;
+use crate::rtsp::{Methods, Rtsp};
+use std::net::SocketAddr;
+
+// What a camera offers, found with OPTIONS and DESCRIBE only
+// (no SETUP, so no stream is started)
+#[derive(Clone, Debug)]
+pub struct ProbeReport {
+    pub server: SocketAddr,
+    // Server answered 401 to OPTIONS or DESCRIBE
+    pub auth_required: bool,
+    // None if the server didn't send a Public header
+    pub supported_methods: Option<Vec<Methods>>,
+    pub tracks: Vec<ProbeTrack>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProbeTrack {
+    pub media: String, // e.g. "video" or "audio"
+    pub control: Option<String>,
+    pub payload_type: Option<u8>,
+    pub codec: Option<String>, // e.g. "H264", "PCMU", "MPEG4-GENERIC"
+    pub clock_rate: Option<u32>,
+    pub channels: Option<u16>,
+    // From the SPS in sprop-parameter-sets (H264 only)
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub framerate: Option<f64>,
+    pub profile_idc: Option<u8>,
+    pub level_idc: Option<u8>,
+}
+
+impl Rtsp {
+    // Run OPTIONS then DESCRIBE and report supported methods,
+    // tracks, codecs, resolutions and whether auth is required
+    pub async fn probe(&mut self) -> Result<ProbeReport, RtspError> {
+        let mut report = ProbeReport {
+            server: self.server_addr(),
+            auth_required: false,
+            supported_methods: None,
+            tracks: Vec::new(),
+        };
+
+        self.send(Methods::Options).await?;
+        if self.status() == Some(401) {
+            report.auth_required = true;
+            return Ok(report);
+        }
+        report.supported_methods = self.supported_methods().map(|methods| methods.to_vec());
+
+        self.send(Methods::Describe).await?;
+        if self.status() == Some(401) {
+            report.auth_required = true;
+            return Ok(report);
+        }
+
+        report.tracks = self
+            .media_sections()
+            .iter()
+            .map(|section| {
+                let sps = section
+                    .fmtp
+                    .as_deref()
+                    .and_then(sprop_parameter_sets)
+                    .and_then(|sprop| {
+                        h264::decode_sprop_parameter_sets(sprop)
+                            .iter()
+                            .find_map(|nal| h264::parse_sps(nal))
+                    });
+
+                ProbeTrack {
+                    media: section.media.clone(),
+                    control: section.control.clone(),
+                    payload_type: section.payload_type,
+                    codec: section.codec.clone(),
+                    clock_rate: section.clock_rate,
+                    channels: section.channels,
+                    width: sps.as_ref().map(|sps| sps.width),
+                    height: sps.as_ref().map(|sps| sps.height),
+                    framerate: sps.as_ref().and_then(|sps| sps.framerate),
+                    profile_idc: sps.as_ref().map(|sps| sps.profile_idc),
+                    level_idc: sps.as_ref().map(|sps| sps.level_idc),
+                }
+            })
+            .collect();
+
+        Ok(report)
+    }
+}
+
+// e.g. packetization-mode=1;profile-level-id=42001f;sprop-parameter-sets=Z0IAH5WoFAFuQA==,aM48gA==
+fn sprop_parameter_sets(fmtp: &str) -> Option<&str> {
+    fmtp.split(';')
+        .filter_map(|param| param.trim().split_once('='))
+        .find(|(name, _)| name.eq_ignore_ascii_case("sprop-parameter-sets"))
+        .map(|(_, value)| value)
+}
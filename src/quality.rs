@@ -0,0 +1,103 @@
+use crate::h264::{self, Pps, Sps};
+
+// Rough quality of each encoded frame, without decoding it: its size
+// against the recent average and the QP its slices were coded with.
+// Good enough to skip frames an encoder starved of bitrate smeared,
+// or to spot a camera set to a far too low bitrate or quality
+//
+// let quality = rtp.frame_quality();
+// if quality.is_some_and(|quality| quality.is_low(40, 0.3)) { skip the frame }
+
+// Weight of the newest frame in the running average sizes
+const AVERAGE_WEIGHT: f64 = 1.0 / 16.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameQuality {
+    pub size: usize, // bytes of the access unit
+    pub keyframe: bool,
+    // Size over the running average of earlier frames of the same
+    // kind (keyframes and the rest are far apart), 1.0 for the first
+    pub relative_size: f64,
+    // Average slice QP, 0 (best) to 51 (worst). None until the
+    // stream's SPS and PPS were seen
+    pub qp: Option<f64>,
+}
+
+impl FrameQuality {
+    // Coded coarser than 'max_qp' (when known) or much smaller than
+    // usual, e.g. is_low(40, 0.3)
+    pub fn is_low(&self, max_qp: i32, min_relative_size: f64) -> bool {
+        self.qp.is_some_and(|qp| qp > max_qp as f64) || self.relative_size < min_relative_size
+    }
+}
+
+#[derive(Default)]
+pub struct QualityEstimator {
+    sps: Option<Sps>,
+    pps: Vec<Pps>, // by id, a stream may use several
+    keyframe_size: Option<f64>,
+    frame_size: Option<f64>,
+}
+
+impl QualityEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Quality of one access unit in Annex B, parameter sets in it are
+    // kept for the frames after it
+    pub fn estimate(&mut self, annexb: &[u8]) -> FrameQuality {
+        let mut qps = Vec::new();
+        let mut keyframe = false;
+
+        for nal in h264::nal_units(annexb) {
+            match nal[0] & 31 {
+                7 => self.sps = h264::parse_sps(nal).or(self.sps.take()),
+                8 => {
+                    if let Some(pps) = h264::parse_pps(nal) {
+                        self.pps.retain(|known| known.pic_parameter_set_id != pps.pic_parameter_set_id);
+                        self.pps.push(pps);
+                    }
+                }
+                nal_type @ (1 | 5) => {
+                    keyframe |= nal_type == 5;
+                    qps.extend(self.slice_qp(nal));
+                }
+                _ => (),
+            }
+        }
+
+        let size = annexb.len();
+        let average = match keyframe {
+            true => &mut self.keyframe_size,
+            false => &mut self.frame_size,
+        };
+        let relative_size = match *average {
+            Some(average) if average > 0.0 => size as f64 / average,
+            _ => 1.0,
+        };
+        *average = Some(match *average {
+            Some(average) => average + (size as f64 - average) * AVERAGE_WEIGHT,
+            None => size as f64,
+        });
+
+        let qp = match qps.is_empty() {
+            true => None,
+            false => Some(qps.iter().sum::<i32>() as f64 / qps.len() as f64),
+        };
+
+        FrameQuality {
+            size,
+            keyframe,
+            relative_size,
+            qp,
+        }
+    }
+
+    fn slice_qp(&self, nal: &[u8]) -> Option<i32> {
+        let sps = self.sps.as_ref()?;
+
+        // Only the PPS the slice names gives a QP
+        self.pps.iter().find_map(|pps| h264::slice_qp(nal, sps, pps))
+    }
+}
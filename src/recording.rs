@@ -0,0 +1,362 @@
+use crate::h264;
+#[cfg(feature = "mux-mp4")]
+use crate::mp4::{self, Sample};
+use log::info;
+#[cfg(feature = "mux-mp4")]
+use log::warn;
+use std::io;
+use std::path::Path;
+use tokio::fs;
+
+// Repair of recordings cut short, e.g. by a power loss while they
+// were being written. Whatever was written completely is kept and
+// the partial access unit (or MP4 sample) at the end is dropped.
+//
+// let repaired = recording::repair_file("recordings/front-door/0042.mp4").await?;
+// info!("kept {} access units", repaired.access_units);
+
+// MP4 timescale the samples are rebuilt on (same as the muxer)
+#[cfg(feature = "mux-mp4")]
+const TIMESCALE: u64 = 90000;
+
+// Framerate assumed for MP4 recordings without an index or timing
+// in their SPS
+#[cfg(feature = "mux-mp4")]
+const DEFAULT_FRAMERATE: f64 = 30.0;
+
+// What was left of a recording after repair_file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Repaired {
+    pub access_units: usize,
+    pub original_len: u64,
+    pub repaired_len: u64,
+}
+
+// Repair a raw H264 (.h264/.264) or MP4 (.mp4) recording in place,
+// meant for segments which weren't closed properly: an intact raw
+// H264 file loses its last access unit. The repaired file replaces
+// the original only once it is written in full
+pub async fn repair_file<P: AsRef<Path>>(path: P) -> io::Result<Repaired> {
+    let path = path.as_ref();
+    let data = fs::read(path).await?;
+
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+
+    let (repaired, access_units) = match extension.as_str() {
+        "h264" | "264" => {
+            let repaired = repair_h264(&data);
+            (repaired.to_vec(), h264::access_units(repaired).len())
+        }
+        #[cfg(feature = "mux-mp4")]
+        "mp4" => {
+            let (samples, skip) = mp4_samples(&data)?;
+            (mp4::mux(&samples, skip)?, samples.len())
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("can't repair {}", path.display()))),
+    };
+
+    if repaired != data {
+        // Next to the original so the rename doesn't cross file systems
+        let temp = path.with_extension(format!("{extension}.repair"));
+        fs::write(&temp, &repaired).await?;
+        fs::rename(&temp, path).await?;
+        info!("[Recording] Repaired {}: {} -> {} bytes", path.display(), data.len(), repaired.len());
+    }
+
+    Ok(Repaired {
+        access_units,
+        original_len: data.len() as u64,
+        repaired_len: repaired.len() as u64,
+    })
+}
+
+// The complete access units of an Annex B stream. There is no telling
+// whether the last one was written in full so it's always dropped
+pub fn repair_h264(annexb: &[u8]) -> &[u8] {
+    match h264::access_units(annexb).last() {
+        Some(last) => &annexb[..annexb.len() - last.len()],
+        None => &[],
+    }
+}
+
+// Rebuild an MP4 recording from the samples still in it. Only the
+// video track is kept (e.g. a subtitle track is dropped)
+#[cfg(feature = "mux-mp4")]
+pub fn repair_mp4(data: &[u8]) -> io::Result<Vec<u8>> {
+    let (samples, skip) = mp4_samples(data)?;
+    mp4::mux(&samples, skip)
+}
+
+// Samples (and the edit list skip) of a damaged MP4. With its moov the
+// index says where they are, without one (moov is written last by
+// many muxers) mdat is walked NAL unit by NAL unit
+#[cfg(feature = "mux-mp4")]
+fn mp4_samples(data: &[u8]) -> io::Result<(Vec<Sample>, u32)> {
+    let top = boxes(data, 0);
+    let find = |kind: &[u8; 4]| top.iter().find(|(found, _)| found == kind);
+
+    if let Some((_, moov)) = find(b"moov") {
+        let mdat_len = find(b"mdat").map_or(data.len(), |(_, mdat)| mdat.len());
+        if let Some(track) = video_track(moov, mdat_len) {
+            return Ok(indexed_samples(data, &track));
+        }
+        warn!("[Recording] No usable video track in moov, scanning mdat");
+    }
+
+    match find(b"mdat") {
+        Some((_, mdat)) => Ok((scanned_samples(mdat), 0)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidData, "no mdat in recording")),
+    }
+}
+
+// What the sample tables of a video track say
+#[cfg(feature = "mux-mp4")]
+struct VideoTrack {
+    timescale: u64,
+    skip: u64, // media time hidden by the edit list
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+    length_size: usize,
+    sizes: Vec<u32>,
+    durations: Vec<u32>,
+    chunk_offsets: Vec<u64>,
+    // (first chunk, samples per chunk), chunks numbered from 1
+    chunks: Vec<(u32, u32)>,
+}
+
+#[cfg(feature = "mux-mp4")]
+fn video_track(moov: &[u8], mdat_len: usize) -> Option<VideoTrack> {
+    let trak = boxes(moov, 0).into_iter().find_map(|(kind, trak)| {
+        let mdia = child(trak, b"mdia")?;
+        let handler = child(mdia, b"hdlr")?.get(8..12)?;
+        (kind == *b"trak" && handler == b"vide").then_some(trak)
+    })?;
+
+    let mdia = child(trak, b"mdia")?;
+    let mdhd = child(mdia, b"mdhd")?;
+    let timescale = match mdhd.first()? {
+        1 => be32(mdhd, 20)?,
+        _ => be32(mdhd, 12)?,
+    } as u64;
+
+    let skip = match child(trak, b"edts").and_then(|edts| child(edts, b"elst")) {
+        Some(elst) if be32(elst, 4)? > 0 => match elst[0] {
+            1 => be64(elst, 16)? as i64,
+            _ => be32(elst, 12)? as i32 as i64,
+        }
+        .max(0) as u64,
+        _ => 0,
+    };
+
+    let stbl = child(child(mdia, b"minf")?, b"stbl")?;
+
+    // stsd: one avc1 entry whose avcC holds the parameter sets
+    let avc1 = boxes(child(stbl, b"stsd")?, 8).into_iter().find(|(kind, _)| kind == b"avc1")?.1;
+    let avcc = child(avc1.get(78..)?, b"avcC")?;
+    let length_size = (avcc.get(4)? & 3) as usize + 1;
+    let sps_len = be16(avcc, 6)? as usize;
+    let sps = avcc.get(8..8 + sps_len)?.to_vec();
+    let pps_at = 8 + sps_len + 1;
+    let pps_len = be16(avcc, pps_at)? as usize;
+    let pps = avcc.get(pps_at + 2..pps_at + 2 + pps_len)?.to_vec();
+
+    // The sample count is whatever the damaged file says: no more
+    // than the table, or mdat for samples all of one size, can hold
+    let stsz = child(stbl, b"stsz")?;
+    let count = be32(stsz, 8)? as usize;
+    let sizes: Vec<u32> = match be32(stsz, 4)? {
+        0 => table(stsz, 12, count, 4).map(|at| be32(stsz, at)).collect::<Option<_>>()?,
+        size => vec![size; count.min(mdat_len / size as usize)],
+    };
+    let count = sizes.len();
+
+    let stts = child(stbl, b"stts")?;
+    let mut durations = Vec::new();
+    for at in table(stts, 8, be32(stts, 4)? as usize, 8) {
+        let (samples, duration) = (be32(stts, at)?, be32(stts, at + 4)?);
+        durations.extend((0..samples).map(|_| duration).take(count - durations.len()));
+    }
+
+    let chunk_offsets = match (child(stbl, b"stco"), child(stbl, b"co64")) {
+        (Some(stco), _) => table(stco, 8, be32(stco, 4)? as usize, 4).map(|at| be32(stco, at).map(|offset| offset as u64)).collect::<Option<_>>()?,
+        (None, Some(co64)) => table(co64, 8, be32(co64, 4)? as usize, 8).map(|at| be64(co64, at)).collect::<Option<_>>()?,
+        (None, None) => return None,
+    };
+
+    let stsc = child(stbl, b"stsc")?;
+    let chunks = table(stsc, 8, be32(stsc, 4)? as usize, 12)
+        .map(|at| Some((be32(stsc, at)?, be32(stsc, at + 4)?)))
+        .collect::<Option<_>>()?;
+
+    Some(VideoTrack { timescale, skip, sps, pps, length_size, sizes, durations, chunk_offsets, chunks })
+}
+
+// Samples up to the first one which isn't in the file in full
+#[cfg(feature = "mux-mp4")]
+fn indexed_samples(data: &[u8], track: &VideoTrack) -> (Vec<Sample>, u32) {
+    let to_ticks = |time: u64| (time * TIMESCALE / track.timescale.max(1)) as u32;
+
+    let mut samples = Vec::new();
+    let mut time = 0u64;
+    let mut index = 0;
+
+    'chunks: for (chunk, &offset) in track.chunk_offsets.iter().enumerate() {
+        let per_chunk = track
+            .chunks
+            .iter()
+            .rev()
+            .find(|(first, _)| *first as usize <= chunk + 1)
+            .map_or(0, |(_, per_chunk)| *per_chunk);
+
+        let mut offset = match usize::try_from(offset) {
+            Ok(offset) => offset,
+            Err(_) => break,
+        };
+        for _ in 0..per_chunk {
+            let size = match track.sizes.get(index) {
+                Some(&size) => size as usize,
+                None => break 'chunks,
+            };
+            let sample = offset.checked_add(size).and_then(|end| data.get(offset..end));
+            let annexb = match sample.and_then(|sample| to_annexb(sample, track.length_size)) {
+                Some(annexb) => annexb,
+                None => break 'chunks,
+            };
+
+            // Parameter sets are only in moov, the muxer wants them in-band
+            let data = match samples.is_empty() {
+                true => [&[0, 0, 0, 1][..], &track.sps, &[0, 0, 0, 1], &track.pps, &annexb].concat(),
+                false => annexb,
+            };
+            samples.push(Sample { timestamp: to_ticks(time), data });
+
+            time += track.durations.get(index).copied().unwrap_or_default() as u64;
+            offset += size;
+            index += 1;
+        }
+    }
+
+    if index < track.sizes.len() {
+        warn!("[Recording] {} of {} samples lost", track.sizes.len() - index, track.sizes.len());
+    }
+
+    (samples, to_ticks(track.skip))
+}
+
+// NAL units of mdat until the first one cut short, grouped into
+// access units and timed by the framerate
+#[cfg(feature = "mux-mp4")]
+fn scanned_samples(mdat: &[u8]) -> Vec<Sample> {
+    let mut annexb = Vec::with_capacity(mdat.len());
+    let mut pos = 0;
+
+    while let Some(len) = be32(mdat, pos) {
+        let nal = match mdat.get(pos + 4..pos + 4 + len as usize) {
+            Some(nal) if !nal.is_empty() => nal,
+            _ => break,
+        };
+        annexb.extend_from_slice(&[0, 0, 0, 1]);
+        annexb.extend_from_slice(nal);
+        pos += 4 + len as usize;
+    }
+
+    let framerate = h264::nal_units(&annexb)
+        .into_iter()
+        .find_map(h264::parse_sps)
+        .and_then(|sps| sps.framerate)
+        .unwrap_or(DEFAULT_FRAMERATE);
+    let duration = TIMESCALE as f64 / framerate;
+
+    h264::access_units(repair_h264(&annexb))
+        .into_iter()
+        .enumerate()
+        .map(|(i, unit)| Sample { timestamp: (i as f64 * duration) as u32, data: unit.to_vec() })
+        .collect()
+}
+
+// Length prefixed NAL units to Annex B, None if one overruns
+#[cfg(feature = "mux-mp4")]
+fn to_annexb(sample: &[u8], length_size: usize) -> Option<Vec<u8>> {
+    let mut annexb = Vec::with_capacity(sample.len() + 4);
+    let mut pos = 0;
+
+    while pos < sample.len() {
+        let len = sample
+            .get(pos..pos + length_size)?
+            .iter()
+            .fold(0usize, |len, &byte| len << 8 | byte as usize);
+        annexb.extend_from_slice(&[0, 0, 0, 1]);
+        annexb.extend_from_slice(sample.get(pos + length_size..pos + length_size + len)?);
+        pos += length_size + len;
+    }
+
+    Some(annexb)
+}
+
+// Boxes in 'data' after 'skip' bytes (e.g. version and entry count)
+// as (type, content). The last one is cut short when the data ends
+// inside it
+#[cfg(feature = "mux-mp4")]
+fn boxes(data: &[u8], skip: usize) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut pos = skip;
+
+    while let (Some(size), Some(kind)) = (be32(data, pos), data.get(pos + 4..pos + 8)) {
+        let kind = [kind[0], kind[1], kind[2], kind[3]];
+        let (header, size) = match size {
+            0 => (8, (data.len() - pos) as u64),
+            1 => match be64(data, pos + 8) {
+                Some(size) => (16, size),
+                None => break,
+            },
+            size => (8, size as u64),
+        };
+        if size < header as u64 {
+            break;
+        }
+
+        // A damaged size may run past the end, or past u64
+        let end = (pos as u64).saturating_add(size).min(data.len() as u64) as usize;
+        if end < pos + header {
+            break;
+        }
+        boxes.push((kind, &data[pos + header..end]));
+        pos = end;
+    }
+
+    boxes
+}
+
+// Content of the first child box of 'kind'
+#[cfg(feature = "mux-mp4")]
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data, 0).into_iter().find(|(found, _)| found == kind).map(|(_, content)| content)
+}
+
+// Offsets of the entries of a table, as many as fit in 'data'
+#[cfg(feature = "mux-mp4")]
+fn table(data: &[u8], start: usize, count: usize, entry_len: usize) -> impl Iterator<Item = usize> {
+    let fits = data.len().saturating_sub(start) / entry_len;
+    (0..count.min(fits)).map(move |entry| start + entry * entry_len)
+}
+
+#[cfg(feature = "mux-mp4")]
+fn be16(data: &[u8], at: usize) -> Option<u16> {
+    let bytes = data.get(at..at + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+#[cfg(feature = "mux-mp4")]
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[cfg(feature = "mux-mp4")]
+fn be64(data: &[u8], at: usize) -> Option<u64> {
+    Some((be32(data, at)? as u64) << 32 | be32(data, at + 4)? as u64)
+}
@@ -0,0 +1,91 @@
+use crate::rtp::RtpHeader;
+use log::info;
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+// The same stream received over two network paths, e.g. a camera
+// reached through two switches or a multicast group joined on two
+// interfaces (see Rtp::add_redundant_path). Whichever copy of a
+// packet arrives first is handed on and the other dropped, so losing
+// either path loses nothing. Both paths must carry the very same
+// RTP stream: same SSRC, sequence numbers and timestamps
+
+// Packets remembered to spot the second copy, a few seconds of video
+const SEEN: usize = 2048;
+// A path quiet this long while the other delivers is reported down
+const PATH_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PathStats {
+    pub packets: u64, // received on the path
+    pub first: u64,   // of them, the copy handed on
+    pub last_packet: Option<Instant>,
+}
+
+impl PathStats {
+    // Something arrived on the path within 'timeout'
+    pub fn is_alive(&self, timeout: Duration) -> bool {
+        self.last_packet.is_some_and(|last_packet| last_packet.elapsed() < timeout)
+    }
+}
+
+pub(crate) struct Redundancy {
+    socket: UdpSocket, // the second path, the first is Rtp's own
+    seen: HashSet<(u32, u16, u32)>,
+    order: VecDeque<(u32, u16, u32)>,
+    paths: [PathStats; 2],
+    down: [bool; 2],
+}
+
+impl Redundancy {
+    pub(crate) fn new(socket: UdpSocket) -> Self {
+        Redundancy {
+            socket,
+            seen: HashSet::new(),
+            order: VecDeque::new(),
+            paths: [PathStats::default(); 2],
+            down: [false; 2],
+        }
+    }
+
+    pub(crate) fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    // Whether a packet received on 'path' (0 or 1) is the first copy,
+    // keyed by SSRC, sequence number and timestamp
+    pub(crate) fn accept(&mut self, path: usize, header: &RtpHeader) -> bool {
+        let now = Instant::now();
+        self.paths[path].packets += 1;
+        self.paths[path].last_packet = Some(now);
+
+        if self.down[path] {
+            info!("[Rtp] Redundant path {path} is back");
+            self.down[path] = false;
+        }
+        let other = 1 - path;
+        if !self.down[other] && self.paths[other].last_packet.is_some_and(|last| now.duration_since(last) > PATH_TIMEOUT) {
+            info!("[Rtp] Redundant path {other} is down, carrying on with path {path}");
+            self.down[other] = true;
+        }
+
+        let key = (header.ssrc, header.sequence_number, header.timestamp);
+        if !self.seen.insert(key) {
+            return false;
+        }
+        self.order.push_back(key);
+        if self.order.len() > SEEN {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+
+        self.paths[path].first += 1;
+        true
+    }
+
+    pub(crate) fn paths(&self) -> [PathStats; 2] {
+        self.paths
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+// Fixture of RTP packets with the time each arrived, relative to
+// the first packet, which can be played back into a Depacketizer
+// (or anything else taking RTP packets).
+//
+// Playback waits using tokio's clock, so inside a test with the
+// clock paused (tokio::time::pause, needs tokio's "test-util"
+// feature) it runs on virtual time: deterministic and without any
+// real sleeping. Keep-alive and timeout logic can be tested the
+// same way by advancing the paused clock.
+#[derive(Default)]
+pub struct Replay {
+    packets: Vec<(Duration, Vec<u8>)>,
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        Replay {
+            packets: Vec::new(),
+        }
+    }
+
+    // Add a packet arriving 'offset' after the start of the fixture
+    pub fn push(&mut self, offset: Duration, packet: &[u8]) {
+        self.packets.push((offset, packet.to_vec()));
+        self.packets.sort_by_key(|(offset, _)| *offset);
+    }
+
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    // Packets in order with their offsets
+    pub fn packets(&self) -> impl Iterator<Item = (Duration, &[u8])> {
+        self.packets
+            .iter()
+            .map(|(offset, packet)| (*offset, packet.as_slice()))
+    }
+
+    // Hand every packet to 'on_packet' at its original timing
+    // e.g. replay.play(|packet| depacketizer.push_rtp_packet(packet))
+    pub async fn play<F>(&self, mut on_packet: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        let start = Instant::now();
+
+        for (offset, packet) in &self.packets {
+            sleep_until(start + *offset).await;
+            on_packet(packet);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use log::debug;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+
+// Host name resolution for the RTSP server address
+//
+// The default SystemResolver asks the OS and, for .local names it
+// can't resolve, falls back to a multicast DNS query. Any other
+// resolver (hickory-dns, a service registry...) can be plugged in
+// with RtspBuilder::resolver by implementing Resolver.
+
+pub type ResolveFuture = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+pub trait Resolver: Send + Sync {
+    // Addresses of 'host' (a domain name, never an IP literal), in order of preference
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture;
+}
+
+// How long to wait for an mDNS answer
+const DEFAULT_MDNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+const MDNS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353);
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+// The OS resolver (getaddrinfo), with mDNS for .local names
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        let host = host.to_string();
+
+        Box::pin(async move {
+            let result = tokio::net::lookup_host((host.as_str(), port))
+                .await
+                .map(|addrs| addrs.collect::<Vec<SocketAddr>>());
+
+            match result {
+                Ok(addrs) if !addrs.is_empty() => Ok(addrs),
+                _ if is_local(&host) => {
+                    debug!("[Resolver] {host} not resolved by the system, trying mDNS");
+                    MdnsResolver::new().resolve(&host, port).await
+                }
+                result => result,
+            }
+        })
+    }
+}
+
+// Multicast DNS (RFC 6762) one-shot query, e.g. for camera.local
+// Only IPv4 addresses (A records) are asked for
+#[derive(Clone, Copy, Debug)]
+pub struct MdnsResolver {
+    timeout: Duration,
+}
+
+impl MdnsResolver {
+    pub fn new() -> Self {
+        MdnsResolver { timeout: DEFAULT_MDNS_TIMEOUT }
+    }
+
+    pub fn with_timeout(timeout: Duration) -> Self {
+        MdnsResolver { timeout }
+    }
+}
+
+impl Default for MdnsResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Resolver for MdnsResolver {
+    fn resolve(&self, host: &str, port: u16) -> ResolveFuture {
+        let host = host.trim_end_matches('.').to_string();
+        let timeout = self.timeout;
+
+        Box::pin(async move {
+            let ips = tokio::time::timeout(timeout, mdns_query(&host))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("no mDNS answer for {host}")))??;
+
+            Ok(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+        })
+    }
+}
+
+fn is_local(host: &str) -> bool {
+    host.trim_end_matches('.').to_ascii_lowercase().ends_with(".local")
+}
+
+// Sent from an ephemeral port, which makes responders answer
+// straight back to us (legacy unicast, RFC 6762 section 6.7)
+async fn mdns_query(host: &str) -> io::Result<Vec<IpAddr>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let id = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.subsec_nanos() as u16)
+        .unwrap_or_default();
+
+    socket.send_to(&mdns_request(id, host)?, MDNS_ADDR).await?;
+
+    let mut buf = [0u8; 1500];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf).await?;
+        let ips = parse_mdns_response(&buf[..len], host);
+
+        if !ips.is_empty() {
+            debug!("[Resolver] mDNS {host} -> {ips:?} (from {from})");
+            return Ok(ips);
+        }
+    }
+}
+
+fn mdns_request(id: u16, host: &str) -> io::Result<Vec<u8>> {
+    let mut request = Vec::with_capacity(18 + host.len());
+    request.extend_from_slice(&id.to_be_bytes());
+    request.extend_from_slice(&[0, 0]); // flags, standard query
+    request.extend_from_slice(&[0, 1]); // one question
+    request.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+
+    for label in host.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid host name {host}")));
+        }
+        request.push(label.len() as u8);
+        request.extend_from_slice(label.as_bytes());
+    }
+    request.push(0);
+
+    request.extend_from_slice(&TYPE_A.to_be_bytes());
+    request.extend_from_slice(&[0x80, 0x01]); // IN class, unicast response wanted
+
+    Ok(request)
+}
+
+// Addresses answered for 'host', other records are ignored
+fn parse_mdns_response(response: &[u8], host: &str) -> Vec<IpAddr> {
+    let mut ips = Vec::new();
+    if response.len() < 12 || response[2] & 0x80 == 0 {
+        return ips;
+    }
+
+    let questions = u16::from_be_bytes([response[4], response[5]]);
+    let records = u16::from_be_bytes([response[6], response[7]]) as usize
+        + u16::from_be_bytes([response[8], response[9]]) as usize
+        + u16::from_be_bytes([response[10], response[11]]) as usize;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        match read_name(response, pos) {
+            Some((_, next)) => pos = next + 4,
+            None => return ips,
+        }
+    }
+
+    for _ in 0..records {
+        let (name, next) = match read_name(response, pos) {
+            Some(name) => name,
+            None => break,
+        };
+        if next + 10 > response.len() {
+            break;
+        }
+
+        let kind = u16::from_be_bytes([response[next], response[next + 1]]);
+        let len = u16::from_be_bytes([response[next + 8], response[next + 9]]) as usize;
+        let data = match response.get(next + 10..next + 10 + len) {
+            Some(data) => data,
+            None => break,
+        };
+        pos = next + 10 + len;
+
+        if !name.eq_ignore_ascii_case(host) {
+            continue;
+        }
+
+        match (kind, data.len()) {
+            (TYPE_A, 4) => ips.push(IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3]))),
+            (TYPE_AAAA, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(data);
+                ips.push(IpAddr::V6(Ipv6Addr::from(octets)));
+            }
+            _ => (),
+        }
+    }
+
+    ips
+}
+
+// Name at 'pos', following compression pointers, and where the
+// record continues after it
+fn read_name(message: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+
+    // Bounded so a pointer loop can't spin forever
+    for _ in 0..128 {
+        let len = *message.get(pos)? as usize;
+
+        match len {
+            0 => {
+                let end = end.unwrap_or(pos + 1);
+                return Some((labels.join("."), end));
+            }
+            len if len & 0xC0 == 0xC0 => {
+                let offset = (len & 0x3F) << 8 | *message.get(pos + 1)? as usize;
+                end.get_or_insert(pos + 2);
+                pos = offset;
+            }
+            len => {
+                let label = message.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).to_string());
+                pos += 1 + len;
+            }
+        }
+    }
+
+    None
+}
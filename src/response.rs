@@ -0,0 +1,116 @@
+use bytes::Bytes;
+use std::collections::HashMap;
+
+// Response to an RTSP request
+//
+// RTSP/1.0 200 OK\r\n          <- version, status code, reason phrase
+// CSeq: 2\r\n                  <- headers (names are case-insensitive)
+// Content-Length: 460\r\n
+// \r\n
+// v=0 ...                      <- body, Content-Length bytes
+#[derive(Clone, Debug)]
+pub struct RtspResponse {
+    pub version: String,
+    pub status: u16,
+    pub reason: String,
+    // Keyed by lower case header name
+    headers: HashMap<String, String>,
+    pub body: Bytes,
+}
+
+impl RtspResponse {
+    // None if the status line can't be parsed
+    pub fn parse(response: &[u8]) -> Option<Self> {
+        // Only the header section is text, the body and anything
+        // following it (e.g. interleaved RTP) is kept as bytes
+        let (head, body) = match find_header_end(response) {
+            Some(end) => response.split_at(end),
+            None => (response, &[][..]),
+        };
+
+        let head = String::from_utf8_lossy(head);
+        let mut lines = head.lines();
+
+        // e.g. RTSP/1.0 200 OK
+        let mut status_line = lines.next()?.splitn(3, ' ');
+        let version = status_line.next()?.to_string();
+        if !version.starts_with("RTSP/") {
+            return None;
+        }
+        let status = status_line.next()?.trim().parse::<u16>().ok()?;
+        let reason = status_line.next().unwrap_or_default().trim().to_string();
+
+        let mut headers: HashMap<String, String> = HashMap::new();
+        for line in lines.take_while(|line| !line.is_empty()) {
+            if let Some((name, value)) = line.split_once(':') {
+                let name = name.trim().to_ascii_lowercase();
+                let value = value.trim();
+
+                // Repeated headers are combined as a list
+                headers
+                    .entry(name)
+                    .and_modify(|existing| {
+                        existing.push_str(", ");
+                        existing.push_str(value);
+                    })
+                    .or_insert_with(|| value.to_string());
+            }
+        }
+
+        let mut response = RtspResponse {
+            version,
+            status,
+            reason,
+            headers,
+            body: Bytes::new(),
+        };
+
+        // Body ends at Content-Length when given
+        let body = match response.content_length() {
+            Some(len) => &body[..len.min(body.len())],
+            None => body,
+        };
+        response.body = Bytes::copy_from_slice(body);
+
+        Some(response)
+    }
+
+    // Header value, name is case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(|value| value.as_str())
+    }
+
+    // All headers as (lower case name, value)
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("Content-Length")?.parse().ok()
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn is_redirect(&self) -> bool {
+        (300..400).contains(&self.status)
+    }
+
+    // Body as text (e.g. SDP or text/parameters)
+    pub fn body_text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+}
+
+// Index just past the blank line (\r\n\r\n) ending the headers
+pub(crate) fn find_header_end(response: &[u8]) -> Option<usize> {
+    response
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+}
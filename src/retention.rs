@@ -0,0 +1,104 @@
+use log::{debug, info};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs;
+
+// Called with each recording just before it is deleted
+type BeforeDeleteFn = Box<dyn FnMut(&Path) + Send>;
+
+// Keeps a directory of recordings (one directory per camera) within
+// a disk quota and/or maximum age by deleting the oldest files first.
+//
+// let mut retention = Retention::new("recordings/front-door");
+// retention.set_max_bytes(Some(50 * 1024 * 1024 * 1024));
+// retention.set_max_age(Some(Duration::from_secs(30 * 24 * 3600)));
+// retention.on_before_delete(|path| archive(path));
+// retention.enforce().await?;
+pub struct Retention {
+    dir: PathBuf,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    before_delete: Option<BeforeDeleteFn>,
+}
+
+impl Retention {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Retention {
+            dir: dir.as_ref().to_path_buf(),
+            max_bytes: None,
+            max_age: None,
+            before_delete: None,
+        }
+    }
+
+    // Total size of all recordings in the directory
+    pub fn set_max_bytes(&mut self, max_bytes: Option<u64>) -> &mut Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    // Recordings last modified longer ago than this are deleted
+    pub fn set_max_age(&mut self, max_age: Option<Duration>) -> &mut Self {
+        self.max_age = max_age;
+        self
+    }
+
+    // e.g. to copy a recording to other storage before it goes
+    pub fn on_before_delete<F: FnMut(&Path) + Send + 'static>(&mut self, before_delete: F) -> &mut Self {
+        self.before_delete = Some(Box::new(before_delete));
+        self
+    }
+
+    // Delete recordings until the directory is within its limits
+    // Returns the paths deleted, oldest first
+    pub async fn enforce(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut recordings = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                recordings.push((metadata.modified()?, metadata.len(), entry.path()));
+            }
+        }
+
+        recordings.sort();
+
+        let now = SystemTime::now();
+        let mut total_bytes: u64 = recordings.iter().map(|(_, len, _)| len).sum();
+        let mut deleted = Vec::new();
+
+        for (modified, len, path) in recordings {
+            let too_old = match self.max_age {
+                Some(max_age) => now.duration_since(modified).unwrap_or_default() > max_age,
+                None => false,
+            };
+            let over_quota = match self.max_bytes {
+                Some(max_bytes) => total_bytes > max_bytes,
+                None => false,
+            };
+
+            // Oldest first, so once one is kept the rest are too
+            if !too_old && !over_quota {
+                break;
+            }
+
+            if let Some(before_delete) = &mut self.before_delete {
+                before_delete(&path);
+            }
+
+            debug!("[Retention] Deleting {}", path.display());
+            fs::remove_file(&path).await?;
+
+            total_bytes -= len;
+            deleted.push(path);
+        }
+
+        if !deleted.is_empty() {
+            info!("[Retention] Deleted {} recordings from {}", deleted.len(), self.dir.display());
+        }
+
+        Ok(deleted)
+    }
+}
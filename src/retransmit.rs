@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// Retransmission for lossy links (RFC 4585 generic NACK, RFC 4588
+// rtx packets). Packets after a gap are held back until what is
+// missing arrives again, or the hold time runs out and it is given
+// up on, so the depacketizer still sees them in order
+
+// A gap this large is a restart of the sequence, not a loss
+const MAX_GAP: u64 = 256;
+// Held packets beyond this are handed on without waiting
+const MAX_HELD: usize = 512;
+// Time before asking again for a packet still missing
+const NACK_INTERVAL: Duration = Duration::from_millis(40);
+
+pub(crate) enum Push {
+    // Next in sequence, nothing held: hand it on right away
+    InOrder,
+    // Kept, see pop
+    Held,
+    // Duplicate, or too late as it was given up on
+    Dropped,
+}
+
+pub(crate) struct Reorder {
+    hold: Duration,
+    ssrc: Option<u32>,
+    // Extended sequence numbers, starting high enough that a packet
+    // before the first one doesn't go below zero
+    next: u64,    // to hand on next
+    highest: u64, // received so far
+    held: BTreeMap<u64, (Instant, Vec<u8>)>,
+    missing: BTreeMap<u64, Option<Instant>>, // when last NACKed
+}
+
+impl Reorder {
+    pub(crate) fn new(hold: Duration) -> Self {
+        Reorder {
+            hold,
+            ssrc: None,
+            next: 0,
+            highest: 0,
+            held: BTreeMap::new(),
+            missing: BTreeMap::new(),
+        }
+    }
+
+    // Packets already held wait for the new hold from now on
+    pub(crate) fn set_hold(&mut self, hold: Duration) {
+        self.hold = hold;
+    }
+
+    pub(crate) fn push(&mut self, ssrc: u32, sequence_number: u16, packet: &[u8]) -> Push {
+        // A new SSRC is a new stream, so is a sequence far behind
+        let behind = (self.next as u16).wrapping_sub(sequence_number) as i16 as i64;
+        if self.ssrc != Some(ssrc) || behind > MAX_GAP as i64 {
+            *self = Reorder { ssrc: Some(ssrc), ..Reorder::new(self.hold) };
+            self.next = (1 << 32) + sequence_number as u64;
+            self.highest = self.next;
+        }
+
+        let delta = sequence_number.wrapping_sub(self.next as u16) as i16 as i64;
+        let seq = self.next.saturating_add_signed(delta);
+
+        if seq < self.next || self.held.contains_key(&seq) {
+            return Push::Dropped;
+        }
+        if seq == self.next && self.held.is_empty() {
+            self.next += 1;
+            self.highest = self.highest.max(seq);
+            return Push::InOrder;
+        }
+
+        if seq > self.highest {
+            match seq - self.highest > MAX_GAP {
+                // Nothing to wait for, hand on what is held
+                true => self.missing.clear(),
+                false => self.missing.extend((self.highest + 1..seq).map(|missing| (missing, None))),
+            }
+            self.highest = seq;
+        }
+        self.missing.remove(&seq);
+        self.held.insert(seq, (Instant::now(), packet.to_vec()));
+
+        Push::Held
+    }
+
+    // Next held packet to hand on: nothing is missing before it any
+    // more, or the hold time ran out waiting for it
+    pub(crate) fn pop(&mut self) -> Option<Vec<u8>> {
+        let (&seq, (received, _)) = self.held.first_key_value()?;
+
+        let waiting = self.missing.range(..seq).next().is_some();
+        if waiting && received.elapsed() < self.hold && self.held.len() <= MAX_HELD {
+            return None;
+        }
+
+        // Give up on whatever is still missing before it
+        self.missing.retain(|&missing, _| missing > seq);
+        self.next = seq + 1;
+        self.held.pop_first().map(|(_, (_, packet))| packet)
+    }
+
+    // Sequence numbers to NACK now: missing and not asked for lately
+    pub(crate) fn nack(&mut self) -> Vec<u16> {
+        let now = Instant::now();
+        let mut lost = Vec::new();
+
+        for (&seq, nacked) in &mut self.missing {
+            if nacked.is_none_or(|nacked| now.duration_since(nacked) >= NACK_INTERVAL) {
+                *nacked = Some(now);
+                lost.push(seq as u16);
+            }
+        }
+
+        lost
+    }
+
+    pub(crate) fn ssrc(&self) -> Option<u32> {
+        self.ssrc
+    }
+}
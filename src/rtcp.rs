@@ -0,0 +1,484 @@
+use crate::rtp::RtpHeader;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// RTCP (RFC 3550 section 6), received on the port after the RTP port.
+// Sender reports tie the RTP timestamps of a stream to the server's
+// wall clock, which is what makes timing across streams and cameras
+// comparable.
+
+pub const PACKET_TYPE_SR: u8 = 200;
+pub const PACKET_TYPE_RR: u8 = 201;
+pub const PACKET_TYPE_SDES: u8 = 202;
+pub const PACKET_TYPE_BYE: u8 = 203;
+pub const PACKET_TYPE_APP: u8 = 204;
+// Transport layer feedback (RFC 4585 section 6.2)
+pub const PACKET_TYPE_RTPFB: u8 = 205;
+// Payload specific feedback (RFC 4585 section 6.3)
+pub const PACKET_TYPE_PSFB: u8 = 206;
+
+// Feedback message type of a generic NACK
+pub const FMT_GENERIC_NACK: u8 = 1;
+// and of a picture loss indication
+pub const FMT_PLI: u8 = 1;
+
+// SDES item types (RFC 3550 section 6.5)
+pub const SDES_CNAME: u8 = 1;
+pub const SDES_NAME: u8 = 2;
+pub const SDES_EMAIL: u8 = 3;
+pub const SDES_PHONE: u8 = 4;
+pub const SDES_LOC: u8 = 5;
+pub const SDES_TOOL: u8 = 6;
+pub const SDES_NOTE: u8 = 7;
+
+// How often receivers should report (RFC 3550 section 6.2 minimum)
+pub const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+// Seconds from the NTP epoch (1900) to the Unix epoch (1970)
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+// 0                   1                   2                   3
+// 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |V=2|P|    RC   |   PT=SR=200   |             length            |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                         SSRC of sender                        |
+// +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+// |              NTP timestamp, most significant word             |
+// |             NTP timestamp, least significant word             |
+// |                         RTP timestamp                         |
+// |                     sender's packet count                     |
+// |                      sender's octet count                     |
+// +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+// Report blocks about what the sender received are ignored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SenderReport {
+    pub ssrc: u32,
+    pub ntp_timestamp: u64, // seconds since 1900 in the upper 32 bits
+    pub rtp_timestamp: u32,
+    pub packet_count: u32,
+    pub octet_count: u32,
+}
+
+impl SenderReport {
+    // A single SR packet, None if it isn't one or is too short
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < 28 || packet[0] >> 6 != 2 || packet[1] != PACKET_TYPE_SR {
+            return None;
+        }
+
+        let word = |at: usize| u32::from_be_bytes([packet[at], packet[at + 1], packet[at + 2], packet[at + 3]]);
+
+        Some(SenderReport {
+            ssrc: word(4),
+            ntp_timestamp: (word(8) as u64) << 32 | word(12) as u64,
+            rtp_timestamp: word(16),
+            packet_count: word(20),
+            octet_count: word(24),
+        })
+    }
+
+    // Server's wall clock when the report was sent
+    pub fn wallclock(&self) -> SystemTime {
+        ntp_to_system_time(self.ntp_timestamp)
+    }
+
+    // Wall clock time of 'rtp_timestamp', which may be before or after
+    // the report (within half the timestamp range)
+    pub fn wallclock_at(&self, rtp_timestamp: u32, clock_rate: u32) -> SystemTime {
+        let ticks = rtp_timestamp.wrapping_sub(self.rtp_timestamp) as i32 as i64;
+        let micros = ticks * 1_000_000 / clock_rate.max(1) as i64;
+
+        match micros >= 0 {
+            true => self.wallclock() + Duration::from_micros(micros as u64),
+            false => self.wallclock() - Duration::from_micros(micros.unsigned_abs()),
+        }
+    }
+}
+
+// Goodbye (RFC 3550 section 6.6): the sources listed stopped sending,
+// e.g. the server ended the stream
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bye {
+    pub ssrcs: Vec<u32>,
+    pub reason: Option<String>,
+}
+
+impl Bye {
+    // A single BYE packet, None if it isn't one or is too short
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < 4 || packet[0] >> 6 != 2 || packet[1] != PACKET_TYPE_BYE {
+            return None;
+        }
+
+        let count = (packet[0] & 0b00011111) as usize;
+        let ssrcs = packet
+            .get(4..4 + count * 4)?
+            .chunks_exact(4)
+            .map(|ssrc| u32::from_be_bytes([ssrc[0], ssrc[1], ssrc[2], ssrc[3]]))
+            .collect();
+
+        // Optional: length byte and text
+        let reason = packet.get(4 + count * 4).and_then(|&len| {
+            let start = 5 + count * 4;
+            packet
+                .get(start..start + len as usize)
+                .map(|reason| String::from_utf8_lossy(reason).into_owned())
+        });
+
+        Some(Bye { ssrcs, reason })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RtcpPacket {
+    SenderReport(SenderReport),
+    Bye(Bye),
+    // Anything not handled (yet)
+    Other { packet_type: u8 },
+}
+
+// Split a compound RTCP packet into its packets, stopping at the
+// first one whose length doesn't add up
+pub fn parse_compound(data: &[u8]) -> Vec<RtcpPacket> {
+    let mut packets = Vec::new();
+    let mut pos = 0;
+
+    while data.len() - pos >= 4 {
+        // Length is in 32 bit words minus one
+        let len = (u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize + 1) * 4;
+        let packet = match data.get(pos..pos + len) {
+            Some(packet) if packet[0] >> 6 == 2 => packet,
+            _ => break,
+        };
+
+        let packet_type = packet[1];
+        match packet_type {
+            PACKET_TYPE_SR => match SenderReport::parse(packet) {
+                Some(report) => packets.push(RtcpPacket::SenderReport(report)),
+                None => packets.push(RtcpPacket::Other { packet_type }),
+            },
+            PACKET_TYPE_BYE => match Bye::parse(packet) {
+                Some(bye) => packets.push(RtcpPacket::Bye(bye)),
+                None => packets.push(RtcpPacket::Other { packet_type }),
+            },
+            _ => packets.push(RtcpPacket::Other { packet_type }),
+        }
+
+        pos += len;
+    }
+
+    packets
+}
+
+// 64 bit NTP timestamp (32.32 fixed point seconds since 1900)
+pub fn ntp_to_system_time(ntp: u64) -> SystemTime {
+    let seconds = (ntp >> 32).saturating_sub(NTP_UNIX_OFFSET);
+    let nanos = ((ntp & 0xFFFF_FFFF) * 1_000_000_000) >> 32;
+
+    UNIX_EPOCH + Duration::new(seconds, nanos as u32)
+}
+
+// Receiver report (RR, RFC 3550 section 6.4.2) sent back to the
+// server, some servers drop sessions that never send one. 'ssrc'
+// is our own, one report block per stream received
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReceiverReport {
+    pub ssrc: u32,
+    pub blocks: Vec<ReportBlock>,
+}
+
+// Reception of one stream since the previous report
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportBlock {
+    pub ssrc: u32,                // of the stream
+    pub fraction_lost: u8,        // out of 256, since the last report
+    pub cumulative_lost: i32,     // 24 bits
+    pub highest_sequence: u32,    // extended with the wrap count
+    pub jitter: u32,              // in RTP timestamp units
+    pub last_sr: u32,             // middle 32 bits of the last SR's NTP timestamp
+    pub delay_since_last_sr: u32, // 1/65536 seconds
+}
+
+impl ReceiverReport {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let count = self.blocks.len().min(31);
+        let words = 1 + 6 * count;
+
+        let mut packet = Vec::with_capacity(4 + words * 4);
+        packet.push(0b10000000 | count as u8); // version 2
+        packet.push(PACKET_TYPE_RR);
+        packet.extend_from_slice(&(words as u16).to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+
+        for block in &self.blocks[..count] {
+            let cumulative_lost = block.cumulative_lost.clamp(-0x800000, 0x7FFFFF) as u32 & 0xFFFFFF;
+
+            packet.extend_from_slice(&block.ssrc.to_be_bytes());
+            packet.extend_from_slice(&(((block.fraction_lost as u32) << 24) | cumulative_lost).to_be_bytes());
+            packet.extend_from_slice(&block.highest_sequence.to_be_bytes());
+            packet.extend_from_slice(&block.jitter.to_be_bytes());
+            packet.extend_from_slice(&block.last_sr.to_be_bytes());
+            packet.extend_from_slice(&block.delay_since_last_sr.to_be_bytes());
+        }
+
+        packet
+    }
+}
+
+// How this client introduces itself in RTCP, sent along with every
+// receiver report so operators can tell receivers of the same stream
+// apart. None items are left out
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Identity {
+    // Canonical name, e.g. user@host. Always sent, made up from the
+    // SSRC when None
+    pub cname: Option<String>,
+    pub name: Option<String>, // e.g. "Lobby NVR"
+    pub email: Option<String>,
+    pub phone: Option<String>,
+    pub location: Option<String>,
+    pub tool: Option<String>, // e.g. "rtsp-rtp-rs 0.3"
+    pub note: Option<String>,
+}
+
+impl Identity {
+    // SDES items for our 'ssrc', CNAME first
+    pub fn description(&self, ssrc: u32) -> SourceDescription {
+        let cname = self.cname.clone().unwrap_or_else(|| format!("rtsp-rtp-rs-{ssrc:08x}"));
+
+        #[rustfmt::skip]
+        let items = [
+            (SDES_NAME,  &self.name),
+            (SDES_EMAIL, &self.email),
+            (SDES_PHONE, &self.phone),
+            (SDES_LOC,   &self.location),
+            (SDES_TOOL,  &self.tool),
+            (SDES_NOTE,  &self.note),
+        ];
+
+        SourceDescription {
+            ssrc,
+            items: std::iter::once((SDES_CNAME, cname))
+                .chain(items.into_iter().filter_map(|(item, text)| Some((item, text.clone()?))))
+                .collect(),
+        }
+    }
+}
+
+// SDES packet (RFC 3550 section 6.5) with a single chunk
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceDescription {
+    pub ssrc: u32,
+    pub items: Vec<(u8, String)>, // item type, text
+}
+
+impl SourceDescription {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut chunk = self.ssrc.to_be_bytes().to_vec();
+        for (item, text) in &self.items {
+            // Text is at most 255 bytes, cut on a character boundary
+            let mut len = text.len().min(255);
+            while !text.is_char_boundary(len) {
+                len -= 1;
+            }
+            chunk.push(*item);
+            chunk.push(len as u8);
+            chunk.extend_from_slice(&text.as_bytes()[..len]);
+        }
+
+        // Null item ending the list, then padding to a 32 bit boundary
+        chunk.push(0);
+        chunk.resize(chunk.len().next_multiple_of(4), 0);
+
+        let mut packet = Vec::with_capacity(4 + chunk.len());
+        packet.push(0b10000000 | 1); // version 2, 1 chunk
+        packet.push(PACKET_TYPE_SDES);
+        packet.extend_from_slice(&((chunk.len() / 4) as u16).to_be_bytes());
+        packet.extend_from_slice(&chunk);
+        packet
+    }
+}
+
+// Generic NACK (RFC 4585 section 6.2.1) asking the sender of
+// 'media_ssrc' to send these sequence numbers again. Each FCI entry
+// is a sequence number and a bitmask of the 16 following it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenericNack {
+    pub ssrc: u32,       // ours
+    pub media_ssrc: u32, // of the stream with the losses
+    pub lost: Vec<u16>,
+}
+
+impl GenericNack {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut lost = self.lost.clone();
+        lost.sort_unstable();
+        lost.dedup();
+
+        let mut fci: Vec<(u16, u16)> = Vec::new();
+        for seq in lost {
+            match fci.last_mut() {
+                Some((pid, blp)) if seq.wrapping_sub(*pid).wrapping_sub(1) < 16 => {
+                    *blp |= 1 << seq.wrapping_sub(*pid).wrapping_sub(1);
+                }
+                _ => fci.push((seq, 0)),
+            }
+        }
+
+        let words = 2 + fci.len();
+        let mut packet = Vec::with_capacity(4 + words * 4);
+        packet.push(0b10000000 | FMT_GENERIC_NACK); // version 2
+        packet.push(PACKET_TYPE_RTPFB);
+        packet.extend_from_slice(&(words as u16).to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        packet.extend_from_slice(&self.media_ssrc.to_be_bytes());
+        for (pid, blp) in fci {
+            packet.extend_from_slice(&pid.to_be_bytes());
+            packet.extend_from_slice(&blp.to_be_bytes());
+        }
+        packet
+    }
+}
+
+// Picture loss indication (RFC 4585 section 6.3.1) asking the sender
+// of 'media_ssrc' for a keyframe
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PictureLossIndication {
+    pub ssrc: u32,       // ours
+    pub media_ssrc: u32, // of the stream to refresh
+}
+
+impl PictureLossIndication {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(12);
+        packet.push(0b10000000 | FMT_PLI); // version 2
+        packet.push(PACKET_TYPE_PSFB);
+        packet.extend_from_slice(&2u16.to_be_bytes());
+        packet.extend_from_slice(&self.ssrc.to_be_bytes());
+        packet.extend_from_slice(&self.media_ssrc.to_be_bytes());
+        packet
+    }
+}
+
+// Frame a packet for the RTSP connection (RFC 2326 section 10.12):
+// '$', the channel, 16 bit length. RTCP goes on the odd channel of
+// the interleaved pair e.g. 1 for interleaved=0-1
+pub fn interleaved(channel: u8, packet: &[u8]) -> Vec<u8> {
+    let packet = &packet[..packet.len().min(u16::MAX as usize)];
+
+    let mut frame = Vec::with_capacity(4 + packet.len());
+    frame.push(b'$');
+    frame.push(channel);
+    frame.extend_from_slice(&(packet.len() as u16).to_be_bytes());
+    frame.extend_from_slice(packet);
+    frame
+}
+
+// Keeps what a report block needs about a received stream
+// (RFC 3550 appendix A.3 and A.8)
+pub(crate) struct Reception {
+    ssrc: Option<u32>,
+    base_sequence: u16,
+    max_sequence: u16,
+    cycles: u32,
+    received: u32,
+    expected_prior: u32,
+    received_prior: u32,
+    jitter: f64,
+    transit: Option<i64>,
+    last_sr: Option<(u32, Instant)>,
+    start: Instant,
+}
+
+impl Reception {
+    pub(crate) fn new() -> Self {
+        Reception {
+            ssrc: None,
+            base_sequence: 0,
+            max_sequence: 0,
+            cycles: 0,
+            received: 0,
+            expected_prior: 0,
+            received_prior: 0,
+            jitter: 0.0,
+            transit: None,
+            last_sr: None,
+            start: Instant::now(),
+        }
+    }
+
+    pub(crate) fn packet(&mut self, header: &RtpHeader, clock_rate: u32) {
+        let sequence = header.sequence_number;
+
+        // A new SSRC is a new stream
+        if self.ssrc != Some(header.ssrc) {
+            *self = Reception { start: self.start, ..Reception::new() };
+            self.ssrc = Some(header.ssrc);
+            self.base_sequence = sequence;
+            self.max_sequence = sequence;
+        }
+
+        // Ahead of the highest so far (duplicates and late packets aren't)
+        let delta = sequence.wrapping_sub(self.max_sequence);
+        if delta != 0 && delta < 0x8000 {
+            if sequence < self.max_sequence {
+                self.cycles = self.cycles.wrapping_add(1 << 16);
+            }
+            self.max_sequence = sequence;
+        }
+        self.received = self.received.wrapping_add(1);
+
+        // Interarrival jitter, in timestamp units
+        let arrival = (self.start.elapsed().as_secs_f64() * clock_rate as f64) as i64;
+        let transit = arrival - header.timestamp as i64;
+        if let Some(previous) = self.transit {
+            let d = (transit - previous).abs() as f64;
+            self.jitter += (d - self.jitter) / 16.0;
+        }
+        self.transit = Some(transit);
+    }
+
+    // Interarrival jitter, in timestamp units
+    pub(crate) fn jitter(&self) -> f64 {
+        self.jitter
+    }
+
+    pub(crate) fn sender_report(&mut self, report: &SenderReport) {
+        self.last_sr = Some(((report.ntp_timestamp >> 16) as u32, Instant::now()));
+    }
+
+    // None until a packet arrived
+    pub(crate) fn report_block(&mut self) -> Option<ReportBlock> {
+        let ssrc = self.ssrc?;
+
+        let highest_sequence = self.cycles.wrapping_add(self.max_sequence as u32);
+        let expected = highest_sequence.wrapping_sub(self.base_sequence as u32).wrapping_add(1);
+        let cumulative_lost = expected as i64 - self.received as i64;
+
+        let expected_interval = expected.wrapping_sub(self.expected_prior) as i64;
+        let received_interval = self.received.wrapping_sub(self.received_prior) as i64;
+        let lost_interval = expected_interval - received_interval;
+        self.expected_prior = expected;
+        self.received_prior = self.received;
+
+        let fraction_lost = match expected_interval > 0 && lost_interval > 0 {
+            true => ((lost_interval << 8) / expected_interval).min(255) as u8,
+            false => 0,
+        };
+
+        let (last_sr, delay_since_last_sr) = match self.last_sr {
+            Some((last_sr, received)) => (last_sr, (received.elapsed().as_secs_f64() * 65536.0) as u32),
+            None => (0, 0),
+        };
+
+        Some(ReportBlock {
+            ssrc,
+            fraction_lost,
+            cumulative_lost: cumulative_lost.clamp(i32::MIN as i64, i32::MAX as i64) as i32,
+            highest_sequence,
+            jitter: self.jitter as u32,
+            last_sr,
+            delay_since_last_sr,
+        })
+    }
+}
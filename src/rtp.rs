@@ -1,52 +1,251 @@
-use anyhow::Result;
-use log::{debug, info, trace};
+use crate::budget::MemoryBudget;
+use crate::capture::Capture;
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::codec::{Codec, PayloadFormat, VideoInfo};
+#[cfg(feature = "decode-openh264")]
+use crate::degrade::{DecodeMode, Degradation};
+use crate::depacketizer::Depacketizer;
+#[cfg(feature = "decode-openh264")]
+use crate::frame::{Frame, Image, PixelFormat};
+use crate::h264;
+use crate::latency::LatencyProfile;
+#[cfg(feature = "decode-openh264")]
+use crate::mask::PrivacyMask;
+#[cfg(feature = "mpegts")]
+use crate::mpegts::{TsDemuxer, PAYLOAD_TYPE_MP2T};
+#[cfg(feature = "decode-openh264")]
+use crate::osd::Osd;
+use crate::packetizer::{AudioPacketizer, Packetizer};
+#[cfg(feature = "decode-openh264")]
+use crate::quality::{FrameQuality, QualityEstimator};
+use crate::redundancy::{PathStats, Redundancy};
+use crate::retransmit::{Push, Reorder};
+use crate::rtcp::{self, Bye, GenericNack, Identity, PictureLossIndication, ReceiverReport, Reception, RtcpPacket, SenderReport};
+use crate::sdp::MediaSection;
+#[cfg(feature = "srtp")]
+use crate::srtp::{MasterKey, Srtp};
+use crate::stats::{Alert, Alerts, Stats, Thresholds};
+use crate::storage::{self, StorageBackend};
+#[cfg(all(feature = "otel", feature = "decode-openh264"))]
+use crate::telemetry::Telemetry;
+use crate::error::RtpError;
+use log::{debug, info, trace, warn};
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hasher};
+#[cfg(feature = "decode-openh264")]
 use openh264::decoder::{DecodedYUV, Decoder};
-use std::net::{IpAddr, SocketAddr};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::task::{Context, Poll};
 use std::path::Path;
+use std::time::{Duration, SystemTime};
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tokio::net::UdpSocket;
 
+type Result<T> = std::result::Result<T, RtpError>;
+
+// RTP timestamp units per second for H264 (RFC 6184)
+const CLOCK_RATE_VIDEO: u32 = 90000;
+
+// Largest RTP packet received when no Blocksize was negotiated, a
+// 1500 byte ethernet MTU with room to spare
+pub const DEFAULT_MAX_PACKET_SIZE: usize = 2048;
+
+// Room for the RTP header (CSRCs, extension) on top of the Blocksize,
+// which only counts the payload
+const HEADER_ROOM: usize = 256;
+
+// Payloads of codecs without a depacketizer waiting for next_payload()
+const MAX_PAYLOADS: usize = 256;
+
+// Length of the fixed part of the RTP header
+pub const RTP_HEADER_LEN: usize = 12;
+
+// Fixed RTP header (RFC 3550 section 5.1)
+//  0                   1                   2                   3
+//  0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1 2 3 4 5 6 7 8 9 0 1
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |V=2|P|X|  CC   |M|     PT      |       sequence number         |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |                           timestamp                           |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// |           synchronization source (SSRC) identifier            |
+// +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+// |            contributing source (CSRC) identifiers             |
+// |                             ....                              |
+// +=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+=+
+// |      defined by profile       |           length              |
+// |                        header extension                       |
+// |                             ....                              |
+// +-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+-+
+// CC CSRCs follow the fixed header, then the extension when X is
+// set, its length in 32 bit words (RFC 3550 section 5.3.1)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RtpHeader {
+    pub version: u8,
+    pub padding: bool,
+    pub extension: bool,
+    pub csrc_count: u8,
+    pub marker: bool,
+    pub payload_type: u8,
+    pub sequence_number: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub csrcs: Vec<u32>,
+    // Profile defined id and data of the header extension
+    pub extension_header: Option<(u16, Vec<u8>)>,
+    // Where the payload starts: fixed header, CSRCs and extension
+    pub payload_offset: usize,
+}
+
+impl RtpHeader {
+    // None if the packet is too short or not RTP version 2
+    pub fn parse(packet: &[u8]) -> Option<Self> {
+        if packet.len() < RTP_HEADER_LEN || packet[0] >> 6 != 2 {
+            return None;
+        }
+
+        let csrc_count = (packet[0] & 0b00001111) as usize;
+        let mut offset = RTP_HEADER_LEN + 4 * csrc_count;
+        let csrcs = packet
+            .get(RTP_HEADER_LEN..offset)?
+            .chunks_exact(4)
+            .map(|csrc| u32::from_be_bytes([csrc[0], csrc[1], csrc[2], csrc[3]]))
+            .collect();
+
+        let extension_header = match packet[0] & 0b00010000 != 0 {
+            true => {
+                let extension = packet.get(offset..offset + 4)?;
+                let profile = u16::from_be_bytes([extension[0], extension[1]]);
+                let len = 4 * u16::from_be_bytes([extension[2], extension[3]]) as usize;
+
+                let data = packet.get(offset + 4..offset + 4 + len)?.to_vec();
+                offset += 4 + len;
+                Some((profile, data))
+            }
+            false => None,
+        };
+
+        // Padding count in the last byte, which counts itself
+        if packet[0] & 0b00100000 != 0 {
+            let padding = *packet.last()? as usize;
+            if padding == 0 || offset + padding > packet.len() {
+                return None;
+            }
+        }
+
+        Some(RtpHeader {
+            version: packet[0] >> 6,
+            padding: packet[0] & 0b00100000 != 0,
+            extension: packet[0] & 0b00010000 != 0,
+            csrc_count: packet[0] & 0b00001111,
+            marker: packet[1] & 0b10000000 != 0,
+            payload_type: packet[1] & 0b01111111,
+            sequence_number: u16::from_be_bytes([packet[2], packet[3]]),
+            timestamp: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+            ssrc: u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]),
+            csrcs,
+            extension_header,
+            payload_offset: offset,
+        })
+    }
+
+    // Payload of the packet this header was parsed from, without the
+    // padding some encoders add at the end
+    pub fn payload<'a>(&self, packet: &'a [u8]) -> &'a [u8] {
+        let end = match (self.padding, packet.last()) {
+            (true, Some(&padding)) => packet.len().saturating_sub(padding as usize),
+            _ => packet.len(),
+        };
+
+        packet.get(self.payload_offset..end).unwrap_or_default()
+    }
+}
+
+// A complete H264 access unit in Annex B, see Rtp::next_access_unit
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessUnit {
+    pub data: Vec<u8>,
+    pub timestamp: u32, // RTP timestamp
+    pub keyframe: bool,
+    pub capture_time: Option<SystemTime>, // see Rtp::capture_time
+}
+
+#[cfg(feature = "decode-openh264")]
+#[derive(Clone, Copy, Debug)]
 pub enum Decoders {
     OpenH264,
 }
 
 pub struct Rtp {
     socket: UdpSocket,
+    rtcp_socket: Option<UdpSocket>, // on the port after the RTP port
+    sender_report: Option<SenderReport>, // last one for this stream
+    bye: Option<Bye>, // the server ended the stream
+    reception: Reception, // for receiver reports
+    rtcp_ssrc: u32,       // ours, in receiver reports
+    identity: Identity,   // SDES sent with receiver reports
+    #[cfg(feature = "srtp")]
+    srtp: Option<Srtp>,
     addr_client: SocketAddr,
     addr_server: SocketAddr,
+    #[cfg(feature = "decode-openh264")]
     type_decoder: Option<Decoders>,
+    #[cfg(feature = "decode-openh264")]
     decoder: Option<Decoder>,
-    buf_rtp: [u8; 2048],
-    buf_temp: Vec<u8>,
-    buf_sps: Vec<u8>,
-    buf_fragments: Vec<u8>,
+    buf_rtp: Vec<u8>, // max_packet_size and a spare byte to spot truncation
     buf_all: Vec<u8>,
-    is_sps_found: bool,
-    is_start_decoding: bool,
-    is_fragment_start: bool,
-    is_fragment_end: bool,
+    #[cfg(feature = "decode-openh264")]
+    recording: bool, // into buf_all, for save_file
+    #[cfg(feature = "decode-openh264")]
+    recording_resumed: bool, // waiting for a keyframe to carry on
+    #[cfg(feature = "decode-openh264")]
+    max_recording: Option<usize>,
+    depacketizer: Depacketizer,
+    #[cfg(feature = "mpegts")]
+    ts_demuxer: TsDemuxer,
+    format: Option<PayloadFormat>,
+    sdp_video: Option<VideoInfo>,
+    header: Option<RtpHeader>, // of the last packet received
+    ssrc: Option<u32>,         // packets from any other source are dropped
+    reorder: Option<Reorder>,  // with retransmission enabled
+    redundancy: Option<Redundancy>, // second path, see add_redundant_path
+    rtx_payload_type: Option<u8>,
+    keyframe_on_join: bool,
+    joined_ssrc: Option<u32>, // last SSRC a keyframe was asked for on joining
+    #[cfg(feature = "decode-openh264")]
+    skip_backlog: bool,
+    last_len: usize,
+    payloads: VecDeque<Vec<u8>>,
+    #[cfg(feature = "decode-openh264")]
+    masks: Vec<PrivacyMask>,
+    #[cfg(feature = "decode-openh264")]
+    latest_keyframe: Option<Frame>,
+    #[cfg(feature = "decode-openh264")]
+    osd: Option<Osd>,
+    clock_base: Option<(u32, SystemTime)>, // RTP timestamp of first packet and when it arrived
+    last_timestamp: u32,
+    recv_timeout: Option<Duration>,
+    stats: Stats,
+    alerts: Option<Alerts>,
+    capture: Option<Capture>,
+    #[cfg(feature = "chaos")]
+    chaos: Option<Chaos>,
+    #[cfg(feature = "decode-openh264")]
+    pixel_format: PixelFormat,
+    #[cfg(feature = "decode-openh264")]
+    degradation: Degradation,
+    #[cfg(feature = "decode-openh264")]
+    quality: QualityEstimator,
+    #[cfg(feature = "decode-openh264")]
+    frame_quality: Option<FrameQuality>,
+    #[cfg(all(feature = "otel", feature = "decode-openh264"))]
+    telemetry: Option<Telemetry>,
 }
 
-// ----------------- NOTE
-// Most implementations will break up IDR frames
-// into fragments (e.g. FU-A)
-// see section 5.8 of RFC 6184
-
-// PAYLOAD starts at byte 14
-// which in 0 index array = 13
-// UNLESS this is a fragment (e.g. FU-A)
-// in which case it's byte 15
-// as FU-A has extra byte for header
-
-// Start prefix code (3 or 4 bytes)
-// For beginning of entire stream or SPS/PPS nal units -> 0x00 0x00 x00 0x01
-// All other nal units use -> 0x00 0x00 0x01
-
-// Byte index where NAL unit starts in RTP packet
-// This is also where the NAL header is which is 1 byte
-const NAL_UNIT_START: usize = 12;
-
 impl Rtp {
     pub async fn new(
         client_ip: Option<&str>,
@@ -54,36 +253,83 @@ impl Rtp {
         addr_server: SocketAddr,
     ) -> Result<Self> {
         // Allow manual selection of client IP which is IP that RTP/UDP server socket will listen
-        // otherwise use default of 0.0.0.0
+        // otherwise use default of 0.0.0.0 (or [::] for an IPv6 server)
         // client PORT is chosen normally before RTSP comm and sent to server during 'SETUP' command
         // server responds with it's server PORT to send RTP
-        let addr_client = match client_ip {
-            Some(ip) => SocketAddr::new(IpAddr::V4(ip.parse()?), client_port),
-            None => format!("0.0.0.0:{client_port}").parse()?,
-        };
-
+        let addr_client = client_addr(client_ip, client_port, addr_server)?;
         let socket = UdpSocket::bind(addr_client).await?;
+        let rtcp_socket = bind_rtcp(addr_client).await;
 
         let result = Rtp {
             socket,
+            rtcp_socket,
+            sender_report: None,
+            bye: None,
+            reception: Reception::new(),
+            rtcp_ssrc: random_ssrc(),
+            identity: Identity::default(),
+            #[cfg(feature = "srtp")]
+            srtp: None,
             addr_client,
             addr_server,
+            #[cfg(feature = "decode-openh264")]
             type_decoder: None,
+            #[cfg(feature = "decode-openh264")]
             decoder: None,
-            buf_rtp: [0u8; 2048],
-            buf_temp: Vec::new(),
-            buf_sps: Vec::new(),
-            buf_fragments: Vec::new(),
+            buf_rtp: vec![0u8; DEFAULT_MAX_PACKET_SIZE + 1],
             buf_all: Vec::new(),
-            is_sps_found: false,
-            is_start_decoding: false,
-            is_fragment_start: false,
-            is_fragment_end: false,
+            #[cfg(feature = "decode-openh264")]
+            recording: true,
+            #[cfg(feature = "decode-openh264")]
+            recording_resumed: false,
+            #[cfg(feature = "decode-openh264")]
+            max_recording: None,
+            depacketizer: Depacketizer::new(),
+            #[cfg(feature = "mpegts")]
+            ts_demuxer: TsDemuxer::new(),
+            format: None,
+            sdp_video: None,
+            header: None,
+            ssrc: None,
+            reorder: None,
+            redundancy: None,
+            rtx_payload_type: None,
+            keyframe_on_join: false,
+            joined_ssrc: None,
+            #[cfg(feature = "decode-openh264")]
+            skip_backlog: false,
+            last_len: 0,
+            payloads: VecDeque::new(),
+            #[cfg(feature = "decode-openh264")]
+            masks: Vec::new(),
+            #[cfg(feature = "decode-openh264")]
+            latest_keyframe: None,
+            #[cfg(feature = "decode-openh264")]
+            osd: None,
+            clock_base: None,
+            last_timestamp: 0,
+            recv_timeout: None,
+            stats: Stats::new(),
+            alerts: None,
+            capture: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+            #[cfg(feature = "decode-openh264")]
+            pixel_format: PixelFormat::default(),
+            #[cfg(feature = "decode-openh264")]
+            degradation: Degradation::new(),
+            #[cfg(feature = "decode-openh264")]
+            quality: QualityEstimator::new(),
+            #[cfg(feature = "decode-openh264")]
+            frame_quality: None,
+            #[cfg(all(feature = "otel", feature = "decode-openh264"))]
+            telemetry: None,
         };
 
         Ok(result)
     }
 
+    #[cfg(feature = "decode-openh264")]
     pub async fn connect(&mut self, decoder: Decoders) -> Result<()> {
         match decoder {
             Decoders::OpenH264 => {
@@ -93,6 +339,12 @@ impl Rtp {
         }
 
         self.type_decoder = Some(decoder);
+        self.connect_raw().await
+    }
+
+    // Connect without a decoder, e.g. to record or forward the stream
+    // Access units are taken from depacketizer() instead of try_decode
+    pub async fn connect_raw(&mut self) -> Result<()> {
         // Connect to the RTP camera server using IP and port
         // provided in SETUP response
         // In the RTP specs, the RTCP server should be
@@ -103,160 +355,833 @@ impl Rtp {
         Ok(())
     }
 
-    pub async fn save_file(&self) {
+    pub async fn save_file(&self) -> Result<()> {
         let path = Path::new("video.h264");
         let display = path.display();
 
         // Open a file in write-only mode, returns `io::Result<File>`
-        let mut file = match File::create(&path).await {
-            Err(why) => panic!("couldn't create {}: {}", display, why),
-            Ok(file) => file,
+        let mut file = File::create(&path).await?;
+
+        file.write_all(&self.buf_all).await?;
+        info!("successfully wrote to {}", display);
+
+        Ok(())
+    }
+
+    // What save_file writes, to a storage backend under 'key' instead
+    pub async fn save_to(&self, storage: &dyn StorageBackend, key: &str) -> Result<()> {
+        storage::store(storage, key, &self.buf_all).await?;
+        info!("[Rtp] Saved {} bytes to {key}", self.buf_all.len());
+
+        Ok(())
+    }
+
+    pub async fn get_rtp(&mut self) -> Result<()> {
+        let (path, len) = match self.recv_timeout {
+            Some(recv_timeout) => match tokio::time::timeout(recv_timeout, self.recv()).await {
+                Ok(received) => received?,
+                Err(_) => {
+                    if let Some(alerts) = &mut self.alerts {
+                        alerts.check_stall(self.stats.stall());
+                    }
+                    return Err(RtpError::Timeout);
+                }
+            },
+            None => self.recv().await?,
         };
+        self.push_received(path, len);
+
+        Ok(())
+    }
+
+    // Next packet into buf_rtp and the path it came on, 1 for the
+    // redundant one. RTCP is handled while waiting, so a BYE ends
+    // the wait with RtpError::StreamEnded
+    async fn recv(&mut self) -> Result<(usize, usize)> {
+        loop {
+            self.check_bye()?;
 
-        match file.write_all(&self.buf_all).await {
-            Err(why) => panic!("couldn't write to {}: {}", display, why),
-            Ok(_) => info!("successfully wrote to {}", display),
+            tokio::select! {
+                len = self.socket.recv(&mut self.buf_rtp) => return Ok((0, len?)),
+                Ok(()) = redundant_readable(self.redundancy.as_ref()) => {
+                    if let Some(len) = self.try_recv_redundant()? {
+                        return Ok((1, len));
+                    }
+                }
+                Ok(()) = rtcp_readable(self.rtcp_socket.as_ref()) => self.receive_rtcp(),
+            }
         }
     }
 
-    pub async fn get_rtp(&mut self) -> Result<()> {
-        let len = self.socket.recv(&mut self.buf_rtp).await?;
-
-        // Get first 16 BITS of RTP packet which is part of header (RFC 6184)
-        let rtp_header_pt1 = &self.buf_rtp[0];
-        let rtp_header_pt2 = &self.buf_rtp[1];
-        trace!(
-            "RTP Header ------->>> {:08b}{:08b}",
-            rtp_header_pt1,
-            rtp_header_pt2
-        );
+    // Same as get_rtp but doesn't wait, false if no packet was waiting
+    pub fn try_get_rtp(&mut self) -> Result<bool> {
+        self.check_bye()?;
 
-        // NAL Unit Header (1st byte of NAL unit)
-        // +---------------+
-        // |0|1|2|3|4|5|6|7|
-        // +-+-+-+-+-+-+-+-+
-        // |F|NRI|  Type   |
-        // +---------------+
-
-        // BYTE 12 is NAL unit header (because of 0 index)
-        let nal_header = &self.buf_rtp[NAL_UNIT_START];
-
-        // Get the NAL unit header TYPE (last 8 BITS)
-        // Use mask 00011111 = decimal 31
-        let nal_header_type = nal_header & 31;
-
-        trace!("{} bytes received", len);
-        trace!("-----------\n{:08b}", nal_header);
-        trace!(
-            "NAL HEADER TYPE: ---------->>> {}:{}",
-            nal_header_type,
-            get_nal_type(nal_header_type)
-        );
+        let received = match self.socket.try_recv(&mut self.buf_rtp) {
+            Ok(len) => Some((0, len)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => self.try_recv_redundant()?.map(|len| (1, len)),
+            Err(e) => return Err(e.into()),
+        };
+
+        match received {
+            Some((path, len)) => {
+                self.push_received(path, len);
+                Ok(true)
+            }
+            None => {
+                self.receive_rtcp();
+                self.check_bye().map(|_| false)
+            }
+        }
+    }
+
+    fn try_recv_redundant(&mut self) -> Result<Option<usize>> {
+        let socket = match &self.redundancy {
+            Some(redundancy) => redundancy.socket(),
+            None => return Ok(None),
+        };
+
+        match socket.try_recv(&mut self.buf_rtp) {
+            Ok(len) => Ok(Some(len)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Receive until an access unit is complete and return it, for
+    // callers that want the encoded video without handling packets
+    // e.g. to remux or forward it
+    //
+    // loop {
+    //     let au = rtp.next_access_unit().await?;
+    // }
+    pub async fn next_access_unit(&mut self) -> Result<AccessUnit> {
+        loop {
+            if let Some(data) = self.depacketizer.pending().map(|data| data.to_vec()) {
+                let timestamp = self.depacketizer.pending_timestamp().unwrap_or(self.last_timestamp);
+                self.depacketizer.clear();
+
+                return Ok(AccessUnit {
+                    keyframe: h264::is_keyframe(&data),
+                    data,
+                    timestamp,
+                    capture_time: self.capture_time_at(timestamp),
+                });
+            }
+
+            self.get_rtp().await?;
+        }
+    }
+
+    // Ready once a packet can be read with try_get_rtp
+    // e.g. to wait on several receivers in one poll_fn. RTCP
+    // arriving makes it ready too, for try_get_rtp to handle
+    pub fn poll_recv_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let Some(rtcp_socket) = &self.rtcp_socket {
+            if rtcp_socket.poll_recv_ready(cx).is_ready() {
+                return Poll::Ready(Ok(()));
+            }
+        }
+        if let Some(redundancy) = &self.redundancy {
+            if let Poll::Ready(ready) = redundancy.socket().poll_recv_ready(cx) {
+                return Poll::Ready(ready);
+            }
+        }
+
+        self.socket.poll_recv_ready(cx)
+    }
+
+    // Err once the server said goodbye to the stream
+    fn check_bye(&self) -> Result<()> {
+        match &self.bye {
+            Some(bye) => Err(RtpError::StreamEnded(bye.reason.clone())),
+            None => Ok(()),
+        }
+    }
+
+    // BYE received for the stream, it won't send anything more
+    pub fn bye(&self) -> Option<&Bye> {
+        self.bye.as_ref()
+    }
+
+    // Packet of 'len' bytes just received into buf_rtp on 'path'
+    fn push_received(&mut self, path: usize, len: usize) {
+        self.receive_rtcp();
+
+        // recv drops what doesn't fit, so a datagram reaching the spare
+        // byte was cut short and would corrupt whatever it is part of
+        if len > self.max_packet_size() {
+            warn!("[Rtp] Dropping packet over {} bytes, see set_max_packet_size", self.max_packet_size());
+            self.stats.truncated += 1;
+            return;
+        }
+
+        if let Some(capture) = &self.capture {
+            capture.udp(self.addr_client.port(), self.addr_server, &self.buf_rtp[..len]);
+        }
+
+        // The copy from the other path already went through
+        if let (Some(redundancy), Some(header)) = (&mut self.redundancy, RtpHeader::parse(&self.buf_rtp[..len])) {
+            if !redundancy.accept(path, &header) {
+                return;
+            }
+        }
+
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            if chaos.corrupt(&mut self.buf_rtp[..len]) {
+                trace!("[Rtp] Chaos corrupted a {len} byte packet");
+            }
+        }
+
+        #[cfg(feature = "srtp")]
+        let len = match &mut self.srtp {
+            Some(srtp) => match srtp.unprotect_rtp(&mut self.buf_rtp[..len]) {
+                Some(len) => len,
+                None => {
+                    trace!("[Rtp] Dropping SRTP packet that failed authentication or was replayed");
+                    return;
+                }
+            },
+            None => len,
+        };
+
+        let mut len = len;
+        let mut header = RtpHeader::parse(&self.buf_rtp[..len]);
+
+        if let Some(rtx) = header.as_ref().filter(|header| Some(header.payload_type) == self.rtx_payload_type) {
+            match self.restore_rtx(rtx, len) {
+                Some(restored) => {
+                    len = restored;
+                    header = RtpHeader::parse(&self.buf_rtp[..len]);
+                }
+                None => return,
+            }
+        }
 
-        trace!("NAL HEADER ---->> {:08b}", nal_header);
+        // Something else sending to our port, e.g. a stale session
+        if let (Some(ssrc), Some(header)) = (self.ssrc, &header) {
+            if header.ssrc != ssrc {
+                trace!("[Rtp] Dropping packet from SSRC {:08x}, expected {ssrc:08x}", header.ssrc);
+                return;
+            }
+        }
 
-        // Check if this is an SPS packet
-        // NAL header byte -> 01100111
-        if nal_header_type == 7u8 {
-            trace!("Sequence started! --------------------------------------");
+        let (reorder, ssrc, sequence_number) = match (&mut self.reorder, &header) {
+            (Some(reorder), Some(header)) => (reorder, header.ssrc, header.sequence_number),
+            _ => return self.process(header, len),
+        };
+        match reorder.push(ssrc, sequence_number, &self.buf_rtp[..len]) {
+            Push::InOrder => self.process(header, len),
+            Push::Held => (),
+            Push::Dropped => trace!("[Rtp] Dropping late or duplicate packet {sequence_number}"),
+        }
 
-            self.is_sps_found = true;
-            self.buf_sps.extend_from_slice(&[0u8, 0u8, 0u8, 1u8]);
-            self.buf_sps
-                .extend_from_slice(&self.buf_rtp[NAL_UNIT_START..len]);
+        // Held packets now in order, or given up waiting for
+        while let Some(packet) = self.reorder.as_mut().and_then(|reorder| reorder.pop()) {
+            self.buf_rtp[..packet.len()].copy_from_slice(&packet);
+            self.process(RtpHeader::parse(&packet), packet.len());
         }
-        // Check if this is an PPS packet
-        else if nal_header_type == 8u8 {
-            debug!("PPS packet ----- ");
 
-            if self.is_sps_found {
-                self.is_start_decoding = true;
+        self.send_nack();
+    }
+
+    // Hand on a packet in buf_rtp, in sequence when retransmission
+    // is enabled
+    fn process(&mut self, header: Option<RtpHeader>, len: usize) {
+        let gap = self.stats.record_packet(len, header.as_ref().map(|header| header.sequence_number));
+        if let Some(alerts) = &mut self.alerts {
+            alerts.check(&self.stats, gap);
+        }
 
-                self.buf_temp.extend_from_slice(self.buf_sps.as_slice());
-                self.buf_temp.extend_from_slice(&[0u8, 0u8, 0u8, 1u8]);
-                self.buf_temp
-                    .extend_from_slice(&self.buf_rtp[NAL_UNIT_START..len]);
-                self.buf_sps.clear();
+        self.last_len = len;
+        self.header = header;
+
+        let header = match &self.header {
+            Some(header) => header.clone(),
+            None => {
+                trace!("[Rtp] Ignoring {len} byte packet without a valid RTP header");
+                return;
             }
+        };
+
+        if self.keyframe_on_join && self.joined_ssrc != Some(header.ssrc) {
+            self.joined_ssrc = Some(header.ssrc);
+            self.request_keyframe();
+        }
+
+        self.depacketize(&header, len);
+        self.reception.packet(&header, self.clock_rate());
+        self.stats.record_jitter(self.reception.jitter(), self.clock_rate());
+
+        self.clock_base.get_or_insert((header.timestamp, SystemTime::now()));
+        self.last_timestamp = header.timestamp;
+    }
+
+    // Rebuild a retransmission (RFC 4588) in buf_rtp as the packet it
+    // stands for: original sequence number in the first two payload
+    // bytes, payload type and SSRC those of the stream. Returns its
+    // length, None if there is nothing to rebuild it as
+    fn restore_rtx(&mut self, rtx: &RtpHeader, len: usize) -> Option<usize> {
+        let payload_type = self.format.as_ref().map(|format| format.payload_type)?;
+        let ssrc = self.reorder.as_ref().and_then(|reorder| reorder.ssrc()).or(self.ssrc)?;
+
+        let payload = rtx.payload(&self.buf_rtp[..len]);
+        if payload.len() < 2 {
+            return None;
+        }
+        let sequence_number = [payload[0], payload[1]];
+
+        let mut packet = self.buf_rtp[..rtx.payload_offset].to_vec();
+        packet[0] &= !0b00100000; // padding went with the rtx payload
+        packet[1] = (packet[1] & 0b10000000) | payload_type;
+        packet[2..4].copy_from_slice(&sequence_number);
+        packet[8..12].copy_from_slice(&ssrc.to_be_bytes());
+        packet.extend_from_slice(&payload[2..]);
+
+        trace!("[Rtp] Retransmission of packet {}", u16::from_be_bytes(sequence_number));
+        self.stats.recovered += 1;
+        self.buf_rtp[..packet.len()].copy_from_slice(&packet);
+        Some(packet.len())
+    }
+
+    // NACK what is missing to the server's RTCP port, see
+    // set_retransmission
+    fn send_nack(&mut self) {
+        let reorder = match (&mut self.reorder, &self.rtcp_socket) {
+            (Some(reorder), Some(_)) => reorder,
+            _ => return,
+        };
+        let media_ssrc = match reorder.ssrc() {
+            Some(ssrc) => ssrc,
+            None => return,
+        };
+
+        let lost = reorder.nack();
+        if lost.is_empty() {
+            return;
+        }
+
+        trace!("[Rtp] NACK for {} packets", lost.len());
+        let nack = GenericNack { ssrc: self.rtcp_ssrc, media_ssrc, lost }.to_bytes();
+        let nack = self.protect_rtcp(nack);
+        let server_rtcp = SocketAddr::new(self.addr_server.ip(), self.addr_server.port().wrapping_add(1));
+        if let Some(Err(e)) = self.rtcp_socket.as_ref().map(|socket| socket.try_send_to(&nack, server_rtcp)) {
+            trace!("[Rtp] NACK not sent: {e}");
         }
-        // Check if this is an SEI packet
-        else if nal_header_type == 6u8 {
-            debug!("SEI packet ----- ");
-
-            self.buf_temp.extend_from_slice(&[0u8, 0u8, 1u8]);
-            self.buf_temp
-                .extend_from_slice(&self.buf_rtp[NAL_UNIT_START..len]);
-        }
-        // Check for fragment (FU-A)
-        else if nal_header_type == 28u8 {
-            debug!("Fragment started!! ----- ");
-            self.is_fragment_start = true;
-
-            // Fragment header (2nd NAL unit byte)
-            //  +---------------+
-            // |0|1|2|3|4|5|6|7| bit position
-            // +-+-+-+-+-+-+-+-+
-            // |S|E|R|  Type   |
-            // +---------------+
-            // S = Start of fragment?
-            // E = End of fragment?
-
-            // Check fragment header which is byte
-            // after NAL header
-            let header_frag = &self.buf_rtp[13];
-            debug!("Fragment header -- {:08b}", header_frag);
-
-            // Or fragment END?
-            if *header_frag & 0b01000000 == 64u8 {
-                trace!("Fragment ended!! ----- ");
-                self.is_fragment_end = true;
-
-                // Reconstruct new NAL header using NAL
-                // NAL unit type in FRAGMENT header
-                // AND NAL priority from original NAL header
-                // use bitmasks to get first 3 bits and last 5 bits
-                let nal_header = *header_frag & 0b00011111;
-                let nal_header = nal_header | 0b01100000;
-                debug!("New NAL header for conbined fragment: {:08b}", nal_header);
-
-                self.buf_temp.extend_from_slice(&[0u8, 0u8, 1u8]);
-                // Need to swap outside nal header to inside payload type
-                // as after combining packet it's not a fragment anymore
-                // TODO: Need to get this from fragment header type instead of hard coding
-                self.buf_temp.push(nal_header);
-                self.buf_temp
-                    .extend_from_slice(self.buf_fragments.as_slice());
-                self.buf_temp.extend_from_slice(&self.buf_rtp[14..len]);
-                self.buf_fragments.clear();
-            } else {
-                // Append fragment payload EXCLUDING ALL HEADERS
-                self.buf_fragments.extend_from_slice(&self.buf_rtp[14..len]);
+    }
+
+    // Ask the server for a keyframe (PLI) on its RTCP port, e.g.
+    // after losses the decoder can't recover from. Does nothing
+    // before the first packet as the stream's SSRC isn't known
+    pub fn request_keyframe(&mut self) {
+        let media_ssrc = match self.header.as_ref().map(|header| header.ssrc).or(self.ssrc) {
+            Some(ssrc) => ssrc,
+            None => return,
+        };
+
+        debug!("[Rtp] Requesting a keyframe from SSRC {media_ssrc:08x}");
+        let pli = PictureLossIndication { ssrc: self.rtcp_ssrc, media_ssrc }.to_bytes();
+        let pli = self.protect_rtcp(pli);
+        let server_rtcp = SocketAddr::new(self.addr_server.ip(), self.addr_server.port().wrapping_add(1));
+        if let Some(Err(e)) = self.rtcp_socket.as_ref().map(|socket| socket.try_send_to(&pli, server_rtcp)) {
+            trace!("[Rtp] PLI not sent: {e}");
+        }
+    }
+
+    // Send a PLI as soon as packets of a new SSRC arrive, so the
+    // picture starts without waiting for the next GOP
+    pub fn set_request_keyframe_on_join(&mut self, on_join: bool) {
+        self.keyframe_on_join = on_join;
+        self.joined_ssrc = None;
+    }
+
+    // Retransmission, decode budget, backlog skipping and keyframe
+    // on join as the profile has them, see LatencyProfile
+    pub fn set_latency_profile(&mut self, profile: LatencyProfile) {
+        let settings = profile.settings();
+
+        self.set_retransmission(settings.retransmission_hold);
+        self.set_request_keyframe_on_join(settings.request_keyframe_on_join);
+        #[cfg(feature = "decode-openh264")]
+        {
+            self.set_decode_budget(settings.decode_budget);
+            self.set_skip_backlog(settings.skip_backlog);
+        }
+    }
+
+    // Handle the RTCP packets waiting, sender reports come every few
+    // seconds so picking them up along with RTP is soon enough
+    fn receive_rtcp(&mut self) {
+        let socket = match &self.rtcp_socket {
+            Some(socket) => socket,
+            None => return,
+        };
+
+        let mut buf = [0u8; 1500];
+        while let Ok(len) = socket.try_recv(&mut buf) {
+            #[cfg(feature = "srtp")]
+            let len = match &mut self.srtp {
+                Some(srtp) => match srtp.unprotect_rtcp(&mut buf[..len]) {
+                    Some(len) => len,
+                    None => {
+                        trace!("[Rtp] Dropping SRTCP packet that failed authentication or was replayed");
+                        continue;
+                    }
+                },
+                None => len,
+            };
+
+            for packet in rtcp::parse_compound(&buf[..len]) {
+                match packet {
+                    // Once RTP arrived, only reports about that stream
+                    RtcpPacket::SenderReport(report) => match &self.header {
+                        Some(header) if header.ssrc != report.ssrc => {
+                            trace!("[Rtp] Ignoring sender report for SSRC {:08x}", report.ssrc);
+                        }
+                        _ => {
+                            trace!("[Rtp] Sender report {report:?}");
+                            self.reception.sender_report(&report);
+                            self.sender_report = Some(report);
+                        }
+                    },
+                    // For the stream once RTP arrived (or the SSRC is known)
+                    RtcpPacket::Bye(bye) => match self.header.as_ref().map(|header| header.ssrc).or(self.ssrc) {
+                        Some(ssrc) if !bye.ssrcs.contains(&ssrc) => {
+                            trace!("[Rtp] Ignoring BYE for SSRC {:08x?}", bye.ssrcs);
+                        }
+                        _ => {
+                            info!("[Rtp] Server ended the stream: {}", bye.reason.as_deref().unwrap_or("no reason given"));
+                            self.bye = Some(bye);
+                        }
+                    },
+                    RtcpPacket::Other { packet_type } => trace!("[Rtp] Ignoring RTCP packet type {packet_type}"),
+                }
             }
-        } else {
-            debug!("Slice packet ----- ");
+        }
+    }
+
+    // Last RTCP sender report received for the stream
+    pub fn sender_report(&self) -> Option<&SenderReport> {
+        self.sender_report.as_ref()
+    }
+
+    // RTCP receiver report on reception since the previous one, along
+    // with our SDES (compound packet), e.g. to send over the RTSP
+    // connection when RTP is interleaved:
+    // rtsp.send_interleaved_rtcp(1, &rtp.receiver_report()).await
+    pub fn receiver_report(&mut self) -> Vec<u8> {
+        let report = ReceiverReport {
+            ssrc: self.rtcp_ssrc,
+            blocks: self.reception.report_block().into_iter().collect(),
+        };
+
+        let mut compound = report.to_bytes();
+        compound.extend_from_slice(&self.identity.description(self.rtcp_ssrc).to_bytes());
+        self.protect_rtcp(compound)
+    }
 
-            self.is_sps_found = false;
-            self.buf_temp.extend_from_slice(&[0u8, 0u8, 1u8]);
-            self.buf_temp
-                .extend_from_slice(&self.buf_rtp[NAL_UNIT_START..len]);
+    // SRTCP when the stream is SRTP
+    fn protect_rtcp(&mut self, packet: Vec<u8>) -> Vec<u8> {
+        #[cfg(feature = "srtp")]
+        if let Some(srtp) = &mut self.srtp {
+            return srtp.protect_rtcp(&packet);
+        }
+
+        packet
+    }
+
+    // Decrypt SRTP (and SRTCP) before anything else looks at packets,
+    // and protect the RTCP sent back. configure_from_sdp sets it up
+    // when the track's SDP has a key
+    #[cfg(feature = "srtp")]
+    pub fn set_srtp(&mut self, srtp: Option<Srtp>) {
+        self.srtp = srtp;
+    }
+
+    #[cfg(feature = "srtp")]
+    pub fn srtp(&self) -> Option<&Srtp> {
+        self.srtp.as_ref()
+    }
+
+    // CNAME, NAME and so on sent with receiver reports
+    pub fn set_identity(&mut self, identity: Identity) {
+        self.identity = identity;
+    }
+
+    pub fn identity(&self) -> &Identity {
+        &self.identity
+    }
+
+    // Send a receiver report to the server's RTCP port (the one after
+    // its RTP port). Call every rtcp::REPORT_INTERVAL or so, some
+    // servers end sessions that never report
+    pub async fn send_receiver_report(&mut self) -> Result<()> {
+        let server_rtcp = SocketAddr::new(self.addr_server.ip(), self.addr_server.port().wrapping_add(1));
+        let report = self.receiver_report();
+
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            tokio::time::sleep(chaos.rtcp_delay()).await;
+        }
+
+        match &self.rtcp_socket {
+            Some(socket) => socket.send_to(&report, server_rtcp).await.map(|_| ())?,
+            None => trace!("[Rtp] No RTCP socket, receiver report not sent"),
         }
 
         Ok(())
     }
 
-    pub fn try_decode(&mut self) -> Result<Option<DecodedYUV>, openh264::Error> {
-        if self.buf_temp.len() == 0 || !self.is_start_decoding {
-            return Ok(None);
-        } else if self.is_fragment_start && !self.is_fragment_end {
-            return Ok(None);
+    // Hand the packet in buf_rtp to the depacketizer for its codec
+    fn depacketize(&mut self, header: &RtpHeader, len: usize) {
+        let payload = header.payload(&self.buf_rtp[..len]);
+        if payload.is_empty() {
+            return;
+        }
+        let payload_type = header.payload_type;
+
+        // Without an rtpmap anything but MP2T is taken to be H264
+        let codec = match &self.format {
+            Some(format) if format.payload_type != payload_type => {
+                trace!("[Rtp] Ignoring packet with payload type {payload_type}, expected {}", format.payload_type);
+                return;
+            }
+            Some(format) => format.codec.clone(),
+            #[cfg(feature = "mpegts")]
+            None if payload_type == PAYLOAD_TYPE_MP2T => Codec::Mp2t,
+            None => Codec::H264,
+        };
+
+        match codec {
+            Codec::H264 => self.depacketizer.push_rtp(header, payload),
+            // Some encoders send H264 inside an MPEG transport stream
+            #[cfg(feature = "mpegts")]
+            Codec::Mp2t => {
+                for es in self.ts_demuxer.push(payload) {
+                    for nal in h264::nal_units(&es) {
+                        self.depacketizer.push_nal(nal);
+                    }
+                }
+            }
+            // No depacketizer (yet), the payload is handed out as is
+            _ => {
+                if self.payloads.len() >= MAX_PAYLOADS {
+                    self.payloads.pop_front();
+                }
+                self.payloads.push_back(payload.to_vec());
+            }
         }
+    }
 
-        // Clear fragment flags
-        self.is_fragment_start = false;
-        self.is_fragment_end = false;
+    // Select the depacketizer from the track's a=rtpmap, instead of
+    // assuming H264. Packets of other payload types are then ignored
+    pub fn set_payload_format(&mut self, format: Option<PayloadFormat>) {
+        if let Some(format) = &format {
+            info!("[Rtp] Payload type {} is {:?}/{}", format.payload_type, format.codec, format.clock_rate);
+        }
+        self.format = format;
+    }
+
+    pub fn payload_format(&self) -> Option<&PayloadFormat> {
+        self.format.as_ref()
+    }
+
+    // Header of the last packet received, None if it wasn't RTP
+    pub fn last_header(&self) -> Option<&RtpHeader> {
+        self.header.as_ref()
+    }
+
+    // Payload of the last packet received
+    pub fn last_payload(&self) -> &[u8] {
+        match &self.header {
+            Some(header) => header.payload(&self.buf_rtp[..self.last_len]),
+            None => &[],
+        }
+    }
+
+    // Payload of the next packet of a codec this crate can't
+    // depacketize (e.g. PCMU audio), oldest first
+    pub fn next_payload(&mut self) -> Option<Vec<u8>> {
+        self.payloads.pop_front()
+    }
+
+    // Receive from a different server address e.g. when SETUP gave
+    // another server port after Rtsp::reconnect
+    pub async fn set_server_addr(&mut self, addr_server: SocketAddr) -> Result<()> {
+        self.addr_server = addr_server;
+        self.bye = None;
+        self.socket.connect(addr_server).await?;
+
+        Ok(())
+    }
+
+    // Receive on a new local address/port, e.g. after Rtsp::resume
+    // moved the transport to new client ports. Decoder and stream
+    // state are kept so decoding carries on. On the same address
+    // the socket is kept and only points to 'addr_server'
+    pub async fn rebind(&mut self, client_ip: Option<&str>, client_port: u16, addr_server: SocketAddr) -> Result<()> {
+        let addr_client = client_addr(client_ip, client_port, addr_server)?;
+        if addr_client == self.addr_client {
+            return self.set_server_addr(addr_server).await;
+        }
+
+        // Free the ports before binding them again, the new address
+        // may only differ by IP: a placeholder stands in meanwhile
+        self.socket = UdpSocket::bind(SocketAddr::new(addr_client.ip(), 0)).await?;
+        self.rtcp_socket = None;
+
+        let socket = UdpSocket::bind(addr_client).await?;
+        socket.connect(addr_server).await?;
+
+        self.socket = socket;
+        self.rtcp_socket = bind_rtcp(addr_client).await;
+        self.bye = None;
+        self.addr_client = addr_client;
+        self.addr_server = addr_server;
+
+        Ok(())
+    }
 
-        // all current packets data
-        self.buf_all.extend_from_slice(self.buf_temp.as_slice());
+    // Also receive the stream on a second path, e.g. the client port
+    // of a SETUP over another network, handing on whichever copy of
+    // each packet arrives first. See redundancy
+    pub async fn add_redundant_path(&mut self, client_ip: Option<&str>, client_port: u16, addr_server: SocketAddr) -> Result<()> {
+        let socket = UdpSocket::bind(client_addr(client_ip, client_port, addr_server)?).await?;
+        socket.connect(addr_server).await?;
+
+        self.redundancy = Some(Redundancy::new(socket));
+        Ok(())
+    }
+
+    pub fn remove_redundant_path(&mut self) {
+        self.redundancy = None;
+    }
+
+    // Packets received on the stream's own path and the redundant one
+    pub fn redundant_paths(&self) -> Option<[PathStats; 2]> {
+        self.redundancy.as_ref().map(|redundancy| redundancy.paths())
+    }
+
+    // Only accept packets from this SSRC, e.g. TrackTransport::ssrc
+    // from the SETUP response. None accepts any
+    pub fn set_ssrc(&mut self, ssrc: Option<u32>) {
+        self.ssrc = ssrc;
+    }
+
+    pub fn ssrc(&self) -> Option<u32> {
+        self.ssrc
+    }
+
+    // Ask the server to send lost packets again (generic NACK) and
+    // hold packets after a gap up to 'hold' until they arrive, so
+    // they still go to the depacketizer in order. Worth it on lossy
+    // links where the round trip is well under 'hold'. None turns
+    // it off (the default). Changing the hold while it is on keeps
+    // the packets already held
+    pub fn set_retransmission(&mut self, hold: Option<Duration>) {
+        match (&mut self.reorder, hold) {
+            (Some(reorder), Some(hold)) => reorder.set_hold(hold),
+            _ => self.reorder = hold.map(Reorder::new),
+        }
+    }
+
+    // Payload type the server sends retransmissions with, set from
+    // the SDP by configure_from_sdp (MediaSection::rtx_payload_type).
+    // None takes packets of it as the stream's own
+    pub fn set_rtx_payload_type(&mut self, rtx_payload_type: Option<u8>) {
+        self.rtx_payload_type = rtx_payload_type;
+    }
+
+    pub fn rtx_payload_type(&self) -> Option<u8> {
+        self.rtx_payload_type
+    }
+
+    // Largest RTP packet that can be received, header included, e.g.
+    // 9000 for jumbo frames. Larger ones are dropped and counted in
+    // Stats::truncated
+    pub fn set_max_packet_size(&mut self, max_packet_size: usize) {
+        self.buf_rtp = vec![0u8; max_packet_size.max(RTP_HEADER_LEN + 1) + 1];
+    }
+
+    pub fn max_packet_size(&self) -> usize {
+        self.buf_rtp.len() - 1
+    }
+
+    // Size the receive buffer for the Blocksize the server agreed to
+    // (Rtsp::blocksize), which doesn't count the RTP header
+    pub fn set_blocksize(&mut self, blocksize: u32) {
+        self.set_max_packet_size(max_packet_size_for(blocksize));
+    }
+
+    // Record every packet received, e.g. into the same capture
+    // as Rtsp::set_capture so the session can be replayed
+    pub fn set_capture(&mut self, capture: Capture) {
+        self.capture = Some(capture);
+    }
+
+    // Corrupt received packets as set on 'chaos', see chaos::Chaos
+    #[cfg(feature = "chaos")]
+    pub fn set_chaos(&mut self, chaos: Chaos) {
+        self.chaos = Some(chaos);
+    }
+
+    // Packets, loss, fps and bitrate received so far
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    // Samples of the rates kept in Stats::history, one a second.
+    // The last 300 (5 minutes) by default, 0 keeps none
+    pub fn set_stats_history(&mut self, len: usize) {
+        self.stats.set_history_len(len);
+    }
+
+    // How long the rates in Stats are worked out over, and so how
+    // often a sample goes into the history. A second by default
+    pub fn set_stats_interval(&mut self, interval: Duration) {
+        self.stats.set_interval(interval);
+    }
+
+    // Call 'on_alert' whenever the stream crosses one of the thresholds
+    // e.g. fps drops below min_fps or no packet for max_stall.
+    // Stalls are only noticed while waiting with a receive timeout set
+    pub fn set_alerts<F: FnMut(Alert) + Send + 'static>(&mut self, thresholds: Thresholds, on_alert: F) {
+        self.alerts = Some(Alerts::new(thresholds, Box::new(on_alert)));
+    }
+
+    pub fn clear_alerts(&mut self) {
+        self.alerts = None;
+    }
+
+    // Longest get_rtp waits for a packet before failing with
+    // RtpError::Timeout, e.g. to notice a camera which stopped sending
+    // None (the default) waits forever
+    pub fn set_recv_timeout(&mut self, recv_timeout: Option<Duration>) {
+        self.recv_timeout = recv_timeout;
+    }
+
+    // Limit the buffers of this stream, see MemoryBudget
+    pub fn set_memory_budget(&mut self, budget: &MemoryBudget) {
+        self.depacketizer.set_max_bytes(budget.max_access_unit);
+
+        #[cfg(feature = "decode-openh264")]
+        {
+            self.max_recording = budget.max_recording;
+            if let Some(max_recording) = budget.max_recording {
+                self.buf_all.truncate(max_recording);
+            }
+        }
+    }
+
+    // Access the depacketizer to push NAL units or RTP packets
+    // which did not arrive on the socket (e.g. SPS/PPS from ONVIF)
+    pub fn depacketizer(&mut self) -> &mut Depacketizer {
+        &mut self.depacketizer
+    }
+
+    // Payload format and parameter sets of the track from its SDP,
+    // see set_payload_format and prime_from_sdp
+    pub fn configure_from_sdp(&mut self, section: &MediaSection) {
+        self.set_payload_format(PayloadFormat::from_sdp(section));
+        self.sdp_video = VideoInfo::from_sdp(section);
+        self.rtx_payload_type = section.rtx_payload_type();
+        #[cfg(feature = "srtp")]
+        if let Some(key) = MasterKey::from_section(section) {
+            info!("[Rtp] SRTP with {:?}", key.suite);
+            self.srtp = Some(Srtp::new(&key));
+        }
+        if let Some(Codec::H264) = self.format.as_ref().map(|format| &format.codec) {
+            self.prime_from_sdp(section);
+        }
+    }
+
+    // Feed the SPS/PPS from the track's SDP (fmtp sprop-parameter-sets)
+    // to the depacketizer, so the first keyframe can be decoded right
+    // away instead of waiting for parameter sets in the stream
+    // Returns false if the SDP has none
+    pub fn prime_from_sdp(&mut self, section: &MediaSection) -> bool {
+        let sprop = section
+            .payload_type
+            .and_then(|payload_type| section.fmtp_for(payload_type))
+            .and_then(|fmtp| fmtp.get("sprop-parameter-sets"));
+
+        match sprop {
+            Some(sprop) => self.depacketizer.push_parameter_sets(sprop) > 0,
+            None => false,
+        }
+    }
+
+    // Resolution and framerate, from the last SPS received or else
+    // what the SDP given to configure_from_sdp said
+    pub fn video_info(&self) -> Option<VideoInfo> {
+        let framerate = self.sdp_video.and_then(|video| video.framerate);
+
+        match self.depacketizer.sps() {
+            Some(sps) => Some(VideoInfo::from_sps(sps, framerate)),
+            None => self.sdp_video,
+        }
+    }
+
+    // Wall clock time of the last packet received, worked out from its
+    // RTP timestamp. Once an RTCP sender report arrived that is the
+    // server's clock, the same for every stream it sends, otherwise
+    // it is relative to when the first packet of the stream arrived
+    pub fn capture_time(&self) -> Option<SystemTime> {
+        self.capture_time_at(self.last_timestamp)
+    }
+
+    // Wall clock time of an RTP timestamp of the stream, as above
+    fn capture_time_at(&self, timestamp: u32) -> Option<SystemTime> {
+        let clock_rate = self.clock_rate();
+
+        if let Some(report) = &self.sender_report {
+            return Some(report.wallclock_at(timestamp, clock_rate));
+        }
+
+        let (base_timestamp, base_time) = self.clock_base?;
+        let ticks = timestamp.wrapping_sub(base_timestamp) as u64;
+
+        Some(base_time + Duration::from_micros(ticks * 1_000_000 / clock_rate as u64))
+    }
+
+    fn clock_rate(&self) -> u32 {
+        match &self.format {
+            Some(format) => format.clock_rate.max(1),
+            None => CLOCK_RATE_VIDEO,
+        }
+    }
+
+}
+
+// Decoding, with the decode-openh264 feature
+#[cfg(feature = "decode-openh264")]
+impl Rtp {
+    pub fn try_decode(&mut self) -> Result<Option<DecodedYUV<'_>>> {
+        // Several frames behind: what comes before a keyframe is
+        // no longer needed to show the latest picture
+        if self.skip_backlog {
+            let skipped = self.depacketizer.skip_to_keyframe();
+            if skipped > 0 {
+                debug!("Skipping {skipped} access units behind a keyframe");
+            }
+        }
+
+        let buf_temp = match self.depacketizer.pending() {
+            Some(buf) => buf,
+            None => return Ok(None),
+        };
+
+        // all current packets data, stops growing once the budget is used up
+        let recording_full = match self.max_recording {
+            Some(max_recording) => self.buf_all.len() + buf_temp.len() > max_recording,
+            None => false,
+        };
+        let is_keyframe = h264::is_keyframe(buf_temp);
+        self.recording_resumed &= !is_keyframe;
+        if self.recording && !self.recording_resumed && !recording_full {
+            self.buf_all.extend_from_slice(buf_temp);
+        }
 
         // DECODE
         // Idea is to store all packets depending on types in buf_temp
@@ -264,56 +1189,342 @@ impl Rtp {
         // Fragment    = 1 packet COMBINED
         // Slice       = 1 packet
         debug!("//////////////////////////////////////////");
-        debug!("Decoding packet size: {:?}", self.buf_temp.len());
+        debug!("Decoding packet size: {:?}", buf_temp.len());
 
+        let quality = self.quality.estimate(buf_temp);
+        self.frame_quality = Some(quality);
+
+        if !self.degradation.should_decode(buf_temp, is_keyframe) {
+            debug!("Skipping access unit in {:?} mode", self.degradation.mode());
+            self.depacketizer.clear();
+            return Ok(None);
+        }
+
+        let started = std::time::Instant::now();
         let maybe_some_yuv = match &mut self.decoder {
-            Some(rtp_decoder) => rtp_decoder.decode(self.buf_temp.as_slice()),
-            None => Err(openh264::Error::msg("Unable to decode NAL unit")),
+            Some(rtp_decoder) => rtp_decoder.decode(buf_temp).map_err(RtpError::from),
+            None => Err(RtpError::NoDecoder),
         };
 
-        self.buf_temp.clear();
+        if let Ok(Some(_)) = &maybe_some_yuv {
+            self.stats.record_frame();
+            self.degradation.update(started.elapsed());
+
+            #[cfg(feature = "otel")]
+            if let Some(telemetry) = &mut self.telemetry {
+                telemetry.decoded(is_keyframe, started.elapsed());
+            }
+        }
+
+        // Keep a copy of each keyframe for latest_frame()
+        if let (true, Ok(Some(yuv))) = (is_keyframe, &maybe_some_yuv) {
+            let mut frame = Frame::from_yuv(yuv);
+            for mask in &self.masks {
+                mask.apply(&mut frame);
+            }
+            self.latest_keyframe = Some(frame);
+        }
+
+        self.depacketizer.clear();
 
         maybe_some_yuv
     }
+
+    // Export a span per GOP and decode time metrics
+    #[cfg(feature = "otel")]
+    pub fn set_telemetry(&mut self, telemetry: Telemetry) {
+        self.telemetry = Some(telemetry);
+    }
+
+    // Longest decoding a frame should take on average. Over budget,
+    // non-reference frames and then everything but keyframes are
+    // skipped, stepping back to full decoding once load drops.
+    // None (the default) always decodes everything
+    pub fn set_decode_budget(&mut self, budget: Option<Duration>) {
+        self.degradation.set_budget(budget);
+    }
+
+    // Decode only from the last keyframe when more than one access
+    // unit is pending, e.g. after a stall, instead of catching up on
+    // every frame in between. Off by default
+    pub fn set_skip_backlog(&mut self, skip_backlog: bool) {
+        self.skip_backlog = skip_backlog;
+    }
+
+    // Keep what is decoded for save_file (the default). Turned off
+    // the recording so far is kept, turned back on it carries on
+    // from the next keyframe so the file still plays
+    pub fn set_recording(&mut self, recording: bool) {
+        self.recording_resumed |= recording && !self.recording;
+        self.recording = recording;
+    }
+
+    // Estimated quality of the access unit try_decode last took,
+    // decoded or skipped
+    pub fn frame_quality(&self) -> Option<FrameQuality> {
+        self.frame_quality
+    }
+
+    // Called whenever the decode budget changes the decode mode
+    pub fn on_decode_mode<F: FnMut(DecodeMode) + Send + 'static>(&mut self, on_change: F) {
+        self.degradation.set_on_change(Box::new(on_change));
+    }
+
+    pub fn decode_mode(&self) -> DecodeMode {
+        self.degradation.mode()
+    }
+
+    // Privacy zones blanked out of every frame returned by try_decode_frame
+    pub fn add_mask(&mut self, mask: PrivacyMask) {
+        self.masks.push(mask);
+    }
+
+    pub fn clear_masks(&mut self) {
+        self.masks.clear();
+    }
+
+    // Receive and decode until a frame comes out, with privacy
+    // masks and OSD applied as for try_decode_frame. Access units
+    // the decoder gives nothing for (e.g. skipped by the decode
+    // budget) are passed over
+    //
+    // loop {
+    //     let frame = rtp.next_frame().await?;
+    // }
+    pub async fn next_frame(&mut self) -> Result<Frame> {
+        loop {
+            if let Some(frame) = self.try_decode_frame()? {
+                return Ok(frame);
+            }
+
+            self.get_rtp().await?;
+        }
+    }
+
+    // Same as try_decode, but returns an owned copy of the frame
+    // with any privacy masks already applied
+    pub fn try_decode_frame(&mut self) -> Result<Option<Frame>> {
+        let mut frame = match self.try_decode()? {
+            Some(yuv) => Frame::from_yuv(&yuv),
+            None => return Ok(None),
+        };
+        frame.framerate = self.video_info().and_then(|video| video.framerate);
+
+        for mask in &self.masks {
+            mask.apply(&mut frame);
+        }
+
+        if let (Some(osd), Some(time)) = (&self.osd, self.capture_time()) {
+            osd.draw_timestamp(&mut frame, time);
+        }
+
+        Ok(Some(frame))
+    }
+
+    // Pixel format of the images returned by try_decode_image
+    pub fn set_pixel_format(&mut self, pixel_format: PixelFormat) {
+        self.pixel_format = pixel_format;
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    // Same as try_decode_frame, but converted to the pixel format
+    // set with set_pixel_format (I420 by default)
+    pub fn try_decode_image(&mut self) -> Result<Option<Image>> {
+        let image = self
+            .try_decode_frame()?
+            .map(|frame| frame.to_image(self.pixel_format));
+
+        Ok(image)
+    }
+
+    // Burn the capture time into every frame returned by try_decode_frame
+    pub fn set_osd(&mut self, osd: Osd) {
+        self.osd = Some(osd);
+    }
+
+    pub fn clear_osd(&mut self) {
+        self.osd = None;
+    }
+
+    // Most recent keyframe decoded (privacy masks applied), so a
+    // snapshot doesn't have to wait for the next IDR
+    pub fn latest_frame(&self) -> Option<&Frame> {
+        self.latest_keyframe.as_ref()
+    }
+
+    // Most recent keyframe as a JPEG, quality is 1-100
+    #[cfg(feature = "jpeg")]
+    pub fn latest_jpeg(&self, quality: u8) -> Option<Vec<u8>> {
+        let frame = self.latest_keyframe.as_ref()?;
+        let mut jpeg = Vec::new();
+
+        let encoder = jpeg_encoder::Encoder::new(&mut jpeg, quality);
+        let result = encoder.encode(
+            &frame.to_rgb(),
+            frame.width as u16,
+            frame.height as u16,
+            jpeg_encoder::ColorType::Rgb,
+        );
+
+        match result {
+            Ok(_) => Some(jpeg),
+            Err(e) => {
+                debug!("Unable to encode JPEG: {e}");
+                None
+            }
+        }
+    }
+}
+
+// Rewrites a packet in place just before it is sent
+type ProtectFn = Box<dyn FnMut(&mut Vec<u8>) + Send>;
+
+// Sends a local H264 stream to a server, e.g. after ANNOUNCE,
+// SETUP (mode=record) and RECORD. RTP goes from our client port
+// to the server port given in the SETUP response.
+//
+// Also sends audio to a camera's backchannel (talk-down) with
+// send_pcm, once the sender's audio packetizer is set up for the
+// backchannel track with set_audio_packetizer.
+pub struct RtpSender {
+    socket: UdpSocket,
+    packetizer: Packetizer,
+    audio_packetizer: Option<AudioPacketizer>,
+    protect: Option<ProtectFn>,
+}
+
+impl RtpSender {
+    pub async fn new(
+        client_ip: Option<&str>,
+        client_port: u16,
+        addr_server: SocketAddr,
+    ) -> Result<Self> {
+        let socket = UdpSocket::bind(client_addr(client_ip, client_port, addr_server)?).await?;
+        socket.connect(addr_server).await?;
+
+        Ok(RtpSender {
+            socket,
+            packetizer: Packetizer::new(),
+            audio_packetizer: None,
+            protect: None,
+        })
+    }
+
+    pub fn packetizer(&mut self) -> &mut Packetizer {
+        &mut self.packetizer
+    }
+
+    pub fn set_audio_packetizer(&mut self, audio_packetizer: AudioPacketizer) {
+        self.audio_packetizer = Some(audio_packetizer);
+    }
+
+    pub fn audio_packetizer(&mut self) -> Option<&mut AudioPacketizer> {
+        self.audio_packetizer.as_mut()
+    }
+
+    // Called with every packet just before it is sent so it can be
+    // rewritten in place, e.g. encrypted as SRTP with externally
+    // negotiated keys (HomeKit camera streams)
+    pub fn set_protect<F: FnMut(&mut Vec<u8>) + Send + 'static>(&mut self, protect: F) {
+        self.protect = Some(Box::new(protect));
+    }
+
+    pub fn clear_protect(&mut self) {
+        self.protect = None;
+    }
+
+    // Send one access unit (Annex B) e.g. SPS + PPS + IDR slice
+    // 'timestamp' is in 90kHz units, e.g. frame number * 90000 / fps
+    pub async fn send_access_unit(&mut self, annexb: &[u8], timestamp: u32) -> Result<()> {
+        let packets = self.packetizer.packetize(annexb, timestamp);
+        self.send_packets(packets).await
+    }
+
+    // Send 8kHz mono PCM through the audio packetizer, a packet per
+    // packet duration. Call as the audio is captured, the remainder
+    // goes out with the next call or flush_pcm
+    pub async fn send_pcm(&mut self, pcm: &[i16]) -> Result<()> {
+        let packets = match &mut self.audio_packetizer {
+            Some(audio_packetizer) => audio_packetizer.push(pcm),
+            None => return Err(RtpError::NoAudioPacketizer),
+        };
+        self.send_packets(packets).await
+    }
+
+    // End of a talkspurt, send whatever audio is left
+    pub async fn flush_pcm(&mut self) -> Result<()> {
+        let packet = match &mut self.audio_packetizer {
+            Some(audio_packetizer) => audio_packetizer.flush(),
+            None => return Err(RtpError::NoAudioPacketizer),
+        };
+        self.send_packets(packet.into_iter().collect()).await
+    }
+
+    async fn send_packets(&mut self, packets: Vec<Vec<u8>>) -> Result<()> {
+        for mut packet in packets {
+            if let Some(protect) = &mut self.protect {
+                protect(&mut packet);
+            }
+            self.socket.send(&packet).await?;
+        }
+
+        Ok(())
+    }
+}
+
+// Receive buffer needed for RTP packets with 'blocksize' byte payloads
+pub fn max_packet_size_for(blocksize: u32) -> usize {
+    blocksize as usize + HEADER_ROOM
+}
+
+fn random_ssrc() -> u32 {
+    RandomState::new().build_hasher().finish() as u32
 }
 
-fn get_nal_type(nal: u8) -> String {
-    let nal_types = r#"0:Unspecified:non-VCL
-        1:Coded slice of a non-IDR picture slice_layer_without_partitioning_rbsp():VCL
-        2:Coded slice data partition A slice_data_partition_a_layer_rbsp():VCL
-        3:Coded slice data partition B slice_data_partition_b_layer_rbsp():VCL
-        4:Coded slice data partition C slice_data_partition_c_layer_rbsp():VCL
-        5:Coded slice of an IDR picture slice_layer_without_partitioning_rbsp():VCL
-        6:Supplemental enhancement information (SEI) sei_rbsp():non-VCL
-        7:Sequence parameter set seq_parameter_set_rbsp():non-VCL
-        8:Picture parameter set pic_parameter_set_rbsp():non-VCL
-        9:Access unit delimiter access_unit_delimiter_rbsp():non-VCL
-        10:End of sequence end_of_seq_rbsp():non-VCL
-        11:End of stream end_of_stream_rbsp():non-VCL
-        12:Filler data filler_data_rbsp():non-VCL
-        13:Sequence parameter set extension seq_parameter_set_extension_rbsp():non-VCL
-        14:Prefix NAL unit prefix_nal_unit_rbsp():non-VCL
-        15:Subset sequence parameter set subset_seq_parameter_set_rbsp():non-VCL
-        16:Reserved:non-VCL
-        18:Reserved:non-VCL
-        19:Coded slice of an auxiliary coded picture without partitioning slice_layer_without_partitioning_rbsp():non-VCL
-        20:Coded slice extension slice_layer_extension_rbsp():non-VCL
-        21:Coded slice extension for depth view components slice_layer_extension_rbsp() (specified in Annex I):non-VCL
-        22:Reserved:non-VCL
-        23:Reserved:non-VCL
-        24:STAP-A:non-VCL
-        25:STAP-B:non-VCL
-        26:MTAP16:non-VCL
-        27:MTAP24:non-VCL
-        28:FU-A:non-VCL
-        29:FU-B:non-VCL
-        30:reserved:non-VCL
-        31:reserved:non-VCL"#;
-
-    nal_types
-        .lines()
-        .enumerate()
-        .filter(|(i, _)| *i as u8 == nal)
-        .map(|(_, line)| line.split(':').collect::<Vec<&str>>()[1])
-        .collect::<String>()
+// RTCP socket on the port after the RTP port, None without a fixed
+// RTP port or if the port is taken: RTCP is a nice to have
+async fn bind_rtcp(addr_client: SocketAddr) -> Option<UdpSocket> {
+    let port = match addr_client.port() {
+        0 | u16::MAX => return None,
+        port => port + 1,
+    };
+
+    match UdpSocket::bind(SocketAddr::new(addr_client.ip(), port)).await {
+        Ok(socket) => Some(socket),
+        Err(e) => {
+            warn!("[Rtp] Unable to bind RTCP port {port}: {e}");
+            None
+        }
+    }
+}
+
+// Waits for packets on the redundant path, forever without one
+async fn redundant_readable(redundancy: Option<&Redundancy>) -> io::Result<()> {
+    match redundancy {
+        Some(redundancy) => redundancy.socket().readable().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Waits for RTCP to arrive, forever without an RTCP socket
+async fn rtcp_readable(socket: Option<&UdpSocket>) -> io::Result<()> {
+    match socket {
+        Some(socket) => socket.readable().await,
+        None => std::future::pending().await,
+    }
+}
+
+// Address to bind RTP sockets to, IPv4 or IPv6 (brackets optional)
+// Default is 0.0.0.0, or [::] when the server is IPv6
+fn client_addr(client_ip: Option<&str>, client_port: u16, addr_server: SocketAddr) -> Result<SocketAddr> {
+    let ip = match client_ip {
+        Some(ip) => ip.trim_matches(&['[', ']'][..]).parse::<IpAddr>()?,
+        None if addr_server.is_ipv6() => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    };
+
+    Ok(SocketAddr::new(ip, client_port))
 }
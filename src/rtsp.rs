@@ -1,17 +1,132 @@
-use anyhow::Result;
-use url::Url;
-use tokio::net::TcpStream;
-use tokio::io::{AsyncWriteExt, ErrorKind};
-use log::debug;
+use crate::capture::{Capture, CaptureStream};
+#[cfg(feature = "chaos")]
+use crate::chaos::Chaos;
+use crate::codec::VideoInfo;
+use crate::conformance::{Conformance, ConformanceReport, Exchange};
+use crate::error::RtspError;
+use crate::journal::{Event, Journal};
+use crate::latency::LatencyProfile;
+use crate::ports::{self, PortPair};
+use crate::proxy::Proxy;
+use crate::resolver::{Resolver, SystemResolver};
+use crate::response::{find_header_end, RtspResponse};
+use crate::rtcp;
+use crate::sdp::{MediaSection, Sdp, SdpBuilder, TrackOverride};
+#[cfg(feature = "otel")]
+use crate::telemetry::{self, Telemetry};
+use bytes::Bytes;
+use url::{Host, Url};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Mutex;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+use log::{debug, info, warn};
 use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::fmt::Display;
+use std::io::Write;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
+// RFC 2326 default when the server doesn't give a Session timeout
+const DEFAULT_SESSION_TIMEOUT: u64 = 60;
+
+// How many 3xx redirects to follow for a single request
+const DEFAULT_MAX_REDIRECTS: u32 = 3;
+
+// How long to wait for the TCP (and TLS) connection and
+// for the response to each request
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Default ports when the URL doesn't give one
+const DEFAULT_PORT_RTSP: u16 = 554;
+const DEFAULT_PORT_RTSPS: u16 = 322;
+
+type Result<T> = std::result::Result<T, RtspError>;
+
+// Connection to the server, either plain TCP or wrapped in TLS (rtsps://)
+trait RtspStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> RtspStream for T {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Methods {
     Options,
     Describe,
     Setup,
     Play,
     Teardown,
+    GetParameter,
+    SetParameter,
+    Announce,
+    Record,
+}
+
+// Client session state (RFC 2326 appendix A.1)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SessionState {
+    Init,      // no session
+    Ready,     // SETUP done
+    Playing,   // PLAY done
+    Recording, // RECORD done
+}
+
+impl SessionState {
+    // Whether 'method' can be sent in this state, requests
+    // not tied to a session can always be sent
+    #[rustfmt::skip]
+    pub fn allows(self, method: Methods) -> bool {
+        match method {
+            Methods::Play     => matches!(self, SessionState::Ready | SessionState::Playing),
+            Methods::Record   => matches!(self, SessionState::Ready | SessionState::Recording),
+            Methods::Teardown => self != SessionState::Init,
+            _                 => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MediaKind {
+    Video,
+    Audio,
+    Application, // e.g. ONVIF metadata
+    Other(String),
+}
+
+impl MediaKind {
+    // From the media of an m= line
+    #[rustfmt::skip]
+    pub fn from_media(media: &str) -> Self {
+        match media {
+            "video"       => MediaKind::Video,
+            "audio"       => MediaKind::Audio,
+            "application" => MediaKind::Application,
+            media         => MediaKind::Other(media.to_string()),
+        }
+    }
+}
+
+// Track offered by DESCRIBE, one per media section
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub index: usize, // what setup_track takes
+    pub kind: MediaKind,
+    pub control: String, // URL SETUP is sent to
+    pub payload_type: Option<u8>,
+    pub codec: Option<String>, // e.g. "H264", "PCMU"
+    pub clock_rate: Option<u32>,
+}
+
+// Transport negotiated by SETUP for one track (media section)
+#[derive(Clone, Debug)]
+pub struct TrackTransport {
+    pub track: usize,
+    pub media: String, // e.g. "video" or "audio"
+    pub client_port_rtp: u16,
+    pub server_addr_rtp: SocketAddr,
+    pub ssrc: Option<u32>, // if the server said which it sends
 }
 
 pub struct Rtsp {
@@ -19,43 +134,307 @@ pub struct Rtsp {
     pub server_addr_rtp: Option<SocketAddr>,
     pub client_port_rtp: u16, // our port which server will send RTP
     server_addr_rtsp: SocketAddr,
-    response_txt: String,
-    cseq: u32,
+    response: Option<RtspResponse>,
+    cseq: Arc<AtomicU32>,
     tcp_addr: SocketAddr,
-    stream: TcpStream,
+    local_ip: Option<IpAddr>, // our address towards the server when set up
+    url: Url,
+    stream: Arc<Mutex<Box<dyn RtspStream>>>,
     transport: String,
     track: String,
     id: String,
+    parameters_body: String,
+    parameters: HashMap<String, String>,
+    supported_methods: Option<Vec<Methods>>,
+    session: Option<String>,
+    state: SessionState,
+    session_timeout: u64,
+    keepalive: Option<JoinHandle<Result<()>>>,
+    runtime: Option<Handle>,
+    secure: bool,
+    accept_invalid_certs: bool,
+    resolver: Arc<dyn Resolver>,
+    proxy: Option<Proxy>,
+    max_redirects: u32,
+    writer: RequestWriter,
+    response_buf: Vec<u8>,
+    sdp: Option<Sdp>, // from DESCRIBE, or sent with ANNOUNCE
+    manual_sdp: Option<Sdp>, // used instead of DESCRIBE's
+    skip_describe: bool,     // tracks given with set_tracks
+    track_overrides: Vec<TrackOverride>,
+    content_base: Option<String>, // base for relative a=control URLs
+    setup_track: usize,
+    transports: Vec<TrackTransport>,
+    ports: Vec<PortPair>, // client port pair held for each track
+    auto_ports: bool,
+    journal: Option<Journal>,
+    conformance: Option<Conformance>,
+    capture: Option<Capture>,
+    #[cfg(feature = "chaos")]
+    chaos: Option<Chaos>,
+    #[cfg(feature = "otel")]
+    telemetry: Option<Telemetry>,
+    headers: Vec<(String, String)>,         // sent with every request
+    request_headers: Vec<(String, String)>, // sent with the next request only
+    announce_sdp: String,
+    record_mode: bool,
+    request_blocksize: Option<u32>, // asked for in SETUP
+    blocksize: Option<u32>,         // from the SETUP response
+    scale: Option<f64>,
+    speed: Option<f64>,
+    keyframes_only: bool,
+    play_scale: Option<f64>,
+    play_speed: Option<f64>,
+    connect_timeout: Duration,
+    request_timeout: Duration,
+    response_limits: ResponseLimits,
+    reconnect_policy: ReconnectPolicy,
+    nodelay: bool, // TCP_NODELAY on the RTSP connection
+}
+
+// Limits on what the server may send in a response, so a misbehaving
+// one can't grow the read buffer forever or trickle a response in
+#[derive(Clone, Copy, Debug)]
+pub struct ResponseLimits {
+    // Status line and headers, blank line included
+    pub max_header_bytes: usize,
+    // Content-Length, e.g. the SDP of DESCRIBE
+    pub max_body_bytes: usize,
+    // Longest wait for more bytes once a response has started.
+    // The whole response must still arrive within the request timeout
+    pub progress_timeout: Option<Duration>,
+}
+
+impl Default for ResponseLimits {
+    fn default() -> Self {
+        ResponseLimits {
+            max_header_bytes: 64 * 1024,
+            max_body_bytes: 1024 * 1024,
+            progress_timeout: Some(Duration::from_secs(5)),
+        }
+    }
+}
+
+// How reconnect() retries, waiting longer after each failure
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub multiplier: u32,
+    // None keeps trying forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2,
+            max_attempts: None,
+        }
+    }
+}
+
+// Options used when connecting
+// e.g. Rtsp::builder(addr).connect_timeout(Duration::from_secs(3)).connect().await?
+pub struct RtspBuilder {
+    addr: String,
+    port_rtp: Option<u16>,
+    secure: bool,
+    accept_invalid_certs: bool,
+    connect_timeout: Duration,
+    request_timeout: Duration,
+    response_limits: ResponseLimits,
+    resolver: Arc<dyn Resolver>,
+    proxy: Option<Proxy>,
+    nodelay: bool,
+}
+
+impl RtspBuilder {
+    pub fn new(addr: &str) -> Self {
+        RtspBuilder {
+            addr: addr.to_string(),
+            port_rtp: None,
+            secure: false,
+            accept_invalid_certs: false,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            response_limits: ResponseLimits::default(),
+            resolver: Arc::new(SystemResolver),
+            proxy: None,
+            nodelay: false,
+        }
+    }
+
+    // Client RTP port, otherwise a free one is picked
+    pub fn port_rtp(mut self, port_rtp: u16) -> Self {
+        self.port_rtp = Some(port_rtp);
+        self
+    }
+
+    // Always use TLS, whatever the URL scheme
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn accept_invalid_certs(mut self, accept_invalid_certs: bool) -> Self {
+        self.accept_invalid_certs = accept_invalid_certs;
+        self
+    }
+
+    // Time allowed to open the connection (TLS handshake included)
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    // Time allowed for the whole response to each request
+    pub fn request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    pub fn response_limits(mut self, response_limits: ResponseLimits) -> Self {
+        self.response_limits = response_limits;
+        self
+    }
+
+    // Resolves the server host name, also on reconnects and redirects
+    // e.g. MdnsResolver or one backed by hickory-dns
+    pub fn resolver<R: Resolver + 'static>(mut self, resolver: R) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    // Connect through a SOCKS5 or HTTP CONNECT proxy, see Proxy
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    // Send requests without waiting to fill a segment (TCP_NODELAY),
+    // which matters most for interleaved RTCP feedback
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    // Connection settings of a latency profile, the Rtp side is set
+    // with Rtp::set_latency_profile
+    pub fn latency_profile(mut self, profile: LatencyProfile) -> Self {
+        self.nodelay = profile.settings().nodelay;
+        self
+    }
+
+    pub async fn connect(self) -> Result<Rtsp> {
+        Rtsp::connect(self).await
+    }
 }
 
 impl Rtsp {
+    // Connects over TLS when the URL scheme is rtsps://
     pub async fn new(addr: &str, port_rtp: Option<u16>) -> Result<Self> {
-        let client_port_rtp = match port_rtp {
-            Some(port) => port,
-            None => 4588u16, // choose a sensible default
-        };
-        
-        let socket_addr = match Url::parse(addr) {
-            Ok(parsed_addr) => parsed_addr.socket_addrs(|| None)?,
-            Err(e) => panic!("[Rtsp] Trying to parse {addr} resulted in {e}"),    
-        };
-        
-        let tcp_stream = TcpStream::connect(socket_addr[0]).await?;
+        Self::connect(RtspBuilder { port_rtp, ..RtspBuilder::new(addr) }).await
+    }
+
+    // Always connect over TLS, whatever the URL scheme
+    // 'accept_invalid_certs' skips certificate validation which is
+    // often needed for cameras using self-signed certificates
+    pub async fn new_secure(addr: &str, port_rtp: Option<u16>, accept_invalid_certs: bool) -> Result<Self> {
+        Self::connect(RtspBuilder {
+            port_rtp,
+            secure: true,
+            accept_invalid_certs,
+            ..RtspBuilder::new(addr)
+        })
+        .await
+    }
 
-        println!("[Rtsp] Connecting to server at: {}", socket_addr[0]);
+    pub fn builder(addr: &str) -> RtspBuilder {
+        RtspBuilder::new(addr)
+    }
+
+    async fn connect(options: RtspBuilder) -> Result<Self> {
+        let RtspBuilder { addr, port_rtp, secure, accept_invalid_certs, connect_timeout, request_timeout, response_limits, resolver, proxy, nodelay } = options;
+        let url = Url::parse(&addr)
+            .map_err(|source| RtspError::InvalidUrl { url: addr.to_string(), source })?;
+
+        let secure = secure || url.scheme() == "rtsps";
+        let (socket_addr, stream) = open_stream(&url, resolver.as_ref(), proxy.as_ref(), secure, accept_invalid_certs, connect_timeout, nodelay).await?;
+        let local_ip = local_ip_towards(socket_addr).await;
+
+        // Without a port from the user pick one no other session
+        // in this process is using
+        let port_pair = match port_rtp {
+            Some(port) => ports::reserve_pair(port),
+            None => ports::allocate_pair(),
+        }
+        .ok_or(RtspError::NoFreePorts)?;
+        let client_port_rtp = port_pair.port();
 
         Ok(Rtsp {
             response_ok: false,
             server_addr_rtp: None,
-            server_addr_rtsp: socket_addr[0],
+            server_addr_rtsp: socket_addr,
             client_port_rtp,
-            response_txt: String::new(),
-            tcp_addr: socket_addr[0],
-            stream: tcp_stream,
+            response: None,
+            tcp_addr: socket_addr,
+            local_ip,
+            url,
+            stream: Arc::new(Mutex::new(stream)),
             transport: String::new(),
             track: String::new(),
             id: String::new(),
-            cseq: 1,
+            parameters_body: String::new(),
+            parameters: HashMap::new(),
+            supported_methods: None,
+            session: None,
+            state: SessionState::Init,
+            session_timeout: DEFAULT_SESSION_TIMEOUT,
+            keepalive: None,
+            runtime: None,
+            secure,
+            accept_invalid_certs,
+            resolver,
+            proxy,
+            max_redirects: DEFAULT_MAX_REDIRECTS,
+            writer: RequestWriter::new(),
+            response_buf: Vec::with_capacity(4096),
+            sdp: None,
+            manual_sdp: None,
+            skip_describe: false,
+            track_overrides: Vec::new(),
+            content_base: None,
+            setup_track: 0,
+            transports: Vec::new(),
+            ports: vec![port_pair],
+            auto_ports: port_rtp.is_none(),
+            journal: None,
+            conformance: None,
+            capture: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+            #[cfg(feature = "otel")]
+            telemetry: None,
+            headers: Vec::new(),
+            request_headers: Vec::new(),
+            announce_sdp: String::new(),
+            record_mode: false,
+            request_blocksize: None,
+            blocksize: None,
+            scale: None,
+            speed: None,
+            keyframes_only: false,
+            play_scale: None,
+            play_speed: None,
+            connect_timeout,
+            request_timeout,
+            response_limits,
+            reconnect_policy: ReconnectPolicy::default(),
+            nodelay,
+            cseq: Arc::new(AtomicU32::new(1)),
         })
     }
 
@@ -67,8 +446,25 @@ impl Rtsp {
             Methods::Setup       => "SETUP",
             Methods::Play        => "PLAY",
             Methods::Teardown    => "TEARDOWN",
+            Methods::GetParameter => "GET_PARAMETER",
+            Methods::SetParameter => "SET_PARAMETER",
+            Methods::Announce    => "ANNOUNCE",
+            Methods::Record      => "RECORD",
         };
 
+        // Once OPTIONS has told us what the server supports
+        // don't bother sending anything else
+        if let Some(supported) = &self.supported_methods {
+            if method_in != Methods::Options && !supported.contains(&method_in) {
+                return Err(RtspError::UnsupportedMethod(method_in));
+            }
+        }
+
+        // e.g. PLAY before SETUP would only get a 455 back
+        if !self.state.allows(method_in) {
+            return Err(RtspError::InvalidState { method: method_in, state: self.state });
+        }
+
         // I think you need to append the token received in SETUP
         // response here? With my test camera, it wasn't needed
 
@@ -76,168 +472,1585 @@ impl Rtsp {
         match method_in {
             Methods::Options     => {
                 println!("[Rtsp][send] Message::Options sending...");    
+                self.transport = String::new();
+                self.track = String::new();
             }
             Methods::Describe    => {
                 println!("[Rtsp][send] Message::Describe sending...");    
+                self.transport = String::new();
+                self.track = String::new();
             }
             Methods::Setup       => {
                 println!("[Rtsp][send] Message::Setup sending...");    
-                let video_codec = "RTP/AVP/UDP";
-                let uni_multicast = "unicast";
+                // SRTP tracks (RTP/SAVP in the SDP) are set up as such
+                let video_codec = match self.media_sections().get(self.setup_track) {
+                    Some(section) if section.protocol.to_ascii_uppercase().starts_with("RTP/SAVP") => "RTP/SAVP/UDP",
+                    _ => "RTP/AVP/UDP",
+                };
+                // Publishing (after ANNOUNCE) we send RTP instead of receiving it
+                let uni_multicast = match self.record_mode {
+                    true => "unicast;mode=record",
+                    false => "unicast",
+                };
                 // Client port is port you are telling server that it needs to send RTP
                 // traffic to. Add +1 to selected port for RTCP traffic. This is by
                 // convention and recommended in RFC.
-                let client_port = format!("{}-{}", self.client_port_rtp, self.client_port_rtp +1);
+                // Each extra track gets the next pair of ports
+                let port_rtp = self.track_port_rtp(self.setup_track)?;
+                let client_port = format!("{}-{}", port_rtp, port_rtp +1);
                 
                 self.transport = format!("Transport: {};{};client_port={}\r\n",
                     video_codec,
                     uni_multicast,
                     client_port);
-                self.track = "/trackID=0\r\n".to_string();
+                self.track = self.control_uri(self.setup_track);
+
+                if let Some(blocksize) = self.request_blocksize {
+                    set_header(&mut self.request_headers, "Blocksize", &blocksize.to_string());
+                }
             }
             Methods::Play        => {
                 println!("[Rtsp][send] Message::Play sending...");    
                 self.transport = String::new();
-                self.track = String::new();
+                self.track = self.aggregate_uri();
+
+                // Trick play e.g. Scale: 4 fast forward, Scale: -1 rewind
+                if let Some(scale) = self.scale {
+                    set_header(&mut self.request_headers, "Scale", &scale.to_string());
+                }
+                if let Some(speed) = self.speed {
+                    set_header(&mut self.request_headers, "Speed", &speed.to_string());
+                }
+                if self.keyframes_only {
+                    set_header(&mut self.request_headers, "Frames", "intra");
+                }
             }
             Methods::Teardown    => {
                 println!("[Rtsp][send] Message::Teardown sending...");    
+                self.transport = String::new();
+                self.track = self.aggregate_uri();
+                self.stop_keepalive();
+            }
+            Methods::GetParameter => {
+                println!("[Rtsp][send] Message::GetParameter sending...");    
+                self.transport = String::new();
+                self.track = self.aggregate_uri();
+            }
+            Methods::SetParameter => {
+                println!("[Rtsp][send] Message::SetParameter sending...");    
+                self.transport = String::new();
+                self.track = self.aggregate_uri();
+            }
+            Methods::Announce    => {
+                println!("[Rtsp][send] Message::Announce sending...");    
+                self.transport = String::new();
+                self.track = String::new();
+            }
+            Methods::Record      => {
+                println!("[Rtsp][send] Message::Record sending...");    
+                self.transport = String::new();
+                self.track = self.aggregate_uri();
             }
         }
 
-        let request = format!(
-            "{} {}{} RTSP/1.0\r\nCSeq: {}\r\n{}{}\r\n",
-            method_str, 
-            self.tcp_addr, 
-            self.track, 
-            self.cseq, 
-            self.transport, 
-            self.id,
-        );
-
-        let mut buf = Vec::with_capacity(4096);
-        let mut buf_size: usize = 0;
-
-        // Send command with proper headers
-        // every command must provide cseq
-        // which is incremented sequence as a header
-        self.stream.write_all(request.as_bytes()).await?;
-
-        'read: loop {
-            // Wait for the socket to be readable
-            self.stream.readable().await?;
-
-            // Try to read data, this may still fail with `WouldBlock`
-            // if the readiness event is a false positive.
-            match self.stream.try_read_buf(&mut buf) {
-                Ok(0) => break,
-                Ok(n) => {
-                    buf_size = n;
-                    break 'read;
-                }
-                Err(e) if e.kind() == ErrorKind::WouldBlock => {
-                    continue;
-                }
-                Err(e) => {
-                    return Err(e.into());
+        // GET_PARAMETER and SET_PARAMETER carry their parameters in the body
+        // An empty GET_PARAMETER is commonly used as a keep-alive
+        // ANNOUNCE carries the SDP of the stream being published
+        let (content_type, body) = match method_in {
+            Methods::GetParameter | Methods::SetParameter => ("text/parameters", std::mem::take(&mut self.parameters_body)),
+            Methods::Announce    => ("application/sdp", self.announce_sdp.clone()),
+            _                    => ("", String::new()),
+        };
+
+        // Headers for this request only are used up by it
+        let request_headers = std::mem::take(&mut self.request_headers);
+
+        // Response buffer is reused between requests
+        let mut buf = std::mem::take(&mut self.response_buf);
+        let mut redirects = 0;
+
+        let (buf_size, cseq) = loop {
+            let cseq = self.cseq.fetch_add(1, Ordering::SeqCst);
+            self.record(Event::Request { method: method_str, cseq });
+
+            // SETUP goes to the track's own URI, requests on the session to
+            // the aggregate control URI and the rest to the presentation URL
+            match self.track.is_empty() {
+                true  => self.writer.start(method_str, presentation_uri(&self.url), cseq),
+                false => self.writer.start(method_str, &self.track, cseq),
+            }
+            self.writer.raw(&self.transport);
+            self.writer.raw(&self.id);
+
+            for (name, value) in self.headers.iter().chain(&request_headers) {
+                self.writer.header(name, value);
+            }
+
+            if !body.is_empty() {
+                self.writer.header("Content-Type", content_type);
+                self.writer.header("Content-Length", body.len());
+            }
+
+            let request = self.writer.finish(body.as_bytes());
+            buf.clear();
+
+            // Send command with proper headers
+            // every command must provide cseq
+            // which is incremented sequence as a header
+            let mut stream = self.stream.lock().await;
+            let exchange = exchange(&mut stream, request, cseq, &mut buf, &self.response_limits);
+
+            let buf_size = tokio::time::timeout(self.request_timeout, exchange)
+                .await
+                .map_err(|_| RtspError::Timeout)??;
+            drop(stream);
+
+            // Some NVRs answer with 3xx pointing at another host/port
+            // Reconnect there and send the same request again
+            match redirect_location(&buf[..buf_size]) {
+                Some(location) => {
+                    if redirects >= self.max_redirects {
+                        return Err(RtspError::TooManyRedirects(self.max_redirects));
+                    }
+
+                    redirects += 1;
+                    self.record(Event::Redirect { location: &location });
+                    // A relative Location is relative to the request URL
+                    let base = Url::parse(&self.track).unwrap_or_else(|_| self.url.clone());
+                    self.redirect(&base, &location).await?;
+                    self.record(Event::Connect);
+
+                    // Track and aggregate URIs still point at the old server
+                    if !self.track.is_empty() && !self.record_mode {
+                        self.rediscover(method_in).await?;
+                    }
                 }
+                None => break (buf_size, cseq),
             }
-        }
+        };
 
-        self.cseq += 1;
         self.check_ok(&buf[..buf_size], method_str);
+        self.response_buf = buf;
+
+        if let Some(conformance) = &mut self.conformance {
+            let exchange = Exchange {
+                method: method_str,
+                cseq,
+                transport: self.transport.strip_prefix("Transport: ").map(str::trim_end),
+                session: self.session.as_deref(),
+            };
+            conformance.check(&exchange, self.response.as_ref());
+        }
+
+        if let Some(status) = self.response.as_ref().map(|response| response.status) {
+            self.record(Event::Response { method: method_str, status });
+
+            if status == 401 {
+                self.record(Event::Unauthorized { method: method_str });
+            }
+        }
         
         match method_in {
-            Methods::Options     => (),
+            Methods::Options     => self.parse_options(),
             Methods::Describe    => self.parse_describe(),
-            Methods::Setup       => self.parse_setup(),
-            Methods::Play        => (),
+            Methods::Setup       => if self.response_ok { self.parse_setup()? },
+            Methods::Play        => self.parse_play(),
             Methods::Teardown    => self.parse_stop(),
+            Methods::GetParameter => self.parse_parameters(),
+            Methods::SetParameter => self.parse_parameters(),
+            Methods::Announce    => self.parse_announce(),
+            Methods::Record      => self.parse_record(),
         }
 
         Ok(self)
     }
 
-    fn check_ok(&mut self, response: &[u8], method: &str) {
-        let response = (*String::from_utf8_lossy(&response)).to_string();
+    // Record session lifecycle events (requests, responses, plays,
+    // teardowns...) to the given journal
+    pub fn set_journal(&mut self, mut journal: Journal) -> &mut Self {
+        journal.record(self.server_addr_rtsp, Event::Connect);
+        self.journal = Some(journal);
+        self
+    }
 
-        if *&response.len() == 0 {
-            eprintln!("[Rtsp][send] {method} Response is empty.");
+    // Check every response from here on against RFC 2326, see
+    // conformance_report. Turning it off drops the report
+    pub fn set_conformance(&mut self, enabled: bool) -> &mut Self {
+        self.conformance = match enabled {
+            true => Some(Conformance::new(&self.server_addr_rtsp.to_string())),
+            false => None,
+        };
+        self
+    }
+
+    // What the checks found so far, None unless set_conformance
+    pub fn conformance_report(&self) -> Option<&ConformanceReport> {
+        self.conformance.as_ref().map(|conformance| conformance.report())
+    }
+
+    // Send an RTCP packet (e.g. Rtp::receiver_report) over the RTSP
+    // connection on an interleaved channel, the odd one of the pair
+    // (1 for interleaved=0-1). TCP-only servers may end sessions that
+    // don't report every rtcp::REPORT_INTERVAL or so
+    pub async fn send_interleaved_rtcp(&mut self, channel: u8, packet: &[u8]) -> Result<()> {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            tokio::time::sleep(chaos.rtcp_delay()).await;
         }
-        else {
-            debug!("//--------------------- {method} RESPONSE");
-            debug!("{:#?}", &response);
+
+        let frame = rtcp::interleaved(channel, packet);
+        self.stream.lock().await.write_all(&frame).await?;
+
+        Ok(())
+    }
+
+    // Record the connection byte for byte from now on, including
+    // reconnects and redirects, see capture::Capture
+    pub async fn set_capture(&mut self, capture: Capture) -> &mut Self {
+        let mut stream = self.stream.lock().await;
+        let placeholder: Box<dyn RtspStream> = Box::new(tokio::io::duplex(1).0);
+        let inner = std::mem::replace(&mut *stream, placeholder);
+        *stream = Box::new(CaptureStream::new(inner, capture.clone()));
+        drop(stream);
+
+        self.capture = Some(capture);
+        self
+    }
+
+    // Failure injection on this connection and the ones after it,
+    // see chaos::Chaos
+    #[cfg(feature = "chaos")]
+    pub async fn set_chaos(&mut self, chaos: Chaos) -> &mut Self {
+        let mut stream = self.stream.lock().await;
+        let placeholder: Box<dyn RtspStream> = Box::new(tokio::io::duplex(1).0);
+        let inner = std::mem::replace(&mut *stream, placeholder);
+        *stream = Box::new(chaos.stream(inner));
+        drop(stream);
+
+        self.chaos = Some(chaos);
+        self
+    }
+
+    // New connections are captured (and chaos injected into) too
+    // once set_capture (set_chaos) was called
+    fn wrap_stream(&self, stream: Box<dyn RtspStream>) -> Box<dyn RtspStream> {
+        #[cfg(feature = "chaos")]
+        let stream: Box<dyn RtspStream> = match &self.chaos {
+            Some(chaos) => Box::new(chaos.stream(stream)),
+            None => stream,
+        };
+
+        match &self.capture {
+            Some(capture) => Box::new(CaptureStream::new(stream, capture.clone())),
+            None => stream,
         }
+    }
 
-        self.response_ok = (&response).contains("200 OK");
-        self.response_txt = response;
+    // Export the same events as OpenTelemetry spans and metrics
+    #[cfg(feature = "otel")]
+    pub fn set_telemetry(&mut self, mut telemetry: Telemetry) -> &mut Self {
+        telemetry.event(self.server_addr_rtsp, &Event::Connect);
+        self.telemetry = Some(telemetry);
+        self
     }
 
-    // Parse OPTIONS methods to determine available methods/commands
-    // fn parse_options(&mut self) {}
-    // fn parse_play(&mut self) {}
+    fn record(&mut self, event: Event) {
+        let server = self.server_addr_rtsp;
 
-    fn parse_describe(&mut self) {
-        // SDP data begins after \r\n\r\n
-        let (_headers, sdp) = self.response_txt.split_once("\r\n\r\n").unwrap();
-        let sdp_fields = sdp.lines();
+        #[cfg(feature = "otel")]
+        if let Some(telemetry) = &mut self.telemetry {
+            telemetry.event(server, &event);
+        }
 
-        debug!("SDP ///---------------\n{:?}", sdp_fields);
+        if let Some(journal) = &mut self.journal {
+            journal.record(server, event);
+        }
     }
 
-    fn parse_setup(&mut self) {
-        let resp_headers = self.response_txt.lines();
+    // Header sent with every request of this session
+    // e.g. add_header("User-Agent", "my-app"), add_header("Require", "onvif-replay")
+    // Adding a header again replaces its value
+    pub fn add_header(&mut self, name: &str, value: &str) -> &mut Self {
+        set_header(&mut self.headers, name, value);
+        self
+    }
 
-        // Parse response from SETUP command
-        let setup_hash: HashMap<&str, &str> = resp_headers
-            .into_iter()
-            .filter(|line| line.contains(":"))
-            .map(|line| line.split(": ").collect::<Vec<&str>>())
-            .map(|v| (v[0], v[1]))
-            .collect();
+    // Header sent with the next request only
+    pub fn add_request_header(&mut self, name: &str, value: &str) -> &mut Self {
+        set_header(&mut self.request_headers, name, value);
+        self
+    }
 
-        // Parse the Transport header of the response
-        // which contains:
-        // 'server_port'
-        // 'ssrc'
-        // 'source' => server IP
-        let transport_hash: HashMap<&str, &str> = setup_hash
-            .get("Transport")
-            .unwrap()
-            .split(';')
-            .collect::<Vec<&str>>()
-            .iter()
-            .filter(|s| s.contains('='))
-            .map(|line| line.split('=').collect::<Vec<&str>>())
-            .map(|v| (v[0], v[1]))
-            .collect();
+    pub fn remove_header(&mut self, name: &str) -> &mut Self {
+        self.headers.retain(|(existing, _)| !existing.eq_ignore_ascii_case(name));
+        self
+    }
 
-        // Create a new server socket address to talk to it via RTP
-        // The address will have the same IP, but the port is sent
-        // via the 'SETUP' command
-        let server_port = transport_hash.get("server_port")
-            .expect("[RTSP][parse_setup] Error finding server_port in response");
+    pub fn set_reconnect_policy(&mut self, reconnect_policy: ReconnectPolicy) -> &mut Self {
+        self.reconnect_policy = reconnect_policy;
+        self
+    }
 
-        // server_port returns port range (e.g. 6600-6601)
-        // first port is RTP port
-        // second port is RTCP port
-        let server_rtp_rtcp: Vec<&str> = server_port.split('-').collect(); 
+    // Re-establish the session after the connection dropped or the
+    // stream stalled (e.g. Rtp::get_rtp timed out), retrying with
+    // backoff as set by set_reconnect_policy. OPTIONS, DESCRIBE,
+    // SETUP and PLAY (or ANNOUNCE, SETUP and RECORD when publishing)
+    // are sent again using the same client ports, so the Rtp already
+    // bound to them carries on once pointed at the new server port
+    // with Rtp::set_server_addr(rtsp.server_addr_rtp.unwrap())
+    pub async fn reconnect(&mut self) -> Result<()> {
+        let policy = self.reconnect_policy.clone();
+        let keepalive = self.keepalive.is_some();
+        let mut delay = policy.initial_delay;
+        let mut attempt = 0;
 
-        // We've been talking to server as something like 192.168.1.100:554
-        // Just remove the '554' port and replace with response in SETUP
-        let mut server_addr = self.server_addr_rtsp.clone();
-        server_addr.set_port(server_rtp_rtcp[0].parse::<u16>()
-            .expect("[RTSP][parse_setup] Error parsing server_port"));
+        self.stop_keepalive();
+
+        loop {
+            attempt += 1;
+            self.record(Event::Reconnect { attempt });
+
+            match self.reestablish().await {
+                Ok(()) => break,
+                Err(e) => {
+                    warn!("[Rtsp][reconnect] Attempt {attempt} failed: {e}");
+                    if policy.max_attempts.is_some_and(|max_attempts| attempt >= max_attempts) {
+                        return Err(e);
+                    }
+                }
+            }
+
+            tokio::time::sleep(delay).await;
+            delay = delay.saturating_mul(policy.multiplier).min(policy.max_delay);
+        }
 
-        self.server_addr_rtp = Some(server_addr);
-        self.id = format!("Session: {}", setup_hash.get("Session")
-            .expect("[RTSP][parse_setup] Error getting Session from hash"));
+        if keepalive {
+            self.start_keepalive()?;
+        }
+
+        Ok(())
     }
 
-    fn parse_stop(&mut self) {
-        match self.response_ok {
-            true  => println!("Shutdown Ok"),
-            false => eprintln!("Shutdown Error"),
+    async fn reestablish(&mut self) -> Result<()> {
+        let (socket_addr, stream) = open_stream(&self.url, self.resolver.as_ref(), self.proxy.as_ref(), self.secure, self.accept_invalid_certs, self.connect_timeout, self.nodelay).await?;
+
+        *self.stream.lock().await = self.wrap_stream(stream);
+        self.server_addr_rtsp = socket_addr;
+        self.tcp_addr = socket_addr;
+        self.local_ip = local_ip_towards(socket_addr).await;
+        self.record(Event::Connect);
+
+        self.session = None;
+        self.state = SessionState::Init;
+        self.id = String::new();
+        self.server_addr_rtp = None;
+        self.supported_methods = None;
+        self.transports.clear();
+
+        self.send(Methods::Options).await?;
+
+        match self.record_mode {
+            true => {
+                self.send(Methods::Announce).await?.check_status()?;
+                self.setup_all().await?;
+                self.send(Methods::Record).await?.check_status()?;
+            }
+            false => {
+                if !self.skip_describe {
+                    self.send(Methods::Describe).await?.check_status()?;
+                }
+                self.setup_all().await?;
+                self.send(Methods::Play).await?.check_status()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether the address we reach the server from has changed since
+    // connecting, e.g. after roaming to another network or a DHCP renew
+    pub async fn local_addr_changed(&self) -> bool {
+        let local_ip = local_ip_towards(self.server_addr_rtsp).await;
+        local_ip.is_some() && local_ip != self.local_ip
+    }
+
+    // Move the RTP transport to a new local address while keeping the
+    // session: every track is SETUP again within the same Session
+    // with new client ports (the same ports when they were chosen by
+    // the user) then PLAY (or RECORD) resumes. Servers which won't
+    // change the transport of a running session get a full reconnect()
+    // instead. Either way the Rtp receivers must be bound again to the
+    // ports in transports(), e.g. with Rtp::rebind
+    pub async fn resume(&mut self) -> Result<&[TrackTransport]> {
+        let keepalive = self.keepalive.is_some();
+        self.stop_keepalive();
+
+        if self.auto_ports {
+            // The old pairs go back once all the new ones are taken
+            let mut new_ports = Vec::new();
+            for _ in 0..self.ports.len() {
+                new_ports.push(ports::allocate_pair().ok_or(RtspError::NoFreePorts)?);
+            }
+            self.ports = new_ports;
+        }
+
+        match self.resume_session().await {
+            Ok(()) => self.local_ip = local_ip_towards(self.server_addr_rtsp).await,
+            Err(e) => {
+                warn!("[Rtsp][resume] Unable to resume session: {e}, reconnecting");
+                self.reconnect().await?;
+            }
+        }
+
+        if keepalive {
+            self.start_keepalive()?;
+        }
+
+        Ok(&self.transports)
+    }
+
+    async fn resume_session(&mut self) -> Result<()> {
+        let state = self.state;
+        if state == SessionState::Init {
+            return Err(RtspError::MissingSession);
+        }
+
+        self.setup_all().await?;
+
+        match state {
+            SessionState::Playing => self.send(Methods::Play).await?.check_status(),
+            SessionState::Recording => self.send(Methods::Record).await?.check_status(),
+            _ => Ok(()),
+        }
+    }
+
+    // Largest and slowest response accepted, see ResponseLimits
+    pub fn set_response_limits(&mut self, response_limits: ResponseLimits) -> &mut Self {
+        self.response_limits = response_limits;
+        self
+    }
+
+    // Time allowed for the whole response to each request
+    pub fn set_request_timeout(&mut self, request_timeout: Duration) -> &mut Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    // Maximum number of 3xx redirects followed for a single request
+    pub fn set_max_redirects(&mut self, max_redirects: u32) -> &mut Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    // Connect to 'location', resolved against 'base' when relative.
+    // Returns the connection it replaced
+    async fn redirect(&mut self, base: &Url, location: &str) -> Result<Connection> {
+        let url = base
+            .join(location.trim())
+            .map_err(|source| RtspError::InvalidUrl { url: location.to_string(), source })?;
+        let secure = self.secure || url.scheme() == "rtsps";
+
+        info!("[Rtsp][send] Redirected to: {url}");
+        let (socket_addr, stream) = open_stream(&url, self.resolver.as_ref(), self.proxy.as_ref(), secure, self.accept_invalid_certs, self.connect_timeout, self.nodelay).await?;
+
+        let connection = Connection {
+            stream: self.wrap_stream(stream),
+            addr: socket_addr,
+            local_ip: local_ip_towards(socket_addr).await,
+            secure,
+        };
+        self.url = url;
+
+        Ok(self.swap_connection(connection).await)
+    }
+
+    // Swap the connection in place so the keep-alive task follows along
+    async fn swap_connection(&mut self, connection: Connection) -> Connection {
+        let stream = std::mem::replace(&mut *self.stream.lock().await, connection.stream);
+        let previous = Connection {
+            stream,
+            addr: self.server_addr_rtsp,
+            local_ip: self.local_ip,
+            secure: self.secure,
+        };
+
+        self.server_addr_rtsp = connection.addr;
+        self.tcp_addr = connection.addr;
+        self.local_ip = connection.local_ip;
+        self.secure = connection.secure;
+
+        previous
+    }
+
+    // After a redirect: DESCRIBE the stream again at the new URL
+    // and take the URI of the request being redirected from it
+    async fn rediscover(&mut self, method: Methods) -> Result<()> {
+        let transport = std::mem::take(&mut self.transport);
+        self.content_base = None;
+        if !self.skip_describe {
+            Box::pin(self.send(Methods::Describe)).await?.check_status()?;
+        }
+
+        self.transport = transport;
+        self.track = match method {
+            Methods::Setup => self.control_uri(self.setup_track),
+            _ => self.aggregate_uri(),
+        };
+
+        Ok(())
+    }
+
+    // Address of the RTSP server currently connected to
+    pub fn server_addr(&self) -> SocketAddr {
+        self.server_addr_rtsp
+    }
+
+    // Last response received from the server
+    pub fn response(&self) -> Option<&RtspResponse> {
+        self.response.as_ref()
+    }
+
+    // Body of the last response exactly as received
+    // (SDP, text/parameters or anything binary a vendor sends back)
+    pub fn response_body(&self) -> &Bytes {
+        static EMPTY: Bytes = Bytes::new();
+
+        match &self.response {
+            Some(response) => &response.body,
+            None => &EMPTY,
         }
     }
-}
\ No newline at end of file
+
+    // Status code of the last response
+    pub fn status(&self) -> Option<u16> {
+        self.response.as_ref().map(|response| response.status)
+    }
+
+    // Turn the last response into an error unless it was 2xx
+    // e.g. 401 => RtspError::Unauthorized, 454 => RtspError::SessionNotFound
+    pub fn check_status(&self) -> Result<()> {
+        let response = self.response
+            .as_ref()
+            .ok_or(RtspError::InvalidResponse)?;
+
+        match response.is_success() {
+            true => Ok(()),
+            false => Err(RtspError::from_status(response.status, &response.reason)),
+        }
+    }
+
+    fn check_ok(&mut self, response: &[u8], method: &str) {
+        if response.is_empty() {
+            eprintln!("[Rtsp][send] {method} Response is empty.");
+        }
+
+        let response = RtspResponse::parse(response);
+
+        match &response {
+            Some(response) => {
+                debug!("//--------------------- {method} RESPONSE");
+                debug!("{:#?}", response);
+            }
+            None => eprintln!("[Rtsp][send] {method} Response is not RTSP."),
+        }
+
+        if let Some(response) = response.as_ref().filter(|response| !response.is_success()) {
+            eprintln!("[Rtsp][send] {method} failed: {} {}", response.status, response.reason);
+        }
+
+        self.response_ok = response.as_ref().is_some_and(|response| response.is_success());
+        self.response = response;
+    }
+
+    // Methods advertised by the server in the OPTIONS response
+    // None until OPTIONS has been sent
+    pub fn supported_methods(&self) -> Option<&[Methods]> {
+        self.supported_methods.as_deref()
+    }
+
+    // Parse OPTIONS methods to determine available methods/commands
+    // e.g. Public: OPTIONS, DESCRIBE, SETUP, TEARDOWN, PLAY, GET_PARAMETER
+    fn parse_options(&mut self) {
+        let public = self.response
+            .as_ref()
+            .and_then(|response| response.header("Public"));
+
+        // Some servers leave out Public, in which case assume anything goes
+        let public = match public {
+            Some(public) => public,
+            None => return,
+        };
+
+        let supported: Vec<Methods> = public
+            .split(',')
+            .filter_map(|method| match method.trim() {
+                "OPTIONS"       => Some(Methods::Options),
+                "DESCRIBE"      => Some(Methods::Describe),
+                "SETUP"         => Some(Methods::Setup),
+                "PLAY"          => Some(Methods::Play),
+                "TEARDOWN"      => Some(Methods::Teardown),
+                "GET_PARAMETER" => Some(Methods::GetParameter),
+                "SET_PARAMETER" => Some(Methods::SetParameter),
+                "ANNOUNCE"      => Some(Methods::Announce),
+                "RECORD"        => Some(Methods::Record),
+                _               => None,
+            })
+            .collect();
+
+        debug!("Supported methods ///---------------\n{:?}", supported);
+        self.supported_methods = Some(supported);
+    }
+
+
+    fn parse_describe(&mut self) {
+        // SDP data is the body of the response
+        let sdp = match &self.response {
+            Some(response) => response.body_text(),
+            None => return,
+        };
+
+        debug!("SDP ///---------------\n{:?}", sdp);
+
+        // Relative URLs in the SDP are relative to Content-Base, or
+        // failing that Content-Location, or the request URL (RFC 2326 C.1.1)
+        self.content_base = self.response.as_ref().and_then(|response| {
+            response
+                .header("Content-Base")
+                .or_else(|| response.header("Content-Location"))
+                .map(|base| base.trim().to_string())
+        });
+        let mut sdp = match &self.manual_sdp {
+            Some(manual_sdp) => manual_sdp.clone(),
+            None => Sdp::parse(&sdp),
+        };
+        for track_override in &self.track_overrides {
+            track_override.apply(&mut sdp);
+        }
+        self.sdp = Some(sdp);
+        self.record_mode = false;
+    }
+
+    // Describe the stream with 'sdp' instead of what the server
+    // returns for DESCRIBE, for servers whose SDP is unusable. It is
+    // the stream's SDP right away, so SETUP can also go ahead
+    // without a DESCRIBE. Relative a=control URLs are relative to
+    // the request URL
+    pub fn set_sdp(&mut self, sdp: Sdp) -> &mut Self {
+        let mut described = sdp.clone();
+        for track_override in &self.track_overrides {
+            track_override.apply(&mut described);
+        }
+
+        self.manual_sdp = Some(sdp);
+        self.sdp = Some(described);
+        self.content_base = None;
+        self.record_mode = false;
+        self
+    }
+
+    // Tracks known beforehand, one media section each with its
+    // control URL and payload type, e.g.
+    // MediaSection::new("video", 96, "H264", 90000, None).with_control("trackID=1")
+    // SETUP can follow straight away and reconnects skip DESCRIBE
+    // too, saving a round trip or getting around a broken DESCRIBE
+    pub fn set_tracks(&mut self, tracks: Vec<MediaSection>) -> &mut Self {
+        let sdp = tracks
+            .into_iter()
+            .fold(SdpBuilder::new(""), |sdp, track| sdp.media(track))
+            .build();
+
+        self.skip_describe = true;
+        self.set_sdp(sdp)
+    }
+
+    // Correct a media section of the server's SDP (or the one given
+    // to set_sdp), now and after every DESCRIBE, see TrackOverride
+    pub fn add_track_override(&mut self, track_override: TrackOverride) -> &mut Self {
+        if let Some(sdp) = self.sdp.as_mut().filter(|_| !self.record_mode) {
+            track_override.apply(sdp);
+        }
+        self.track_overrides.push(track_override);
+        self
+    }
+
+    // Back to the SDP as the server describes it, from the next DESCRIBE
+    pub fn clear_sdp_overrides(&mut self) -> &mut Self {
+        self.manual_sdp = None;
+        self.skip_describe = false;
+        self.track_overrides.clear();
+        self
+    }
+
+    // SDP describing the stream to publish, sent with ANNOUNCE
+    // Its media sections are the tracks set up afterwards
+    pub fn set_announce_sdp(&mut self, sdp: &str) -> &mut Self {
+        self.announce_sdp = sdp.to_string();
+        self
+    }
+
+    fn parse_announce(&mut self) {
+        if self.response_ok {
+            self.content_base = None;
+            self.sdp = Some(Sdp::parse(&self.announce_sdp));
+            self.record_mode = true;
+        }
+    }
+
+    fn parse_record(&mut self) {
+        if self.response_ok {
+            self.state = SessionState::Recording;
+            self.record(Event::Record);
+        }
+    }
+
+    // URI to SETUP a track, built from its a=control attribute
+    // which is either absolute or relative to the base URL
+    fn control_uri(&self, track: usize) -> String {
+        let control = self.media_sections()
+            .get(track)
+            .and_then(|section| section.control.as_deref())
+            // No SDP (or no a=control) so guess the common default
+            .map(|control| control.to_string())
+            .unwrap_or_else(|| format!("trackID={}", track));
+
+        self.resolve_control(&control)
+    }
+
+    // URI for requests on the whole session (PLAY, TEARDOWN...) from the
+    // session level a=control or Content-Base, empty to use the presentation URL
+    fn aggregate_uri(&self) -> String {
+        let aggregate_control = self.sdp.as_ref().and_then(|sdp| sdp.control.as_ref());
+
+        match (aggregate_control, &self.content_base) {
+            (Some(control), _) => self.resolve_control(control),
+            (None, Some(content_base)) => content_base.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    // Resolve an a=control URL against the base URL
+    // "*" is the base URL itself
+    fn resolve_control(&self, control: &str) -> String {
+        if control.starts_with("rtsp://") || control.starts_with("rtsps://") {
+            return control.to_string();
+        }
+
+        let base = match &self.content_base {
+            Some(content_base) => content_base.clone(),
+            None => presentation_uri(&self.url),
+        };
+
+        match control {
+            "*" | "" => base,
+            // Most servers expect the control appended as a path segment
+            // even when the base doesn't end with '/'
+            control => format!("{}/{}", base.trim_end_matches('/'), control.trim_start_matches('/')),
+        }
+    }
+
+    // Tracks described by the last DESCRIBE (or ANNOUNCE), so the
+    // ones wanted can be set up with setup_track
+    pub fn tracks(&self) -> Vec<Track> {
+        self.media_sections()
+            .iter()
+            .enumerate()
+            .map(|(index, section)| Track {
+                index,
+                kind: MediaKind::from_media(&section.media),
+                control: self.control_uri(index),
+                payload_type: section.payload_type,
+                codec: section.codec.clone(),
+                clock_rate: section.clock_rate,
+            })
+            .collect()
+    }
+
+    // SETUP a single track (see tracks()), sharing the Session of
+    // any track already set up. Send PLAY once all are set up
+    pub async fn setup_track(&mut self, track: usize) -> Result<&TrackTransport> {
+        let count = self.media_sections().len().max(1);
+        if track >= count {
+            return Err(RtspError::SetupFailed(track));
+        }
+
+        self.setup_track = track;
+        let result = self.send(Methods::Setup).await.map(|_| ());
+        self.setup_track = 0;
+
+        result?;
+        if !self.response_ok {
+            // e.g. 461 when the server won't do UDP
+            return Err(self.check_status().err().unwrap_or(RtspError::SetupFailed(track)));
+        }
+
+        self.transports
+            .iter()
+            .find(|transport| transport.track == track)
+            .ok_or(RtspError::SetupFailed(track))
+    }
+
+    // Send one SETUP per media section found by DESCRIBE, all
+    // sharing the same Session. Each track receives RTP on its
+    // own client port pair, see transports().
+    pub async fn setup_all(&mut self) -> Result<&[TrackTransport]> {
+        self.transports.clear();
+
+        // Without DESCRIBE just set up the first track
+        let count = self.media_sections().len().max(1);
+
+        for track in 0..count {
+            self.setup_track(track).await?;
+        }
+
+        Ok(&self.transports)
+    }
+
+    // Switch to another stream (e.g. main <-> substream) on the same
+    // server without reconnecting. The new URL is described, then the
+    // current session is torn down and the new one set up and played
+    // over the same connection with the same client ports and headers.
+    // Only reconnects if the new URL points at a different server.
+    pub async fn switch_stream(&mut self, addr: &str) -> Result<&[TrackTransport]> {
+        let url = Url::parse(addr)
+            .map_err(|source| RtspError::InvalidUrl { url: addr.to_string(), source })?;
+        let keepalive = self.keepalive.is_some();
+        self.stop_keepalive();
+
+        let same_server = url.scheme() == self.url.scheme()
+            && url.host_str() == self.url.host_str()
+            && url.port() == self.url.port();
+
+        // The new stream is described before the old session is torn
+        // down, a wrong URL leaves the old session playing. A manual
+        // SDP or track list was the old stream's
+        let old_url = self.url.clone();
+        let old_sdp = self.sdp.take();
+        let old_content_base = self.content_base.take();
+        let id = std::mem::take(&mut self.id);
+        let overrides = (self.manual_sdp.take(), std::mem::take(&mut self.skip_describe), std::mem::take(&mut self.track_overrides));
+
+        let mut old_connection = None;
+        let described = async {
+            match same_server {
+                true => self.url = url,
+                false => old_connection = Some(self.redirect(&url, addr).await?),
+            }
+            self.send(Methods::Describe).await?.check_status()
+        }
+        .await;
+
+        // Back to the old session, to tear it down or to carry on with it
+        let new_url = std::mem::replace(&mut self.url, old_url);
+        let new_sdp = std::mem::replace(&mut self.sdp, old_sdp);
+        let new_content_base = std::mem::replace(&mut self.content_base, old_content_base);
+        self.id = id;
+        let new_connection = match old_connection {
+            Some(connection) => Some(self.swap_connection(connection).await),
+            None => None,
+        };
+
+        if let Err(e) = described {
+            (self.manual_sdp, self.skip_describe, self.track_overrides) = overrides;
+            if keepalive {
+                self.start_keepalive()?;
+            }
+            return Err(e);
+        }
+
+        // Ports stay with us, the RTP sockets are already bound to them.
+        // Should TEARDOWN fail the server ends the old session once it
+        // times out
+        let ports = std::mem::take(&mut self.ports);
+        if self.session.is_some() {
+            if let Err(e) = self.send(Methods::Teardown).await {
+                warn!("[Rtsp][switch_stream] TEARDOWN of the old stream failed: {e}");
+            }
+        }
+        self.ports = ports;
+
+        self.session = None;
+        self.state = SessionState::Init;
+        self.id = String::new();
+        self.server_addr_rtp = None;
+        self.transports.clear();
+        self.url = new_url;
+        self.sdp = new_sdp;
+        self.content_base = new_content_base;
+        if let Some(connection) = new_connection {
+            self.swap_connection(connection).await;
+        }
+
+        self.setup_all().await?;
+        self.send(Methods::Play).await?.check_status()?;
+
+        if keepalive {
+            self.start_keepalive()?;
+        }
+
+        Ok(&self.transports)
+    }
+
+    // SDP of the stream, once DESCRIBE (or ANNOUNCE) succeeded
+    pub fn sdp(&self) -> Option<&Sdp> {
+        self.sdp.as_ref()
+    }
+
+    // Media sections of the SDP, one per track
+    pub fn media_sections(&self) -> &[MediaSection] {
+        match &self.sdp {
+            Some(sdp) => &sdp.media,
+            None => &[],
+        }
+    }
+
+    // Resolution and framerate of the first video track, from the
+    // SDP. The SPS received in band is authoritative, see Rtp::video_info
+    pub fn video_info(&self) -> Option<VideoInfo> {
+        self.media_sections()
+            .iter()
+            .filter(|section| section.media == "video")
+            .find_map(VideoInfo::from_sdp)
+    }
+
+    // Transports set up so far, one per track
+    pub fn transports(&self) -> &[TrackTransport] {
+        &self.transports
+    }
+
+    // Port pairs for extra tracks are taken as they are needed
+    fn track_port_rtp(&mut self, track: usize) -> Result<u16> {
+        while self.ports.len() <= track {
+            let port = match self.auto_ports {
+                true => ports::allocate_pair(),
+                false => u16::try_from(self.ports.len())
+                    .ok()
+                    .and_then(|pairs| pairs.checked_mul(2))
+                    .and_then(|offset| self.client_port_rtp.checked_add(offset))
+                    .and_then(ports::reserve_pair),
+            };
+
+            self.ports.push(port.ok_or(RtspError::NoFreePorts)?);
+        }
+
+        Ok(self.ports[track].port())
+    }
+
+    // Hand port pairs back so other sessions can use them
+    fn release_ports(&mut self) {
+        self.ports.clear();
+    }
+
+    fn parse_setup(&mut self) -> Result<()> {
+        let response = self.response
+            .as_ref()
+            .ok_or(RtspError::InvalidResponse)?;
+
+        self.blocksize = response
+            .header("Blocksize")
+            .and_then(|blocksize| blocksize.trim().parse().ok());
+
+        // Parse the Transport header of the response
+        // which contains:
+        // 'server_port'
+        // 'ssrc'
+        // 'source' => server IP
+        let transport_hash: HashMap<&str, &str> = response
+            .header("Transport")
+            .ok_or(RtspError::MissingTransportHeader)?
+            .split(';')
+            .collect::<Vec<&str>>()
+            .iter()
+            .filter(|s| s.contains('='))
+            .map(|line| line.split('=').collect::<Vec<&str>>())
+            .map(|v| (v[0], v[1]))
+            .collect();
+
+        // Create a new server socket address to talk to it via RTP
+        // The address will have the same IP, but the port is sent
+        // via the 'SETUP' command
+        let server_port = transport_hash.get("server_port")
+            .ok_or(RtspError::InvalidServerPort)?;
+
+        // server_port returns port range (e.g. 6600-6601)
+        // first port is RTP port
+        // second port is RTCP port
+        let server_rtp_rtcp: Vec<&str> = server_port.split('-').collect(); 
+
+        // We've been talking to server as something like 192.168.1.100:554
+        // Just remove the '554' port and replace with response in SETUP
+        let mut server_addr = self.server_addr_rtsp;
+        server_addr.set_port(server_rtp_rtcp[0].parse::<u16>()
+            .map_err(|_| RtspError::InvalidServerPort)?);
+
+        // First track set up stays available as server_addr_rtp
+        let track = self.setup_track;
+        if track == 0 || self.transports.iter().all(|transport| transport.track == track) {
+            self.server_addr_rtp = Some(server_addr);
+        }
+
+        // Hex, e.g. ssrc=1A2B3C4D (RFC 2326 12.39)
+        let ssrc = transport_hash
+            .get("ssrc")
+            .and_then(|ssrc| u32::from_str_radix(ssrc.trim(), 16).ok());
+
+        let media = match self.media_sections().get(track) {
+            Some(section) => section.media.clone(),
+            None => "video".to_string(),
+        };
+
+        self.transports.retain(|transport| transport.track != track);
+        self.transports.push(TrackTransport {
+            track,
+            media,
+            client_port_rtp: self.ports.get(track).map_or(self.client_port_rtp, PortPair::port),
+            server_addr_rtp: server_addr,
+            ssrc,
+        });
+
+        // Session header may carry a timeout after the id
+        // e.g. Session: 12345678;timeout=60
+        let session = response.header("Session")
+            .ok_or(RtspError::MissingSession)?
+            .to_string();
+        let mut session_parts = session.split(';');
+        let session_id = session_parts.next().unwrap_or_default().trim().to_string();
+
+        self.session_timeout = session_parts
+            .filter_map(|part| part.trim().strip_prefix("timeout="))
+            .find_map(|timeout| timeout.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_SESSION_TIMEOUT);
+
+        self.id = format!("Session: {}\r\n", session_id);
+        self.session = Some(session_id);
+        if self.state == SessionState::Init {
+            self.state = SessionState::Ready;
+        }
+
+        Ok(())
+    }
+
+    // Where the session is: Init, Ready (after SETUP), Playing or Recording
+    pub fn state(&self) -> SessionState {
+        self.state
+    }
+
+    // Session timeout in seconds as given in the SETUP response
+    pub fn session_timeout(&self) -> u64 {
+        self.session_timeout
+    }
+
+    // Spawn a background task which keeps the session alive by
+    // sending GET_PARAMETER (or OPTIONS if the server doesn't
+    // support it) at half the session timeout
+    pub fn start_keepalive(&mut self) -> Result<()> {
+        let session = match &self.session {
+            Some(session) => session.clone(),
+            None => return Err(RtspError::MissingSession),
+        };
+
+        self.stop_keepalive();
+
+        let use_get_parameter = match &self.supported_methods {
+            Some(supported) => supported.contains(&Methods::GetParameter),
+            None => true,
+        };
+        let method_str = if use_get_parameter { "GET_PARAMETER" } else { "OPTIONS" };
+        let interval = Duration::from_secs((self.session_timeout / 2).max(1));
+
+        let stream = self.stream.clone();
+        let cseq = self.cseq.clone();
+        let uri = match self.aggregate_uri() {
+            uri if uri.is_empty() => presentation_uri(&self.url),
+            uri => uri,
+        };
+        let request_timeout = self.request_timeout;
+        let response_limits = self.response_limits;
+        // Same headers as send, e.g. Authorization
+        let headers = self.headers.clone();
+        let id = format!("Session: {}\r\n", session);
+
+        let task = async move {
+            let mut writer = RequestWriter::new();
+            let mut buf = Vec::with_capacity(4096);
+
+            loop {
+                tokio::time::sleep(interval).await;
+
+                let cseq = cseq.fetch_add(1, Ordering::SeqCst);
+                writer.start(method_str, &uri, cseq);
+                writer.raw(&id);
+                for (name, value) in &headers {
+                    writer.header(name, value);
+                }
+                let request = writer.finish(&[]);
+
+                let mut stream = stream.lock().await;
+                buf.clear();
+
+                let exchange = exchange(&mut stream, request, cseq, &mut buf, &response_limits);
+                let result = match tokio::time::timeout(request_timeout, exchange).await {
+                    Ok(Ok(buf_size)) => match RtspResponse::parse(&buf[..buf_size]) {
+                        Some(response) if response.is_success() => Ok(()),
+                        Some(response) => Err(RtspError::from_status(response.status, &response.reason)),
+                        None => Err(RtspError::InvalidResponse),
+                    },
+                    Ok(Err(e)) => Err(e),
+                    Err(_) => {
+                        // Whatever is left of the response would be read
+                        // as the answer to the next request. Drop the
+                        // connection, send fails and the session is
+                        // reconnected
+                        *stream = Box::new(tokio::io::duplex(1).0);
+                        Err(RtspError::Timeout)
+                    }
+                };
+                drop(stream);
+
+                #[cfg(feature = "otel")]
+                telemetry::keepalive(method_str, result.as_ref().map(|_| ()).map_err(|e| e.to_string()));
+
+                match result {
+                    Ok(()) => debug!("[Rtsp][keepalive] {method_str} sent"),
+                    Err(e) => {
+                        warn!("[Rtsp][keepalive] Stopping keep-alive: {e}");
+                        return Err(e);
+                    }
+                }
+            }
+        };
+
+        // Spawn on the runtime chosen by the host, otherwise the current one
+        let handle = match &self.runtime {
+            Some(runtime) => runtime.spawn(task),
+            None => tokio::spawn(task),
+        };
+
+        self.keepalive = Some(handle);
+        Ok(())
+    }
+
+    // Runtime used for background network tasks (e.g. keep-alive)
+    // By default they are spawned on the runtime calling start_keepalive
+    pub fn set_runtime(&mut self, runtime: Handle) -> &mut Self {
+        self.runtime = Some(runtime);
+        self
+    }
+
+    pub fn stop_keepalive(&mut self) {
+        if let Some(handle) = self.keepalive.take() {
+            handle.abort();
+        }
+    }
+
+    // Error which ended the keep-alive task, if it has ended. The
+    // task isn't restarted, a failed keep-alive means the session
+    // is about to time out
+    pub async fn check_keepalive(&mut self) -> Result<()> {
+        match &self.keepalive {
+            Some(handle) if handle.is_finished() => (),
+            _ => return Ok(()),
+        }
+
+        match self.keepalive.take() {
+            Some(handle) => join_task("keep-alive", handle).await,
+            None => Ok(()),
+        }
+    }
+
+    // Stop the keep-alive task and wait until it is gone, so nothing
+    // of the session is left running. Err if it failed before
+    pub async fn join_keepalive(&mut self) -> Result<()> {
+        match self.keepalive.take() {
+            Some(handle) => {
+                handle.abort();
+                join_task("keep-alive", handle).await
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Parameters to send in the body of the next GET_PARAMETER
+    // (one name per line) or SET_PARAMETER ("name: value" per line)
+    pub fn set_parameters(&mut self, body: &str) -> &mut Self {
+        self.parameters_body = body.to_string();
+        if !self.parameters_body.is_empty() && !self.parameters_body.ends_with("\r\n") {
+            self.parameters_body.push_str("\r\n");
+        }
+
+        self
+    }
+
+    // Parameters returned in the body of the last
+    // GET_PARAMETER or SET_PARAMETER response
+    pub fn parameters(&self) -> &HashMap<String, String> {
+        &self.parameters
+    }
+
+    fn parse_parameters(&mut self) {
+        self.parameters.clear();
+
+        // Parameters are text, one per line in the body
+        let body = match &self.response {
+            Some(response) => response.body_text(),
+            None => return,
+        };
+
+        for line in body.lines() {
+            if let Some((name, value)) = line.split_once(':') {
+                self.parameters.insert(name.trim().to_string(), value.trim().to_string());
+            }
+        }
+
+        debug!("Parameters ///---------------\n{:?}", self.parameters);
+    }
+
+    // Blocksize sent with every SETUP (RFC 2326 12.7), the largest RTP
+    // payload the server should send, e.g. to stay under the path MTU
+    pub fn set_blocksize(&mut self, blocksize: Option<u32>) -> &mut Self {
+        self.request_blocksize = blocksize;
+        self
+    }
+
+    // Blocksize the server agreed to in the last SETUP response, None
+    // if it didn't say. Size receive buffers with Rtp::set_blocksize
+    pub fn blocksize(&self) -> Option<u32> {
+        self.blocksize
+    }
+
+    // Scale sent with every PLAY (RFC 2326 12.34) e.g. 2.0 plays
+    // twice as fast, -1.0 plays backwards. None for normal play
+    pub fn set_scale(&mut self, scale: Option<f64>) -> &mut Self {
+        self.scale = scale;
+        self
+    }
+
+    // Speed sent with every PLAY (RFC 2326 12.35), the rate data
+    // is delivered at rather than the rate it is played back at
+    pub fn set_speed(&mut self, speed: Option<f64>) -> &mut Self {
+        self.speed = speed;
+        self
+    }
+
+    // Ask for keyframes only with every PLAY (ONVIF streaming spec
+    // "Frames: intra"), e.g. to save bandwidth. Takes effect with the
+    // next PLAY, servers which don't know the header send everything
+    pub fn set_keyframes_only(&mut self, keyframes_only: bool) -> &mut Self {
+        self.keyframes_only = keyframes_only;
+        self
+    }
+
+    // Scale the server actually used for the last PLAY, which may
+    // differ from what was asked for. None if it didn't say
+    pub fn play_scale(&self) -> Option<f64> {
+        self.play_scale
+    }
+
+    pub fn play_speed(&self) -> Option<f64> {
+        self.play_speed
+    }
+
+    fn parse_play(&mut self) {
+        let header = |name: &str| {
+            self.response
+                .as_ref()
+                .and_then(|response| response.header(name))
+                .and_then(|value| value.trim().parse::<f64>().ok())
+        };
+
+        self.play_scale = header("Scale");
+        self.play_speed = header("Speed");
+
+        if self.response_ok {
+            self.state = SessionState::Playing;
+            self.record(Event::Play);
+        }
+    }
+
+    fn parse_stop(&mut self) {
+        // The server may still be sending to the ports when TEARDOWN
+        // failed, they go back with the Rtsp then
+        if self.response_ok {
+            self.release_ports();
+            self.session = None;
+            self.id = String::new();
+            self.state = SessionState::Init;
+        }
+        self.record(Event::Teardown { ok: self.response_ok });
+
+        match self.response_ok {
+            true  => println!("Shutdown Ok"),
+            false => eprintln!("Shutdown Error"),
+        }
+    }
+}
+
+impl Drop for Rtsp {
+    fn drop(&mut self) {
+        self.stop_keepalive();
+        self.release_ports();
+        self.record(Event::Disconnect);
+    }
+}
+
+// What redirect replaces, kept by switch_stream to tear down the
+// old session on the server it was set up with
+struct Connection {
+    stream: Box<dyn RtspStream>,
+    addr: SocketAddr,
+    local_ip: Option<IpAddr>,
+    secure: bool,
+}
+
+// Serializes requests into a buffer which is kept and reused
+// between requests, so sending doesn't allocate once it has grown
+struct RequestWriter {
+    buf: Vec<u8>,
+}
+
+impl RequestWriter {
+    fn new() -> Self {
+        RequestWriter {
+            buf: Vec::with_capacity(1024),
+        }
+    }
+
+    // Request line and CSeq, which every request must have
+    fn start(&mut self, method: &str, uri: impl Display, cseq: u32) {
+        self.buf.clear();
+        // Writing to a Vec can't fail
+        let _ = write!(self.buf, "{} {} RTSP/1.0\r\nCSeq: {}\r\n", method, uri, cseq);
+    }
+
+    fn header(&mut self, name: &str, value: impl Display) {
+        let _ = write!(self.buf, "{}: {}\r\n", name, value);
+    }
+
+    // Already formatted header line(s) including trailing \r\n
+    fn raw(&mut self, lines: &str) {
+        self.buf.extend_from_slice(lines.as_bytes());
+    }
+
+    fn finish(&mut self, body: &[u8]) -> &[u8] {
+        self.buf.extend_from_slice(b"\r\n");
+        self.buf.extend_from_slice(body);
+        &self.buf
+    }
+}
+
+// Local address the OS would send from to reach 'server'
+// Connecting a UDP socket only picks the route, nothing is sent
+async fn local_ip_towards(server: SocketAddr) -> Option<IpAddr> {
+    let bind_addr: SocketAddr = match server.is_ipv6() {
+        true => "[::]:0".parse().ok()?,
+        false => "0.0.0.0:0".parse().ok()?,
+    };
+
+    let socket = UdpSocket::bind(bind_addr).await.ok()?;
+    socket.connect(server).await.ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
+
+// URL for the request line, credentials never go there
+fn presentation_uri(url: &Url) -> String {
+    let mut url = url.clone();
+    let _ = url.set_username("");
+    let _ = url.set_password(None);
+
+    url.to_string()
+}
+
+// Outcome of a background task, cancelled counts as done and a
+// panic becomes an error instead of going unnoticed
+async fn join_task(name: &'static str, handle: JoinHandle<Result<()>>) -> Result<()> {
+    match handle.await {
+        Ok(result) => result,
+        Err(e) if e.is_cancelled() => Ok(()),
+        Err(_) => {
+            warn!("[Rtsp] {name} task panicked");
+            Err(RtspError::TaskPanicked(name))
+        }
+    }
+}
+
+// Replace a header already in the list (names are case-insensitive) or add it
+fn set_header(headers: &mut Vec<(String, String)>, name: &str, value: &str) {
+    match headers.iter_mut().find(|(existing, _)| existing.eq_ignore_ascii_case(name)) {
+        Some(header) => header.1 = value.to_string(),
+        None => headers.push((name.to_string(), value.to_string())),
+    }
+}
+
+async fn open_stream(url: &Url, resolver: &dyn Resolver, proxy: Option<&Proxy>, secure: bool, accept_invalid_certs: bool, connect_timeout: Duration, nodelay: bool) -> Result<(SocketAddr, Box<dyn RtspStream>)> {
+    tokio::time::timeout(connect_timeout, connect_stream(url, resolver, proxy, secure, accept_invalid_certs, nodelay))
+        .await
+        .map_err(|_| RtspError::Timeout)?
+}
+
+async fn connect_stream(url: &Url, resolver: &dyn Resolver, proxy: Option<&Proxy>, secure: bool, accept_invalid_certs: bool, nodelay: bool) -> Result<(SocketAddr, Box<dyn RtspStream>)> {
+    let default_port = if secure { DEFAULT_PORT_RTSPS } else { DEFAULT_PORT_RTSP };
+    let port = url.port().unwrap_or(default_port);
+
+    // IP literals need no lookup
+    let resolved = match url.host() {
+        Some(Host::Ipv4(ip)) => Ok(vec![SocketAddr::new(IpAddr::V4(ip), port)]),
+        Some(Host::Ipv6(ip)) => Ok(vec![SocketAddr::new(IpAddr::V6(ip), port)]),
+        Some(Host::Domain(domain)) => resolver.resolve(domain, port).await,
+        None => Ok(Vec::new()),
+    };
+
+    let (socket_addr, tcp_stream) = match proxy {
+        // The proxy resolves the host name, which may well be unknown
+        // here. The proxy's address then stands in for the server's
+        Some(proxy) => {
+            let host = url.host_str().ok_or_else(|| RtspError::UnresolvedAddress(url.to_string()))?;
+            let tcp_stream = proxy.connect(host, port).await?;
+
+            let socket_addr = match resolved.ok().and_then(|addrs| addrs.first().copied()) {
+                Some(addr) => addr,
+                None => tcp_stream.peer_addr()?,
+            };
+            println!("[Rtsp] Connecting to server at: {} through proxy {}", socket_addr, proxy.addr());
+
+            (socket_addr, tcp_stream)
+        }
+        None => {
+            let socket_addr = match resolved?.first() {
+                Some(addr) => *addr,
+                None => return Err(RtspError::UnresolvedAddress(url.to_string())),
+            };
+            let tcp_stream = TcpStream::connect(socket_addr).await?;
+            println!("[Rtsp] Connecting to server at: {}", socket_addr);
+
+            (socket_addr, tcp_stream)
+        }
+    };
+    tcp_stream.set_nodelay(nodelay)?;
+
+    let stream: Box<dyn RtspStream> = match secure {
+        true => {
+            // SNI uses the host name from the URL
+            // IPv6 literals come bracketed e.g. rtsps://[fe80::1]:322/
+            let domain = url.host_str().unwrap_or_default().trim_matches(&['[', ']'][..]);
+            connect_tls(tcp_stream, domain, accept_invalid_certs).await?
+        }
+        false => Box::new(tcp_stream),
+    };
+
+    Ok((socket_addr, stream))
+}
+
+// Location header of a 3xx response, if this is one
+fn redirect_location(response: &[u8]) -> Option<String> {
+    let response = RtspResponse::parse(response)?;
+
+    match response.is_redirect() {
+        true => response.header("Location").map(|location| location.to_string()),
+        false => None,
+    }
+}
+
+// CSeq header of a response, if it has one
+fn response_cseq(response: &[u8]) -> Option<u32> {
+    RtspResponse::parse(response)?
+        .header("CSeq")?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// Write a request and read the response to it. The response must
+// carry the CSeq of the request, a lower one is left over from an
+// earlier request so skip past it
+async fn exchange(stream: &mut Box<dyn RtspStream>, request: &[u8], cseq: u32, buf: &mut Vec<u8>, limits: &ResponseLimits) -> Result<usize> {
+    stream.write_all(request).await?;
+
+    loop {
+        let buf_size = read_response(stream, buf, limits).await?;
+
+        match response_cseq(&buf[..buf_size]) {
+            Some(got) if got < cseq => {
+                warn!("[Rtsp] Discarding stale response CSeq {got}, expected {cseq}");
+                buf.drain(..buf_size);
+            }
+            Some(got) if got > cseq => {
+                return Err(RtspError::CSeqMismatch { expected: cseq, got });
+            }
+            _ => return Ok(buf_size),
+        }
+    }
+}
+
+// Read a whole response from the server into buf, which may span
+// several TCP segments: headers up to the blank line, then
+// Content-Length bytes of body. Returns the length of the response,
+// anything read past it is left at the end of buf.
+async fn read_response(stream: &mut Box<dyn RtspStream>, buf: &mut Vec<u8>, limits: &ResponseLimits) -> Result<usize> {
+    loop {
+        if let Some(len) = response_len(buf, limits)? {
+            return Ok(len);
+        }
+
+        let read = match limits.progress_timeout {
+            Some(progress_timeout) if !buf.is_empty() => tokio::time::timeout(progress_timeout, stream.read_buf(buf))
+                .await
+                .map_err(|_| RtspError::Protocol(format!("response stalled for {progress_timeout:?}")))??,
+            _ => stream.read_buf(buf).await?,
+        };
+
+        // Connection closed, hand back whatever arrived
+        if read == 0 {
+            return Ok(buf.len());
+        }
+    }
+}
+
+// Length of the response at the start of buf once all of it is there
+fn response_len(buf: &[u8], limits: &ResponseLimits) -> Result<Option<usize>> {
+    let header_end = match find_header_end(buf) {
+        Some(header_end) if header_end <= limits.max_header_bytes => header_end,
+        None if buf.len() <= limits.max_header_bytes => return Ok(None),
+        _ => {
+            return Err(RtspError::Protocol(format!(
+                "response headers longer than {} bytes",
+                limits.max_header_bytes
+            )))
+        }
+    };
+
+    let content_length = RtspResponse::parse(&buf[..header_end])
+        .and_then(|response| response.content_length())
+        .unwrap_or(0);
+    if content_length > limits.max_body_bytes {
+        return Err(RtspError::Protocol(format!(
+            "response body of {content_length} bytes is over the {} byte limit",
+            limits.max_body_bytes
+        )));
+    }
+
+    let len = header_end + content_length;
+    Ok((buf.len() >= len).then_some(len))
+}
+
+#[cfg(feature = "tls")]
+async fn connect_tls(tcp_stream: TcpStream, domain: &str, accept_invalid_certs: bool) -> Result<Box<dyn RtspStream>> {
+    use tokio_native_tls::native_tls;
+
+    let connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(accept_invalid_certs)
+        .danger_accept_invalid_hostnames(accept_invalid_certs)
+        .build()
+        .map_err(|e| RtspError::Tls(e.to_string()))?;
+    let connector = tokio_native_tls::TlsConnector::from(connector);
+
+    let tls_stream = connector
+        .connect(domain, tcp_stream)
+        .await
+        .map_err(|e| RtspError::Tls(e.to_string()))?;
+
+    Ok(Box::new(tls_stream))
+}
+
+#[cfg(not(feature = "tls"))]
+async fn connect_tls(_tcp_stream: TcpStream, _domain: &str, _accept_invalid_certs: bool) -> Result<Box<dyn RtspStream>> {
+    Err(RtspError::TlsNotEnabled)
+}
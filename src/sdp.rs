@@ -0,0 +1,775 @@
+use crate::h264;
+use std::fmt;
+
+// Session Description Protocol (RFC 4566) as returned by DESCRIBE
+// or sent with ANNOUNCE, parsed into typed session and media sections
+//
+// Unknown lines are skipped and malformed values are left as None,
+// cameras are not always strict about SDP.
+//
+// The other way round, SdpBuilder puts one together for publishing
+// and to_string() writes it out:
+//
+// let sdp = SdpBuilder::new("Camera")
+//     .origin("192.168.1.10")
+//     .control("*")
+//     .media(MediaSection::h264(96, &sps, &pps).with_control("trackID=0"))
+//     .build();
+// rtsp.set_announce_sdp(&sdp.to_string());
+
+// Session level description
+#[derive(Clone, Debug, Default)]
+pub struct Sdp {
+    pub version: Option<u32>,           // v=
+    pub origin: Option<String>,         // o=
+    pub session_name: Option<String>,   // s=
+    pub information: Option<String>,    // i=
+    pub connection: Option<Connection>, // c=
+    pub bandwidth: Vec<Bandwidth>,      // b=
+    pub attributes: Vec<Attribute>,     // a= before the first m=
+    pub control: Option<String>,        // a=control: aggregate control URL
+    pub media: Vec<MediaSection>,
+}
+
+// Media section (m=) and everything up to the next one
+#[derive(Clone, Debug, Default)]
+pub struct MediaSection {
+    pub media: String,            // e.g. "video", "audio" or "application"
+    pub port: u16,
+    pub protocol: String,         // e.g. "RTP/AVP"
+    pub formats: Vec<String>,     // payload types for RTP
+    pub payload_type: Option<u8>, // first format
+    pub control: Option<String>,  // a=control: relative or absolute URL
+    pub codec: Option<String>,    // encoding name of the payload type e.g. H264
+    pub clock_rate: Option<u32>,
+    pub channels: Option<u16>,
+    pub fmtp: Option<String>,     // format parameters of the payload type
+    pub framerate: Option<f64>,   // vendor attributes, see resolve_vendor_attributes
+    pub dimensions: Option<(usize, usize)>, // width, height
+    pub connection: Option<Connection>,
+    pub bandwidth: Vec<Bandwidth>,
+    pub rtpmaps: Vec<RtpMap>,
+    pub fmtps: Vec<Fmtp>,
+    pub attributes: Vec<Attribute>,
+}
+
+// c=<nettype> <addrtype> <connection-address>
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Connection {
+    pub net_type: String,  // "IN"
+    pub addr_type: String, // "IP4" or "IP6"
+    pub address: String,   // may carry /ttl for multicast
+}
+
+// b=<bwtype>:<bandwidth> e.g. b=AS:5000 (kbps)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Bandwidth {
+    pub kind: String,
+    pub value: u32,
+}
+
+// a=<name>[:<value>]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+// a=rtpmap:<payload type> <encoding name>/<clock rate>[/<channels>]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RtpMap {
+    pub payload_type: u8,
+    pub encoding: String,
+    pub clock_rate: u32,
+    pub channels: Option<u16>,
+}
+
+// a=fmtp:<payload type> <name>=<value>;...
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Fmtp {
+    pub payload_type: u8,
+    pub params: Vec<(String, String)>,
+}
+
+impl Fmtp {
+    // Parameter by name (case-insensitive) e.g. "sprop-parameter-sets"
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl Sdp {
+    pub fn parse(sdp: &str) -> Self {
+        let mut session = Sdp::default();
+
+        for line in sdp.lines() {
+            let line = line.trim();
+            let (kind, value) = match line.split_once('=') {
+                Some((kind, value)) if kind.len() == 1 => (kind, value),
+                _ => continue,
+            };
+
+            if kind == "m" {
+                session.media.push(MediaSection::parse(value));
+                continue;
+            }
+
+            match session.media.last_mut() {
+                Some(section) => section.parse_line(kind, value),
+                None => session.parse_line(kind, value),
+            }
+        }
+
+        for section in &mut session.media {
+            section.resolve_payload_type();
+            section.resolve_vendor_attributes(&session.attributes);
+        }
+
+        session
+    }
+
+    // Session level attribute by name e.g. "range"
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    fn parse_line(&mut self, kind: &str, value: &str) {
+        match kind {
+            "v" => self.version = value.parse().ok(),
+            "o" => self.origin = Some(value.to_string()),
+            "s" => self.session_name = Some(value.to_string()),
+            "i" => self.information = Some(value.to_string()),
+            "c" => self.connection = parse_connection(value),
+            "b" => self.bandwidth.extend(parse_bandwidth(value)),
+            "a" => {
+                let attribute = parse_attribute(value);
+                if attribute.name == "control" {
+                    self.control = attribute.value.clone();
+                }
+                self.attributes.push(attribute);
+            }
+            _ => (),
+        }
+    }
+}
+
+impl MediaSection {
+    // m=<media> <port>[/<count>] <proto> <fmt> ...
+    fn parse(value: &str) -> Self {
+        let mut fields = value.split_whitespace();
+
+        MediaSection {
+            media: fields.next().unwrap_or_default().to_string(),
+            port: fields
+                .next()
+                .and_then(|port| port.split('/').next())
+                .and_then(|port| port.parse().ok())
+                .unwrap_or_default(),
+            protocol: fields.next().unwrap_or_default().to_string(),
+            formats: fields.map(|format| format.to_string()).collect(),
+            ..MediaSection::default()
+        }
+    }
+
+    // Attribute by name e.g. "framerate"
+    pub fn attribute(&self, name: &str) -> Option<&Attribute> {
+        self.attributes.iter().find(|attribute| attribute.name == name)
+    }
+
+    pub fn rtpmap(&self, payload_type: u8) -> Option<&RtpMap> {
+        self.rtpmaps.iter().find(|rtpmap| rtpmap.payload_type == payload_type)
+    }
+
+    pub fn fmtp_for(&self, payload_type: u8) -> Option<&Fmtp> {
+        self.fmtps.iter().find(|fmtp| fmtp.payload_type == payload_type)
+    }
+
+    // Payload type of retransmissions (RFC 4588) of the track's
+    // format: a=rtpmap:97 rtx/90000 with a=fmtp:97 apt=96
+    pub fn rtx_payload_type(&self) -> Option<u8> {
+        let payload_type = self.payload_type?;
+
+        self.rtpmaps
+            .iter()
+            .filter(|rtpmap| rtpmap.encoding.eq_ignore_ascii_case("rtx"))
+            .map(|rtpmap| rtpmap.payload_type)
+            .find(|&rtx| {
+                self.fmtp_for(rtx)
+                    .and_then(|fmtp| fmtp.get("apt"))
+                    .and_then(|apt| apt.trim().parse::<u8>().ok())
+                    == Some(payload_type)
+            })
+    }
+
+    fn parse_line(&mut self, kind: &str, value: &str) {
+        match kind {
+            "c" => self.connection = parse_connection(value),
+            "b" => self.bandwidth.extend(parse_bandwidth(value)),
+            "a" => {
+                let attribute = parse_attribute(value);
+                let attribute_value = attribute.value.as_deref().unwrap_or_default();
+
+                match attribute.name.as_str() {
+                    "control" => self.control = attribute.value.clone(),
+                    "rtpmap" => self.rtpmaps.extend(parse_rtpmap(attribute_value)),
+                    "fmtp" => self.fmtps.extend(parse_fmtp(attribute_value)),
+                    _ => (),
+                }
+                self.attributes.push(attribute);
+            }
+            _ => (),
+        }
+    }
+
+    // Codec details of the first payload type (the one SETUP gets)
+    fn resolve_payload_type(&mut self) {
+        self.payload_type = self.formats.first().and_then(|format| format.parse().ok());
+
+        // Static payload types (RFC 3551) often come without an rtpmap
+        let rtpmap = match self.payload_type {
+            Some(payload_type) => self.rtpmap(payload_type).cloned().or_else(|| static_rtpmap(payload_type)),
+            None => self.rtpmaps.first().cloned(),
+        };
+        if let Some(rtpmap) = rtpmap {
+            self.codec = Some(rtpmap.encoding);
+            self.clock_rate = Some(rtpmap.clock_rate);
+            self.channels = rtpmap.channels;
+        }
+
+        let fmtp = match self.payload_type {
+            Some(payload_type) => self.fmtp_for(payload_type),
+            None => self.fmtps.first(),
+        };
+        self.fmtp = fmtp.map(|fmtp| {
+            fmtp.params
+                .iter()
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect::<Vec<String>>()
+                .join(";")
+        });
+    }
+
+    // Picture size and rate from the nonstandard attributes cameras use,
+    // the section's own first, then the session level ones:
+    //   a=framerate:25            a=x-framerate:25
+    //   a=x-dimensions:1280,720   a=framesize:96 1280-720
+    //   a=cliprect:0,0,720,1280   (top, left, bottom, right)
+    fn resolve_vendor_attributes(&mut self, session_attributes: &[Attribute]) {
+        let attributes = || self.attributes.iter().chain(session_attributes);
+
+        self.framerate = attributes().find_map(parse_framerate);
+        self.dimensions = attributes().find_map(|attribute| parse_dimensions(attribute, self.payload_type));
+    }
+}
+
+impl MediaSection {
+    // Media section for one payload type, e.g.
+    // MediaSection::new("audio", 0, "PCMU", 8000, None)
+    pub fn new(media: &str, payload_type: u8, encoding: &str, clock_rate: u32, channels: Option<u16>) -> Self {
+        let mut section = MediaSection {
+            media: media.to_string(),
+            protocol: "RTP/AVP".to_string(),
+            formats: vec![payload_type.to_string()],
+            rtpmaps: vec![RtpMap {
+                payload_type,
+                encoding: encoding.to_string(),
+                clock_rate,
+                channels,
+            }],
+            ..MediaSection::default()
+        };
+        section.resolve_payload_type();
+        section
+    }
+
+    // H264 video (RFC 6184) with the SPS and PPS (NAL units WITHOUT
+    // start codes) as sprop-parameter-sets, so receivers can start
+    // decoding at the first keyframe
+    pub fn h264(payload_type: u8, sps: &[u8], pps: &[u8]) -> Self {
+        let mut params = vec![("packetization-mode".to_string(), "1".to_string())];
+        if sps.len() >= 4 {
+            let profile_level_id = format!("{:02x}{:02x}{:02x}", sps[1], sps[2], sps[3]);
+            params.push(("profile-level-id".to_string(), profile_level_id));
+        }
+        params.push((
+            "sprop-parameter-sets".to_string(),
+            h264::encode_sprop_parameter_sets(&[sps, pps]),
+        ));
+
+        MediaSection::new("video", payload_type, "H264", 90000, None).with_fmtp(params)
+    }
+
+    // a=control:, relative to the session's (or absolute)
+    pub fn with_control(mut self, control: &str) -> Self {
+        self.control = Some(control.to_string());
+        self
+    }
+
+    // a=fmtp: parameters of the section's payload type
+    pub fn with_fmtp(mut self, params: Vec<(String, String)>) -> Self {
+        let payload_type = self.payload_type.unwrap_or_default();
+        self.fmtps.retain(|fmtp| fmtp.payload_type != payload_type);
+        self.fmtps.push(Fmtp { payload_type, params });
+        self.resolve_payload_type();
+        self
+    }
+
+    pub fn with_attribute(mut self, name: &str, value: Option<&str>) -> Self {
+        self.attributes.push(Attribute {
+            name: name.to_string(),
+            value: value.map(|value| value.to_string()),
+        });
+        self.resolve_vendor_attributes(&[]);
+        self
+    }
+}
+
+// Corrections to one media section of the SDP a server describes
+// its stream with, for servers that get it wrong e.g. the payload
+// type they actually send or parameter sets that are missing
+//
+// rtsp.add_track_override(
+//     TrackOverride::new(0)
+//         .payload_type(96)
+//         .codec("H264", 90000, None)
+//         .sprop_parameter_sets("Z0IAH5WoFAFuQA==,aM48gA=="),
+// );
+#[derive(Clone, Debug, Default)]
+pub struct TrackOverride {
+    track: usize,
+    payload_type: Option<u8>,
+    rtpmap: Option<(String, u32, Option<u16>)>,
+    fmtp: Vec<(String, String)>,
+    control: Option<String>,
+}
+
+impl TrackOverride {
+    // For the media section at index 'track', in SDP order
+    pub fn new(track: usize) -> Self {
+        TrackOverride {
+            track,
+            ..TrackOverride::default()
+        }
+    }
+
+    // The payload type the stream really comes with. The rtpmap and
+    // fmtp of the one described move over to it
+    pub fn payload_type(mut self, payload_type: u8) -> Self {
+        self.payload_type = Some(payload_type);
+        self
+    }
+
+    // a=rtpmap: of the payload type e.g. ("H264", 90000, None)
+    pub fn codec(mut self, encoding: &str, clock_rate: u32, channels: Option<u16>) -> Self {
+        self.rtpmap = Some((encoding.to_string(), clock_rate, channels));
+        self
+    }
+
+    // One a=fmtp: parameter, replacing the server's value if any
+    pub fn fmtp(mut self, name: &str, value: &str) -> Self {
+        self.fmtp.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn sprop_parameter_sets(self, sprop: &str) -> Self {
+        self.fmtp("sprop-parameter-sets", sprop)
+    }
+
+    // a=control:, relative or absolute like the server's
+    pub fn control(mut self, control: &str) -> Self {
+        self.control = Some(control.to_string());
+        self
+    }
+
+    pub fn track(&self) -> usize {
+        self.track
+    }
+
+    // Apply to the section the override is for, if the SDP has it
+    pub fn apply(&self, sdp: &mut Sdp) {
+        let section = match sdp.media.get_mut(self.track) {
+            Some(section) => section,
+            None => return,
+        };
+
+        if let Some(payload_type) = self.payload_type {
+            let described = section.payload_type;
+            section.formats.retain(|format| format.parse() != Ok(payload_type));
+            section.formats.insert(0, payload_type.to_string());
+
+            if let Some(described) = described.filter(|&described| described != payload_type) {
+                if section.rtpmap(payload_type).is_none() {
+                    let rtpmap = section.rtpmap(described).cloned().or_else(|| static_rtpmap(described));
+                    section.rtpmaps.extend(rtpmap.map(|rtpmap| RtpMap { payload_type, ..rtpmap }));
+                }
+                if section.fmtp_for(payload_type).is_none() {
+                    let fmtp = section.fmtp_for(described).cloned();
+                    section.fmtps.extend(fmtp.map(|fmtp| Fmtp { payload_type, ..fmtp }));
+                }
+            }
+        }
+
+        let payload_type = section
+            .formats
+            .first()
+            .and_then(|format| format.parse().ok())
+            .unwrap_or_default();
+
+        if let Some((encoding, clock_rate, channels)) = &self.rtpmap {
+            section.rtpmaps.retain(|rtpmap| rtpmap.payload_type != payload_type);
+            section.rtpmaps.push(RtpMap {
+                payload_type,
+                encoding: encoding.clone(),
+                clock_rate: *clock_rate,
+                channels: *channels,
+            });
+        }
+
+        if !self.fmtp.is_empty() {
+            let mut params = section.fmtp_for(payload_type).map(|fmtp| fmtp.params.clone()).unwrap_or_default();
+            for (name, value) in &self.fmtp {
+                params.retain(|(param, _)| !param.eq_ignore_ascii_case(name));
+                params.push((name.clone(), value.clone()));
+            }
+            section.fmtps.retain(|fmtp| fmtp.payload_type != payload_type);
+            section.fmtps.push(Fmtp { payload_type, params });
+        }
+
+        if let Some(control) = &self.control {
+            section.control = Some(control.clone());
+        }
+
+        section.resolve_payload_type();
+    }
+}
+
+// Puts together a session description, see the top of this file
+pub struct SdpBuilder {
+    sdp: Sdp,
+}
+
+impl SdpBuilder {
+    // An empty 'session_name' is written as s=- (RFC 4566 section 5.3)
+    pub fn new(session_name: &str) -> Self {
+        let session_name = match session_name.trim().is_empty() {
+            true => "-",
+            false => session_name,
+        };
+
+        SdpBuilder {
+            sdp: Sdp {
+                version: Some(0),
+                origin: Some("- 0 0 IN IP4 0.0.0.0".to_string()),
+                session_name: Some(session_name.to_string()),
+                ..Sdp::default()
+            },
+        }
+    }
+
+    // o= line with a session id from the current time, 'address' is
+    // the host creating the session (IPv4 or IPv6)
+    pub fn origin(mut self, address: &str) -> Self {
+        let session_id = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+
+        self.sdp.origin = Some(format!("- {session_id} {session_id} IN {} {address}", addr_type(address)));
+        self
+    }
+
+    pub fn information(mut self, information: &str) -> Self {
+        self.sdp.information = Some(information.to_string());
+        self
+    }
+
+    // c= line, e.g. the destination of a multicast session
+    pub fn connection(mut self, address: &str) -> Self {
+        self.sdp.connection = Some(Connection {
+            net_type: "IN".to_string(),
+            addr_type: addr_type(address).to_string(),
+            address: address.to_string(),
+        });
+        self
+    }
+
+    // b=AS:<kbps>
+    pub fn bandwidth(mut self, kbps: u32) -> Self {
+        self.sdp.bandwidth.push(Bandwidth { kind: "AS".to_string(), value: kbps });
+        self
+    }
+
+    // Session level a=control:, usually "*"
+    pub fn control(mut self, control: &str) -> Self {
+        self.sdp.control = Some(control.to_string());
+        self
+    }
+
+    pub fn attribute(mut self, name: &str, value: Option<&str>) -> Self {
+        self.sdp.attributes.push(Attribute {
+            name: name.to_string(),
+            value: value.map(|value| value.to_string()),
+        });
+        self
+    }
+
+    pub fn media(mut self, section: MediaSection) -> Self {
+        self.sdp.media.push(section);
+        self
+    }
+
+    pub fn build(self) -> Sdp {
+        self.sdp
+    }
+}
+
+fn addr_type(address: &str) -> &'static str {
+    match address.contains(':') {
+        true => "IP6",
+        false => "IP4",
+    }
+}
+
+// Written out in the order RFC 4566 requires. Parsed attributes
+// that also have their own field (control, rtpmap, fmtp) are
+// written once, from the field
+impl fmt::Display for Sdp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v={}\r\n", self.version.unwrap_or_default())?;
+        if let Some(origin) = &self.origin {
+            write!(f, "o={origin}\r\n")?;
+        }
+        // s= is mandatory and must not be empty, "-" when there is no name
+        let session_name = self.session_name.as_deref().filter(|name| !name.trim().is_empty());
+        write!(f, "s={}\r\n", session_name.unwrap_or("-"))?;
+        if let Some(information) = &self.information {
+            write!(f, "i={information}\r\n")?;
+        }
+        if let Some(connection) = &self.connection {
+            write!(f, "{connection}")?;
+        }
+        for bandwidth in &self.bandwidth {
+            write!(f, "{bandwidth}")?;
+        }
+        write!(f, "t=0 0\r\n")?;
+
+        if let Some(control) = &self.control {
+            write!(f, "a=control:{control}\r\n")?;
+        }
+        for attribute in self.attributes.iter().filter(|attribute| attribute.name != "control") {
+            write!(f, "{attribute}")?;
+        }
+
+        for section in &self.media {
+            write!(f, "{section}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for MediaSection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m={} {} {}", self.media, self.port, self.protocol)?;
+        for format in &self.formats {
+            write!(f, " {format}")?;
+        }
+        write!(f, "\r\n")?;
+
+        if let Some(connection) = &self.connection {
+            write!(f, "{connection}")?;
+        }
+        for bandwidth in &self.bandwidth {
+            write!(f, "{bandwidth}")?;
+        }
+
+        for rtpmap in &self.rtpmaps {
+            write!(f, "a=rtpmap:{} {}/{}", rtpmap.payload_type, rtpmap.encoding, rtpmap.clock_rate)?;
+            if let Some(channels) = rtpmap.channels {
+                write!(f, "/{channels}")?;
+            }
+            write!(f, "\r\n")?;
+        }
+        for fmtp in &self.fmtps {
+            let params = fmtp
+                .params
+                .iter()
+                .map(|(name, value)| match value.is_empty() {
+                    true => name.clone(),
+                    false => format!("{name}={value}"),
+                })
+                .collect::<Vec<String>>()
+                .join(";");
+            write!(f, "a=fmtp:{} {params}\r\n", fmtp.payload_type)?;
+        }
+        if let Some(control) = &self.control {
+            write!(f, "a=control:{control}\r\n")?;
+        }
+
+        let attributes = self
+            .attributes
+            .iter()
+            .filter(|attribute| !matches!(attribute.name.as_str(), "control" | "rtpmap" | "fmtp"));
+        for attribute in attributes {
+            write!(f, "{attribute}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "c={} {} {}\r\n", self.net_type, self.addr_type, self.address)
+    }
+}
+
+impl fmt::Display for Bandwidth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "b={}:{}\r\n", self.kind, self.value)
+    }
+}
+
+impl fmt::Display for Attribute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "a={}:{value}\r\n", self.name),
+            None => write!(f, "a={}\r\n", self.name),
+        }
+    }
+}
+
+#[rustfmt::skip]
+fn static_rtpmap(payload_type: u8) -> Option<RtpMap> {
+    let (encoding, clock_rate) = match payload_type {
+        0  => ("PCMU", 8000),
+        8  => ("PCMA", 8000),
+        14 => ("MPA", 90000),
+        26 => ("JPEG", 90000),
+        33 => ("MP2T", 90000),
+        _  => return None,
+    };
+
+    Some(RtpMap {
+        payload_type,
+        encoding: encoding.to_string(),
+        clock_rate,
+        channels: None,
+    })
+}
+
+fn parse_connection(value: &str) -> Option<Connection> {
+    let mut fields = value.split_whitespace();
+
+    Some(Connection {
+        net_type: fields.next()?.to_string(),
+        addr_type: fields.next()?.to_string(),
+        address: fields.next()?.to_string(),
+    })
+}
+
+fn parse_bandwidth(value: &str) -> Option<Bandwidth> {
+    let (kind, bandwidth) = value.split_once(':')?;
+
+    Some(Bandwidth {
+        kind: kind.trim().to_string(),
+        value: bandwidth.trim().parse().ok()?,
+    })
+}
+
+fn parse_attribute(value: &str) -> Attribute {
+    match value.split_once(':') {
+        Some((name, value)) => Attribute {
+            name: name.trim().to_string(),
+            value: Some(value.trim().to_string()),
+        },
+        None => Attribute {
+            name: value.trim().to_string(),
+            value: None,
+        },
+    }
+}
+
+// a=framerate:<fps> or a=x-framerate:<fps>
+fn parse_framerate(attribute: &Attribute) -> Option<f64> {
+    match attribute.name.as_str() {
+        "framerate" | "x-framerate" => attribute
+            .value
+            .as_deref()
+            .and_then(|value| value.trim().parse::<f64>().ok())
+            .filter(|framerate| *framerate > 0.0),
+        _ => None,
+    }
+}
+
+// Width and height, a=framesize only counts for 'payload_type'
+fn parse_dimensions(attribute: &Attribute, payload_type: Option<u8>) -> Option<(usize, usize)> {
+    let value = attribute.value.as_deref()?.trim();
+    let number = |value: &str| value.trim().parse::<usize>().ok();
+
+    let (width, height) = match attribute.name.as_str() {
+        "x-dimensions" => {
+            let (width, height) = value.split_once(',')?;
+            (number(width)?, number(height)?)
+        }
+        "framesize" => {
+            let (format, size) = value.split_once(' ')?;
+            if payload_type.is_some() && format.parse::<u8>().ok() != payload_type {
+                return None;
+            }
+            let (width, height) = size.trim().split_once('-')?;
+            (number(width)?, number(height)?)
+        }
+        "cliprect" => {
+            let rect: Vec<usize> = value.split(',').map(number).collect::<Option<Vec<usize>>>()?;
+            match rect[..] {
+                [top, left, bottom, right] => (right.checked_sub(left)?, bottom.checked_sub(top)?),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    match width > 0 && height > 0 {
+        true => Some((width, height)),
+        false => None,
+    }
+}
+
+// e.g. 96 H264/90000 or 97 MPEG4-GENERIC/16000/2
+fn parse_rtpmap(value: &str) -> Option<RtpMap> {
+    let (payload_type, encoding) = value.split_once(' ')?;
+    let mut encoding = encoding.trim().split('/');
+
+    Some(RtpMap {
+        payload_type: payload_type.trim().parse().ok()?,
+        encoding: encoding.next()?.to_string(),
+        clock_rate: encoding.next()?.parse().ok()?,
+        channels: encoding.next().and_then(|channels| channels.parse().ok()),
+    })
+}
+
+// e.g. 96 packetization-mode=1;profile-level-id=42e01f;sprop-parameter-sets=Z0IAH...,aM4...
+fn parse_fmtp(value: &str) -> Option<Fmtp> {
+    let (payload_type, params) = value.split_once(' ')?;
+
+    let params = params
+        .split(';')
+        .filter_map(|param| {
+            let param = param.trim();
+            match param.split_once('=') {
+                Some((name, value)) => Some((name.trim().to_string(), value.trim().to_string())),
+                None if !param.is_empty() => Some((param.to_string(), String::new())),
+                None => None,
+            }
+        })
+        .collect();
+
+    Some(Fmtp {
+        payload_type: payload_type.trim().parse().ok()?,
+        params,
+    })
+}
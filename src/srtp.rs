@@ -0,0 +1,519 @@
+use crate::rtp::RTP_HEADER_LEN;
+use crate::sdp::{Attribute, MediaSection, Sdp};
+use aes::Aes128;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::digest::KeyInit;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::fmt;
+
+// SRTP and SRTCP (RFC 3711): RTP and RTCP encrypted with AES in
+// counter mode and authenticated with HMAC-SHA1. The master key comes
+// from the SDP, either in the clear with SDES (a=crypto, RFC 4568) or
+// in a MIKEY message (a=key-mgmt, RFC 4567). Packets are decrypted in
+// place before anything else looks at them:
+//
+// if let Some(key) = MasterKey::from_sdp(rtsp.sdp().unwrap(), 0) {
+//     rtp.set_srtp(Some(Srtp::new(&key)));
+// }
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+type HmacSha1 = Hmac<Sha1>;
+
+// Key derivation labels (RFC 3711 section 4.3.1)
+const LABEL_RTP_ENCRYPTION: u8 = 0;
+const LABEL_RTP_AUTH: u8 = 1;
+const LABEL_RTP_SALT: u8 = 2;
+const LABEL_RTCP_ENCRYPTION: u8 = 3;
+const LABEL_RTCP_AUTH: u8 = 4;
+const LABEL_RTCP_SALT: u8 = 5;
+
+// Authentication key length for HMAC-SHA1, and its block size the
+// key is padded to
+const AUTH_KEY_LEN: usize = 20;
+const SHA1_BLOCK_LEN: usize = 64;
+// SRTCP tag length, 80 bits for both suites (RFC 4568 section 6.2)
+const RTCP_TAG_LEN: usize = 10;
+// SRTCP index word after the RTCP packet: E flag and 31 bit index
+const RTCP_INDEX_LEN: usize = 4;
+// E flag of the SRTCP index, set when the packet is encrypted
+const RTCP_ENCRYPTED: u32 = 0x8000_0000;
+
+// Packets this far behind the highest one are taken as replays
+const REPLAY_WINDOW: u64 = 64;
+
+// Longest MKI an a=crypto line may give (RFC 4568 section 6.1)
+const MAX_MKI_LEN: usize = 128;
+
+// MIKEY (RFC 3830 section 6) payload types and values used here
+const MIKEY_PAYLOAD_LAST: u8 = 0;
+const MIKEY_PAYLOAD_KEMAC: u8 = 1;
+const MIKEY_PAYLOAD_T: u8 = 5;
+const MIKEY_PAYLOAD_ID: u8 = 6;
+const MIKEY_PAYLOAD_SP: u8 = 10;
+const MIKEY_PAYLOAD_RAND: u8 = 11;
+const MIKEY_ENCRYPTION_NULL: u8 = 0;
+const MIKEY_MAC_HMAC_SHA1: u8 = 1;
+// Key data types (section 6.13): TGKs would need the MIKEY PRF to
+// derive a TEK from, which isn't done here
+const MIKEY_KEY_TGK: u8 = 0;
+const MIKEY_KEY_TGK_SALT: u8 = 1;
+const MIKEY_KEY_TEK: u8 = 2;
+const MIKEY_KEY_TEK_SALT: u8 = 3;
+const MIKEY_SP_AUTH_TAG_LEN: u8 = 11;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CryptoSuite {
+    AesCm128HmacSha1_80,
+    AesCm128HmacSha1_32,
+}
+
+impl CryptoSuite {
+    // Name as in a=crypto, e.g. AES_CM_128_HMAC_SHA1_80
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "AES_CM_128_HMAC_SHA1_80" => Some(CryptoSuite::AesCm128HmacSha1_80),
+            "AES_CM_128_HMAC_SHA1_32" => Some(CryptoSuite::AesCm128HmacSha1_32),
+            _ => None,
+        }
+    }
+
+    // Length of the SRTP authentication tag
+    pub fn tag_len(self) -> usize {
+        match self {
+            CryptoSuite::AesCm128HmacSha1_80 => 10,
+            CryptoSuite::AesCm128HmacSha1_32 => 4,
+        }
+    }
+}
+
+// What the server keys the stream with. Debug leaves out the key
+#[derive(Clone, PartialEq, Eq)]
+pub struct MasterKey {
+    pub suite: CryptoSuite,
+    pub key: [u8; 16],
+    pub salt: [u8; 14],
+    pub mki_len: usize, // master key identifier before the tag, 0 for none
+    pub roc: u32,       // rollover counter the stream starts at, from MIKEY
+}
+
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey")
+            .field("suite", &self.suite)
+            .field("mki_len", &self.mki_len)
+            .field("roc", &self.roc)
+            .finish_non_exhaustive()
+    }
+}
+
+impl MasterKey {
+    // Key of the track: its own a=crypto or a=key-mgmt, or else the
+    // a=key-mgmt of the whole session
+    pub fn from_sdp(sdp: &Sdp, track: usize) -> Option<Self> {
+        sdp.media
+            .get(track)
+            .and_then(MasterKey::from_section)
+            .or_else(|| MasterKey::from_attributes(&sdp.attributes))
+    }
+
+    // Key from the media section alone, the first a=crypto with a
+    // supported suite
+    pub fn from_section(section: &MediaSection) -> Option<Self> {
+        MasterKey::from_attributes(&section.attributes)
+    }
+
+    // e.g. 1 AES_CM_128_HMAC_SHA1_80 inline:WVNfX19zZW1jdGwgKCkgewkyMjA7fQp9CnVubGVz|2^20|1:4
+    pub fn from_crypto_attribute(value: &str) -> Option<Self> {
+        let mut fields = value.split_whitespace();
+        let _tag = fields.next()?;
+        let suite = CryptoSuite::from_name(fields.next()?)?;
+
+        // Several keys are for rekeying, the first one is used
+        let params = fields.next()?.split(';').next()?.strip_prefix("inline:")?;
+        let mut params = params.split('|');
+        let key_salt = STANDARD.decode(params.next()?).ok()?;
+        if key_salt.len() != 30 {
+            return None;
+        }
+
+        // Optional lifetime, then MKI value:length
+        let mki_len = match params.find_map(|param| param.split_once(':')) {
+            Some((_, len)) => len.parse::<usize>().ok().filter(|len| (1..=MAX_MKI_LEN).contains(len))?,
+            None => 0,
+        };
+
+        Some(MasterKey {
+            suite,
+            key: key_salt[..16].try_into().ok()?,
+            salt: key_salt[16..].try_into().ok()?,
+            mki_len,
+            roc: 0,
+        })
+    }
+
+    // e.g. mikey AQAFgM0XAAAAAAAAAAAAAAAAAAA...
+    // Only keys sent in the clear (NULL encryption) can be read, those
+    // wrapped with a pre-shared or public key can't
+    pub fn from_key_mgmt_attribute(value: &str) -> Option<Self> {
+        let (protocol, data) = value.trim().split_once(' ')?;
+        if !protocol.eq_ignore_ascii_case("mikey") {
+            return None;
+        }
+
+        parse_mikey(&STANDARD.decode(data.trim()).ok()?)
+    }
+
+    fn from_attributes(attributes: &[Attribute]) -> Option<Self> {
+        let value = |name: &'static str| {
+            attributes
+                .iter()
+                .filter(move |attribute| attribute.name == name)
+                .filter_map(|attribute| attribute.value.as_deref())
+        };
+
+        value("crypto")
+            .find_map(MasterKey::from_crypto_attribute)
+            .or_else(|| value("key-mgmt").find_map(MasterKey::from_key_mgmt_attribute))
+    }
+}
+
+// Session keys for one direction of RTP or RTCP
+struct SessionKeys {
+    encryption: [u8; 16],
+    auth: [u8; SHA1_BLOCK_LEN], // zero padded as HMAC would
+    salt: [u8; 14],
+}
+
+impl SessionKeys {
+    fn derive(master: &MasterKey, labels: [u8; 3]) -> Self {
+        let mut keys = SessionKeys {
+            encryption: [0; 16],
+            auth: [0; SHA1_BLOCK_LEN],
+            salt: [0; 14],
+        };
+        derive(master, labels[0], &mut keys.encryption);
+        derive(master, labels[1], &mut keys.auth[..AUTH_KEY_LEN]);
+        derive(master, labels[2], &mut keys.salt);
+        keys
+    }
+
+    // AES-CM over 'data' for the packet 'index' of 'ssrc'
+    // (RFC 3711 section 4.1.1)
+    fn apply_keystream(&self, ssrc: u32, index: u64, data: &mut [u8]) {
+        let mut iv = [0u8; 16];
+        iv[..14].copy_from_slice(&self.salt);
+        for (byte, ssrc) in iv[4..8].iter_mut().zip(ssrc.to_be_bytes()) {
+            *byte ^= ssrc;
+        }
+        for (byte, index) in iv[8..14].iter_mut().zip(&index.to_be_bytes()[2..]) {
+            *byte ^= index;
+        }
+
+        Aes128Ctr::new(&self.encryption.into(), &iv.into()).apply_keystream(data);
+    }
+
+    fn mac(&self) -> HmacSha1 {
+        <HmacSha1 as KeyInit>::new(&self.auth.into())
+    }
+}
+
+// Key derivation (RFC 3711 section 4.3) with a key derivation rate
+// of 0, so each session key is derived once
+fn derive(master: &MasterKey, label: u8, out: &mut [u8]) {
+    let mut iv = [0u8; 16];
+    iv[..14].copy_from_slice(&master.salt);
+    iv[7] ^= label;
+
+    out.fill(0);
+    Aes128Ctr::new(&master.key.into(), &iv.into()).apply_keystream(out);
+}
+
+// Decrypts what the server sends, and protects the RTCP we send back
+// with the same master key
+pub struct Srtp {
+    suite: CryptoSuite,
+    mki_len: usize,
+    rtp: SessionKeys,
+    rtcp: SessionKeys,
+    roc: u32,
+    highest: Option<u16>, // highest sequence number authenticated
+    replay: Replay,
+    rtcp_replay: Replay,
+    rtcp_index: u32, // of the next SRTCP packet we send
+}
+
+impl Srtp {
+    pub fn new(master: &MasterKey) -> Self {
+        Srtp {
+            suite: master.suite,
+            mki_len: master.mki_len,
+            rtp: SessionKeys::derive(master, [LABEL_RTP_ENCRYPTION, LABEL_RTP_AUTH, LABEL_RTP_SALT]),
+            rtcp: SessionKeys::derive(master, [LABEL_RTCP_ENCRYPTION, LABEL_RTCP_AUTH, LABEL_RTCP_SALT]),
+            roc: master.roc,
+            highest: None,
+            replay: Replay::new(),
+            rtcp_replay: Replay::new(),
+            rtcp_index: 0,
+        }
+    }
+
+    // Decrypt an SRTP packet in place. Returns the length of the RTP
+    // packet now at its start, None if it failed authentication or is
+    // a replay
+    pub fn unprotect_rtp(&mut self, packet: &mut [u8]) -> Option<usize> {
+        let len = packet.len().checked_sub(self.mki_len + self.suite.tag_len())?;
+        let header_len = header_len(&packet[..len])?;
+        let sequence_number = u16::from_be_bytes([packet[2], packet[3]]);
+        let ssrc = u32::from_be_bytes([packet[8], packet[9], packet[10], packet[11]]);
+
+        // Rollover counter the packet is most likely from
+        // (RFC 3711 appendix A)
+        let roc = match self.highest {
+            None => self.roc,
+            Some(highest) if highest < 0x8000 => match sequence_number as i32 - highest as i32 > 0x8000 {
+                true => self.roc.wrapping_sub(1),
+                false => self.roc,
+            },
+            Some(highest) => match highest - 0x8000 > sequence_number {
+                true => self.roc.wrapping_add(1),
+                false => self.roc,
+            },
+        };
+        let index = (roc as u64) << 16 | sequence_number as u64;
+        if self.replay.seen(index) {
+            return None;
+        }
+
+        let mut mac = self.rtp.mac();
+        mac.update(&packet[..len]);
+        mac.update(&roc.to_be_bytes());
+        mac.verify_truncated_left(&packet[len + self.mki_len..]).ok()?;
+
+        self.rtp.apply_keystream(ssrc, index, &mut packet[header_len..len]);
+
+        self.replay.accept(index);
+        match self.highest {
+            Some(_) if roc == self.roc.wrapping_add(1) => {
+                self.roc = roc;
+                self.highest = Some(sequence_number);
+            }
+            Some(highest) if roc == self.roc && sequence_number > highest => self.highest = Some(sequence_number),
+            Some(_) => (),
+            None => self.highest = Some(sequence_number),
+        }
+
+        Some(len)
+    }
+
+    // Decrypt an SRTCP packet (a compound one) in place, returns the
+    // length of the RTCP now at its start
+    pub fn unprotect_rtcp(&mut self, packet: &mut [u8]) -> Option<usize> {
+        let tagged = packet.len().checked_sub(self.mki_len + RTCP_TAG_LEN)?;
+        let len = tagged.checked_sub(RTCP_INDEX_LEN)?;
+        if len < 8 {
+            return None;
+        }
+
+        let word = u32::from_be_bytes(packet[len..tagged].try_into().ok()?);
+        let index = (word & !RTCP_ENCRYPTED) as u64;
+        if self.rtcp_replay.seen(index) {
+            return None;
+        }
+
+        let mut mac = self.rtcp.mac();
+        mac.update(&packet[..tagged]);
+        mac.verify_truncated_left(&packet[tagged + self.mki_len..]).ok()?;
+
+        if word & RTCP_ENCRYPTED != 0 {
+            let ssrc = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+            self.rtcp.apply_keystream(ssrc, index, &mut packet[8..len]);
+        }
+
+        self.rtcp_replay.accept(index);
+        Some(len)
+    }
+
+    // SRTCP packet of an RTCP one we send (e.g. a receiver report).
+    // The MKI, when the key has one, is sent as zeros
+    pub fn protect_rtcp(&mut self, packet: &[u8]) -> Vec<u8> {
+        let mut protected = packet.to_vec();
+        if protected.len() < 8 {
+            return protected;
+        }
+
+        let index = self.rtcp_index;
+        self.rtcp_index = (self.rtcp_index + 1) & !RTCP_ENCRYPTED;
+
+        let ssrc = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+        self.rtcp.apply_keystream(ssrc, index as u64, &mut protected[8..]);
+        protected.extend_from_slice(&(index | RTCP_ENCRYPTED).to_be_bytes());
+
+        let mut mac = self.rtcp.mac();
+        mac.update(&protected);
+        let tag = mac.finalize().into_bytes();
+
+        protected.resize(protected.len() + self.mki_len, 0);
+        protected.extend_from_slice(&tag[..RTCP_TAG_LEN]);
+        protected
+    }
+
+    pub fn suite(&self) -> CryptoSuite {
+        self.suite
+    }
+}
+
+// Indexes received within REPLAY_WINDOW of the highest
+// (RFC 3711 section 3.3.2)
+struct Replay {
+    highest: Option<u64>,
+    window: u64, // bit n set: highest - n was received
+}
+
+impl Replay {
+    fn new() -> Self {
+        Replay { highest: None, window: 0 }
+    }
+
+    fn seen(&self, index: u64) -> bool {
+        match self.highest {
+            Some(highest) if index <= highest => {
+                let behind = highest - index;
+                behind >= REPLAY_WINDOW || self.window & (1 << behind) != 0
+            }
+            _ => false,
+        }
+    }
+
+    fn accept(&mut self, index: u64) {
+        match self.highest {
+            Some(highest) if index <= highest => self.window |= 1 << (highest - index),
+            Some(highest) => {
+                let ahead = index - highest;
+                self.window = match ahead < REPLAY_WINDOW {
+                    true => self.window << ahead | 1,
+                    false => 1,
+                };
+                self.highest = Some(index);
+            }
+            None => {
+                self.window = 1;
+                self.highest = Some(index);
+            }
+        }
+    }
+}
+
+// RTP header length with CSRCs and extension, without RtpHeader::parse
+// as the padding it checks is still encrypted
+fn header_len(packet: &[u8]) -> Option<usize> {
+    if packet.len() < RTP_HEADER_LEN || packet[0] >> 6 != 2 {
+        return None;
+    }
+
+    let mut len = RTP_HEADER_LEN + (packet[0] & 0x0F) as usize * 4;
+    if packet[0] & 0x10 != 0 {
+        let words = packet.get(len + 2..len + 4)?;
+        len += 4 + u16::from_be_bytes([words[0], words[1]]) as usize * 4;
+    }
+
+    (len <= packet.len()).then_some(len)
+}
+
+// MIKEY message (RFC 3830) carrying the TEK (our master key) in its
+// KEMAC payload without encryption
+fn parse_mikey(message: &[u8]) -> Option<MasterKey> {
+    // Common header: version, data type, next payload, V and PRF,
+    // CSB ID, #CS, CS ID map type, then per CS: policy, SSRC, ROC
+    if message.len() < 10 || message[0] != 1 {
+        return None;
+    }
+    let cs_count = message[8] as usize;
+    let roc = match message[9] {
+        0 if cs_count > 0 => be32(message.get(15..19)?),
+        _ => 0,
+    };
+
+    let mut next = message[2];
+    let mut pos = 10 + match message[9] {
+        0 => cs_count * 9,
+        _ => 0,
+    };
+    let mut suite = CryptoSuite::AesCm128HmacSha1_80;
+    let mut key = None;
+
+    while next != MIKEY_PAYLOAD_LAST {
+        let payload = message.get(pos..)?;
+        let this = next;
+        next = *payload.first()?;
+
+        pos += match this {
+            // Timestamp: type, then an 8 byte NTP time or 4 byte counter
+            MIKEY_PAYLOAD_T => 2 + if *payload.get(1)? == 2 { 4 } else { 8 },
+            MIKEY_PAYLOAD_RAND => 2 + *payload.get(1)? as usize,
+            MIKEY_PAYLOAD_ID => 4 + be16(payload.get(2..4)?) as usize,
+            // Security policy, parameters as type, length, value
+            MIKEY_PAYLOAD_SP => {
+                let params_len = be16(payload.get(3..5)?) as usize;
+                let mut params = payload.get(5..5 + params_len)?;
+                while let [kind, len, rest @ ..] = params {
+                    let value = rest.get(..*len as usize)?;
+                    if *kind == MIKEY_SP_AUTH_TAG_LEN && value == [4] {
+                        suite = CryptoSuite::AesCm128HmacSha1_32;
+                    }
+                    params = &rest[*len as usize..];
+                }
+                5 + params_len
+            }
+            MIKEY_PAYLOAD_KEMAC => {
+                if *payload.get(1)? != MIKEY_ENCRYPTION_NULL {
+                    return None;
+                }
+                let data_len = be16(payload.get(2..4)?) as usize;
+                key = parse_key_data(payload.get(4..4 + data_len)?);
+                let mac_len = match payload.get(4 + data_len)? {
+                    &MIKEY_MAC_HMAC_SHA1 => 20,
+                    _ => 0,
+                };
+                5 + data_len + mac_len
+            }
+            _ => return None,
+        };
+    }
+
+    let (key, salt) = key?;
+    Some(MasterKey { suite, key, salt, mki_len: 0, roc })
+}
+
+// Key data sub-payload: next, type and KV, length, key, then
+// length and salt for the types with one. A TEK without a salt
+// has a salt of zeros (RFC 3711 section 8.2)
+fn parse_key_data(data: &[u8]) -> Option<([u8; 16], [u8; 14])> {
+    let key_type = data.get(1)? >> 4;
+    if matches!(key_type, MIKEY_KEY_TGK | MIKEY_KEY_TGK_SALT) {
+        return None;
+    }
+
+    let key_len = be16(data.get(2..4)?) as usize;
+    let key = data.get(4..4 + key_len)?;
+    let salt = match key_type {
+        MIKEY_KEY_TEK => [0u8; 14],
+        MIKEY_KEY_TEK_SALT => {
+            let salt_len = be16(data.get(4 + key_len..6 + key_len)?) as usize;
+            data.get(6 + key_len..6 + key_len + salt_len)?.try_into().ok()?
+        }
+        _ => return None,
+    };
+
+    Some((key.try_into().ok()?, salt))
+}
+
+fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
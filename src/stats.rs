@@ -0,0 +1,325 @@
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Rates (fps, bitrate, loss) are worked out over this long,
+// unless set otherwise
+const WINDOW: Duration = Duration::from_secs(1);
+// Windows kept in the history unless set otherwise, 5 minutes
+const DEFAULT_HISTORY: usize = 300;
+
+// Receive statistics for one RTP stream
+//
+// Totals count everything since the stream started, rates
+// cover the last complete window (about a second).
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub packets: u64,
+    pub bytes: u64,
+    pub lost: u64,      // packets missing from the sequence numbers
+    pub truncated: u64, // packets larger than the receive buffer, dropped
+    pub recovered: u64, // retransmissions received, see Rtp::set_retransmission
+    pub frames: u64,
+    pub fps: f64,
+    pub bitrate: f64, // bits per second
+    pub loss_percent: f64,
+    pub jitter_ms: f64, // interarrival jitter (RFC 3550 section 6.4.1)
+    last_packet: Option<Instant>,
+    last_seq: Option<u16>,
+    window: Window,
+    interval: Option<Duration>, // of the windows, WINDOW when None
+    history: StatsHistory,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Window {
+    start: Option<Instant>,
+    packets: u64,
+    bytes: u64,
+    lost: u64,
+    frames: u64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A sample of the rates for each window, oldest first
+    pub fn history(&self) -> &StatsHistory {
+        &self.history
+    }
+
+    pub(crate) fn set_history_len(&mut self, len: usize) {
+        self.history.len = len;
+        self.history.trim();
+    }
+
+    // Applies to the window under way too
+    pub(crate) fn set_interval(&mut self, interval: Duration) {
+        self.interval = Some(interval);
+    }
+
+    pub(crate) fn record_jitter(&mut self, jitter: f64, clock_rate: u32) {
+        self.jitter_ms = jitter * 1000.0 / clock_rate.max(1) as f64;
+    }
+
+    // Time since the last packet arrived
+    pub fn stall(&self) -> Duration {
+        self.last_packet
+            .map(|last_packet| last_packet.elapsed())
+            .unwrap_or_default()
+    }
+
+    // Returns the gap since the previous packet
+    pub(crate) fn record_packet(&mut self, len: usize, sequence_number: Option<u16>) -> Duration {
+        let now = Instant::now();
+        let gap = self
+            .last_packet
+            .map(|last_packet| now.duration_since(last_packet))
+            .unwrap_or_default();
+        self.last_packet = Some(now);
+
+        self.packets += 1;
+        self.bytes += len as u64;
+        self.window.packets += 1;
+        self.window.bytes += len as u64;
+
+        if let Some(seq) = sequence_number {
+
+            if let Some(last_seq) = self.last_seq {
+                // Anything far out of order is a restart, not a loss
+                let missing = seq.wrapping_sub(last_seq).wrapping_sub(1);
+                if missing < 0x8000 {
+                    self.lost += missing as u64;
+                    self.window.lost += missing as u64;
+                }
+            }
+            self.last_seq = Some(seq);
+        }
+
+        self.roll_window(now);
+        gap
+    }
+
+    #[cfg(feature = "decode-openh264")]
+    pub(crate) fn record_frame(&mut self) {
+        self.frames += 1;
+        self.window.frames += 1;
+    }
+
+    // Update the rates once a window has completed
+    fn roll_window(&mut self, now: Instant) {
+        let start = *self.window.start.get_or_insert(now);
+        let elapsed = now.duration_since(start);
+
+        if elapsed < self.interval.unwrap_or(WINDOW) {
+            return;
+        }
+
+        let secs = elapsed.as_secs_f64();
+        let expected = self.window.packets + self.window.lost;
+
+        self.fps = self.window.frames as f64 / secs;
+        self.bitrate = self.window.bytes as f64 * 8.0 / secs;
+        self.loss_percent = match expected {
+            0 => 0.0,
+            expected => self.window.lost as f64 * 100.0 / expected as f64,
+        };
+
+        self.history.push(StatsSample {
+            time: SystemTime::now(),
+            fps: self.fps,
+            bitrate: self.bitrate,
+            loss_percent: self.loss_percent,
+            jitter_ms: self.jitter_ms,
+            packets: self.window.packets,
+            lost: self.window.lost,
+        });
+
+        self.window = Window {
+            start: Some(now),
+            ..Window::default()
+        };
+    }
+}
+
+// Rates of one window, about a second
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StatsSample {
+    pub time: SystemTime, // end of the window
+    pub fps: f64,
+    pub bitrate: f64,
+    pub loss_percent: f64,
+    pub jitter_ms: f64,
+    pub packets: u64,
+    pub lost: u64,
+}
+
+// The last samples, e.g. dumped to a file when a user reports a
+// stream that stutters now and then
+//
+// std::fs::write("stats.csv", rtp.stats().history().to_csv())?;
+#[derive(Clone, Debug)]
+pub struct StatsHistory {
+    len: usize, // samples kept, 0 keeps none
+    samples: VecDeque<StatsSample>,
+}
+
+impl Default for StatsHistory {
+    fn default() -> Self {
+        StatsHistory {
+            len: DEFAULT_HISTORY,
+            samples: VecDeque::new(),
+        }
+    }
+}
+
+impl StatsHistory {
+    pub fn samples(&self) -> impl Iterator<Item = &StatsSample> {
+        self.samples.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    // One line per sample after a header line, time in seconds
+    // since the Unix epoch e.g.
+    // time,fps,bitrate,loss_percent,jitter_ms,packets,lost
+    // 1714557600.012,25.00,2048000,0.00,1.84,190,0
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("time,fps,bitrate,loss_percent,jitter_ms,packets,lost\n");
+        for sample in &self.samples {
+            let _ = writeln!(
+                csv,
+                "{:.3},{:.2},{:.0},{:.2},{:.2},{},{}",
+                unix_time(sample.time),
+                sample.fps,
+                sample.bitrate,
+                sample.loss_percent,
+                sample.jitter_ms,
+                sample.packets,
+                sample.lost
+            );
+        }
+        csv
+    }
+
+    // e.g. [{"time":1714557600.012,"fps":25.00,"bitrate":2048000,
+    // "loss_percent":0.00,"jitter_ms":1.84,"packets":190,"lost":0}]
+    pub fn to_json(&self) -> String {
+        let samples: Vec<String> = self
+            .samples
+            .iter()
+            .map(|sample| {
+                format!(
+                    r#"{{"time":{:.3},"fps":{:.2},"bitrate":{:.0},"loss_percent":{:.2},"jitter_ms":{:.2},"packets":{},"lost":{}}}"#,
+                    unix_time(sample.time),
+                    sample.fps,
+                    sample.bitrate,
+                    sample.loss_percent,
+                    sample.jitter_ms,
+                    sample.packets,
+                    sample.lost
+                )
+            })
+            .collect();
+
+        format!("[{}]", samples.join(","))
+    }
+
+    fn push(&mut self, sample: StatsSample) {
+        self.samples.push_back(sample);
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        while self.samples.len() > self.len {
+            self.samples.pop_front();
+        }
+    }
+}
+
+fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64()
+}
+
+// Limits which raise an alert when crossed, None is not checked
+#[derive(Clone, Debug, Default)]
+pub struct Thresholds {
+    pub min_fps: Option<f64>,
+    pub min_bitrate: Option<f64>,
+    pub max_loss_percent: Option<f64>,
+    pub max_stall: Option<Duration>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Alert {
+    LowFps(f64),
+    LowBitrate(f64),
+    HighLoss(f64),
+    Stall(Duration),
+}
+
+type AlertFn = Box<dyn FnMut(Alert) + Send>;
+
+// Checks stats against thresholds and calls back when one is
+// crossed. Each alert is raised once, then again only after the
+// stream has recovered and crossed the threshold another time.
+pub(crate) struct Alerts {
+    thresholds: Thresholds,
+    on_alert: AlertFn,
+    low_fps: bool,
+    low_bitrate: bool,
+    high_loss: bool,
+    stalled: bool,
+}
+
+impl Alerts {
+    pub(crate) fn new(thresholds: Thresholds, on_alert: AlertFn) -> Self {
+        Alerts {
+            thresholds,
+            on_alert,
+            low_fps: false,
+            low_bitrate: false,
+            high_loss: false,
+            stalled: false,
+        }
+    }
+
+    // After each packet, 'gap' is the time since the one before
+    pub(crate) fn check(&mut self, stats: &Stats, gap: Duration) {
+        let thresholds = &self.thresholds;
+
+        // Rates mean nothing until the first window completes
+        if stats.window.start.is_some() && stats.packets > stats.window.packets {
+            let low_fps = thresholds.min_fps.is_some_and(|min| stats.fps < min);
+            let low_bitrate = thresholds.min_bitrate.is_some_and(|min| stats.bitrate < min);
+            let high_loss = thresholds.max_loss_percent.is_some_and(|max| stats.loss_percent > max);
+
+            raise(&mut self.on_alert, &mut self.low_fps, low_fps, Alert::LowFps(stats.fps));
+            raise(&mut self.on_alert, &mut self.low_bitrate, low_bitrate, Alert::LowBitrate(stats.bitrate));
+            raise(&mut self.on_alert, &mut self.high_loss, high_loss, Alert::HighLoss(stats.loss_percent));
+        }
+
+        self.check_stall(gap);
+    }
+
+    // Also called while waiting, e.g. when a receive times out
+    pub(crate) fn check_stall(&mut self, stall: Duration) {
+        let stalled = self.thresholds.max_stall.is_some_and(|max| stall > max);
+        raise(&mut self.on_alert, &mut self.stalled, stalled, Alert::Stall(stall));
+    }
+}
+
+fn raise(on_alert: &mut AlertFn, raised: &mut bool, breached: bool, alert: Alert) {
+    if breached && !*raised {
+        on_alert(alert);
+    }
+    *raised = breached;
+}
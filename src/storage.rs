@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::pin::Pin;
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+// Where recordings go. LocalStorage writes them under a directory,
+// anything else (S3, MinIO, an object store) can be plugged in by
+// implementing StorageBackend, e.g. with a multipart upload:
+// create starts it, write uploads parts, finalize completes it
+//
+// let storage = LocalStorage::new("recordings");
+// rtp.save_to(&storage, "front-door/0001.h264").await?;
+// mp4::export_clip_to(&replay, start, end, &[], &storage, "front-door/clip.mp4").await?;
+
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+pub trait StorageBackend: Send + Sync {
+    // Start a recording at 'key', a relative path with / separators
+    // e.g. "front-door/2024-05-01T10-00-00.mp4"
+    fn create<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Box<dyn StorageWriter>>;
+}
+
+// Bytes of one recording, in order. Nothing is at the key until
+// finalize succeeds, a writer dropped before then at most leaves
+// something for the backend to clean up (LocalStorage's .part file)
+pub trait StorageWriter: Send {
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> StorageFuture<'a, ()>;
+
+    fn finalize(self: Box<Self>) -> StorageFuture<'static, ()>;
+}
+
+// Whole recording in one go
+pub async fn store(storage: &dyn StorageBackend, key: &str, data: &[u8]) -> io::Result<()> {
+    let mut writer = storage.create(key).await?;
+    writer.write(data).await?;
+    writer.finalize().await
+}
+
+// Recordings as files under 'root', written to a temporary file next
+// to the final one and renamed into place by finalize
+#[derive(Clone, Debug)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        LocalStorage { root: root.as_ref().to_path_buf() }
+    }
+
+    // File a key is stored at, keys can't climb out of the root
+    pub fn path(&self, key: &str) -> io::Result<PathBuf> {
+        let key = Path::new(key);
+        if key.as_os_str().is_empty() || !key.components().all(|component| matches!(component, Component::Normal(_))) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid storage key {}", key.display())));
+        }
+
+        Ok(self.root.join(key))
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn create<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Box<dyn StorageWriter>> {
+        Box::pin(async move {
+            let path = self.path(key)?;
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).await?;
+            }
+
+            let mut temp = path.clone().into_os_string();
+            temp.push(".part");
+            let temp = PathBuf::from(temp);
+            let file = File::create(&temp).await?;
+
+            Ok(Box::new(LocalWriter { file, temp, path }) as Box<dyn StorageWriter>)
+        })
+    }
+}
+
+struct LocalWriter {
+    file: File,
+    temp: PathBuf,
+    path: PathBuf,
+}
+
+impl StorageWriter for LocalWriter {
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> StorageFuture<'a, ()> {
+        Box::pin(self.file.write_all(data))
+    }
+
+    fn finalize(self: Box<Self>) -> StorageFuture<'static, ()> {
+        Box::pin(async move {
+            let LocalWriter { mut file, temp, path } = *self;
+            file.flush().await?;
+            file.sync_all().await?;
+            drop(file);
+
+            fs::rename(&temp, &path).await
+        })
+    }
+}
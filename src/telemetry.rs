@@ -0,0 +1,167 @@
+use crate::journal::Event;
+use opentelemetry::global::{self, BoxedSpan, BoxedTracer};
+use opentelemetry::metrics::{Counter, Histogram};
+use opentelemetry::trace::{Span, Status, TraceContextExt, Tracer};
+use opentelemetry::{Context, KeyValue};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+const NAME: &str = "rtsp-rtp-rs";
+
+// OpenTelemetry export of the session handshake, each request,
+// keep-alives and decoding (one span per GOP), with the 'otel' feature
+//
+// Spans and metrics go to the global tracer and meter providers, so
+// set those up (e.g. with opentelemetry-otlp) before connecting.
+//
+// rtsp.set_telemetry(Telemetry::new());
+// rtp.set_telemetry(Telemetry::new());
+//
+// Spans: rtsp.handshake (connect until PLAY/RECORD) with a child
+// rtsp.request per request, rtsp.keepalive, rtp.gop
+// Metrics: rtsp.requests, rtsp.request.duration (s),
+// rtp.decode.duration (s), rtp.gop.frames
+pub struct Telemetry {
+    tracer: BoxedTracer,
+    requests: Counter<u64>,
+    request_duration: Histogram<f64>,
+    decode_duration: Histogram<f64>,
+    gop_frames: Histogram<u64>,
+    handshake: Option<BoxedSpan>,
+    request: Option<(BoxedSpan, Instant)>,
+    gop: Option<(BoxedSpan, u64)>,
+}
+
+impl Telemetry {
+    pub fn new() -> Self {
+        let meter = global::meter(NAME);
+
+        Telemetry {
+            tracer: global::tracer(NAME),
+            requests: meter.u64_counter("rtsp.requests").init(),
+            request_duration: meter.f64_histogram("rtsp.request.duration").init(),
+            decode_duration: meter.f64_histogram("rtp.decode.duration").init(),
+            gop_frames: meter.u64_histogram("rtp.gop.frames").init(),
+            handshake: None,
+            request: None,
+            gop: None,
+        }
+    }
+
+    // Same events as the Journal, turned into spans
+    pub(crate) fn event(&mut self, server: SocketAddr, event: &Event) {
+        match event {
+            Event::Connect | Event::Reconnect { .. } => {
+                let mut handshake = self.tracer.start("rtsp.handshake");
+                handshake.set_attribute(KeyValue::new("server.address", server.to_string()));
+                if let Event::Reconnect { attempt } = event {
+                    handshake.set_attribute(KeyValue::new("rtsp.reconnect.attempt", *attempt as i64));
+                }
+
+                if let Some(mut previous) = self.handshake.replace(handshake) {
+                    previous.set_status(Status::error("interrupted"));
+                    previous.end();
+                }
+            }
+            Event::Redirect { location } => {
+                self.add_event("redirect", vec![KeyValue::new("rtsp.location", location.to_string())]);
+            }
+            Event::Request { method, cseq } => {
+                let cx = match &self.handshake {
+                    Some(handshake) => Context::new().with_remote_span_context(handshake.span_context().clone()),
+                    None => Context::new(),
+                };
+
+                let mut request = self.tracer.start_with_context("rtsp.request", &cx);
+                request.set_attribute(KeyValue::new("rtsp.method", method.to_string()));
+                request.set_attribute(KeyValue::new("rtsp.cseq", *cseq as i64));
+
+                if let Some((mut previous, _)) = self.request.replace((request, Instant::now())) {
+                    previous.end();
+                }
+            }
+            Event::Response { method, status } => {
+                let attributes = [
+                    KeyValue::new("rtsp.method", method.to_string()),
+                    KeyValue::new("rtsp.status", *status as i64),
+                ];
+                self.requests.add(1, &attributes);
+
+                if let Some((mut request, started)) = self.request.take() {
+                    self.request_duration.record(started.elapsed().as_secs_f64(), &attributes);
+                    request.set_attribute(KeyValue::new("rtsp.status", *status as i64));
+                    if !(200..300).contains(status) {
+                        request.set_status(Status::error(format!("{method} answered {status}")));
+                    }
+                    request.end();
+                }
+            }
+            Event::Unauthorized { method } => {
+                self.add_event("unauthorized", vec![KeyValue::new("rtsp.method", method.to_string())]);
+            }
+            Event::Play | Event::Record => {
+                if let Some(mut handshake) = self.handshake.take() {
+                    handshake.set_status(Status::Ok);
+                    handshake.end();
+                }
+            }
+            Event::Teardown { .. } | Event::Disconnect => {
+                if let Some(mut handshake) = self.handshake.take() {
+                    handshake.set_status(Status::error("closed before playing"));
+                    handshake.end();
+                }
+            }
+        }
+    }
+
+    // After each frame decoded, a keyframe starts a new GOP span
+    pub(crate) fn decoded(&mut self, keyframe: bool, took: Duration) {
+        self.decode_duration.record(took.as_secs_f64(), &[]);
+
+        if keyframe {
+            self.end_gop();
+            self.gop = Some((self.tracer.start("rtp.gop"), 0));
+        }
+
+        if let Some((_, frames)) = &mut self.gop {
+            *frames += 1;
+        }
+    }
+
+    fn end_gop(&mut self) {
+        if let Some((mut gop, frames)) = self.gop.take() {
+            self.gop_frames.record(frames, &[]);
+            gop.set_attribute(KeyValue::new("rtp.gop.frames", frames as i64));
+            gop.end();
+        }
+    }
+
+    fn add_event(&mut self, name: &'static str, attributes: Vec<KeyValue>) {
+        if let Some(handshake) = &mut self.handshake {
+            handshake.add_event(name, attributes);
+        }
+    }
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        self.end_gop();
+    }
+}
+
+// One span per keep-alive, sent from the keep-alive task
+pub(crate) fn keepalive(method: &'static str, result: Result<(), String>) {
+    let mut span = global::tracer(NAME).start("rtsp.keepalive");
+    span.set_attribute(KeyValue::new("rtsp.method", method));
+
+    if let Err(e) = result {
+        span.set_status(Status::error(e));
+    }
+    span.end();
+}
@@ -0,0 +1,236 @@
+use crate::h264;
+use log::debug;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// In memory timeshift buffer (pause live TV style)
+//
+// Encoded units (e.g. the Annex B data returned by
+// Depacketizer::pending) are recorded continuously with the
+// time they arrived. A viewer reads them back with pop(), which
+// only hands out units once they are older than the current delay.
+// Pausing the viewer keeps recording, and resuming continues where
+// the viewer left off, now that much further behind live.
+// Anything older than the window is discarded.
+//
+// Units holding an IDR are indexed along with the SPS/PPS in effect,
+// so seek_back() lands on a keyframe the decoder can start from.
+pub struct Timeshift {
+    window: Duration,
+    delay: Duration,
+    paused_at: Option<Instant>,
+    units: VecDeque<(Instant, Vec<u8>)>,
+    bytes: usize,
+    max_bytes: Option<usize>,
+    // Sequence number of the first unit in 'units'
+    first_seq: u64,
+    // Sequence number of the next unit handed to the viewer
+    read_seq: u64,
+    // IDR units, oldest first
+    checkpoints: VecDeque<Checkpoint>,
+    // Latest SPS and PPS seen (Annex B)
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+    // Parameter sets to hand out before the unit seeked to
+    seek_parameter_sets: Vec<u8>,
+    send_parameter_sets: bool,
+}
+
+// Where decoding can start again: an IDR and the parameter sets it needs
+struct Checkpoint {
+    seq: u64,
+    parameter_sets: Vec<u8>,
+}
+
+impl Timeshift {
+    pub fn new(window: Duration) -> Self {
+        Timeshift {
+            window,
+            delay: Duration::ZERO,
+            paused_at: None,
+            units: VecDeque::new(),
+            bytes: 0,
+            max_bytes: None,
+            first_seq: 0,
+            read_seq: 0,
+            checkpoints: VecDeque::new(),
+            sps: Vec::new(),
+            pps: Vec::new(),
+            seek_parameter_sets: Vec::new(),
+            send_parameter_sets: false,
+        }
+    }
+
+    // Most memory the recorded units may take, see MemoryBudget
+    // Oldest units are dropped first. None is unbounded
+    pub fn set_max_bytes(&mut self, max_bytes: Option<usize>) {
+        self.max_bytes = max_bytes;
+        self.trim(Instant::now());
+    }
+
+    // Record a unit as it arrives from the live stream
+    pub fn push(&mut self, unit: &[u8]) {
+        let now = Instant::now();
+
+        let mut is_keyframe = false;
+        for nal in h264::nal_units(unit) {
+            match nal[0] & 31 {
+                5 => is_keyframe = true,
+                7 => self.sps = annexb(nal),
+                8 => self.pps = annexb(nal),
+                _ => (),
+            }
+        }
+        if is_keyframe {
+            self.checkpoints.push_back(Checkpoint {
+                seq: self.first_seq + self.units.len() as u64,
+                parameter_sets: [self.sps.as_slice(), self.pps.as_slice()].concat(),
+            });
+        }
+
+        self.units.push_back((now, unit.to_vec()));
+        self.bytes += unit.len();
+
+        self.trim(now);
+    }
+
+    // Drop anything that has fallen out of the window or the byte limit
+    fn trim(&mut self, now: Instant) {
+        while let Some((time, unit)) = self.units.front() {
+            let over_bytes = match self.max_bytes {
+                Some(max_bytes) => self.bytes > max_bytes,
+                None => false,
+            };
+            if now.duration_since(*time) <= self.window && !over_bytes {
+                break;
+            }
+
+            self.bytes -= unit.len();
+            self.units.pop_front();
+            self.first_seq += 1;
+        }
+
+        while let Some(checkpoint) = self.checkpoints.front() {
+            if checkpoint.seq >= self.first_seq {
+                break;
+            }
+            self.checkpoints.pop_front();
+        }
+
+        // Viewer fell behind the window, jump to oldest available
+        if self.read_seq < self.first_seq {
+            debug!("[Timeshift] viewer fell out of window, skipping ahead");
+            self.read_seq = self.first_seq;
+            self.delay = self.window;
+        }
+    }
+
+    // Next unit for the viewer, if one is due
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        if self.paused_at.is_some() {
+            return None;
+        }
+
+        let index = (self.read_seq - self.first_seq) as usize;
+        let (time, unit) = self.units.get(index)?;
+
+        if time.elapsed() < self.delay {
+            return None;
+        }
+
+        // After a seek the decoder gets the parameter sets first
+        if self.send_parameter_sets {
+            self.send_parameter_sets = false;
+            return Some(self.seek_parameter_sets.as_slice());
+        }
+
+        self.read_seq += 1;
+        Some(unit.as_slice())
+    }
+
+    pub fn pause(&mut self) {
+        if self.paused_at.is_none() {
+            self.paused_at = Some(Instant::now());
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(paused_at) = self.paused_at.take() {
+            self.delay = (self.delay + paused_at.elapsed()).min(self.window);
+        }
+    }
+
+    // Jump back 'by' from where the viewer is, to the keyframe at or
+    // before that point (or the oldest one buffered), so decoding can
+    // start right there. The next pop() returns the SPS/PPS the
+    // keyframe needs, then the keyframe. False if no keyframe is buffered
+    pub fn seek_back(&mut self, by: Duration) -> bool {
+        let now = Instant::now();
+        let position = now.checked_sub(self.delay()).unwrap_or(now);
+        let target = position.checked_sub(by).unwrap_or(position);
+
+        let checkpoint = self
+            .checkpoints
+            .iter()
+            .rev()
+            .find(|checkpoint| self.unit_time(checkpoint.seq).is_some_and(|time| time <= target))
+            .or_else(|| self.checkpoints.front());
+
+        let (seq, parameter_sets) = match checkpoint {
+            Some(checkpoint) => (checkpoint.seq, checkpoint.parameter_sets.clone()),
+            None => return false,
+        };
+        let time = match self.unit_time(seq) {
+            Some(time) => time,
+            None => return false,
+        };
+
+        debug!("[Timeshift] Seeking back {by:?} to keyframe {seq}");
+        self.read_seq = seq;
+        self.seek_parameter_sets = parameter_sets;
+        self.send_parameter_sets = !self.seek_parameter_sets.is_empty();
+
+        // Behind live by as much as the keyframe is old, paused or not
+        self.delay = now.duration_since(time).min(self.window);
+        if let Some(paused_at) = &mut self.paused_at {
+            *paused_at = now;
+        }
+
+        true
+    }
+
+    // Number of keyframes buffered, i.e. seek points
+    pub fn keyframes(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    fn unit_time(&self, seq: u64) -> Option<Instant> {
+        let index = seq.checked_sub(self.first_seq)? as usize;
+        self.units.get(index).map(|(time, _)| *time)
+    }
+
+    // Skip everything buffered and return to the live edge
+    pub fn go_live(&mut self) {
+        self.paused_at = None;
+        self.send_parameter_sets = false;
+        self.delay = Duration::ZERO;
+        self.read_seq = self.first_seq + self.units.len() as u64;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused_at.is_some()
+    }
+
+    // How far behind live the viewer currently is
+    pub fn delay(&self) -> Duration {
+        match self.paused_at {
+            Some(paused_at) => (self.delay + paused_at.elapsed()).min(self.window),
+            None => self.delay,
+        }
+    }
+}
+
+// NAL unit with a 4 byte start code
+fn annexb(nal: &[u8]) -> Vec<u8> {
+    [&[0u8, 0, 0, 1][..], nal].concat()
+}